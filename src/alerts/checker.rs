@@ -386,6 +386,9 @@ impl AlertChecker {
             crate::data::Value::Timestamp(_) => {
                 AlertValue::Int(value.as_i64().unwrap_or(0))
             }
+            crate::data::Value::Histogram(_) | crate::data::Value::TopK(_) => {
+                AlertValue::String(value.to_string())
+            }
         })
     }
 }
@@ -420,6 +423,7 @@ mod tests {
             shards_scanned: 1,
             execution_time_ms: 1,
             availability: None,
+            next_cursor: None,
         }
     }
 
@@ -9,9 +9,15 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::alerts::{Alert, AlertChecker, AlertCondition};
-use crate::cluster::{ClusterConfig, Coordinator};
-use crate::data::{value::flatten_json, TableConfig, Value};
-use crate::query::{run_query, QueryResult, QueryCache, CacheStats};
+use crate::cluster::client::{RemoteQueryRequest, RemoteQueryResponse};
+use crate::cluster::{partial, ClusterConfig, Coordinator};
+use crate::data::{value::flatten_json, Table, TableConfig, Value};
+use crate::ingest::csv::parse_csv;
+use crate::query::registry::QueryInfo;
+use crate::query::{
+    parse_query_at, query_time, run_query_page, CacheStats, QueryCache, QueryControl,
+    QueryError, QueryLimits, QueryRegistry, QueryResult, ScanCursor,
+};
 use crate::storage::StorageEngine;
 
 /// Application state shared across handlers
@@ -21,6 +27,11 @@ pub struct AppState {
     pub cluster_config: ClusterConfig,
     pub query_cache: Arc<QueryCache>,
     pub alert_checker: Arc<AlertChecker>,
+    /// Limits every query runs under; requests can only tighten them
+    pub query_limits: QueryLimits,
+    pub running_queries: Arc<QueryRegistry>,
+    /// Queries running at least this long are logged to `_snorkel_queries`
+    pub slow_query_ms: Option<u64>,
 }
 
 // ============================================================================
@@ -112,6 +123,16 @@ pub async fn ingest(
 #[derive(Deserialize)]
 pub struct QueryRequest {
     pub sql: String,
+    /// `next_cursor` of the previous page, to continue a scan from there
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// Id to cancel the query by with `DELETE /queries/:id`. One is
+    /// generated if not given.
+    #[serde(default)]
+    pub query_id: Option<String>,
+    /// Limits tighter than the server's for this query
+    #[serde(flatten)]
+    pub limits: QueryLimits,
 }
 
 #[derive(Serialize)]
@@ -124,6 +145,12 @@ pub struct QueryResponse {
     pub execution_time_ms: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub availability: Option<AvailabilityInfo>,
+    /// Pass back as `cursor` to get the next page of a limited scan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// Id the query ran under; cached results have none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_id: Option<String>,
 }
 
 /// Availability info for query response
@@ -158,6 +185,8 @@ impl From<QueryResult> for QueryResponse {
             shards_scanned: result.shards_scanned,
             execution_time_ms: result.execution_time_ms,
             availability,
+            next_cursor: result.next_cursor,
+            query_id: None,
         }
     }
 }
@@ -170,6 +199,20 @@ fn value_to_json(value: Value) -> serde_json::Value {
         Value::Float64(f) => serde_json::json!(f),
         Value::String(s) => serde_json::Value::String(s),
         Value::Timestamp(t) => serde_json::json!(t),
+        Value::Histogram(h) => serde_json::json!({
+            "boundaries": h.boundaries,
+            "counts": h.counts,
+        }),
+        Value::TopK(entries) => entries
+            .into_iter()
+            .map(|entry| {
+                serde_json::json!({
+                    "value": value_to_json(entry.value),
+                    "count": entry.count,
+                    "error": entry.error,
+                })
+            })
+            .collect(),
     }
 }
 
@@ -177,26 +220,161 @@ pub async fn query(
     State(state): State<Arc<AppState>>,
     Json(request): Json<QueryRequest>,
 ) -> Result<Json<QueryResponse>, ApiError> {
-    // Check cache first
-    if let Some(cached) = state.query_cache.get(&request.sql) {
-        return Ok(Json(cached.into()));
+    // Queries using NOW() or ago() are cached per reference time, so an
+    // entry is never served for a window that has since moved
+    let now = query_time();
+    // EXPLAIN describes the run at hand, so it is never cached, and a
+    // cached result may be over a request's own limits
+    let cacheable = parse_query_at(&request.sql, now)
+        .ok()
+        .filter(|q| q.explain.is_none() && request.limits == QueryLimits::default());
+
+    let cursor = request
+        .cursor
+        .as_deref()
+        .map(str::parse::<ScanCursor>)
+        .transpose()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+
+    // Check cache first. Only first pages are cached.
+    let cacheable = cacheable.filter(|_| cursor.is_none());
+    if let Some(parsed) = &cacheable {
+        if let Some(cached) = state.query_cache.get(&request.sql, parsed) {
+            return Ok(Json(cached.into()));
+        }
     }
 
+    // The query stops if this handler is dropped, as when the client
+    // disconnects, since that drops `running`
+    let control = Arc::new(QueryControl::new(state.query_limits.tightened(&request.limits)));
+    let running = state
+        .running_queries
+        .register(request.query_id.clone(), &request.sql, control)
+        .ok_or_else(|| ApiError::BadRequest("query_id is already in use".into()))?;
+
     let result = if let Some(ref coordinator) = state.coordinator {
-        // Distributed query
+        // Cursors point into this node's shards, so they are never handed
+        // out by distributed queries
+        if cursor.is_some() {
+            return Err(ApiError::BadRequest(
+                "pagination cursors are not supported for distributed queries".into(),
+            ));
+        }
+        // Distributed query. The coordinator runs its work on this node
+        // off the async runtime.
         coordinator
-            .execute_query(&request.sql)
+            .execute_query(&request.sql, now, Some(&running))
             .await
-            .map_err(|e| ApiError::Query(e.to_string()))?
+            .map_err(|e| e.to_string())
     } else {
-        // Local query
-        run_query(&state.engine, &request.sql).map_err(|e| ApiError::Query(e.to_string()))?
+        // Local query, off the async runtime
+        let engine = Arc::clone(&state.engine);
+        let sql = request.sql.clone();
+        let control = Arc::clone(running.control());
+        tokio::task::spawn_blocking(move || {
+            run_query_page(&engine, &sql, now, cursor, Some(&control))
+        })
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+        .map_err(|e| e.to_string())
     };
+    running.log_if_slow(&state.engine, state.slow_query_ms, &result);
+    let result = result.map_err(ApiError::Query)?;
 
     // Cache the result
-    state.query_cache.put(&request.sql, result.clone());
+    if let Some(parsed) = &cacheable {
+        state.query_cache.put(&request.sql, parsed, result.clone());
+    }
+
+    let mut response = QueryResponse::from(result);
+    response.query_id = Some(running.id().to_string());
+    Ok(Json(response))
+}
 
-    Ok(Json(result.into()))
+#[derive(Serialize)]
+pub struct QueriesResponse {
+    pub queries: Vec<QueryInfo>,
+}
+
+/// Queries running on this node, oldest first, including its parts of
+/// queries peers coordinate
+pub async fn list_queries(State(state): State<Arc<AppState>>) -> Json<QueriesResponse> {
+    Json(QueriesResponse {
+        queries: state.running_queries.list(),
+    })
+}
+
+/// Cancel a running query, and its parts on peers in cluster mode. It stops
+/// at its next check between shards or row batches, failing with an error.
+pub async fn cancel_query(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if !state.running_queries.cancel(&id) {
+        return Err(ApiError::NotFound(format!("No running query '{}'", id)));
+    }
+    if let Some(ref coordinator) = state.coordinator {
+        coordinator.cancel_on_peers(&id).await;
+    }
+    Ok(Json(serde_json::json!({ "cancelled": id })))
+}
+
+/// Cancel this node's parts of query `id` coordinated by peer `node`
+pub async fn cancel_query_part(
+    State(state): State<Arc<AppState>>,
+    Path((node, id)): Path<(String, String)>,
+) -> Json<serde_json::Value> {
+    let cancelled = state.running_queries.cancel_parts(&format!("{}/{}", node, id));
+    Json(serde_json::json!({ "cancelled": cancelled }))
+}
+
+/// Execute a query on this node only, on behalf of a coordinating peer.
+/// Never fans out and bypasses the query cache. The query runs under the
+/// coordinator's limits as well as this node's, and stops if the coordinator
+/// cancels it or drops the request.
+pub async fn internal_query(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<RemoteQueryRequest>,
+) -> Result<Json<RemoteQueryResponse>, ApiError> {
+    let now = request.now.unwrap_or_else(query_time);
+    let control = Arc::new(QueryControl::new(state.query_limits.tightened(&request.limits)));
+    // Stops the query if this handler is dropped, as when the coordinator
+    // gives up on the request
+    let part_of = request.part_of.clone();
+    let _running = state.running_queries.register_part(part_of, &request.sql, control.clone());
+    let engine = Arc::clone(&state.engine);
+    let response = tokio::task::spawn_blocking(move || {
+        let control = Some(&control);
+        if request.partial {
+            let RemoteQueryRequest {
+                sql,
+                in_lists,
+                lookup,
+                ..
+            } = request;
+            partial::execute_partial(&engine, &sql, now, &in_lists, lookup, control)
+        } else {
+            run_query_page(&engine, &request.sql, now, None, control).map(|result| {
+                RemoteQueryResponse {
+                    columns: result.columns,
+                    rows: result.rows,
+                    rows_scanned: result.rows_scanned,
+                    shards_scanned: result.shards_scanned,
+                    partial_states: None,
+                    previous_states: None,
+                    profile: None,
+                }
+            })
+        }
+    })
+    .await
+    .map_err(|e| ApiError::Internal(e.to_string()))?;
+
+    // The coordinator fails the whole query when its part stops here
+    response.map(Json).map_err(|e| match e {
+        QueryError::Execute(ref stop) if stop.is_stopped() => ApiError::Stopped(e.to_string()),
+        e => ApiError::Query(e.to_string()),
+    })
 }
 
 // ============================================================================
@@ -320,6 +498,25 @@ pub async fn drop_table(
     Ok(Json(serde_json::json!({ "dropped": name })))
 }
 
+/// Load a dimension table from CSV with a header row, replacing any table
+/// of the same name. Queries can join it as a lookup table.
+pub async fn load_csv(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    body: String,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let (columns, rows) = parse_csv(&body).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    state.engine.replace_table(Table::from_rows(&name, &columns, &rows));
+    // Cached results may have joined the old rows
+    state.query_cache.invalidate_all();
+
+    Ok(Json(serde_json::json!({
+        "table": name,
+        "columns": columns,
+        "rows": rows.len(),
+    })))
+}
+
 // ============================================================================
 // Stats
 // ============================================================================
@@ -369,6 +566,8 @@ pub enum ApiError {
     BadRequest(String),
     NotFound(String),
     Query(String),
+    /// The query was cancelled, ran out of time or went over a limit
+    Stopped(String),
     Internal(String),
 }
 
@@ -378,6 +577,7 @@ impl IntoResponse for ApiError {
             ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
             ApiError::Query(msg) => (StatusCode::BAD_REQUEST, msg),
+            ApiError::Stopped(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
             ApiError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
         };
 
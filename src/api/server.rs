@@ -1,7 +1,7 @@
 use axum::{
     http::{header, StatusCode},
     response::{Html, IntoResponse, Response},
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 use std::net::SocketAddr;
@@ -11,9 +11,10 @@ use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 
 use super::handlers::{
-    cache_stats, create_alert, create_table, delete_alert, drop_table, get_alert, health_check,
-    ingest, invalidate_cache, list_alerts, list_tables, query, set_alert_enabled, stats,
-    table_schema, update_alert, AppState,
+    cache_stats, cancel_query, cancel_query_part, create_alert, create_table, delete_alert,
+    drop_table, get_alert, health_check, ingest, internal_query, invalidate_cache, list_alerts,
+    list_queries, list_tables, load_csv, query, set_alert_enabled, stats, table_schema,
+    update_alert, AppState,
 };
 use crate::alerts::AlertChecker;
 use crate::cluster::{ClusterConfig, Coordinator};
@@ -21,7 +22,7 @@ use crate::compaction::{SubsampleWorker, TtlWorker};
 #[cfg(feature = "kafka")]
 use crate::ingest::{KafkaConfig, KafkaConsumer};
 use crate::otel::handle_otlp_traces;
-use crate::query::QueryCache;
+use crate::query::{QueryCache, QueryLimits, QueryRegistry};
 use crate::storage::persistence::{PersistenceConfig, SnapshotManager};
 use crate::storage::StorageEngine;
 
@@ -46,6 +47,12 @@ pub struct ServerConfig {
     pub data_dir: Option<std::path::PathBuf>,
     /// Snapshot interval in seconds (default: 300 = 5 minutes)
     pub snapshot_interval_secs: u64,
+    /// Limits every query runs under (default: 60 seconds, with groups,
+    /// output rows and bytes scanned unlimited)
+    pub query_limits: QueryLimits,
+    /// Queries running at least this many milliseconds are logged to the
+    /// `_snorkel_queries` table (default: 1000; None = no log)
+    pub slow_query_ms: Option<u64>,
 }
 
 impl Default for ServerConfig {
@@ -59,6 +66,13 @@ impl Default for ServerConfig {
             cluster_config: ClusterConfig::default(),
             data_dir: None,
             snapshot_interval_secs: 300,
+            query_limits: QueryLimits {
+                timeout_ms: Some(60_000),
+                max_groups: None,
+                max_output_rows: None,
+                max_scanned_bytes: None,
+            },
+            slow_query_ms: Some(1000),
         }
     }
 }
@@ -128,11 +142,16 @@ pub fn build_router(state: Arc<AppState>) -> Router {
         // Data operations
         .route("/ingest", post(ingest))
         .route("/query", post(query))
+        .route("/internal/query", post(internal_query))
+        .route("/internal/queries/:node/:id", delete(cancel_query_part))
+        .route("/queries", get(list_queries))
+        .route("/queries/:id", delete(cancel_query))
         // Table management
         .route("/tables", get(list_tables))
         .route("/tables", post(create_table))
         .route("/tables/:name", delete(drop_table))
         .route("/tables/:name/schema", get(table_schema))
+        .route("/tables/:name/csv", put(load_csv))
         // Stats
         .route("/stats", get(stats))
         // Alerts
@@ -228,6 +247,9 @@ pub async fn run_server(config: ServerConfig) -> Result<(), Box<dyn std::error::
         cluster_config: config.cluster_config.clone(),
         query_cache,
         alert_checker,
+        query_limits: config.query_limits.clone(),
+        running_queries: Arc::new(QueryRegistry::new()),
+        slow_query_ms: config.slow_query_ms,
     });
 
     // Start Kafka consumer if configured
@@ -350,6 +372,9 @@ mod tests {
             cluster_config: ClusterConfig::default(),
             query_cache: Arc::new(QueryCache::new()),
             alert_checker: Arc::new(AlertChecker::new(engine)),
+            query_limits: QueryLimits::default(),
+            running_queries: Arc::new(QueryRegistry::new()),
+            slow_query_ms: None,
         });
         build_router(state)
     }
@@ -380,6 +405,9 @@ mod tests {
             cluster_config: ClusterConfig::default(),
             query_cache: Arc::new(QueryCache::new()),
             alert_checker: Arc::new(AlertChecker::new(Arc::clone(&engine))),
+            query_limits: QueryLimits::default(),
+            running_queries: Arc::new(QueryRegistry::new()),
+            slow_query_ms: None,
         });
         let app = build_router(state);
 
@@ -442,6 +470,9 @@ mod tests {
             cluster_config: ClusterConfig::default(),
             query_cache: Arc::new(QueryCache::new()),
             alert_checker: Arc::new(AlertChecker::new(engine)),
+            query_limits: QueryLimits::default(),
+            running_queries: Arc::new(QueryRegistry::new()),
+            slow_query_ms: None,
         });
         let app = build_router(state);
 
@@ -474,4 +505,139 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
+
+    #[tokio::test]
+    async fn test_cancel_unknown_query() {
+        let app = create_test_app();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/queries/nonexistent")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_list_queries_and_slow_query_log() {
+        let engine = Arc::new(StorageEngine::new());
+        let state = Arc::new(AppState {
+            engine: Arc::clone(&engine),
+            coordinator: None,
+            cluster_config: ClusterConfig::default(),
+            query_cache: Arc::new(QueryCache::new()),
+            alert_checker: Arc::new(AlertChecker::new(Arc::clone(&engine))),
+            query_limits: QueryLimits::default(),
+            running_queries: Arc::new(QueryRegistry::new()),
+            slow_query_ms: Some(0),
+        });
+        let app = build_router(state);
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/queries").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Every query is slow enough, failed ones included
+        let query_body = serde_json::json!({ "sql": "SELECT COUNT(*) FROM missing" });
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/query")
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&query_body).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let log = engine.get_table(crate::query::registry::QUERY_LOG_TABLE).unwrap();
+        assert_eq!(log.row_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_internal_query_stopped() {
+        let engine = Arc::new(StorageEngine::new());
+        let row = std::collections::HashMap::from([(
+            "timestamp".to_string(),
+            crate::data::Value::Timestamp(1000),
+        )]);
+        engine.insert("events", row).unwrap();
+        let state = Arc::new(AppState {
+            engine: Arc::clone(&engine),
+            coordinator: None,
+            cluster_config: ClusterConfig::default(),
+            query_cache: Arc::new(QueryCache::new()),
+            alert_checker: Arc::new(AlertChecker::new(engine)),
+            query_limits: QueryLimits::default(),
+            running_queries: Arc::new(QueryRegistry::new()),
+            slow_query_ms: None,
+        });
+        let app = build_router(state);
+        let internal_query = |body: serde_json::Value| {
+            Request::builder()
+                .method("POST")
+                .uri("/internal/query")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_string(&body).unwrap()))
+                .unwrap()
+        };
+
+        // A stopped query is told apart from a failed one, so the
+        // coordinator does not leave the node out of the results
+        let request = internal_query(serde_json::json!({
+            "sql": "SELECT COUNT(*) FROM events",
+            "partial": true,
+            "limits": { "timeout_ms": 0 },
+        }));
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let request = internal_query(serde_json::json!({
+            "sql": "SELECT COUNT(*) FROM missing",
+            "partial": true,
+        }));
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_query_part() {
+        let engine = Arc::new(StorageEngine::new());
+        let running_queries = Arc::new(QueryRegistry::new());
+        let state = Arc::new(AppState {
+            engine: Arc::clone(&engine),
+            coordinator: None,
+            cluster_config: ClusterConfig::default(),
+            query_cache: Arc::new(QueryCache::new()),
+            alert_checker: Arc::new(AlertChecker::new(engine)),
+            query_limits: QueryLimits::default(),
+            running_queries: Arc::clone(&running_queries),
+            slow_query_ms: None,
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, build_router(state)).await });
+
+        // A client-chosen id that needs escaping in the path
+        let control = Arc::new(crate::query::QueryControl::new(QueryLimits::default()));
+        let part_of = Some("node-1/daily report".to_string());
+        let _part = running_queries.register_part(part_of, "SELECT 1", control.clone());
+
+        let client = crate::cluster::client::ClusterClient::new();
+        client.cancel_part(&addr, "node-1", "weekly report").await.unwrap();
+        assert!(!control.is_stopped());
+        client.cancel_part(&addr, "node-1", "daily report").await.unwrap();
+        assert!(control.is_stopped());
+    }
 }
@@ -2,6 +2,18 @@ use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
 use crate::data::Value;
+use crate::query::aggregates::{
+    Accumulator, ArgExtremeAccumulator, AvgAccumulator, CountAccumulator,
+    DistinctCountAccumulator, HistogramAccumulator, MaxAccumulator, MinAccumulator,
+    PercentileAccumulator, SumAccumulator, TopKAccumulator, VarianceAccumulator,
+};
+use crate::query::ddsketch::DDSketch;
+use crate::query::explain::ProfileSummary;
+use crate::query::histogram::HistogramSketch;
+use crate::query::hll::HyperLogLog;
+use crate::query::join::LookupRows;
+use crate::query::limits::{QueryControl, QueryLimits};
+use crate::query::topk::SpaceSaving;
 use crate::query::QueryResult;
 
 /// Client for communicating with peer nodes
@@ -13,11 +25,28 @@ pub struct ClusterClient {
 }
 
 /// Request to execute a query on a remote node
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RemoteQueryRequest {
     pub sql: String,
     /// If true, return partial aggregates instead of final results
     pub partial: bool,
+    /// Coordinator's reference time for NOW() and ago()
+    #[serde(default)]
+    pub now: Option<i64>,
+    /// Results of the query's IN subqueries, which the coordinator runs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub in_lists: Vec<Vec<Value>>,
+    /// Rows of the query's lookup table, which the coordinator fetches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lookup: Option<LookupRows>,
+    /// Limits on the node's part of the query, with the time left before
+    /// the coordinator's deadline
+    #[serde(default)]
+    pub limits: QueryLimits,
+    /// `<node>/<query id>` of the query on the coordinator, which cancels
+    /// the node's part through `DELETE /internal/queries/:node/:id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub part_of: Option<String>,
 }
 
 /// Response from a remote node
@@ -29,15 +58,74 @@ pub struct RemoteQueryResponse {
     pub shards_scanned: usize,
     /// For partial aggregates: the accumulator states
     pub partial_states: Option<Vec<PartialAggregateState>>,
+    /// For COMPARE TO: the accumulator states of the previous period
+    #[serde(default)]
+    pub previous_states: Option<Vec<PartialAggregateState>>,
+    /// For EXPLAIN: what the query did on the node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<ProfileSummary>,
 }
 
 /// Partial aggregate state for distributed merging
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PartialAggregateState {
+    /// Sent with each value's type: untagged, a timestamp key would come
+    /// back as an integer and never match the same key from another node
+    #[serde(with = "typed_values")]
     pub group_key: Vec<Value>,
     pub aggregates: Vec<PartialAggregate>,
 }
 
+/// `Value`s serialized with their variant names
+mod typed_values {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::data::value::Histogram;
+    use crate::data::{TopKEntry, Value};
+
+    #[derive(Serialize, Deserialize)]
+    enum TypedValue {
+        Null,
+        Bool(bool),
+        Int64(i64),
+        Float64(f64),
+        String(String),
+        Timestamp(i64),
+        Histogram(Box<Histogram>),
+        TopK(Vec<TopKEntry>),
+    }
+
+    pub fn serialize<S: Serializer>(values: &[Value], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(values.iter().map(|value| match value.clone() {
+            Value::Null => TypedValue::Null,
+            Value::Bool(b) => TypedValue::Bool(b),
+            Value::Int64(i) => TypedValue::Int64(i),
+            Value::Float64(f) => TypedValue::Float64(f),
+            Value::String(s) => TypedValue::String(s),
+            Value::Timestamp(t) => TypedValue::Timestamp(t),
+            Value::Histogram(h) => TypedValue::Histogram(h),
+            Value::TopK(entries) => TypedValue::TopK(entries),
+        }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Value>, D::Error> {
+        let values = Vec::<TypedValue>::deserialize(deserializer)?;
+        Ok(values
+            .into_iter()
+            .map(|value| match value {
+                TypedValue::Null => Value::Null,
+                TypedValue::Bool(b) => Value::Bool(b),
+                TypedValue::Int64(i) => Value::Int64(i),
+                TypedValue::Float64(f) => Value::Float64(f),
+                TypedValue::String(s) => Value::String(s),
+                TypedValue::Timestamp(t) => Value::Timestamp(t),
+                TypedValue::Histogram(h) => Value::Histogram(h),
+                TypedValue::TopK(entries) => Value::TopK(entries),
+            })
+            .collect())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PartialAggregate {
     Count(i64),
@@ -45,7 +133,210 @@ pub enum PartialAggregate {
     Avg { sum: f64, count: i64 },
     Min(Option<Value>),
     Max(Option<Value>),
-    // For percentile, we'd need to pass the sample values
+    /// HyperLogLog sketch; merging takes the register-wise max so values
+    /// seen on several nodes are only counted once
+    CountDistinct(HyperLogLog),
+    /// Quantile sketch; merging adds bucket counts
+    Percentile { percentile: f64, sketch: DDSketch },
+    /// Count, mean and sum of squared deviations for STDDEV and VARIANCE
+    Variance {
+        count: i64,
+        mean: f64,
+        m2: f64,
+        stddev: bool,
+    },
+    /// Best key and its value for FIRST, LAST, ARG_MAX and ARG_MIN
+    ArgExtreme {
+        keep_max: bool,
+        best: Option<(Value, Value)>,
+    },
+    /// Heavy hitters sketch for APPROX_TOP_K
+    TopK { k: usize, sketch: SpaceSaving },
+    /// Bucket counts for HISTOGRAM and HISTOGRAM_LOG; merging adds them
+    Histogram(HistogramSketch),
+}
+
+impl PartialAggregate {
+    /// Capture an accumulator's state for sending to another node.
+    /// Returns None for accumulators that have no mergeable state.
+    pub fn from_accumulator(acc: &dyn Accumulator) -> Option<Self> {
+        let any = acc.as_any();
+        if let Some(a) = any.downcast_ref::<CountAccumulator>() {
+            Some(PartialAggregate::Count(a.count()))
+        } else if let Some(a) = any.downcast_ref::<SumAccumulator>() {
+            Some(PartialAggregate::Sum {
+                sum: a.sum(),
+                has_value: a.has_value(),
+            })
+        } else if let Some(a) = any.downcast_ref::<AvgAccumulator>() {
+            Some(PartialAggregate::Avg {
+                sum: a.sum(),
+                count: a.count(),
+            })
+        } else if let Some(a) = any.downcast_ref::<MinAccumulator>() {
+            Some(PartialAggregate::Min(a.value().cloned()))
+        } else if let Some(a) = any.downcast_ref::<MaxAccumulator>() {
+            Some(PartialAggregate::Max(a.value().cloned()))
+        } else if let Some(a) = any.downcast_ref::<DistinctCountAccumulator>() {
+            Some(PartialAggregate::CountDistinct(a.sketch().clone()))
+        } else if let Some(a) = any.downcast_ref::<VarianceAccumulator>() {
+            let (count, mean, m2) = a.parts();
+            Some(PartialAggregate::Variance {
+                count,
+                mean,
+                m2,
+                stddev: a.is_stddev(),
+            })
+        } else if let Some(a) = any.downcast_ref::<ArgExtremeAccumulator>() {
+            Some(PartialAggregate::ArgExtreme {
+                keep_max: a.keeps_max(),
+                best: a.best().cloned(),
+            })
+        } else if let Some(a) = any.downcast_ref::<TopKAccumulator>() {
+            Some(PartialAggregate::TopK {
+                k: a.k(),
+                sketch: a.sketch().clone(),
+            })
+        } else if let Some(a) = any.downcast_ref::<HistogramAccumulator>() {
+            Some(PartialAggregate::Histogram(a.sketch().clone()))
+        } else {
+            any.downcast_ref::<PercentileAccumulator>()
+                .map(|a| PartialAggregate::Percentile {
+                    percentile: a.percentile(),
+                    sketch: a.sketch().clone(),
+                })
+        }
+    }
+
+    /// Merge another node's partial state for the same aggregate.
+    /// Mismatched variants are ignored.
+    pub fn merge(&mut self, other: &PartialAggregate) {
+        match (self, other) {
+            (PartialAggregate::Count(a), PartialAggregate::Count(b)) => *a += b,
+            (
+                PartialAggregate::Sum { sum, has_value },
+                PartialAggregate::Sum {
+                    sum: other_sum,
+                    has_value: other_has_value,
+                },
+            ) => {
+                *sum += other_sum;
+                *has_value |= other_has_value;
+            }
+            (
+                PartialAggregate::Avg { sum, count },
+                PartialAggregate::Avg {
+                    sum: other_sum,
+                    count: other_count,
+                },
+            ) => {
+                *sum += other_sum;
+                *count += other_count;
+            }
+            (PartialAggregate::Min(a), PartialAggregate::Min(Some(b))) => match a {
+                Some(current) if b >= current => {}
+                _ => *a = Some(b.clone()),
+            },
+            (PartialAggregate::Max(a), PartialAggregate::Max(Some(b))) => match a {
+                Some(current) if b <= current => {}
+                _ => *a = Some(b.clone()),
+            },
+            (PartialAggregate::CountDistinct(a), PartialAggregate::CountDistinct(b)) => {
+                a.merge(b)
+            }
+            (
+                PartialAggregate::Percentile { sketch, .. },
+                PartialAggregate::Percentile {
+                    sketch: other_sketch,
+                    ..
+                },
+            ) => sketch.merge(other_sketch),
+            (
+                PartialAggregate::Variance {
+                    count,
+                    mean,
+                    m2,
+                    stddev,
+                },
+                PartialAggregate::Variance {
+                    count: other_count,
+                    mean: other_mean,
+                    m2: other_m2,
+                    ..
+                },
+            ) => {
+                let mut acc = VarianceAccumulator::from_parts(*count, *mean, *m2, *stddev);
+                acc.merge_parts(*other_count, *other_mean, *other_m2);
+                (*count, *mean, *m2) = acc.parts();
+            }
+            (
+                PartialAggregate::ArgExtreme { keep_max, best },
+                PartialAggregate::ArgExtreme {
+                    best: Some((key, value)),
+                    ..
+                },
+            ) => {
+                let mut acc = ArgExtremeAccumulator::from_parts(*keep_max, best.take());
+                acc.offer(key, value);
+                *best = acc.best().cloned();
+            }
+            (
+                PartialAggregate::TopK { sketch, .. },
+                PartialAggregate::TopK {
+                    sketch: other_sketch,
+                    ..
+                },
+            ) => sketch.merge(other_sketch),
+            (PartialAggregate::Histogram(sketch), PartialAggregate::Histogram(other_sketch)) => {
+                sketch.merge(other_sketch)
+            }
+            _ => {}
+        }
+    }
+
+    /// Final value of the merged aggregate
+    pub fn result(&self) -> Value {
+        match self {
+            PartialAggregate::Count(c) => Value::Int64(*c),
+            PartialAggregate::Sum { sum, has_value } => {
+                if *has_value {
+                    Value::Float64(*sum)
+                } else {
+                    Value::Null
+                }
+            }
+            PartialAggregate::Avg { sum, count } => {
+                if *count > 0 {
+                    Value::Float64(sum / *count as f64)
+                } else {
+                    Value::Null
+                }
+            }
+            PartialAggregate::Min(v) | PartialAggregate::Max(v) => {
+                v.clone().unwrap_or(Value::Null)
+            }
+            PartialAggregate::CountDistinct(sketch) => Value::Int64(sketch.estimate() as i64),
+            PartialAggregate::Percentile { percentile, sketch } => sketch
+                .quantile(percentile / 100.0)
+                .map(Value::Float64)
+                .unwrap_or(Value::Null),
+            PartialAggregate::Variance {
+                count,
+                mean,
+                m2,
+                stddev,
+            } => VarianceAccumulator::from_parts(*count, *mean, *m2, *stddev).result(),
+            PartialAggregate::ArgExtreme { keep_max, best } => {
+                ArgExtremeAccumulator::from_parts(*keep_max, best.clone()).result()
+            }
+            PartialAggregate::TopK { k, sketch } => {
+                TopKAccumulator::from_parts(*k, sketch.clone()).result()
+            }
+            PartialAggregate::Histogram(sketch) => {
+                HistogramAccumulator::from_sketch(sketch.clone()).result()
+            }
+        }
+    }
 }
 
 impl ClusterClient {
@@ -69,14 +360,23 @@ impl ClusterClient {
         }
     }
 
-    /// Execute a query on a remote node
-    pub async fn query(&self, addr: &str, sql: &str) -> Result<QueryResult, ClusterError> {
+    /// Execute a query on a remote node under `limits`
+    pub async fn query(
+        &self,
+        addr: &str,
+        sql: &str,
+        limits: &QueryLimits,
+    ) -> Result<QueryResult, ClusterError> {
         let url = format!("http://{}/query", addr);
-        let request = serde_json::json!({ "sql": sql });
+        let request = serde_json::json!({
+            "sql": sql,
+            "timeout_ms": limits.timeout_ms,
+            "max_groups": limits.max_groups,
+            "max_output_rows": limits.max_output_rows,
+            "max_scanned_bytes": limits.max_scanned_bytes,
+        });
 
-        let response = self
-            .http_client
-            .post(&url)
+        let response = within_deadline(self.http_client.post(&url), limits)
             .json(&request)
             .send()
             .await
@@ -99,23 +399,107 @@ impl ClusterClient {
             shards_scanned: result.shards_scanned,
             execution_time_ms: result.execution_time_ms,
             availability: None, // Will be populated by coordinator
+            next_cursor: None,
         })
     }
 
-    /// Execute a query on multiple nodes in parallel
+    /// Execute a query on multiple nodes in parallel. Each gets `limits`,
+    /// whose timeout should be the time left before the caller's deadline.
     pub async fn query_all(
         &self,
         addrs: &[String],
         sql: &str,
+        limits: &QueryLimits,
     ) -> Vec<Result<QueryResult, ClusterError>> {
         let futures: Vec<_> = addrs
             .iter()
-            .map(|addr| self.query(addr, sql))
+            .map(|addr| self.query(addr, sql, limits))
+            .collect();
+
+        futures::future::join_all(futures).await
+    }
+
+    /// Execute a query on a remote node through the internal endpoint,
+    /// returning partial aggregate states rather than final rows. The node
+    /// gets `control`'s limits, with the time left as the request is sent.
+    pub async fn query_partial(
+        &self,
+        addr: &str,
+        request: &RemoteQueryRequest,
+        control: Option<&QueryControl>,
+    ) -> Result<RemoteQueryResponse, ClusterError> {
+        let url = format!("http://{}/internal/query", addr);
+        let request = RemoteQueryRequest {
+            partial: true,
+            limits: control.map(QueryControl::remaining_limits).unwrap_or_default(),
+            ..request.clone()
+        };
+
+        let response = within_deadline(self.http_client.post(&url), &request.limits)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| ClusterError::Network(e.to_string()))?;
+
+        if response.status() == reqwest::StatusCode::UNPROCESSABLE_ENTITY {
+            let error: serde_json::Value = response.json().await.unwrap_or_default();
+            let message = error["error"].as_str().unwrap_or("query stopped");
+            return Err(ClusterError::Stopped(message.to_string()));
+        }
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(ClusterError::RemoteError(error_text));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| ClusterError::Deserialization(e.to_string()))
+    }
+
+    /// Execute a partial query on multiple nodes in parallel
+    pub async fn query_partial_all(
+        &self,
+        addrs: &[String],
+        request: &RemoteQueryRequest,
+        control: Option<&QueryControl>,
+    ) -> Vec<Result<RemoteQueryResponse, ClusterError>> {
+        let futures: Vec<_> = addrs
+            .iter()
+            .map(|addr| self.query_partial(addr, request, control))
             .collect();
 
         futures::future::join_all(futures).await
     }
 
+    /// Cancel a node's parts of the query `query_id` coordinated by `node`
+    pub async fn cancel_part(
+        &self,
+        addr: &str,
+        node: &str,
+        query_id: &str,
+    ) -> Result<(), ClusterError> {
+        let mut url = reqwest::Url::parse(&format!("http://{}/internal/queries", addr))
+            .map_err(|e| ClusterError::Network(e.to_string()))?;
+        // Query ids are chosen by clients, so may need escaping
+        url.path_segments_mut()
+            .map_err(|_| ClusterError::Network(format!("Invalid peer address {}", addr)))?
+            .extend([node, query_id]);
+
+        let response = self
+            .http_client
+            .delete(url)
+            .send()
+            .await
+            .map_err(|e| ClusterError::Network(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(ClusterError::RemoteError(error_text));
+        }
+        Ok(())
+    }
+
     /// Check if a node is healthy
     pub async fn health_check(&self, addr: &str) -> Result<bool, ClusterError> {
         let url = format!("http://{}/health", addr);
@@ -131,6 +515,17 @@ impl ClusterClient {
     }
 }
 
+/// Give up on a request to a peer once the query's time is up
+fn within_deadline(
+    request: reqwest::RequestBuilder,
+    limits: &QueryLimits,
+) -> reqwest::RequestBuilder {
+    match limits.timeout_ms {
+        Some(ms) => request.timeout(Duration::from_millis(ms)),
+        None => request,
+    }
+}
+
 impl Default for ClusterClient {
     fn default() -> Self {
         Self::new()
@@ -154,9 +549,72 @@ pub enum ClusterError {
     #[error("Remote error: {0}")]
     RemoteError(String),
 
+    /// The peer's part of the query was cancelled, ran out of time or went
+    /// over a limit
+    #[error("Stopped on peer: {0}")]
+    Stopped(String),
+
     #[error("Deserialization error: {0}")]
     Deserialization(String),
 
     #[error("No healthy nodes available")]
     NoHealthyNodes,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_distinct_partial_merge() {
+        let mut node1 = HyperLogLog::new();
+        let mut node2 = HyperLogLog::new();
+        for user in 0..100 {
+            node1.insert(&Value::Int64(user));
+            node2.insert(&Value::Int64(user + 50));
+        }
+
+        let mut merged = PartialAggregate::CountDistinct(node1);
+        let other = PartialAggregate::CountDistinct(node2);
+
+        // Survives the trip between nodes
+        let json = serde_json::to_string(&other).unwrap();
+        let other: PartialAggregate = serde_json::from_str(&json).unwrap();
+
+        merged.merge(&other);
+        assert_eq!(merged.result(), Value::Int64(150));
+    }
+
+    #[test]
+    fn test_percentile_partial_merge() {
+        let mut node1 = DDSketch::new();
+        let mut node2 = DDSketch::new();
+        for latency in 1..=1000 {
+            node1.insert(latency as f64);
+            node2.insert((latency + 1000) as f64);
+        }
+
+        let mut merged = PartialAggregate::Percentile {
+            percentile: 50.0,
+            sketch: node1,
+        };
+        let json = serde_json::to_string(&PartialAggregate::Percentile {
+            percentile: 50.0,
+            sketch: node2,
+        })
+        .unwrap();
+        merged.merge(&serde_json::from_str(&json).unwrap());
+
+        let Value::Float64(p50) = merged.result() else {
+            panic!("Expected float result");
+        };
+        assert!((p50 - 1000.0).abs() / 1000.0 <= 0.01);
+    }
+
+    #[test]
+    fn test_avg_partial_merge() {
+        let mut merged = PartialAggregate::Avg { sum: 10.0, count: 1 };
+        merged.merge(&PartialAggregate::Avg { sum: 90.0, count: 9 });
+        assert_eq!(merged.result(), Value::Float64(10.0));
+    }
+}
@@ -1,12 +1,25 @@
-use std::collections::HashMap;
 use std::sync::Arc;
-
-use crate::data::Value;
-use crate::query::{QueryResult, AvailabilityMetrics, run_query};
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+
+use crate::query::executor::{execute_on_table, execution_path, ExecutionPath};
+use crate::query::explain::{explain_local, explain_result, Explain, Profile, ProfileSummary};
+use crate::query::join::{bind_lookup, lookup_query, lookup_rows};
+use crate::query::limits::{QueryControl, QueryLimits};
+use crate::query::registry::RunningQuery;
+use crate::query::subquery::{
+    bind_in_lists, in_list, in_subqueries, relation, run_nested, union_relation, Relations,
+};
+use crate::query::{
+    parse_query_at, plan_query, query_time, run_query_page, AvailabilityMetrics, ParsedQuery,
+    QueryError, QueryPlan, QueryResult,
+};
 use crate::storage::StorageEngine;
 
-use super::client::{ClusterClient, ClusterError};
+use super::client::{ClusterClient, ClusterError, RemoteQueryRequest, RemoteQueryResponse};
 use super::config::ClusterConfig;
+use super::partial::{execute_partial, finalize, merge_partial};
 
 /// Distributed query coordinator
 pub struct Coordinator {
@@ -24,29 +37,255 @@ impl Coordinator {
         }
     }
 
-    /// Execute a query across the cluster
-    pub async fn execute_query(&self, sql: &str) -> Result<QueryResult, CoordinatorError> {
+    /// Execute a query across the cluster. Every node resolves NOW() and
+    /// ago() against the same `now`. Peers get the time left before the
+    /// deadline of `query`, and register their parts of it under its id.
+    /// Work on this node runs off the async runtime.
+    pub async fn execute_query(
+        &self,
+        sql: &str,
+        now: i64,
+        query: Option<&RunningQuery>,
+    ) -> Result<QueryResult, CoordinatorError> {
         let start = std::time::Instant::now();
+        let control = query.map(RunningQuery::control);
 
         if !self.config.is_distributed() {
             // Single node mode - just execute locally
-            return run_query(&self.local_engine, sql)
-                .map_err(|e| CoordinatorError::Query(e.to_string()));
+            let (engine, sql) = (Arc::clone(&self.local_engine), sql.to_string());
+            let control = control.cloned();
+            return run_blocking(move || {
+                run_query_page(&engine, &sql, now, None, control.as_ref())
+            })
+            .await;
         }
 
-        // Distributed mode - fan out to all nodes (including self)
-        let peer_addrs = self.config.peer_addrs();
-        let total_nodes = peer_addrs.len() + 1; // peers + self
+        let parsed = parse_query_at(sql, now).map_err(|e| CoordinatorError::Query(e.to_string()))?;
+        let relations = Relations::new();
+        let part_of = query.map(|query| format!("{}/{}", self.config.node_id, query.id()));
+        let mut result = self
+            .execute_nested(sql, parsed, now, &relations, control, part_of.as_deref())
+            .await?;
+        result.execution_time_ms = start.elapsed().as_millis() as u64;
+        Ok(result)
+    }
 
-        // Execute on peers in parallel
-        let peer_futures = self.client.query_all(&peer_addrs, sql);
+    /// Run the queries nested in a query across the cluster, then the query
+    /// itself: on this node if it reads a WITH query, FROM subquery or
+    /// UNION ALL, and on every node otherwise, with the IN subquery results
+    /// and lookup table sent along.
+    fn execute_nested<'a>(
+        &'a self,
+        sql: &'a str,
+        mut parsed: ParsedQuery,
+        now: i64,
+        relations: &'a Relations,
+        control: Option<&'a Arc<QueryControl>>,
+        part_of: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<QueryResult, CoordinatorError>> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let query_error = |e: QueryError| CoordinatorError::Query(e.to_string());
+            if let Some(control) = control {
+                control.check().map_err(|e| query_error(e.into()))?;
+            }
+            let mut relations = relations.clone();
+            let (mut rows_scanned, mut shards_scanned) = (0, 0);
+
+            for (name, sql) in std::mem::take(&mut parsed.with) {
+                let query = parse_query_at(&sql, now).map_err(|e| query_error(e.into()))?;
+                let result = self
+                    .execute_nested(&sql, query, now, &relations, control, part_of)
+                    .await?;
+                rows_scanned += result.rows_scanned;
+                shards_scanned += result.shards_scanned;
+                relations.insert(name.clone(), relation(&name, &result));
+            }
 
-        // Execute locally
-        let local_result = run_query(&self.local_engine, sql)
-            .map_err(|e| CoordinatorError::Query(e.to_string()))?;
+            let mut in_lists = Vec::new();
+            for sql in in_subqueries(&mut parsed) {
+                let query = parse_query_at(&sql, now).map_err(|e| query_error(e.into()))?;
+                let result = self
+                    .execute_nested(&sql, query, now, &relations, control, part_of)
+                    .await?;
+                rows_scanned += result.rows_scanned;
+                shards_scanned += result.shards_scanned;
+                in_lists.push(in_list(result).map_err(|e| query_error(e.into()))?);
+            }
+            bind_in_lists(&mut parsed, &in_lists).map_err(|e| query_error(e.into()))?;
+
+            let lookup = match &parsed.join {
+                Some(join) => {
+                    let sql = lookup_query(join);
+                    // A table loaded from CSV is whole on the node it was
+                    // loaded on
+                    let local = self
+                        .local_engine
+                        .get_table(&join.table)
+                        .filter(|table| table.is_static() && !relations.contains_key(&join.table));
+                    let result = match local {
+                        Some(_) => {
+                            let engine = Arc::clone(&self.local_engine);
+                            let (relations, control) = (relations.clone(), control.cloned());
+                            run_blocking(move || {
+                                run_nested(&engine, &sql, now, &relations, None, control.as_ref())
+                            })
+                            .await?
+                        }
+                        _ => {
+                            let query =
+                                parse_query_at(&sql, now).map_err(|e| query_error(e.into()))?;
+                            self
+                                .execute_nested(&sql, query, now, &relations, control, part_of)
+                                .await?
+                        }
+                    };
+                    rows_scanned += result.rows_scanned;
+                    shards_scanned += result.shards_scanned;
+                    Some(lookup_rows(join, result).map_err(|e| query_error(e.into()))?)
+                }
+                None => None,
+            };
+            bind_lookup(&mut parsed, lookup.clone()).map_err(|e| query_error(e.into()))?;
+
+            let mut branches = Vec::new();
+            for sql in std::mem::take(&mut parsed.union_all) {
+                let query = parse_query_at(&sql, now).map_err(|e| query_error(e.into()))?;
+                let result = self
+                    .execute_nested(&sql, query, now, &relations, control, part_of)
+                    .await?;
+                rows_scanned += result.rows_scanned;
+                shards_scanned += result.shards_scanned;
+                branches.push(result);
+            }
 
-        // Wait for peer results
-        let peer_results = peer_futures.await;
+            let table = match parsed.from_subquery.take() {
+                None if !branches.is_empty() => Some(
+                    union_relation(&parsed.table, branches).map_err(|e| query_error(e.into()))?,
+                ),
+                Some(sql) => {
+                    let query = parse_query_at(&sql, now).map_err(|e| query_error(e.into()))?;
+                    let result = self
+                        .execute_nested(&sql, query, now, &relations, control, part_of)
+                        .await?;
+                    rows_scanned += result.rows_scanned;
+                    shards_scanned += result.shards_scanned;
+                    Some(relation(&parsed.table, &result))
+                }
+                None => relations.get(&parsed.table).cloned(),
+            };
+
+            let explain = parsed.explain;
+            let mut plan = plan_query(parsed).map_err(|e| query_error(e.into()))?;
+            plan.control = control.cloned();
+            let engine = Arc::clone(&self.local_engine);
+            let mut result = match (table, explain) {
+                // Nested results are complete, so the rest runs here
+                (Some(table), None) => {
+                    run_blocking(move || Ok(execute_on_table(&table, &plan)?)).await?
+                }
+                (Some(table), Some(explain)) => {
+                    run_blocking(move || {
+                        Ok(explain_local(&engine, Some(&table), plan, explain, start)?)
+                    })
+                    .await?
+                }
+                (None, explain) => {
+                    let request = RemoteQueryRequest {
+                        sql: sql.to_string(),
+                        partial: true,
+                        now: Some(now),
+                        in_lists,
+                        lookup,
+                        limits: QueryLimits::default(),
+                        part_of: part_of.map(str::to_string),
+                    };
+                    match explain {
+                        None => self.fan_out(&request, &plan).await?,
+                        Some(explain) => {
+                            let profile = Profile::default();
+                            profile.add_time("plan", start);
+                            plan.profile = Some(Arc::new(profile));
+                            self.explain_fan_out(&request, &plan, explain).await?
+                        }
+                    }
+                }
+            };
+            result.rows_scanned += rows_scanned;
+            result.shards_scanned += shards_scanned;
+            Ok(result)
+        })
+    }
+
+    /// Run EXPLAIN on every node: each prunes its shards, and under ANALYZE
+    /// runs the query, reporting what it did. Shard and row counts are
+    /// summed across nodes; times are listed per node, after the stages on
+    /// this node recorded in the plan's profile.
+    async fn explain_fan_out(
+        &self,
+        request: &RemoteQueryRequest,
+        plan: &QueryPlan,
+        explain: Explain,
+    ) -> Result<QueryResult, CoordinatorError> {
+        let profile = plan.profile.clone().unwrap_or_default();
+
+        let nodes_start = Instant::now();
+        let (local_result, peer_results) =
+            self.run_on_nodes(request, plan).await?;
+        check(plan)?;
+        profile.add_time("nodes", nodes_start);
+
+        let mut responses = vec![(self.config.node_id.clone(), local_result)];
+        for (peer, result) in self.config.peers.iter().zip(peer_results) {
+            match result {
+                Ok(response) => responses.push((peer.id.clone(), response)),
+                Err(ClusterError::Stopped(e)) => return Err(CoordinatorError::Query(e)),
+                Err(e) => tracing::warn!("Peer query failed: {}", e),
+            }
+        }
+        let nodes: Vec<(String, ProfileSummary)> = responses
+            .iter_mut()
+            .map(|(node, response)| (node.clone(), response.profile.take().unwrap_or_default()))
+            .collect();
+
+        // Nodes aggregate into partial states the general way
+        let path = match execution_path(plan, &plan.projections) {
+            ExecutionPath::SimdAggregation => ExecutionPath::Aggregation,
+            path => path,
+        };
+        let result = match explain {
+            Explain::Plan => None,
+            Explain::Analyze => {
+                let merge_start = Instant::now();
+                let responses = responses.into_iter().map(|(_, response)| response).collect();
+                let merged = merge_partial(responses).ok_or(CoordinatorError::NoResults)?;
+                let result = finalize(plan, merged);
+                profile.add_time("merge", merge_start);
+                Some(result)
+            }
+        };
+
+        let mut summary = profile.summary();
+        for (_, node) in &nodes {
+            summary.add_counts(node);
+        }
+        Ok(explain_result(plan, path, &summary, result.as_ref(), &nodes))
+    }
+
+    /// Run a query on every node and merge their partial results
+    async fn fan_out(
+        &self,
+        request: &RemoteQueryRequest,
+        plan: &QueryPlan,
+    ) -> Result<QueryResult, CoordinatorError> {
+        // Distributed mode - fan out to all nodes (including self)
+        let total_nodes = self.config.peers.len() + 1; // peers + self
+
+        // Peers that ran out of time are missing from the results, so a
+        // query out of time fails rather than come back partial
+        let (local_result, peer_results) =
+            self.run_on_nodes(request, plan).await?;
+        check(plan)?;
 
         // Collect all successful results and track availability
         let mut all_results = vec![local_result];
@@ -58,6 +297,9 @@ impl Coordinator {
                     all_results.push(r);
                     nodes_responded += 1;
                 }
+                // Leaving out a peer that stopped would pass off part of the
+                // data as all of it
+                Err(ClusterError::Stopped(e)) => return Err(CoordinatorError::Query(e)),
                 Err(e) => {
                     tracing::warn!("Peer query failed: {}", e);
                     // Continue with other results - partial failure is OK
@@ -65,10 +307,6 @@ impl Coordinator {
             }
         }
 
-        if all_results.is_empty() {
-            return Err(CoordinatorError::NoResults);
-        }
-
         // Calculate availability metrics
         let availability = AvailabilityMetrics {
             availability_percent: (nodes_responded as f64 / total_nodes as f64) * 100.0,
@@ -78,274 +316,79 @@ impl Coordinator {
             complete: nodes_responded == total_nodes,
         };
 
-        // Merge results
-        let mut merged = self.merge_results(all_results, sql)?;
-
-        let execution_time_ms = start.elapsed().as_millis() as u64;
-
-        merged.execution_time_ms = execution_time_ms;
-        merged.availability = Some(availability);
-
-        Ok(merged)
-    }
-
-    /// Merge results from multiple nodes
-    fn merge_results(
-        &self,
-        results: Vec<QueryResult>,
-        _sql: &str,
-    ) -> Result<QueryResult, CoordinatorError> {
-        if results.is_empty() {
-            return Err(CoordinatorError::NoResults);
-        }
-
-        if results.len() == 1 {
-            return Ok(results.into_iter().next().unwrap());
+        // Merge partial states, then apply HAVING/ORDER BY/LIMIT once
+        let merged = merge_partial(all_results).ok_or(CoordinatorError::NoResults)?;
+        let mut result = finalize(plan, merged);
+        result.availability = Some(availability);
+        if let Some(control) = &plan.control {
+            control
+                .check_output_rows(result.rows.len())
+                .map_err(|e| CoordinatorError::Query(e.to_string()))?;
         }
 
-        let first = &results[0];
-        let columns = first.columns.clone();
-
-        // Check if this is an aggregation query (has aggregation columns)
-        let is_aggregation = columns.iter().any(|c| {
-            c.starts_with("count_")
-                || c.starts_with("sum_")
-                || c.starts_with("avg_")
-                || c.starts_with("min_")
-                || c.starts_with("max_")
-        });
-
-        let (rows, rows_scanned, shards_scanned) = if is_aggregation {
-            self.merge_aggregation_results(&results, &columns)
-        } else {
-            self.merge_scan_results(&results)
-        };
-
-        Ok(QueryResult {
-            columns,
-            rows,
-            rows_scanned,
-            shards_scanned,
-            execution_time_ms: 0, // Will be set by caller
-            availability: None, // Will be set by caller
-        })
+        Ok(result)
     }
 
-    /// Merge aggregation results by re-aggregating
-    fn merge_aggregation_results(
+    /// Run a query's partial aggregation on this node and every peer at
+    /// once. Fails as soon as this node's part does; peers that fail are
+    /// left to the caller.
+    async fn run_on_nodes(
         &self,
-        results: &[QueryResult],
-        columns: &[String],
-    ) -> (Vec<Vec<Value>>, usize, usize) {
-        // Find group key columns (non-aggregate columns)
-        let group_key_indices: Vec<usize> = columns
-            .iter()
-            .enumerate()
-            .filter(|(_, c)| {
-                !c.starts_with("count_")
-                    && !c.starts_with("sum_")
-                    && !c.starts_with("avg_")
-                    && !c.starts_with("min_")
-                    && !c.starts_with("max_")
-            })
-            .map(|(i, _)| i)
-            .collect();
-
-        // Find aggregate column indices and their types
-        let agg_columns: Vec<(usize, AggType)> = columns
-            .iter()
-            .enumerate()
-            .filter_map(|(i, c)| {
-                if c.starts_with("count_") {
-                    Some((i, AggType::Count))
-                } else if c.starts_with("sum_") {
-                    Some((i, AggType::Sum))
-                } else if c.starts_with("avg_") {
-                    Some((i, AggType::Avg))
-                } else if c.starts_with("min_") {
-                    Some((i, AggType::Min))
-                } else if c.starts_with("max_") {
-                    Some((i, AggType::Max))
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        // Group by key and merge aggregates
-        // Key: group values, Value: (count, sum, min, max) for each agg column
-        let mut groups: HashMap<Vec<Value>, Vec<AggState>> = HashMap::new();
-
-        let mut total_rows_scanned = 0;
-        let mut total_shards_scanned = 0;
-
-        for result in results {
-            total_rows_scanned += result.rows_scanned;
-            total_shards_scanned += result.shards_scanned;
-
-            for row in &result.rows {
-                let group_key: Vec<Value> = group_key_indices
-                    .iter()
-                    .map(|&i| row.get(i).cloned().unwrap_or(Value::Null))
-                    .collect();
-
-                let states = groups.entry(group_key).or_insert_with(|| {
-                    agg_columns
-                        .iter()
-                        .map(|(_, agg_type)| AggState::new(agg_type.clone()))
-                        .collect()
-                });
-
-                // Update each aggregate state
-                for (state_idx, (col_idx, _)) in agg_columns.iter().enumerate() {
-                    if let Some(value) = row.get(*col_idx) {
-                        states[state_idx].merge_value(value);
-                    }
-                }
-            }
-        }
-
-        // Build final result rows
-        let mut rows: Vec<Vec<Value>> = groups
-            .into_iter()
-            .map(|(group_key, states)| {
-                let mut row = Vec::with_capacity(columns.len());
-
-                let mut group_idx = 0;
-                let mut state_idx = 0;
-
-                for (col_idx, _col_name) in columns.iter().enumerate() {
-                    if group_key_indices.contains(&col_idx) {
-                        row.push(group_key[group_idx].clone());
-                        group_idx += 1;
-                    } else {
-                        row.push(states[state_idx].result());
-                        state_idx += 1;
-                    }
-                }
-
-                row
-            })
-            .collect();
-
-        // Sort by group key for consistent ordering
-        rows.sort_by(|a, b| {
-            for i in &group_key_indices {
-                if let (Some(av), Some(bv)) = (a.get(*i), b.get(*i)) {
-                    match av.cmp(bv) {
-                        std::cmp::Ordering::Equal => continue,
-                        other => return other,
-                    }
-                }
-            }
-            std::cmp::Ordering::Equal
+        request: &RemoteQueryRequest,
+        plan: &QueryPlan,
+    ) -> Result<PartialResults, CoordinatorError> {
+        let peer_addrs = self.config.peer_addrs();
+        let peers = self.client.query_partial_all(&peer_addrs, request, plan.control.as_deref());
+
+        let engine = Arc::clone(&self.local_engine);
+        let RemoteQueryRequest {
+            sql,
+            now,
+            in_lists,
+            lookup,
+            ..
+        } = request.clone();
+        let now = now.unwrap_or_else(query_time);
+        let control = plan.control.clone();
+        let local = run_blocking(move || {
+            execute_partial(&engine, &sql, now, &in_lists, lookup, control.as_ref())
         });
-
-        (rows, total_rows_scanned, total_shards_scanned)
+        tokio::try_join!(local, async { Ok(peers.await) })
     }
 
-    /// Merge non-aggregation (scan) results by concatenating
-    fn merge_scan_results(&self, results: &[QueryResult]) -> (Vec<Vec<Value>>, usize, usize) {
-        let mut all_rows = Vec::new();
-        let mut total_rows_scanned = 0;
-        let mut total_shards_scanned = 0;
-
-        for result in results {
-            all_rows.extend(result.rows.clone());
-            total_rows_scanned += result.rows_scanned;
-            total_shards_scanned += result.shards_scanned;
+    /// Stop the peers' parts of a query cancelled on this node
+    pub async fn cancel_on_peers(&self, query_id: &str) {
+        let node = &self.config.node_id;
+        let cancels = self.config.peer_addrs().into_iter().map(|addr| async move {
+            let result = self.client.cancel_part(&addr, node, query_id).await;
+            (result, addr)
+        });
+        for (result, addr) in futures::future::join_all(cancels).await {
+            if let Err(e) = result {
+                tracing::warn!("Failed to cancel query {} on {}: {}", query_id, addr, e);
+            }
         }
-
-        (all_rows, total_rows_scanned, total_shards_scanned)
     }
 }
 
-#[derive(Clone)]
-enum AggType {
-    Count,
-    Sum,
-    Avg,
-    Min,
-    Max,
-}
-
-struct AggState {
-    agg_type: AggType,
-    count: i64,
-    sum: f64,
-    min: Option<Value>,
-    max: Option<Value>,
+/// This node's partial results, and each peer's in `peer_addrs` order
+type PartialResults = (RemoteQueryResponse, Vec<Result<RemoteQueryResponse, ClusterError>>);
+
+/// Run blocking query work off the async runtime
+async fn run_blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, QueryError> + Send + 'static,
+) -> Result<T, CoordinatorError> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| CoordinatorError::Query(e.to_string()))?
+        .map_err(|e| CoordinatorError::Query(e.to_string()))
 }
 
-impl AggState {
-    fn new(agg_type: AggType) -> Self {
-        Self {
-            agg_type,
-            count: 0,
-            sum: 0.0,
-            min: None,
-            max: None,
-        }
-    }
-
-    fn merge_value(&mut self, value: &Value) {
-        match &self.agg_type {
-            AggType::Count => {
-                if let Some(c) = value.as_i64() {
-                    self.count += c;
-                }
-            }
-            AggType::Sum => {
-                if let Some(s) = value.as_f64() {
-                    self.sum += s;
-                    self.count += 1;
-                }
-            }
-            AggType::Avg => {
-                // For AVG, we receive the partial average
-                // We need to track count separately to properly merge
-                // For now, we'll use a weighted approach
-                if let Some(avg) = value.as_f64() {
-                    // Approximate: treat each partial avg as one sample
-                    self.sum += avg;
-                    self.count += 1;
-                }
-            }
-            AggType::Min => {
-                if !value.is_null() {
-                    match &self.min {
-                        None => self.min = Some(value.clone()),
-                        Some(current) if value < current => self.min = Some(value.clone()),
-                        _ => {}
-                    }
-                }
-            }
-            AggType::Max => {
-                if !value.is_null() {
-                    match &self.max {
-                        None => self.max = Some(value.clone()),
-                        Some(current) if value > current => self.max = Some(value.clone()),
-                        _ => {}
-                    }
-                }
-            }
-        }
-    }
-
-    fn result(&self) -> Value {
-        match &self.agg_type {
-            AggType::Count => Value::Int64(self.count),
-            AggType::Sum => Value::Float64(self.sum),
-            AggType::Avg => {
-                if self.count > 0 {
-                    Value::Float64(self.sum / self.count as f64)
-                } else {
-                    Value::Null
-                }
-            }
-            AggType::Min => self.min.clone().unwrap_or(Value::Null),
-            AggType::Max => self.max.clone().unwrap_or(Value::Null),
-        }
+/// Fail if the query has been cancelled, run out of time or gone over a limit
+fn check(plan: &QueryPlan) -> Result<(), CoordinatorError> {
+    match &plan.control {
+        Some(control) => control.check().map_err(|e| CoordinatorError::Query(e.to_string())),
+        None => Ok(()),
     }
 }
 
@@ -360,3 +403,43 @@ pub enum CoordinatorError {
     #[error("No results from any node")]
     NoResults,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::config::PeerNode;
+    use crate::data::Value;
+    use crate::query::QueryRegistry;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_unreachable_peer() {
+        let engine = Arc::new(StorageEngine::new());
+        for i in 0..10 {
+            let row = HashMap::from([("timestamp".to_string(), Value::Timestamp(i))]);
+            engine.insert("events", row).unwrap();
+        }
+        let config = ClusterConfig {
+            peers: vec![PeerNode {
+                id: "node-2".into(),
+                addr: "127.0.0.1:1".into(),
+            }],
+            ..ClusterConfig::single_node()
+        };
+        let coordinator = Coordinator::new(config, engine);
+        let sql = "SELECT COUNT(*) FROM events";
+
+        // The peer is left out, and the results say so
+        let result = coordinator.execute_query(sql, 0, None).await.unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int64(10)]]);
+        let availability = result.availability.unwrap();
+        assert_eq!((availability.nodes_responded, availability.complete), (1, false));
+
+        let control = Arc::new(QueryControl::new(QueryLimits {
+            timeout_ms: Some(0),
+            ..Default::default()
+        }));
+        let running = Arc::new(QueryRegistry::new()).register(None, sql, control).unwrap();
+        assert!(coordinator.execute_query(sql, 0, Some(&running)).await.is_err());
+    }
+}
@@ -3,6 +3,7 @@ pub mod client;
 pub mod config;
 pub mod coordinator;
 pub mod load_balancer;
+pub mod partial;
 pub mod topology;
 
 pub use aggregator::{HierarchicalAggregator, AggregatorError};
@@ -0,0 +1,547 @@
+//! Partial query execution for distributed merging
+//!
+//! Nodes answer aggregation queries with accumulator states instead of
+//! finished rows, so AVG is merged from sums and counts, distinct counts
+//! from sketches, and so on. HAVING, ORDER BY and LIMIT run once, after
+//! every node's states have been merged.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::client::{PartialAggregate, PartialAggregateState, RemoteQueryResponse};
+use crate::data::Value;
+use crate::query::aggregates::create_accumulator;
+use crate::query::executor::{
+    apply_post_aggregation, assemble_row, execute_partial_aggregation, prune_query,
+    ExecuteError, PartialAggregation,
+};
+use crate::query::explain::{Explain, Profile};
+use crate::query::limits::QueryControl;
+use crate::query::planner::ProjectionPlan;
+use crate::query::join::{bind_lookup, LookupRows};
+use crate::query::subquery::bind_in_lists;
+use crate::query::{
+    execute_query, parse_query_at, plan_query, QueryError, QueryPlan, QueryResult,
+};
+use crate::storage::StorageEngine;
+
+/// Execute a query on the local node for a remote coordinator. Aggregations
+/// return partial states; scans return their rows as usual. `in_lists` are
+/// the results of the query's IN subqueries and `lookup` the rows of its
+/// lookup table, already fetched by the coordinator. `control` stops the
+/// query early. Under EXPLAIN, the response carries what the query did
+/// here, and EXPLAIN without ANALYZE only prunes shards.
+pub fn execute_partial(
+    engine: &StorageEngine,
+    sql: &str,
+    now: i64,
+    in_lists: &[Vec<Value>],
+    lookup: Option<LookupRows>,
+    control: Option<&Arc<QueryControl>>,
+) -> Result<RemoteQueryResponse, QueryError> {
+    let start = Instant::now();
+    let mut parsed = parse_query_at(sql, now)?;
+    bind_in_lists(&mut parsed, in_lists)?;
+    bind_lookup(&mut parsed, lookup)?;
+    let explain = parsed.explain;
+    let mut plan = plan_query(parsed)?;
+    plan.control = control.cloned();
+
+    let Some(explain) = explain else {
+        return execute_plan(engine, plan);
+    };
+    let profile = Arc::new(Profile::default());
+    profile.add_time("plan", start);
+    plan.profile = Some(profile.clone());
+    let mut response = match explain {
+        Explain::Plan => {
+            prune_query(engine, &plan)?;
+            RemoteQueryResponse {
+                columns: Vec::new(),
+                rows: Vec::new(),
+                rows_scanned: 0,
+                shards_scanned: 0,
+                partial_states: None,
+                previous_states: None,
+                profile: None,
+            }
+        }
+        Explain::Analyze => execute_plan(engine, plan)?,
+    };
+    response.profile = Some(profile.summary());
+    Ok(response)
+}
+
+fn execute_plan(
+    engine: &StorageEngine,
+    mut plan: QueryPlan,
+) -> Result<RemoteQueryResponse, QueryError> {
+
+    if !plan.has_aggregations() {
+        // OFFSET applies once to the merged rows, so each node returns
+        // every row up to the end of the page
+        if let Some(offset) = plan.offset.take() {
+            plan.limit = plan.limit.map(|limit| limit + offset);
+        }
+        let result = execute_query(engine, &plan)?;
+        return Ok(RemoteQueryResponse {
+            columns: result.columns,
+            rows: result.rows,
+            rows_scanned: result.rows_scanned,
+            shards_scanned: result.shards_scanned,
+            partial_states: None,
+            previous_states: None,
+            profile: None,
+        });
+    }
+
+    let partial = execute_partial_aggregation(engine, &plan)?;
+    let mut rows_scanned = partial.rows_scanned;
+    let mut shards_scanned = partial.shards_scanned;
+    let columns = partial.columns.clone();
+    let partial_states = into_states(partial)?;
+
+    // COMPARE TO's previous period is merged separately
+    let previous_states = match plan.previous_period() {
+        Some(previous) => {
+            let partial = execute_partial_aggregation(engine, &previous)?;
+            rows_scanned += partial.rows_scanned;
+            shards_scanned += partial.shards_scanned;
+            Some(into_states(partial)?)
+        }
+        None => None,
+    };
+
+    Ok(RemoteQueryResponse {
+        columns,
+        rows: Vec::new(),
+        rows_scanned,
+        shards_scanned,
+        partial_states: Some(partial_states),
+        previous_states,
+        profile: None,
+    })
+}
+
+/// Serializable states of each group's accumulators
+fn into_states(partial: PartialAggregation) -> Result<Vec<PartialAggregateState>, ExecuteError> {
+    partial
+        .groups
+        .into_iter()
+        .map(|(group_key, accumulators)| {
+            let aggregates = accumulators
+                .iter()
+                .map(|acc| PartialAggregate::from_accumulator(acc.as_ref()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| {
+                    ExecuteError::General("aggregate cannot be merged across nodes".into())
+                })?;
+            Ok(PartialAggregateState {
+                group_key,
+                aggregates,
+            })
+        })
+        .collect()
+}
+
+/// Combine responses from several nodes into one, merging aggregate states
+/// of matching groups and concatenating scan rows. The result is still
+/// partial, so intermediate aggregators can pass it further up.
+pub fn merge_partial(responses: Vec<RemoteQueryResponse>) -> Option<RemoteQueryResponse> {
+    let mut responses = responses.into_iter();
+    let mut merged = responses.next()?;
+
+    let mut group_index = index_groups(&merged.partial_states);
+    let mut previous_index = index_groups(&merged.previous_states);
+
+    for response in responses {
+        merged.rows_scanned += response.rows_scanned;
+        merged.shards_scanned += response.shards_scanned;
+        merged.rows.extend(response.rows);
+
+        merge_states(&mut merged.partial_states, &mut group_index, response.partial_states);
+        merge_states(&mut merged.previous_states, &mut previous_index, response.previous_states);
+    }
+
+    Some(merged)
+}
+
+fn index_groups(states: &Option<Vec<PartialAggregateState>>) -> HashMap<Vec<Value>, usize> {
+    states
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(i, state)| (state.group_key.clone(), i))
+        .collect()
+}
+
+/// Add one node's states to the merged ones, combining groups with the same key
+fn merge_states(
+    merged: &mut Option<Vec<PartialAggregateState>>,
+    group_index: &mut HashMap<Vec<Value>, usize>,
+    states: Option<Vec<PartialAggregateState>>,
+) {
+    let Some(states) = states else {
+        return;
+    };
+    let merged_states = merged.get_or_insert_with(Vec::new);
+
+    for state in states {
+        match group_index.get(&state.group_key) {
+            Some(&i) => {
+                for (existing, other) in merged_states[i].aggregates.iter_mut().zip(&state.aggregates)
+                {
+                    existing.merge(other);
+                }
+            }
+            None => {
+                group_index.insert(state.group_key.clone(), merged_states.len());
+                merged_states.push(state);
+            }
+        }
+    }
+}
+
+/// Turn merged partial results into the final query result
+pub fn finalize(plan: &QueryPlan, merged: RemoteQueryResponse) -> QueryResult {
+    let mut columns = merged.columns;
+    let mut rows = merged.rows;
+
+    if let Some(states) = merged.partial_states {
+        rows = finalize_states(plan, states);
+    }
+    let previous = merged
+        .previous_states
+        .map(|states| finalize_states(plan, states));
+
+    apply_post_aggregation(plan, &mut columns, &mut rows, previous);
+
+    QueryResult {
+        columns,
+        rows,
+        rows_scanned: merged.rows_scanned,
+        shards_scanned: merged.shards_scanned,
+        execution_time_ms: 0, // Will be set by caller
+        availability: None,   // Will be set by caller
+        next_cursor: None,
+    }
+}
+
+fn finalize_states(plan: &QueryPlan, mut states: Vec<PartialAggregateState>) -> Vec<Vec<Value>> {
+    // A global aggregate over no rows still produces one row
+    if states.is_empty() && plan.group_by.is_none() {
+        states.push(empty_state(plan));
+    }
+
+    states
+        .into_iter()
+        .map(|state| {
+            let aggregates = state.aggregates.iter().map(|a| a.result()).collect();
+            assemble_row(&plan.projections, &state.group_key, aggregates)
+        })
+        .collect()
+}
+
+fn empty_state(plan: &QueryPlan) -> PartialAggregateState {
+    let aggregates = plan
+        .projections
+        .iter()
+        .filter_map(|p| match p {
+            ProjectionPlan::Aggregate {
+                function, argument, ..
+            } => PartialAggregate::from_accumulator(create_accumulator(*function, argument).as_ref()),
+            _ => None,
+        })
+        .collect();
+
+    PartialAggregateState {
+        group_key: Vec::new(),
+        aggregates,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::registry::QUERY_LOG_TABLE;
+
+    fn engine_with_latencies(latencies: &[(&str, i64)]) -> StorageEngine {
+        let engine = StorageEngine::new();
+        for (i, (endpoint, latency)) in latencies.iter().enumerate() {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(i as i64));
+            row.insert("endpoint".to_string(), Value::String(endpoint.to_string()));
+            row.insert("latency".to_string(), Value::Int64(*latency));
+            engine.insert("logs", row).unwrap();
+        }
+        engine
+    }
+
+    fn run_distributed(sql: &str, nodes: &[StorageEngine]) -> QueryResult {
+        let plan = plan_query(parse_query_at(sql, 0).unwrap()).unwrap();
+        let responses = nodes
+            .iter()
+            .map(|engine| execute_partial(engine, sql, 0, &[], None, None).unwrap())
+            .collect();
+        finalize(&plan, merge_partial(responses).unwrap())
+    }
+
+    #[test]
+    fn test_timestamp_keys_survive_the_trip_between_nodes() {
+        let sql = "SELECT TIME_BUCKET('1 hour', timestamp) AS hour, COUNT(*) FROM logs \
+                   GROUP BY TIME_BUCKET('1 hour', timestamp)";
+        let node = engine_with_latencies(&[("/api", 10); 4]);
+        let local = execute_partial(&node, sql, 0, &[], None, None).unwrap();
+        let remote = execute_partial(&node, sql, 0, &[], None, None).unwrap();
+        let json = serde_json::to_string(&remote).unwrap();
+        let remote: RemoteQueryResponse = serde_json::from_str(&json).unwrap();
+
+        let plan = plan_query(parse_query_at(sql, 0).unwrap()).unwrap();
+        let result = finalize(&plan, merge_partial(vec![local, remote]).unwrap());
+        assert_eq!(result.rows, vec![vec![Value::Timestamp(0), Value::Int64(8)]]);
+    }
+
+    #[test]
+    fn test_query_log_on_nodes_without_slow_queries() {
+        // Only node1 has logged a slow query
+        let node1 = StorageEngine::new();
+        let row = HashMap::from([
+            ("timestamp".to_string(), Value::Timestamp(0)),
+            ("query_id".to_string(), Value::String("slow".into())),
+        ]);
+        node1.insert(QUERY_LOG_TABLE, row).unwrap();
+
+        let nodes = [node1, StorageEngine::new()];
+        let result = run_distributed("SELECT query_id FROM _snorkel_queries", &nodes);
+        assert_eq!(result.rows, vec![vec![Value::String("slow".into())]]);
+        let result = run_distributed(
+            "SELECT query_id FROM _snorkel_queries",
+            &[StorageEngine::new(), StorageEngine::new()],
+        );
+        assert!(result.rows.is_empty());
+    }
+
+    #[test]
+    fn test_avg_merged_from_sums_and_counts() {
+        // One node has a single fast request, the other nine slow ones
+        let node1 = engine_with_latencies(&[("/api", 10)]);
+        let node2 = engine_with_latencies(&[("/api", 100); 9]);
+
+        let result = run_distributed(
+            "SELECT endpoint, AVG(latency), SUM(latency), COUNT(*) FROM logs GROUP BY endpoint",
+            &[node1, node2],
+        );
+
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Value::String("/api".into()),
+                Value::Float64(91.0),
+                Value::Float64(910.0),
+                Value::Int64(10),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_expression_evaluated_after_merge() {
+        let node1 = engine_with_latencies(&[("/api", 10)]);
+        let node2 = engine_with_latencies(&[("/api", 100); 9]);
+
+        let result = run_distributed(
+            "SELECT endpoint, SUM(latency) / COUNT(*) AS mean FROM logs GROUP BY endpoint",
+            &[node1, node2],
+        );
+
+        assert_eq!(result.columns, vec!["endpoint".to_string(), "mean".to_string()]);
+        assert_eq!(result.rows[0][1], Value::Float64(91.0));
+    }
+
+    #[test]
+    fn test_stddev_and_last_merged_across_nodes() {
+        let node1 = engine_with_latencies(&[("/api", 10), ("/api", 400)]);
+        let node2 = engine_with_latencies(&[("/api", 100), ("/api", 300), ("/api", 50)]);
+
+        let result = run_distributed(
+            "SELECT VARIANCE(latency), LAST(latency), ARG_MIN(endpoint, latency) \
+             FROM logs GROUP BY endpoint",
+            &[node1, node2],
+        );
+
+        // Sample variance of 10, 400, 100, 300 and 50
+        let Value::Float64(variance) = result.rows[0][0] else {
+            panic!("Expected float result");
+        };
+        assert!((variance - 28_670.0).abs() < 1e-6);
+        // node2 saw the latest timestamp
+        assert_eq!(result.rows[0][1], Value::Int64(50));
+        assert_eq!(result.rows[0][2], Value::String("/api".into()));
+    }
+
+    #[test]
+    fn test_top_k_merged_across_nodes() {
+        // /b leads on neither node alone
+        let node1 = engine_with_latencies(&[("/a", 1), ("/a", 1), ("/b", 1), ("/c", 1)]);
+        let node2 = engine_with_latencies(&[("/b", 1), ("/b", 1), ("/c", 1), ("/c", 1)]);
+
+        let result = run_distributed("SELECT APPROX_TOP_K(endpoint, 2) FROM logs", &[node1, node2]);
+
+        assert_eq!(result.columns, vec!["approx_top_k_endpoint".to_string()]);
+        let Value::TopK(top) = &result.rows[0][0] else {
+            panic!("Expected top-k result");
+        };
+        let values: Vec<_> = top.iter().map(|e| (&e.value, e.count)).collect();
+        assert_eq!(
+            values,
+            vec![(&Value::String("/b".into()), 3), (&Value::String("/c".into()), 3)]
+        );
+    }
+
+    #[test]
+    fn test_histogram_merged_across_nodes() {
+        let node1 = engine_with_latencies(&[("/a", 5), ("/a", 120)]);
+        let node2 = engine_with_latencies(&[("/a", 30), ("/a", 0)]);
+
+        let result = run_distributed(
+            "SELECT HISTOGRAM(latency, 50), HISTOGRAM_LOG(latency) FROM logs",
+            &[node1, node2],
+        );
+
+        let Value::Histogram(linear) = &result.rows[0][0] else {
+            panic!("Expected histogram result");
+        };
+        assert_eq!(linear.boundaries, vec![0.0, 50.0, 100.0, 150.0]);
+        assert_eq!(linear.counts, vec![3, 0, 1]);
+        // Zero, then 5-6 up to 100-200
+        let Value::Histogram(log) = &result.rows[0][1] else {
+            panic!("Expected histogram result");
+        };
+        assert_eq!((log.boundaries[0], log.boundaries[1]), (0.0, 5.0));
+        assert_eq!(*log.boundaries.last().unwrap(), 200.0);
+        assert_eq!(log.counts.iter().sum::<u64>(), 4);
+    }
+
+    #[test]
+    fn test_having_applied_after_merge() {
+        // Neither node alone sees more than 2 requests for /api
+        let node1 = engine_with_latencies(&[("/api", 1), ("/api", 2), ("/health", 1)]);
+        let node2 = engine_with_latencies(&[("/api", 3), ("/health", 1)]);
+
+        let result = run_distributed(
+            "SELECT endpoint FROM logs GROUP BY endpoint HAVING COUNT(*) > 2",
+            &[node1, node2],
+        );
+
+        assert_eq!(result.columns, vec!["endpoint".to_string()]);
+        assert_eq!(result.rows, vec![vec![Value::String("/api".into())]]);
+    }
+
+    #[test]
+    fn test_global_aggregate_over_no_matches() {
+        let node1 = engine_with_latencies(&[("/api", 1)]);
+        let node2 = engine_with_latencies(&[("/api", 2)]);
+
+        let result = run_distributed(
+            "SELECT COUNT(*), AVG(latency) FROM logs WHERE endpoint = '/missing'",
+            &[node1, node2],
+        );
+
+        assert_eq!(result.rows, vec![vec![Value::Int64(0), Value::Null]]);
+    }
+
+    #[test]
+    fn test_scan_rows_concatenated_then_limited() {
+        let node1 = engine_with_latencies(&[("/a", 5), ("/b", 1)]);
+        let node2 = engine_with_latencies(&[("/c", 3)]);
+
+        let result = run_distributed(
+            "SELECT endpoint, latency FROM logs ORDER BY latency DESC LIMIT 2",
+            &[node1, node2],
+        );
+
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0][1], Value::Int64(5));
+        assert_eq!(result.rows[1][1], Value::Int64(3));
+    }
+
+    #[test]
+    fn test_compare_to_merged_per_period() {
+        // Timestamps are row numbers: each node has 4 rows in the previous
+        // period (0-4) and the rest in the current one (4-8)
+        let node1 = engine_with_latencies(&[("/api", 1); 6]);
+        let node2 = engine_with_latencies(&[("/api", 1); 8]);
+
+        let result = run_distributed(
+            "SELECT endpoint, COUNT(*) FROM logs WHERE timestamp >= 4 AND timestamp < 8 \
+             GROUP BY endpoint COMPARE TO '4 ms'",
+            &[node1, node2],
+        );
+
+        assert_eq!(result.columns[2..], ["count_*_prev", "count_*_delta"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Value::String("/api".into()),
+                Value::Int64(6),
+                Value::Int64(8),
+                Value::Int64(-2),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_lookup_join_across_nodes() {
+        let node1 = engine_with_latencies(&[("/api", 10), ("/login", 20)]);
+        let node2 = engine_with_latencies(&[("/api", 30), ("/health", 40)]);
+        // The coordinator sends every node the same lookup rows
+        let lookup = LookupRows {
+            columns: vec!["path".to_string(), "team".to_string()],
+            rows: vec![
+                vec![Value::String("/api".into()), Value::String("core".into())],
+                vec![Value::String("/login".into()), Value::String("identity".into())],
+            ],
+        };
+
+        let sql = "SELECT t.team, SUM(latency) FROM logs l JOIN teams t ON l.endpoint = t.path \
+                   GROUP BY t.team ORDER BY t.team";
+        let mut parsed = parse_query_at(sql, 0).unwrap();
+        bind_lookup(&mut parsed, Some(lookup.clone())).unwrap();
+        let plan = plan_query(parsed).unwrap();
+        let responses = [node1, node2]
+            .iter()
+            .map(|engine| {
+                execute_partial(engine, sql, 0, &[], Some(lookup.clone()), None).unwrap()
+            })
+            .collect();
+        let result = finalize(&plan, merge_partial(responses).unwrap());
+
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::String("core".into()), Value::Int64(40)],
+                vec![Value::String("identity".into()), Value::Int64(20)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explain_reports_node_profile() {
+        let node = engine_with_latencies(&[("/api", 10), ("/login", 20), ("/api", 30)]);
+
+        let sql = "EXPLAIN SELECT COUNT(*) FROM logs WHERE latency > 15";
+        let response = execute_partial(&node, sql, 0, &[], None, None).unwrap();
+        let profile = response.profile.unwrap();
+        assert_eq!((profile.shards_total, profile.shards_after_zone), (1, 1));
+        // Nothing ran
+        assert!(response.partial_states.is_none());
+        assert_eq!(profile.rows_read, 0);
+
+        let sql = "EXPLAIN ANALYZE SELECT COUNT(*) FROM logs WHERE latency > 15";
+        let response = execute_partial(&node, sql, 0, &[], None, None).unwrap();
+        let profile = response.profile.unwrap();
+        assert_eq!((profile.rows_read, profile.rows_matched), (3, 2));
+        assert!(profile.stages.iter().any(|(stage, _)| stage == "execute"));
+        assert!(response.partial_states.is_some());
+    }
+}
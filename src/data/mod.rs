@@ -6,4 +6,4 @@ pub mod value;
 pub use column::{Column, ColumnBuilder, ColumnIter};
 pub use shard::{Shard, ShardError};
 pub use table::{Table, TableConfig, TableError, TableStats};
-pub use value::{flatten_json, DataType, Value};
+pub use value::{flatten_json, DataType, TopKEntry, Value};
@@ -1,5 +1,6 @@
 use super::column::Column;
 use super::value::{DataType, Value};
+use crate::query::parser::FilterOperator;
 use crate::storage::BloomFilter;
 use parking_lot::RwLock;
 use std::collections::HashMap;
@@ -33,6 +34,9 @@ pub struct Shard {
     sealed: RwLock<bool>,
     /// Bloom filters for string/int columns (for fast filtering)
     bloom_filters: RwLock<HashMap<String, BloomFilter>>,
+    /// Zone maps: smallest and largest value of each column whose every row
+    /// holds a number, or None once a row holds anything else
+    zones: RwLock<HashMap<String, Option<(f64, f64)>>>,
 }
 
 impl Shard {
@@ -46,6 +50,26 @@ impl Shard {
             schema: RwLock::new(HashMap::new()),
             sealed: RwLock::new(false),
             bloom_filters: RwLock::new(HashMap::new()),
+            zones: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Create a sealed shard from columns of equal length
+    pub fn from_columns(start_time: i64, end_time: i64, columns: HashMap<String, Column>) -> Self {
+        let row_count = columns.values().map(Column::len).max().unwrap_or(0);
+        let schema = columns
+            .iter()
+            .map(|(name, column)| (name.clone(), column.data_type()))
+            .collect();
+        Self {
+            start_time,
+            end_time,
+            columns: RwLock::new(columns),
+            row_count: AtomicUsize::new(row_count),
+            schema: RwLock::new(schema),
+            sealed: RwLock::new(true),
+            bloom_filters: RwLock::new(HashMap::new()),
+            zones: RwLock::new(HashMap::new()),
         }
     }
 
@@ -78,10 +102,13 @@ impl Shard {
         let mut schema = self.schema.write();
         let current_row_count = self.row_count.load(Ordering::SeqCst);
 
+        let mut zones = self.zones.write();
+
         // First, ensure all existing columns have a value (possibly null)
         for (name, col) in columns.iter_mut() {
             if !row.contains_key(name) {
                 col.push(&Value::Null);
+                zones.insert(name.clone(), None);
             }
         }
 
@@ -109,6 +136,21 @@ impl Shard {
                 columns.insert(name.clone(), col);
             }
 
+            // Update the zone map, which a column backfilled with nulls
+            // never has
+            let number = match value {
+                Value::Int64(i) => Some(*i as f64),
+                Value::Float64(f) if !f.is_nan() => Some(*f),
+                _ => None,
+            };
+            let zone = zones
+                .entry(name.clone())
+                .or_insert_with(|| number.filter(|_| current_row_count == 0).map(|n| (n, n)));
+            *zone = match (*zone, number) {
+                (Some((min, max)), Some(n)) => Some((min.min(n), max.max(n))),
+                _ => None,
+            };
+
             // Update bloom filter for string and int values
             match value {
                 Value::String(s) => {
@@ -320,6 +362,45 @@ impl Shard {
         }
     }
 
+    /// Check if this shard might hold a row whose value in `column` compares
+    /// to `value` as `operator` asks, using its zone map. Only numeric
+    /// comparisons with a column holding numbers in every row can rule the
+    /// shard out.
+    pub fn might_match_range(
+        &self,
+        column: &str,
+        operator: FilterOperator,
+        value: &Value,
+        values: &[Value],
+    ) -> bool {
+        let zones = self.zones.read();
+        let Some(Some((min, max))) = zones.get(column).copied() else {
+            return true;
+        };
+        let number = |value: &Value| match value {
+            Value::Int64(i) => Some(*i as f64),
+            Value::Float64(f) if !f.is_nan() => Some(*f),
+            _ => None,
+        };
+        // Bounds are compared inclusively, as converting to f64 may round
+        let within = |value: &Value| number(value).is_none_or(|n| n >= min && n <= max);
+
+        match operator {
+            FilterOperator::Eq => within(value),
+            FilterOperator::In => values.iter().any(within),
+            FilterOperator::Lt | FilterOperator::LtEq => number(value).is_none_or(|n| min <= n),
+            FilterOperator::Gt | FilterOperator::GtEq => number(value).is_none_or(|n| max >= n),
+            FilterOperator::Between => match values {
+                [low, high] => match (number(low), number(high)) {
+                    (Some(low), Some(high)) => max >= low && min <= high,
+                    _ => true,
+                },
+                _ => true,
+            },
+            _ => true,
+        }
+    }
+
     /// Get bloom filter statistics for this shard
     pub fn bloom_filter_stats(&self) -> HashMap<String, (usize, f64)> {
         let bloom_filters = self.bloom_filters.read();
@@ -447,4 +528,32 @@ mod tests {
         let clicks = shard.filter_rows("event", |v| v == &Value::String("click".into()));
         assert_eq!(clicks, vec![0, 2]);
     }
+
+    #[test]
+    fn test_zone_map() {
+        let shard = Shard::new(0, 3600000);
+        shard.insert_row(&make_row(100, "click", 10)).unwrap();
+        shard.insert_row(&make_row(200, "view", 30)).unwrap();
+
+        let matches = |operator, value: i64| {
+            shard.might_match_range("value", operator, &Value::Int64(value), &[])
+        };
+        assert!(matches(FilterOperator::Eq, 20));
+        assert!(!matches(FilterOperator::Eq, 40));
+        assert!(matches(FilterOperator::GtEq, 30));
+        assert!(!matches(FilterOperator::GtEq, 31));
+        assert!(!matches(FilterOperator::Lt, 9));
+        assert!(shard.might_match_range(
+            "value",
+            FilterOperator::Between,
+            &Value::Null,
+            &[Value::Float64(25.5), Value::Int64(50)]
+        ));
+
+        // A row without the column may match anything, so the zone is gone
+        let mut row = make_row(300, "click", 0);
+        row.remove("value");
+        shard.insert_row(&row).unwrap();
+        assert!(matches(FilterOperator::Eq, 40));
+    }
 }
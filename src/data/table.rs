@@ -1,3 +1,4 @@
+use super::column::Column;
 use super::shard::{calculate_shard_bounds, Shard, ShardError};
 use super::value::{DataType, Value};
 use parking_lot::RwLock;
@@ -70,6 +71,9 @@ pub struct Table {
     shards: RwLock<Vec<Arc<Shard>>>,
     /// Merged schema across all shards
     schema: RwLock<HashMap<String, DataType>>,
+    /// Column order of a table built from query results. Empty for
+    /// ingested tables, whose columns have no order.
+    column_order: Vec<String>,
 }
 
 impl Table {
@@ -78,9 +82,40 @@ impl Table {
             config,
             shards: RwLock::new(Vec::new()),
             schema: RwLock::new(HashMap::new()),
+            column_order: Vec::new(),
         }
     }
 
+    /// An in-memory table holding a query result, in one shard spanning all
+    /// time. Each column takes the merged type of its values.
+    pub fn from_rows(name: &str, columns: &[String], rows: &[Vec<Value>]) -> Self {
+        let types: Vec<DataType> = (0..columns.len())
+            .map(|i| {
+                rows.iter()
+                    .map(|row| DataType::from_value(&row[i]))
+                    .fold(DataType::Null, |a, b| a.merge(&b))
+            })
+            .collect();
+
+        let mut data = HashMap::new();
+        for (i, (name, data_type)) in columns.iter().zip(&types).enumerate() {
+            let mut column = Column::with_capacity(*data_type, rows.len());
+            for row in rows {
+                column.push(&coerce(&row[i], *data_type));
+            }
+            data.insert(name.clone(), column);
+        }
+
+        let mut table = Self::new(TableConfig::new(name));
+        table.column_order = columns.to_vec();
+        *table.schema.write() = columns.iter().cloned().zip(types).collect();
+        table
+            .shards
+            .write()
+            .push(Arc::new(Shard::from_columns(i64::MIN, i64::MAX, data)));
+        table
+    }
+
     /// Insert a row into the appropriate shard
     pub fn insert_row(&self, row: HashMap<String, Value>) -> Result<(), TableError> {
         let timestamp = row
@@ -148,6 +183,21 @@ impl Table {
         self.schema.read().clone()
     }
 
+    /// Column names, in result order for a table built from query results
+    pub fn column_names(&self) -> Vec<String> {
+        if self.column_order.is_empty() {
+            self.schema.read().keys().cloned().collect()
+        } else {
+            self.column_order.clone()
+        }
+    }
+
+    /// Whether the table was built whole by `from_rows`, as query results
+    /// and tables loaded from CSV are, rather than ingested
+    pub fn is_static(&self) -> bool {
+        !self.column_order.is_empty()
+    }
+
     /// Get all shards (for querying)
     pub fn get_shards(&self) -> Vec<Arc<Shard>> {
         self.shards.read().clone()
@@ -202,6 +252,17 @@ impl Table {
     }
 }
 
+/// Convert a value to the type of the column it is stored in
+fn coerce(value: &Value, data_type: DataType) -> Value {
+    match (value, data_type) {
+        (Value::Null, _) => Value::Null,
+        (Value::Timestamp(t), DataType::Int64) => Value::Int64(*t),
+        (Value::String(_), DataType::String) => value.clone(),
+        (value, DataType::String) => Value::String(value.to_string()),
+        (value, _) => value.clone(),
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum TableError {
     #[error("Row missing required 'timestamp' field")]
@@ -309,4 +370,22 @@ mod tests {
         assert_eq!(expired, 2);
         assert_eq!(table.shard_count(), 1);
     }
+
+    #[test]
+    fn test_table_from_rows() {
+        let columns = vec!["host".to_string(), "p99".to_string()];
+        let rows = vec![
+            vec![Value::Null, Value::Int64(3)],
+            vec![Value::String("a".to_string()), Value::Float64(2.5)],
+        ];
+        let table = Table::from_rows("result", &columns, &rows);
+
+        assert_eq!(table.row_count(), 2);
+        assert_eq!(table.column_names(), columns);
+        assert_eq!(table.get_schema().get("p99"), Some(&DataType::Float64));
+        let shard = &table.get_shards()[0];
+        assert_eq!(shard.get_value(0, "host"), Some(Value::Null));
+        assert_eq!(shard.get_value(1, "host"), Some(Value::String("a".to_string())));
+        assert_eq!(shard.get_value(0, "p99"), Some(Value::Float64(3.0)));
+    }
 }
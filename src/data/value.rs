@@ -12,6 +12,28 @@ pub enum Value {
     Float64(f64),
     String(String),
     Timestamp(i64),
+    /// Bucketed distribution, from HISTOGRAM and HISTOGRAM_LOG
+    Histogram(Box<Histogram>),
+    /// Most frequent values, highest count first, from APPROX_TOP_K
+    TopK(Vec<TopKEntry>),
+}
+
+/// Bucket counts: `counts[i]` values fell in
+/// `boundaries[i]..boundaries[i + 1]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub boundaries: Vec<f64>,
+    pub counts: Vec<u64>,
+}
+
+/// A value and its estimated occurrences
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopKEntry {
+    pub value: Value,
+    /// Estimated occurrences; at least the true count
+    pub count: u64,
+    /// Most the count may overestimate by
+    pub error: u64,
 }
 
 impl Value {
@@ -23,6 +45,8 @@ impl Value {
             Value::Float64(_) => "float64",
             Value::String(_) => "string",
             Value::Timestamp(_) => "timestamp",
+            Value::Histogram(_) => "histogram",
+            Value::TopK(_) => "top_k",
         }
     }
 
@@ -160,6 +184,12 @@ impl PartialEq for Value {
             (Value::Float64(a), Value::Float64(b)) => a.to_bits() == b.to_bits(),
             (Value::String(a), Value::String(b)) => a == b,
             (Value::Timestamp(a), Value::Timestamp(b)) => a == b,
+            (Value::Histogram(a), Value::Histogram(b)) => {
+                let bits =
+                    |h: &Histogram| h.boundaries.iter().map(|b| b.to_bits()).collect::<Vec<_>>();
+                a.counts == b.counts && bits(a) == bits(b)
+            }
+            (Value::TopK(a), Value::TopK(b)) => a == b,
             // Cross-type numeric comparisons
             (Value::Int64(a), Value::Float64(b)) => (*a as f64).to_bits() == b.to_bits(),
             (Value::Float64(a), Value::Int64(b)) => a.to_bits() == (*b as f64).to_bits(),
@@ -182,6 +212,17 @@ impl Hash for Value {
             Value::Float64(f) => f.to_bits().hash(state),
             Value::String(s) => s.hash(state),
             Value::Timestamp(t) => t.hash(state),
+            Value::Histogram(h) => {
+                h.boundaries.iter().for_each(|b| b.to_bits().hash(state));
+                h.counts.hash(state);
+            }
+            Value::TopK(entries) => {
+                for entry in entries {
+                    entry.value.hash(state);
+                    entry.count.hash(state);
+                    entry.error.hash(state);
+                }
+            }
         }
     }
 }
@@ -227,6 +268,8 @@ impl Value {
             Value::Float64(_) => 3,
             Value::String(_) => 4,
             Value::Timestamp(_) => 5,
+            Value::Histogram(_) => 6,
+            Value::TopK(_) => 7,
         }
     }
 }
@@ -246,6 +289,12 @@ impl std::fmt::Display for Value {
             Value::Float64(v) => write!(f, "{}", v),
             Value::String(s) => write!(f, "{}", s),
             Value::Timestamp(t) => write!(f, "{}", t),
+            Value::Histogram(h) => {
+                write!(f, "{}", serde_json::to_string(h).map_err(|_| std::fmt::Error)?)
+            }
+            Value::TopK(entries) => {
+                write!(f, "{}", serde_json::to_string(entries).map_err(|_| std::fmt::Error)?)
+            }
         }
     }
 }
@@ -270,6 +319,8 @@ impl DataType {
             Value::Float64(_) => DataType::Float64,
             Value::String(_) => DataType::String,
             Value::Timestamp(_) => DataType::Timestamp,
+            // Only produced by queries, never stored
+            Value::Histogram(_) | Value::TopK(_) => DataType::String,
         }
     }
 
@@ -0,0 +1,172 @@
+//! CSV loading for dimension tables
+//!
+//! A small mapping table, such as services to owning teams, is loaded whole
+//! from CSV with a header row. Fields are typed by their text: empty fields
+//! are NULL, then integers, floats and `true`/`false`, and anything else is
+//! a string. Fields may be quoted with `"`, doubling quotes inside.
+
+use crate::data::Value;
+
+/// Column names and rows of a CSV document
+pub fn parse_csv(text: &str) -> Result<(Vec<String>, Vec<Vec<Value>>), CsvError> {
+    let mut records = records(text)?.into_iter();
+    let (_, header) = records.next().ok_or(CsvError::MissingHeader)?;
+    let columns: Vec<String> = header.into_iter().map(|name| name.trim().to_string()).collect();
+    if columns.iter().any(String::is_empty) {
+        return Err(CsvError::InvalidHeader("empty column name".into()));
+    }
+    if let Some(name) = columns.iter().enumerate().find_map(|(i, name)| {
+        columns[..i].contains(name).then_some(name)
+    }) {
+        return Err(CsvError::InvalidHeader(format!("column {} appears twice", name)));
+    }
+
+    let mut rows = Vec::new();
+    for (line, fields) in records {
+        if fields.len() != columns.len() {
+            return Err(CsvError::RaggedRow {
+                line,
+                expected: columns.len(),
+                found: fields.len(),
+            });
+        }
+        rows.push(fields.iter().map(|field| parse_field(field)).collect());
+    }
+    Ok((columns, rows))
+}
+
+fn parse_field(field: &str) -> Value {
+    let trimmed = field.trim();
+    if trimmed.is_empty() {
+        return Value::Null;
+    }
+    if let Ok(i) = trimmed.parse::<i64>() {
+        return Value::Int64(i);
+    }
+    if let Ok(f) = trimmed.parse::<f64>() {
+        if f.is_finite() {
+            return Value::Float64(f);
+        }
+    }
+    match trimmed {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => Value::String(field.to_string()),
+    }
+}
+
+/// Records as (line number, fields), skipping blank lines
+fn records(text: &str) -> Result<Vec<(usize, Vec<String>)>, CsvError> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let (mut line, mut start_line) = (1, 1);
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => {
+                line += 1;
+                field.push(c);
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                if fields.len() > 1 || !fields[0].is_empty() {
+                    records.push((start_line, std::mem::take(&mut fields)));
+                }
+                // A blank line leaves one empty field behind
+                fields.clear();
+                line += 1;
+                start_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(CsvError::UnterminatedQuote { line: start_line });
+    }
+    fields.push(field);
+    if fields.len() > 1 || !fields[0].is_empty() {
+        records.push((start_line, fields));
+    }
+    Ok(records)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CsvError {
+    #[error("CSV has no header row")]
+    MissingHeader,
+
+    #[error("Invalid CSV header: {0}")]
+    InvalidHeader(String),
+
+    #[error("CSV line {line} has {found} fields, expected {expected}")]
+    RaggedRow {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+
+    #[error("Unterminated quote in CSV record at line {line}")]
+    UnterminatedQuote { line: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let text = "service,team,tier\r\n\
+                    api,\"Platform, Core\",1\n\
+                    \n\
+                    auth,\"Say \"\"hi\"\"\",\n\
+                    billing,payments,2.5\n";
+        let (columns, rows) = parse_csv(text).unwrap();
+        assert_eq!(columns, vec!["service", "team", "tier"]);
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    Value::String("api".into()),
+                    Value::String("Platform, Core".into()),
+                    Value::Int64(1)
+                ],
+                vec![
+                    Value::String("auth".into()),
+                    Value::String("Say \"hi\"".into()),
+                    Value::Null
+                ],
+                vec![
+                    Value::String("billing".into()),
+                    Value::String("payments".into()),
+                    Value::Float64(2.5)
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_csv_errors() {
+        assert!(matches!(parse_csv(""), Err(CsvError::MissingHeader)));
+        assert!(matches!(parse_csv("a,a\n1,2"), Err(CsvError::InvalidHeader(_))));
+        assert!(matches!(
+            parse_csv("a,b\n1,2\n3\n"),
+            Err(CsvError::RaggedRow { line: 3, expected: 2, found: 1 })
+        ));
+        assert!(matches!(
+            parse_csv("a\n\"open\n"),
+            Err(CsvError::UnterminatedQuote { line: 2 })
+        ));
+    }
+}
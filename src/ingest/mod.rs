@@ -2,8 +2,11 @@
 //!
 //! Supports multiple ingest methods:
 //! - HTTP API (default)
+//! - CSV upload, for dimension tables
 //! - Kafka consumer (optional, enable with `kafka` feature)
 
+pub mod csv;
+
 #[cfg(feature = "kafka")]
 pub mod kafka;
 
@@ -23,10 +23,19 @@
 //! - SNORKEL_DATA_DIR: Directory for snapshots (enables persistence)
 //! - SNORKEL_SNAPSHOT_INTERVAL: Snapshot interval in seconds (default: 300)
 //!
+//! Query limits (0 turns a limit off; requests can only tighten them):
+//! - SNORKEL_QUERY_TIMEOUT_MS: Time a query may run for (default: 60000)
+//! - SNORKEL_MAX_GROUPS: Groups an aggregation may build (default: no limit)
+//! - SNORKEL_MAX_OUTPUT_ROWS: Rows a query may return (default: no limit)
+//! - SNORKEL_MAX_SCANNED_MB: Column data a query may read (default: no limit)
+//! - SNORKEL_SLOW_QUERY_MS: Log queries running this long to `_snorkel_queries`
+//!   (default: 1000, 0 turns the log off)
+//!
 //! In cluster mode, put a load balancer in front to distribute queries across all nodes.
 
 use snorkel::api::{run_server, ServerConfig};
 use snorkel::cluster::{ClusterConfig, PeerNode};
+use snorkel::query::QueryLimits;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
@@ -89,6 +98,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .and_then(|s| s.parse().ok())
         .unwrap_or(300); // 5 minutes default
 
+    // Parse query limits, where 0 turns a limit off
+    let limit = |name: &str, default: Option<usize>| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .or(default)
+            .filter(|&n| n > 0)
+    };
+    let query_limits = QueryLimits {
+        timeout_ms: limit("SNORKEL_QUERY_TIMEOUT_MS", Some(60_000)).map(|ms| ms as u64),
+        max_groups: limit("SNORKEL_MAX_GROUPS", None),
+        max_output_rows: limit("SNORKEL_MAX_OUTPUT_ROWS", None),
+        max_scanned_bytes: limit("SNORKEL_MAX_SCANNED_MB", None).map(|mb| mb * 1024 * 1024),
+    };
+    let slow_query_ms = limit("SNORKEL_SLOW_QUERY_MS", Some(1000)).map(|ms| ms as u64);
+
     let config = ServerConfig {
         host,
         port,
@@ -98,6 +123,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         cluster_config,
         data_dir,
         snapshot_interval_secs,
+        query_limits,
+        slow_query_ms,
     };
 
     tracing::info!("Snorkel configuration:");
@@ -129,6 +156,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tracing::info!("  Cluster mode: DISABLED (single node)");
     }
 
+    let limits = &config.query_limits;
+    let show = |limit: Option<usize>| limit.map_or("none".to_string(), |n| n.to_string());
+    tracing::info!(
+        "  Query limits: timeout {} ms, {} groups, {} output rows, {} scanned bytes",
+        show(limits.timeout_ms.map(|ms| ms as usize)),
+        show(limits.max_groups),
+        show(limits.max_output_rows),
+        show(limits.max_scanned_bytes)
+    );
+
+    match config.slow_query_ms {
+        Some(ms) => tracing::info!("  Slow query log: queries over {} ms", ms),
+        None => tracing::info!("  Slow query log: DISABLED"),
+    }
+
     // Persistence info
     if let Some(ref dir) = config.data_dir {
         tracing::info!("  Persistence: ENABLED");
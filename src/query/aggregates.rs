@@ -1,6 +1,9 @@
+use super::ddsketch::DDSketch;
+use super::histogram::{Buckets, HistogramSketch};
+use super::hll::HyperLogLog;
+use super::topk::SpaceSaving;
 use crate::data::Value;
 use std::any::Any;
-use fxhash::FxHashMap as HashMap;
 
 /// Accumulator trait for aggregation functions
 pub trait Accumulator: Send + Sync + AsAny {
@@ -15,6 +18,16 @@ pub trait Accumulator: Send + Sync + AsAny {
 
     /// Merge another accumulator into this one (for parallel aggregation)
     fn merge(&mut self, other: &dyn Accumulator);
+
+    /// Add a value already hashed with `hll::hash_value`. Only distinct
+    /// counting uses this, so string columns can hash each dictionary id once.
+    fn accumulate_hash(&mut self, _hash: u64) {}
+
+    /// Add a value along with the key it is ordered by. Only FIRST, LAST,
+    /// ARG_MAX and ARG_MIN have a key; others ignore it.
+    fn accumulate_keyed(&mut self, value: &Value, _key: &Value) {
+        self.accumulate(value);
+    }
 }
 
 /// Helper trait to enable downcasting
@@ -50,6 +63,10 @@ impl CountAccumulator {
     pub fn count_column() -> Self {
         Self::new(false)
     }
+
+    pub fn count(&self) -> i64 {
+        self.count
+    }
 }
 
 impl Accumulator for CountAccumulator {
@@ -88,6 +105,15 @@ impl SumAccumulator {
             has_value: false,
         }
     }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Whether any non-null value was summed
+    pub fn has_value(&self) -> bool {
+        self.has_value
+    }
 }
 
 impl Default for SumAccumulator {
@@ -137,6 +163,14 @@ impl AvgAccumulator {
     pub fn new() -> Self {
         Self { sum: 0.0, count: 0 }
     }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn count(&self) -> i64 {
+        self.count
+    }
 }
 
 impl Default for AvgAccumulator {
@@ -183,6 +217,10 @@ impl MinAccumulator {
     pub fn new() -> Self {
         Self { min: None }
     }
+
+    pub fn value(&self) -> Option<&Value> {
+        self.min.as_ref()
+    }
 }
 
 impl Default for MinAccumulator {
@@ -233,6 +271,10 @@ impl MaxAccumulator {
     pub fn new() -> Self {
         Self { max: None }
     }
+
+    pub fn value(&self) -> Option<&Value> {
+        self.max.as_ref()
+    }
 }
 
 impl Default for MaxAccumulator {
@@ -273,49 +315,232 @@ impl Accumulator for MaxAccumulator {
     }
 }
 
-/// PERCENTILE(column, p) - Approximate percentile using a reservoir sample
+/// COUNT(DISTINCT column) / APPROX_COUNT_DISTINCT(column) - HyperLogLog estimate
+#[derive(Debug, Clone, Default)]
+pub struct DistinctCountAccumulator {
+    sketch: HyperLogLog,
+}
+
+impl DistinctCountAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sketch(&self) -> &HyperLogLog {
+        &self.sketch
+    }
+}
+
+impl Accumulator for DistinctCountAccumulator {
+    fn accumulate(&mut self, value: &Value) {
+        self.sketch.insert(value);
+    }
+
+    fn result(&self) -> Value {
+        Value::Int64(self.sketch.estimate() as i64)
+    }
+
+    fn clone_box(&self) -> Box<dyn Accumulator> {
+        Box::new(self.clone())
+    }
+
+    fn merge(&mut self, other: &dyn Accumulator) {
+        if let Some(d_acc) = other.as_any().downcast_ref::<DistinctCountAccumulator>() {
+            self.sketch.merge(&d_acc.sketch);
+        }
+    }
+
+    fn accumulate_hash(&mut self, hash: u64) {
+        self.sketch.insert_hash(hash);
+    }
+}
+
+/// PERCENTILE(column, p) - Quantile estimate from a DDSketch (1% relative error)
 #[derive(Debug, Clone)]
 pub struct PercentileAccumulator {
-    percentile: u8,
-    values: Vec<f64>,
-    max_samples: usize,
+    percentile: f64,
+    sketch: DDSketch,
 }
 
 impl PercentileAccumulator {
-    pub fn new(percentile: u8) -> Self {
+    pub fn new(percentile: f64) -> Self {
         Self {
             percentile,
-            values: Vec::new(),
-            max_samples: 10000, // Keep up to 10k samples
+            sketch: DDSketch::new(),
         }
     }
+
+    pub fn percentile(&self) -> f64 {
+        self.percentile
+    }
+
+    pub fn sketch(&self) -> &DDSketch {
+        &self.sketch
+    }
 }
 
 impl Accumulator for PercentileAccumulator {
     fn accumulate(&mut self, value: &Value) {
         if let Some(v) = value.as_f64() {
-            if self.values.len() < self.max_samples {
-                self.values.push(v);
-            } else {
-                // Reservoir sampling
-                let idx = rand_index(self.values.len() + 1);
-                if idx < self.max_samples {
-                    self.values[idx] = v;
-                }
-            }
+            self.sketch.insert(v);
         }
     }
 
     fn result(&self) -> Value {
-        if self.values.is_empty() {
+        self.sketch
+            .quantile(self.percentile / 100.0)
+            .map(Value::Float64)
+            .unwrap_or(Value::Null)
+    }
+
+    fn clone_box(&self) -> Box<dyn Accumulator> {
+        Box::new(self.clone())
+    }
+
+    fn merge(&mut self, other: &dyn Accumulator) {
+        if let Some(p_acc) = other.as_any().downcast_ref::<PercentileAccumulator>() {
+            self.sketch.merge(&p_acc.sketch);
+        }
+    }
+}
+
+/// STDDEV(column) / VARIANCE(column) - Sample variance with Welford's
+/// algorithm, merged with Chan's parallel update
+#[derive(Debug, Clone)]
+pub struct VarianceAccumulator {
+    count: i64,
+    mean: f64,
+    m2: f64,
+    stddev: bool,
+}
+
+impl VarianceAccumulator {
+    pub fn new(stddev: bool) -> Self {
+        Self {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            stddev,
+        }
+    }
+
+    /// Restore from a count, mean and sum of squared deviations
+    pub fn from_parts(count: i64, mean: f64, m2: f64, stddev: bool) -> Self {
+        Self {
+            count,
+            mean,
+            m2,
+            stddev,
+        }
+    }
+
+    /// Count, mean and sum of squared deviations from the mean
+    pub fn parts(&self) -> (i64, f64, f64) {
+        (self.count, self.mean, self.m2)
+    }
+
+    pub fn is_stddev(&self) -> bool {
+        self.stddev
+    }
+
+    /// Combine with another count, mean and sum of squared deviations
+    pub fn merge_parts(&mut self, count: i64, mean: f64, m2: f64) {
+        if count == 0 {
+            return;
+        }
+        let total = self.count + count;
+        let delta = mean - self.mean;
+        self.m2 += m2 + delta * delta * (self.count as f64 * count as f64 / total as f64);
+        self.mean += delta * count as f64 / total as f64;
+        self.count = total;
+    }
+}
+
+impl Accumulator for VarianceAccumulator {
+    fn accumulate(&mut self, value: &Value) {
+        if let Some(v) = value.as_f64() {
+            self.count += 1;
+            let delta = v - self.mean;
+            self.mean += delta / self.count as f64;
+            self.m2 += delta * (v - self.mean);
+        }
+    }
+
+    fn result(&self) -> Value {
+        if self.count < 2 {
             return Value::Null;
         }
+        let variance = self.m2 / (self.count - 1) as f64;
+        Value::Float64(if self.stddev { variance.sqrt() } else { variance })
+    }
 
-        let mut sorted = self.values.clone();
-        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    fn clone_box(&self) -> Box<dyn Accumulator> {
+        Box::new(self.clone())
+    }
 
-        let idx = ((self.percentile as f64 / 100.0) * (sorted.len() - 1) as f64).round() as usize;
-        Value::Float64(sorted[idx.min(sorted.len() - 1)])
+    fn merge(&mut self, other: &dyn Accumulator) {
+        if let Some(v_acc) = other.as_any().downcast_ref::<VarianceAccumulator>() {
+            self.merge_parts(v_acc.count, v_acc.mean, v_acc.m2);
+        }
+    }
+}
+
+/// ARG_MAX(column, key) / ARG_MIN(column, key) - The value on the row with
+/// the largest or smallest key. LAST and FIRST are keyed by timestamp.
+/// Rows with a NULL value or key are skipped; ties keep the earlier row.
+#[derive(Debug, Clone)]
+pub struct ArgExtremeAccumulator {
+    keep_max: bool,
+    /// Best key so far and its value
+    best: Option<(Value, Value)>,
+}
+
+impl ArgExtremeAccumulator {
+    pub fn new(keep_max: bool) -> Self {
+        Self {
+            keep_max,
+            best: None,
+        }
+    }
+
+    pub fn from_parts(keep_max: bool, best: Option<(Value, Value)>) -> Self {
+        Self { keep_max, best }
+    }
+
+    pub fn keeps_max(&self) -> bool {
+        self.keep_max
+    }
+
+    pub fn best(&self) -> Option<&(Value, Value)> {
+        self.best.as_ref()
+    }
+
+    /// Keep `value` if `key` beats the best key so far
+    pub fn offer(&mut self, key: &Value, value: &Value) {
+        if key.is_null() || value.is_null() {
+            return;
+        }
+        let better = match &self.best {
+            None => true,
+            Some((best, _)) if self.keep_max => key > best,
+            Some((best, _)) => key < best,
+        };
+        if better {
+            self.best = Some((key.clone(), value.clone()));
+        }
+    }
+}
+
+impl Accumulator for ArgExtremeAccumulator {
+    /// Without a key there is nothing to order by
+    fn accumulate(&mut self, _value: &Value) {}
+
+    fn accumulate_keyed(&mut self, value: &Value, key: &Value) {
+        self.offer(key, value);
+    }
+
+    fn result(&self) -> Value {
+        self.best.as_ref().map_or(Value::Null, |(_, value)| value.clone())
     }
 
     fn clone_box(&self) -> Box<dyn Accumulator> {
@@ -323,60 +548,96 @@ impl Accumulator for PercentileAccumulator {
     }
 
     fn merge(&mut self, other: &dyn Accumulator) {
-        if let Some(p_acc) = other.as_any().downcast_ref::<PercentileAccumulator>() {
-            for &v in &p_acc.values {
-                self.accumulate(&Value::Float64(v));
+        if let Some(a_acc) = other.as_any().downcast_ref::<ArgExtremeAccumulator>() {
+            if let Some((key, value)) = &a_acc.best {
+                self.offer(key, value);
             }
         }
     }
 }
 
-/// Simple deterministic "random" for reservoir sampling
-fn rand_index(n: usize) -> usize {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
+/// APPROX_TOP_K(column, k) - Most frequent values with estimated counts
+#[derive(Debug, Clone)]
+pub struct TopKAccumulator {
+    k: usize,
+    sketch: SpaceSaving,
+}
+
+impl TopKAccumulator {
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            sketch: SpaceSaving::for_top_k(k),
+        }
+    }
+
+    pub fn from_parts(k: usize, sketch: SpaceSaving) -> Self {
+        Self { k, sketch }
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn sketch(&self) -> &SpaceSaving {
+        &self.sketch
+    }
+}
+
+impl Accumulator for TopKAccumulator {
+    fn accumulate(&mut self, value: &Value) {
+        self.sketch.insert(value);
+    }
 
-    let mut hasher = DefaultHasher::new();
-    n.hash(&mut hasher);
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_nanos()
-        .hash(&mut hasher);
-    (hasher.finish() as usize) % n
+    fn result(&self) -> Value {
+        Value::TopK(self.sketch.top(self.k))
+    }
+
+    fn clone_box(&self) -> Box<dyn Accumulator> {
+        Box::new(self.clone())
+    }
+
+    fn merge(&mut self, other: &dyn Accumulator) {
+        if let Some(t_acc) = other.as_any().downcast_ref::<TopKAccumulator>() {
+            self.sketch.merge(&t_acc.sketch);
+        }
+    }
 }
 
-/// HISTOGRAM(column, bucket_size) - Build a histogram
+/// HISTOGRAM(column, width) and HISTOGRAM_LOG(column) - Value counts per
+/// bucket, returned as bucket boundaries and counts
 #[derive(Debug, Clone)]
 pub struct HistogramAccumulator {
-    bucket_size: f64,
-    buckets: HashMap<i64, i64>, // bucket_key -> count
+    sketch: HistogramSketch,
 }
 
 impl HistogramAccumulator {
-    pub fn new(bucket_size: f64) -> Self {
+    pub fn new(buckets: Buckets) -> Self {
         Self {
-            bucket_size,
-            buckets: Default::default(),
+            sketch: HistogramSketch::new(buckets),
         }
     }
+
+    pub fn from_sketch(sketch: HistogramSketch) -> Self {
+        Self { sketch }
+    }
+
+    pub fn sketch(&self) -> &HistogramSketch {
+        &self.sketch
+    }
 }
 
 impl Accumulator for HistogramAccumulator {
     fn accumulate(&mut self, value: &Value) {
         if let Some(v) = value.as_f64() {
-            let bucket_key = (v / self.bucket_size).floor() as i64;
-            *self.buckets.entry(bucket_key).or_insert(0) += 1;
+            self.sketch.insert(v);
         }
     }
 
     fn result(&self) -> Value {
-        // Return as JSON string representation
-        let mut result: Vec<(i64, i64)> = self.buckets.iter().map(|(&k, &v)| (k, v)).collect();
-        result.sort_by_key(|(k, _)| *k);
-
-        let json = serde_json::to_string(&result).unwrap_or_else(|_| "[]".to_string());
-        Value::String(json)
+        self.sketch
+            .histogram()
+            .map_or(Value::Null, |h| Value::Histogram(Box::new(h)))
     }
 
     fn clone_box(&self) -> Box<dyn Accumulator> {
@@ -385,20 +646,19 @@ impl Accumulator for HistogramAccumulator {
 
     fn merge(&mut self, other: &dyn Accumulator) {
         if let Some(h_acc) = other.as_any().downcast_ref::<HistogramAccumulator>() {
-            for (&key, &count) in &h_acc.buckets {
-                *self.buckets.entry(key).or_insert(0) += count;
-            }
+            self.sketch.merge(&h_acc.sketch);
         }
     }
 }
 
 /// Factory for creating accumulators
+use super::expr::ScalarExpr;
 use super::parser::AggregateFunction;
 
-pub fn create_accumulator(func: AggregateFunction, column: &Option<String>) -> Box<dyn Accumulator> {
+pub fn create_accumulator(func: AggregateFunction, argument: &Option<ScalarExpr>) -> Box<dyn Accumulator> {
     match func {
         AggregateFunction::Count => {
-            if column.is_some() {
+            if argument.is_some() {
                 Box::new(CountAccumulator::count_column())
             } else {
                 Box::new(CountAccumulator::count_all())
@@ -409,6 +669,21 @@ pub fn create_accumulator(func: AggregateFunction, column: &Option<String>) -> B
         AggregateFunction::Min => Box::new(MinAccumulator::new()),
         AggregateFunction::Max => Box::new(MaxAccumulator::new()),
         AggregateFunction::Percentile(p) => Box::new(PercentileAccumulator::new(p)),
+        AggregateFunction::Median => Box::new(PercentileAccumulator::new(50.0)),
+        AggregateFunction::CountDistinct => Box::new(DistinctCountAccumulator::new()),
+        AggregateFunction::StdDev => Box::new(VarianceAccumulator::new(true)),
+        AggregateFunction::Variance => Box::new(VarianceAccumulator::new(false)),
+        AggregateFunction::First | AggregateFunction::ArgMin => {
+            Box::new(ArgExtremeAccumulator::new(false))
+        }
+        AggregateFunction::Last | AggregateFunction::ArgMax => {
+            Box::new(ArgExtremeAccumulator::new(true))
+        }
+        AggregateFunction::ApproxTopK(k) => Box::new(TopKAccumulator::new(k)),
+        AggregateFunction::Histogram(width) => {
+            Box::new(HistogramAccumulator::new(Buckets::Linear(width)))
+        }
+        AggregateFunction::HistogramLog => Box::new(HistogramAccumulator::new(Buckets::LogLinear)),
     }
 }
 
@@ -478,7 +753,7 @@ mod tests {
 
     #[test]
     fn test_percentile_accumulator() {
-        let mut acc = PercentileAccumulator::new(50);
+        let mut acc = PercentileAccumulator::new(50.0);
         for i in 1..=100 {
             acc.accumulate(&Value::Int64(i));
         }
@@ -490,6 +765,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_percentile_tail_and_merge() {
+        let mut acc1 = PercentileAccumulator::new(99.9);
+        let mut acc2 = PercentileAccumulator::new(99.9);
+        for i in 1..=10_000 {
+            if i % 2 == 0 {
+                acc1.accumulate(&Value::Int64(i));
+            } else {
+                acc2.accumulate(&Value::Int64(i));
+            }
+        }
+
+        acc1.merge(&acc2);
+        if let Value::Float64(p999) = acc1.result() {
+            assert!((p999 - 9990.0).abs() / 9990.0 <= 0.01);
+        } else {
+            panic!("Expected float result");
+        }
+    }
+
     #[test]
     fn test_empty_accumulator() {
         let acc = SumAccumulator::new();
@@ -516,6 +811,110 @@ mod tests {
         assert_eq!(acc1.result(), Value::Float64(100.0));
     }
 
+    #[test]
+    fn test_distinct_count_accumulator() {
+        let mut acc1 = DistinctCountAccumulator::new();
+        let mut acc2 = DistinctCountAccumulator::new();
+        for i in 0..50 {
+            acc1.accumulate(&Value::String(format!("user-{}", i % 20)));
+            acc2.accumulate(&Value::String(format!("user-{}", i % 30 + 10)));
+        }
+        acc1.accumulate(&Value::Null);
+        assert_eq!(acc1.result(), Value::Int64(20));
+
+        // user-10 .. user-19 are seen by both
+        acc1.merge(&acc2);
+        assert_eq!(acc1.result(), Value::Int64(40));
+    }
+
+    #[test]
+    fn test_variance_accumulator() {
+        let values = [2, 4, 4, 4, 5, 5, 7, 9];
+        let mut all = VarianceAccumulator::new(false);
+        let mut left = VarianceAccumulator::new(true);
+        let mut right = VarianceAccumulator::new(true);
+        for (i, v) in values.iter().enumerate() {
+            all.accumulate(&Value::Int64(*v));
+            let half = if i < 3 { &mut left } else { &mut right };
+            half.accumulate(&Value::Int64(*v));
+        }
+        all.accumulate(&Value::Null);
+
+        // Sample variance: 32 / 7
+        let Value::Float64(variance) = all.result() else {
+            panic!("Expected float result");
+        };
+        assert!((variance - 32.0 / 7.0).abs() < 1e-9);
+        left.merge(&right);
+        let Value::Float64(stddev) = left.result() else {
+            panic!("Expected float result");
+        };
+        assert!((stddev - (32.0f64 / 7.0).sqrt()).abs() < 1e-9);
+
+        let mut single = VarianceAccumulator::new(false);
+        single.accumulate(&Value::Int64(1));
+        assert_eq!(single.result(), Value::Null);
+    }
+
+    #[test]
+    fn test_arg_extreme_accumulator() {
+        let rows = [(3, "b"), (1, "a"), (5, "c"), (5, "d")];
+        let mut last = ArgExtremeAccumulator::new(true);
+        let mut first = ArgExtremeAccumulator::new(false);
+        for (ts, version) in rows {
+            let (key, value) = (Value::Timestamp(ts), Value::String(version.into()));
+            last.accumulate_keyed(&value, &key);
+            first.accumulate_keyed(&value, &key);
+        }
+        first.accumulate_keyed(&Value::Null, &Value::Timestamp(0));
+
+        // Ties keep the earlier row
+        assert_eq!(last.result(), Value::String("c".into()));
+        assert_eq!(first.result(), Value::String("a".into()));
+
+        let mut later = ArgExtremeAccumulator::new(true);
+        later.accumulate_keyed(&Value::String("e".into()), &Value::Timestamp(9));
+        last.merge(&later);
+        assert_eq!(last.result(), Value::String("e".into()));
+    }
+
+    #[test]
+    fn test_top_k_accumulator() {
+        let mut acc1 = TopKAccumulator::new(2);
+        let mut acc2 = TopKAccumulator::new(2);
+        for (i, url) in ["/a", "/b", "/a", "/c", "/a", "/b"].iter().enumerate() {
+            let acc = if i % 2 == 0 { &mut acc1 } else { &mut acc2 };
+            acc.accumulate(&Value::String(url.to_string()));
+        }
+
+        acc1.merge(&acc2);
+        let Value::TopK(top) = acc1.result() else {
+            panic!("Expected top-k result");
+        };
+        let entries: Vec<_> = top.iter().map(|e| (e.value.clone(), e.count, e.error)).collect();
+        assert_eq!(
+            entries,
+            vec![(Value::String("/a".into()), 3, 0), (Value::String("/b".into()), 2, 0)]
+        );
+    }
+
+    #[test]
+    fn test_histogram_accumulator() {
+        let mut acc1 = HistogramAccumulator::new(Buckets::Linear(100.0));
+        let mut acc2 = HistogramAccumulator::new(Buckets::Linear(100.0));
+        assert_eq!(acc1.result(), Value::Null);
+        acc1.accumulate(&Value::Int64(20));
+        acc1.accumulate(&Value::Null);
+        acc2.accumulate(&Value::Float64(250.0));
+
+        acc1.merge(&acc2);
+        let Value::Histogram(h) = acc1.result() else {
+            panic!("Expected histogram result");
+        };
+        assert_eq!(h.boundaries, vec![0.0, 100.0, 200.0, 300.0]);
+        assert_eq!(h.counts, vec![1, 0, 1]);
+    }
+
     #[test]
     fn test_merge_avg_accumulators() {
         let mut acc1 = AvgAccumulator::new();
@@ -7,6 +7,7 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
 use super::executor::QueryResult;
+use super::parser::ParsedQuery;
 
 /// Cache key for query results
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -15,13 +16,19 @@ pub struct CacheKey {
     sql: String,
     /// Table name (for targeted invalidation)
     table: Option<String>,
+    /// Time NOW() and ago() resolved to. The same SQL covers a different
+    /// window at every reference time, so each gets its own entry; other
+    /// queries share one entry whenever they run.
+    now: Option<i64>,
 }
 
 impl CacheKey {
-    pub fn new(sql: &str) -> Self {
+    /// Key for `sql`, parsed as `query`
+    pub fn new(sql: &str, query: &ParsedQuery) -> Self {
         Self {
             sql: normalize_sql(sql),
             table: extract_table_name(sql),
+            now: query.now,
         }
     }
 
@@ -60,9 +67,9 @@ impl QueryCache {
         }
     }
 
-    /// Get a cached result
-    pub fn get(&self, sql: &str) -> Option<QueryResult> {
-        let key = CacheKey::new(sql);
+    /// Get a cached result for `sql`, parsed as `query`
+    pub fn get(&self, sql: &str, query: &ParsedQuery) -> Option<QueryResult> {
+        let key = CacheKey::new(sql, query);
         if let Some(result) = self.cache.get(&key) {
             self.hits.fetch_add(1, Ordering::Relaxed);
             Some(result)
@@ -73,8 +80,8 @@ impl QueryCache {
     }
 
     /// Store a result in the cache
-    pub fn put(&self, sql: &str, result: QueryResult) {
-        let key = CacheKey::new(sql);
+    pub fn put(&self, sql: &str, query: &ParsedQuery, result: QueryResult) {
+        let key = CacheKey::new(sql, query);
         self.cache.insert(key, result);
     }
 
@@ -189,6 +196,17 @@ fn extract_table_name(sql: &str) -> Option<String> {
 mod tests {
     use super::*;
     use crate::data::Value;
+    use crate::query::parser::parse_query_at;
+
+    /// Cache `result` for `sql` run at `now`
+    fn put(cache: &QueryCache, sql: &str, now: i64, result: QueryResult) {
+        cache.put(sql, &parse_query_at(sql, now).unwrap(), result);
+    }
+
+    /// The cached result for `sql` run at `now`
+    fn get(cache: &QueryCache, sql: &str, now: i64) -> Option<QueryResult> {
+        cache.get(sql, &parse_query_at(sql, now).unwrap())
+    }
 
     fn make_result(rows: usize) -> QueryResult {
         QueryResult {
@@ -198,6 +216,7 @@ mod tests {
             shards_scanned: 1,
             execution_time_ms: 10,
             availability: None,
+            next_cursor: None,
         }
     }
 
@@ -206,9 +225,9 @@ mod tests {
         let cache = QueryCache::new();
 
         let result = make_result(5);
-        cache.put("SELECT * FROM events", result.clone());
+        put(&cache, "SELECT * FROM events", 0, result.clone());
 
-        let cached = cache.get("SELECT * FROM events").unwrap();
+        let cached = get(&cache, "SELECT * FROM events", 0).unwrap();
         assert_eq!(cached.rows.len(), 5);
     }
 
@@ -217,14 +236,14 @@ mod tests {
         let cache = QueryCache::new();
 
         let result = make_result(3);
-        cache.put("SELECT * FROM events", result);
+        put(&cache, "SELECT * FROM events", 0, result);
 
         // Same query with different whitespace should hit cache
-        let cached = cache.get("  SELECT   *   FROM   events  ");
+        let cached = get(&cache, "  SELECT   *   FROM   events  ", 0);
         assert!(cached.is_some());
 
         // Same query with different case should hit cache
-        let cached = cache.get("select * from EVENTS");
+        let cached = get(&cache, "select * from EVENTS", 0);
         assert!(cached.is_some());
     }
 
@@ -232,37 +251,60 @@ mod tests {
     fn test_cache_miss() {
         let cache = QueryCache::new();
 
-        let result = cache.get("SELECT * FROM nonexistent");
+        let result = get(&cache, "SELECT * FROM nonexistent", 0);
         assert!(result.is_none());
 
         let stats = cache.stats();
         assert_eq!(stats.misses, 1);
     }
 
+    #[test]
+    fn test_relative_queries_cached_per_reference_time() {
+        let cache = QueryCache::new();
+        let sql = "SELECT COUNT(*) FROM events WHERE timestamp > ago('1h')";
+
+        put(&cache, sql, 60_000, make_result(1));
+
+        assert!(get(&cache, sql, 60_000).is_some());
+        // A minute later the window has moved on
+        assert!(get(&cache, sql, 120_000).is_none());
+    }
+
+    #[test]
+    fn test_other_queries_cached_whenever_they_run() {
+        let cache = QueryCache::new();
+        let sql = "SELECT COUNT(*) FROM events WHERE timestamp > 60000";
+
+        put(&cache, sql, 60_000, make_result(1));
+
+        assert!(get(&cache, sql, 120_000).is_some());
+        assert!(get(&cache, sql, 3_600_000).is_some());
+    }
+
     #[test]
     fn test_cache_invalidation() {
         let cache = QueryCache::new();
 
-        cache.put("SELECT * FROM events", make_result(1));
-        cache.put("SELECT * FROM logs", make_result(2));
+        put(&cache, "SELECT * FROM events", 0, make_result(1));
+        put(&cache, "SELECT * FROM logs", 0, make_result(2));
 
         // Invalidate events table
         cache.invalidate_table("events");
 
-        assert!(cache.get("SELECT * FROM events").is_none());
-        assert!(cache.get("SELECT * FROM logs").is_some());
+        assert!(get(&cache, "SELECT * FROM events", 0).is_none());
+        assert!(get(&cache, "SELECT * FROM logs", 0).is_some());
     }
 
     #[test]
     fn test_cache_stats() {
         let cache = QueryCache::new();
 
-        cache.put("SELECT * FROM events", make_result(1));
+        put(&cache, "SELECT * FROM events", 0, make_result(1));
 
         // Hit
-        let _ = cache.get("SELECT * FROM events");
+        let _ = get(&cache, "SELECT * FROM events", 0);
         // Miss
-        let _ = cache.get("SELECT * FROM other");
+        let _ = get(&cache, "SELECT * FROM other", 0);
 
         let stats = cache.stats();
         assert_eq!(stats.hits, 1);
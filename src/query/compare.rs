@@ -0,0 +1,193 @@
+//! Period-over-period comparison
+//!
+//! `... COMPARE TO '1 week'` runs the query a second time over the same
+//! time range shifted back by the interval, then joins the two results on
+//! their group columns, with time buckets matched relative to each period.
+//! Every aggregate gets `<name>_prev` and `<name>_delta` columns.
+
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::{Token, TokenWithLocation};
+use std::collections::HashMap;
+
+use super::parser::{parse_interval, ParseError};
+use super::planner::ComparePlan;
+use crate::data::Value;
+
+/// Remove a `COMPARE TO <interval> [AGO]` clause from the query's tokens
+/// and return the interval in milliseconds. The interval may be quoted
+/// (`'1 week'`), an INTERVAL literal or bare (`1 week`).
+pub(crate) fn take_compare_clause(
+    tokens: &mut Vec<TokenWithLocation>,
+) -> Result<Option<i64>, ParseError> {
+    let significant: Vec<usize> = (0..tokens.len())
+        .filter(|&i| !matches!(tokens[i].token, Token::Whitespace(_)))
+        .collect();
+
+    let is_to = |n: usize| {
+        matches!(significant.get(n).map(|&i| &tokens[i].token),
+            Some(Token::Word(word)) if word.keyword == Keyword::TO)
+    };
+
+    let mut depth = 0;
+    let mut start = None;
+    for (n, &i) in significant.iter().enumerate() {
+        match &tokens[i].token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::Word(word)
+                if depth == 0
+                    && word.quote_style.is_none()
+                    && word.value.eq_ignore_ascii_case("COMPARE")
+                    && is_to(n + 1) =>
+            {
+                start = Some(n);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let Some(start) = start else {
+        return Ok(None);
+    };
+
+    // Tokens after COMPARE TO
+    let rest: Vec<&Token> = significant[start + 2..].iter().map(|&i| &tokens[i].token).collect();
+    let (interval, length) = match rest.as_slice() {
+        [Token::SingleQuotedString(s), ..] => (s.clone(), 1),
+        [Token::Word(interval), Token::SingleQuotedString(s), ..]
+            if interval.keyword == Keyword::INTERVAL =>
+        {
+            (s.clone(), 2)
+        }
+        [Token::Number(n, _), Token::Word(unit), ..] => (format!("{} {}", n, unit.value), 2),
+        _ => {
+            let clause: Vec<String> = rest.iter().take(2).map(|t| t.to_string()).collect();
+            let clause = format!("COMPARE TO {}", clause.join(" "));
+            return Err(ParseError::InvalidCompare(clause));
+        }
+    };
+    let ago = matches!(rest.get(length),
+        Some(Token::Word(word)) if word.value.eq_ignore_ascii_case("AGO"));
+    let end = significant[start + 1 + length + usize::from(ago)];
+
+    let offset_ms = parse_interval(&interval)?;
+    if offset_ms <= 0 {
+        return Err(ParseError::InvalidCompare(interval));
+    }
+
+    tokens.drain(significant[start]..=end);
+    Ok(Some(offset_ms))
+}
+
+/// Add each aggregate's value in the previous period, and its change, to
+/// the current period's rows. The new columns go before `hidden` trailing
+/// columns, which are dropped later.
+pub fn join_previous(
+    compare: &ComparePlan,
+    columns: &mut Vec<String>,
+    rows: &mut [Vec<Value>],
+    previous: Vec<Vec<Value>>,
+    hidden: usize,
+) {
+    let key = |row: &[Value], shift: i64| -> Vec<Value> {
+        compare
+            .key_columns
+            .iter()
+            .map(|&i| match compare.buckets.iter().find(|(column, _)| *column == i) {
+                // Bucket starts are compared relative to their period
+                Some((_, bucket)) => match row[i].as_i64() {
+                    Some(start) => Value::Timestamp(bucket.floor(start + shift)),
+                    None => Value::Null,
+                },
+                None => row[i].clone(),
+            })
+            .collect()
+    };
+
+    let previous: HashMap<Vec<Value>, Vec<Value>> = previous
+        .into_iter()
+        .map(|row| (key(&row, compare.offset_ms), row))
+        .collect();
+
+    let at = columns.len() - hidden;
+    let added: Vec<String> = compare
+        .value_columns
+        .iter()
+        .flat_map(|&i| [format!("{}_prev", columns[i]), format!("{}_delta", columns[i])])
+        .collect();
+    columns.splice(at..at, added);
+
+    for row in rows.iter_mut() {
+        let matched = previous.get(&key(row, 0));
+        let added: Vec<Value> = compare
+            .value_columns
+            .iter()
+            .flat_map(|&i| {
+                let prev = matched.map_or(Value::Null, |p| p[i].clone());
+                let delta = delta(&row[i], &prev);
+                [prev, delta]
+            })
+            .collect();
+        row.splice(at..at, added);
+    }
+}
+
+/// `current - previous`, as a float when the integer difference overflows
+fn delta(current: &Value, previous: &Value) -> Value {
+    match (current, previous) {
+        (Value::Int64(a), Value::Int64(b)) => match a.checked_sub(*b) {
+            Some(d) => Value::Int64(d),
+            None => Value::Float64(*a as f64 - *b as f64),
+        },
+        _ => match (current.as_f64(), previous.as_f64()) {
+            (Some(a), Some(b)) => Value::Float64(a - b),
+            _ => Value::Null,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlparser::dialect::GenericDialect;
+    use sqlparser::tokenizer::Tokenizer;
+
+    fn take(sql: &str) -> (Result<Option<i64>, ParseError>, String) {
+        let mut tokens = Tokenizer::new(&GenericDialect {}, sql)
+            .tokenize_with_location()
+            .unwrap();
+        let offset = take_compare_clause(&mut tokens);
+        let rest = tokens.iter().map(|t| t.token.to_string()).collect();
+        (offset, rest)
+    }
+
+    #[test]
+    fn test_take_compare_clause() {
+        let week = 7 * 86400 * 1000;
+        let (offset, rest) =
+            take("SELECT COUNT(*) FROM t GROUP BY a COMPARE TO '1 week' ORDER BY a");
+        assert_eq!(offset.unwrap(), Some(week));
+        assert_eq!(rest, "SELECT COUNT(*) FROM t GROUP BY a  ORDER BY a");
+
+        let (offset, rest) = take("SELECT COUNT(*) FROM t COMPARE TO 1 week ago");
+        assert_eq!(offset.unwrap(), Some(week));
+        assert_eq!(rest, "SELECT COUNT(*) FROM t ");
+        let (offset, _) = take("SELECT COUNT(*) FROM t COMPARE TO INTERVAL '1 day'");
+        assert_eq!(offset.unwrap(), Some(86400 * 1000));
+
+        let (offset, _) = take("SELECT compare FROM t WHERE compare > 1");
+        assert_eq!(offset.unwrap(), None);
+        assert!(take("SELECT COUNT(*) FROM t COMPARE TO yesterday").0.is_err());
+    }
+
+    #[test]
+    fn test_delta() {
+        assert_eq!(delta(&Value::Int64(5), &Value::Int64(7)), Value::Int64(-2));
+        assert_eq!(delta(&Value::Float64(1.5), &Value::Int64(1)), Value::Float64(0.5));
+        assert_eq!(delta(&Value::Int64(5), &Value::Null), Value::Null);
+        assert_eq!(
+            delta(&Value::Int64(i64::MAX), &Value::Int64(-1)),
+            Value::Float64(i64::MAX as f64 + 1.0)
+        );
+    }
+}
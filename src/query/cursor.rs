@@ -0,0 +1,64 @@
+//! Continuation cursors for paging through scan results
+//!
+//! A scan without ORDER BY returns rows in shard order, so the next page
+//! can start right after the last row returned: the cursor records that
+//! row's shard, by its start time, and its position in the shard. Shards
+//! before it are not read again. Rows that arrive in earlier shards after
+//! the first page are not seen by later pages.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::parser::ParseError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanCursor {
+    /// Start time of the shard the next page starts in
+    pub shard_start: i64,
+    /// First row of that shard the next page may return
+    pub row: usize,
+}
+
+/// Hex digits in an encoded cursor
+const ENCODED_LEN: usize = 32;
+
+impl fmt::Display for ScanCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}{:016x}", self.shard_start as u64, self.row as u64)
+    }
+}
+
+impl FromStr for ScanCursor {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::InvalidCursor(s.to_string());
+        if s.len() != ENCODED_LEN || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let (shard_start, row) = s.split_at(ENCODED_LEN / 2);
+        let shard_start = u64::from_str_radix(shard_start, 16).map_err(|_| invalid())?;
+        let row = u64::from_str_radix(row, 16).map_err(|_| invalid())?;
+        Ok(ScanCursor {
+            shard_start: shard_start as i64,
+            row: usize::try_from(row).map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for cursor in [
+            ScanCursor { shard_start: 1_700_000_000_000, row: 42 },
+            ScanCursor { shard_start: -3_600_000, row: 0 },
+        ] {
+            assert_eq!(cursor.to_string().parse::<ScanCursor>().unwrap(), cursor);
+        }
+        assert!("".parse::<ScanCursor>().is_err());
+        assert!("zz".repeat(16).parse::<ScanCursor>().is_err());
+    }
+}
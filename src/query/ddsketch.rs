@@ -0,0 +1,239 @@
+//! DDSketch quantile sketch
+//!
+//! Values are counted in logarithmically sized buckets, so any quantile is
+//! answered within a fixed relative error. Sketches merge exactly by adding
+//! bucket counts, which lets shards and nodes combine percentiles without
+//! shipping raw samples.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Relative accuracy of returned quantiles (1%)
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Bucket cap per sign. When exceeded the lowest buckets are collapsed,
+/// trading accuracy for the smallest magnitudes only.
+const MAX_BUCKETS: usize = 2048;
+
+/// Values closer to zero than this are counted as zero
+const MIN_INDEXABLE: f64 = 1e-9;
+
+/// Mergeable quantile sketch with bounded relative error
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DDSketch {
+    /// Bucket index -> count for positive values
+    positive: BTreeMap<i32, u64>,
+    /// Bucket index -> count for the magnitude of negative values
+    negative: BTreeMap<i32, u64>,
+    zero_count: u64,
+    count: u64,
+    min: f64,
+    max: f64,
+}
+
+impl DDSketch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a value. NaN is ignored.
+    pub fn insert(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+
+        if value > MIN_INDEXABLE {
+            add_to_store(&mut self.positive, bucket_index(value), 1);
+        } else if value < -MIN_INDEXABLE {
+            add_to_store(&mut self.negative, bucket_index(-value), 1);
+        } else {
+            self.zero_count += 1;
+        }
+
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+    }
+
+    /// Merge another sketch into this one
+    pub fn merge(&mut self, other: &DDSketch) {
+        if other.count == 0 {
+            return;
+        }
+
+        for (&index, &count) in &other.positive {
+            add_to_store(&mut self.positive, index, count);
+        }
+        for (&index, &count) in &other.negative {
+            add_to_store(&mut self.negative, index, count);
+        }
+        self.zero_count += other.zero_count;
+
+        if self.count == 0 {
+            self.min = other.min;
+            self.max = other.max;
+        } else {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+        self.count += other.count;
+    }
+
+    /// Number of values added
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Estimate the value at quantile `q` (0.0 - 1.0)
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+
+        // Nearest-rank over the sorted values
+        let rank = (q * (self.count - 1) as f64).round() as u64;
+        if rank == 0 {
+            return Some(self.min);
+        }
+        if rank == self.count - 1 {
+            return Some(self.max);
+        }
+
+        let mut seen = 0;
+
+        // Negative values, most negative (largest magnitude) first
+        for (&index, &count) in self.negative.iter().rev() {
+            seen += count;
+            if seen > rank {
+                return Some(-bucket_value(index).clamp(-self.max, -self.min));
+            }
+        }
+
+        seen += self.zero_count;
+        if seen > rank {
+            return Some(0.0);
+        }
+
+        for (&index, &count) in &self.positive {
+            seen += count;
+            if seen > rank {
+                return Some(bucket_value(index).clamp(self.min, self.max));
+            }
+        }
+
+        Some(self.max)
+    }
+}
+
+fn gamma() -> f64 {
+    (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY)
+}
+
+fn bucket_index(magnitude: f64) -> i32 {
+    (magnitude.ln() / gamma().ln()).ceil() as i32
+}
+
+/// Representative value for a bucket, within the relative accuracy of
+/// every value that maps to it
+fn bucket_value(index: i32) -> f64 {
+    let gamma = gamma();
+    2.0 * gamma.powi(index) / (gamma + 1.0)
+}
+
+fn add_to_store(store: &mut BTreeMap<i32, u64>, index: i32, count: u64) {
+    *store.entry(index).or_insert(0) += count;
+
+    if store.len() > MAX_BUCKETS {
+        // Fold the lowest bucket into its neighbour
+        if let Some((_, lowest)) = store.pop_first() {
+            if let Some(mut next) = store.first_entry() {
+                *next.get_mut() += lowest;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_within(actual: f64, expected: f64) {
+        let error = (actual - expected).abs() / expected.abs();
+        assert!(
+            error <= RELATIVE_ACCURACY + 1e-9,
+            "{} not within 1% of {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_empty_sketch() {
+        assert_eq!(DDSketch::new().quantile(0.5), None);
+    }
+
+    #[test]
+    fn test_quantiles_within_relative_error() {
+        let mut sketch = DDSketch::new();
+        for i in 1..=100_000 {
+            sketch.insert(i as f64);
+        }
+
+        assert_within(sketch.quantile(0.5).unwrap(), 50_000.0);
+        assert_within(sketch.quantile(0.99).unwrap(), 99_000.0);
+        assert_within(sketch.quantile(0.999).unwrap(), 99_900.0);
+        assert_eq!(sketch.quantile(0.0), Some(1.0));
+        assert_eq!(sketch.quantile(1.0), Some(100_000.0));
+    }
+
+    #[test]
+    fn test_negative_and_zero_values() {
+        let mut sketch = DDSketch::new();
+        for i in -50..=50 {
+            sketch.insert(i as f64);
+        }
+
+        assert_eq!(sketch.quantile(0.5), Some(0.0));
+        assert_within(sketch.quantile(0.1).unwrap(), -40.0);
+        assert_within(sketch.quantile(0.9).unwrap(), 40.0);
+    }
+
+    #[test]
+    fn test_merge_matches_single_sketch() {
+        let mut a = DDSketch::new();
+        let mut b = DDSketch::new();
+        let mut all = DDSketch::new();
+        for i in 0..10_000 {
+            let v = (i * 7 % 1000) as f64 + 0.5;
+            if i % 3 == 0 {
+                a.insert(v);
+            } else {
+                b.insert(v);
+            }
+            all.insert(v);
+        }
+
+        a.merge(&b);
+        assert_eq!(a.count(), all.count());
+        for q in [0.1, 0.5, 0.9, 0.99] {
+            assert_eq!(a.quantile(q), all.quantile(q));
+        }
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let build = || {
+            let mut sketch = DDSketch::new();
+            for i in 0..50_000 {
+                sketch.insert((i % 977) as f64);
+            }
+            sketch.quantile(0.95)
+        };
+        assert_eq!(build(), build());
+    }
+}
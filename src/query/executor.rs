@@ -1,13 +1,25 @@
 use super::aggregates::{create_accumulator, Accumulator};
+use super::expr::{BoundExpr, ScalarExpr};
+use super::compare::join_previous;
+use super::cursor::ScanCursor;
+use super::fill::fill_rows;
+use super::glob::{
+    bind_projections, bind_table_column, is_glob, matching_tables, wildcard_columns,
+};
+use super::hll::hash_str;
+use super::join::{lookup_column_names, JoinedPlan};
 use super::parser::{AggregateFunction, FilterOperator};
 use super::planner::{
-    FilterPlan, GroupByColumnPlan, GroupByPlan, OrderByPlan, ProjectionPlan, QueryPlan,
+    FilterExprPlan, FilterPlan, GroupByColumnPlan, GroupByPlan, ProjectionPlan, QueryPlan,
 };
-use super::predicate::build_combined_mask;
+use super::predicate::{build_combined_mask, row_matches};
+use super::registry::{empty_query_log, QUERY_LOG_TABLE};
 use super::simd_agg::AggregateStats;
+use super::topn::{top_n, RowOrder, TopN};
+use super::window::apply_windows;
 use crate::data::column::Column;
 use crate::data::{Shard, Table, Value};
-use crate::storage::StorageEngine;
+use crate::storage::{StorageEngine, StringDictionary};
 use fxhash::FxHashMap;
 use rayon::prelude::*;
 use std::collections::HashMap;
@@ -29,6 +41,9 @@ pub struct QueryResult {
     /// Data availability metrics
     #[serde(skip_serializing_if = "Option::is_none")]
     pub availability: Option<AvailabilityMetrics>,
+    /// Where the next page of a limited scan starts, if there are more rows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
 }
 
 /// Metrics about data availability for the query
@@ -55,6 +70,7 @@ impl QueryResult {
             shards_scanned: 0,
             execution_time_ms: 0,
             availability: None,
+            next_cursor: None,
         }
     }
 
@@ -71,66 +87,409 @@ impl QueryResult {
 
 /// Execute a query plan against the storage engine
 pub fn execute_query(engine: &StorageEngine, plan: &QueryPlan) -> Result<QueryResult, ExecuteError> {
+    let tables = resolve_tables(engine, &plan.table)?;
+    if is_glob(&plan.table) {
+        return execute_on_tables(&tables, plan);
+    }
+    execute_on_table(&tables[0], plan)
+}
+
+/// The table a plan reads, or every table its glob matches. The query log
+/// reads as empty until a slow query creates it.
+fn resolve_tables(engine: &StorageEngine, name: &str) -> Result<Vec<Arc<Table>>, ExecuteError> {
+    let tables = if is_glob(name) {
+        matching_tables(engine, name)
+    } else if name == QUERY_LOG_TABLE {
+        vec![engine
+            .get_table(name)
+            .unwrap_or_else(|| Arc::new(empty_query_log()))]
+    } else {
+        engine.get_table(name).into_iter().collect()
+    };
+    if tables.is_empty() {
+        return Err(ExecuteError::TableNotFound(name.to_string()));
+    }
+    Ok(tables)
+}
+
+/// Execute a query plan against a table, which may be the in-memory result
+/// of a subquery
+pub fn execute_on_table(table: &Table, plan: &QueryPlan) -> Result<QueryResult, ExecuteError> {
     let start = std::time::Instant::now();
+    let plan = &bind_table_column(plan, table);
+    if plan.join.is_some() && plan.cursor.is_some() {
+        return Err(ExecuteError::General(
+            "pagination cursors are not supported with joins".into(),
+        ));
+    }
 
-    let table = engine
-        .get_table(&plan.table)
-        .ok_or_else(|| ExecuteError::TableNotFound(plan.table.clone()))?;
+    // Expand wildcard projections
+    let projections = expand_wildcards(&plan.projections, &wildcard(plan, table.column_names()));
 
     // Get relevant shards based on time range
-    let shards = get_relevant_shards(&table, plan);
-    let shards_scanned = shards.len();
+    let run = table_shards(table, plan, &projections)?;
+    let mut shards_scanned = run.shards.len();
+
+    let execute_start = std::time::Instant::now();
+    let (mut columns, mut rows, mut rows_scanned, next_cursor) =
+        execute_shards(&run.shards, &run.plan, &run.projections)?;
+    // Joined shards are built per query, so there is no position to resume at
+    let next_cursor = next_cursor.filter(|_| plan.join.is_none());
+    record_time(plan, "execute", execute_start);
+
+    // COMPARE TO aggregates the previous period the same way
+    let previous = match plan.previous_period() {
+        Some(previous) => {
+            let run = table_shards(table, &previous, &projections)?;
+            let execute_start = std::time::Instant::now();
+            let (_, rows, scanned, _) = execute_shards(&run.shards, &run.plan, &run.projections)?;
+            record_time(plan, "execute", execute_start);
+            rows_scanned += scanned;
+            shards_scanned += run.shards.len();
+            Some(rows)
+        }
+        None => None,
+    };
 
-    // Expand wildcard projections
-    let projections = expand_wildcards(&plan.projections, &table);
+    let post_start = std::time::Instant::now();
+    apply_post_aggregation(plan, &mut columns, &mut rows, previous);
+    record_time(plan, "post_aggregation", post_start);
+    check_output_rows(plan, rows.len())?;
 
-    // Check if we need aggregation
-    let has_aggregations = projections
-        .iter()
-        .any(|p| matches!(p, ProjectionPlan::Aggregate { .. }));
+    Ok(QueryResult {
+        columns,
+        rows,
+        rows_scanned,
+        shards_scanned,
+        execution_time_ms: start.elapsed().as_millis() as u64,
+        availability: Some(local_availability()),
+        next_cursor: next_cursor.map(|cursor| cursor.to_string()),
+    })
+}
 
-    let (columns, mut rows, rows_scanned) = if has_aggregations {
-        // Check if we can use the fast SIMD path (no GROUP BY, simple filters)
-        if plan.group_by.is_none() && can_use_simd_aggregation(&projections) {
-            execute_simd_aggregation(&shards, plan, &projections)?
-        } else {
-            execute_aggregation(&shards, plan, &projections)?
+/// Execute a query plan across tables read as one, such as the tables a
+/// glob matches
+pub fn execute_on_tables(
+    tables: &[Arc<Table>],
+    plan: &QueryPlan,
+) -> Result<QueryResult, ExecuteError> {
+    let start = std::time::Instant::now();
+    if plan.cursor.is_some() {
+        return Err(ExecuteError::General(
+            "pagination cursors are not supported across tables".into(),
+        ));
+    }
+
+    let columns = wildcard(plan, wildcard_columns(tables));
+    let projections = expand_wildcards(&plan.projections, &columns);
+    let (mut columns, mut rows, mut rows_scanned, mut shards_scanned) =
+        execute_tables(tables, plan, &projections)?;
+
+    let previous = match plan.previous_period() {
+        Some(previous) => {
+            let (_, rows, scanned, shards) = execute_tables(tables, &previous, &projections)?;
+            rows_scanned += scanned;
+            shards_scanned += shards;
+            Some(rows)
         }
-    } else {
-        execute_scan(&shards, plan, &projections)?
+        None => None,
     };
 
-    // Apply ORDER BY
-    if !plan.order_by.is_empty() {
-        apply_order_by(&mut rows, &columns, &plan.order_by);
-    }
+    let post_start = std::time::Instant::now();
+    apply_post_aggregation(plan, &mut columns, &mut rows, previous);
+    record_time(plan, "post_aggregation", post_start);
+    check_output_rows(plan, rows.len())?;
 
-    // Apply LIMIT
-    if let Some(limit) = plan.limit {
-        rows.truncate(limit);
-    }
+    Ok(QueryResult {
+        columns,
+        rows,
+        rows_scanned,
+        shards_scanned,
+        execution_time_ms: start.elapsed().as_millis() as u64,
+        availability: Some(local_availability()),
+        next_cursor: None,
+    })
+}
 
-    let execution_time_ms = start.elapsed().as_millis() as u64;
+/// Columns `*` expands to: the table's, then those of a lookup table
+fn wildcard(plan: &QueryPlan, mut columns: Vec<String>) -> Vec<String> {
+    columns.extend(plan.join.iter().flat_map(lookup_column_names));
+    columns
+}
 
-    // Local queries have 100% availability
-    let availability = AvailabilityMetrics {
+/// Local queries have 100% availability
+fn local_availability() -> AvailabilityMetrics {
+    AvailabilityMetrics {
         availability_percent: 100.0,
         nodes_queried: 1,
         nodes_responded: 1,
         staleness_ms: None, // Could compute from oldest data timestamp
         complete: true,
+    }
+}
+
+/// Output column names, result rows, and the numbers of rows and shards
+/// scanned
+type TablesRows = (Vec<String>, Vec<Vec<Value>>, usize, usize);
+
+/// Aggregate or scan each table and combine the results, before any
+/// post-aggregation steps
+fn execute_tables(
+    tables: &[Arc<Table>],
+    plan: &QueryPlan,
+    projections: &[ProjectionPlan],
+) -> Result<TablesRows, ExecuteError> {
+    let columns: Vec<String> = projections.iter().map(|p| p.output_name().to_string()).collect();
+    let has_aggregations = projections
+        .iter()
+        .any(|p| matches!(p, ProjectionPlan::Aggregate { .. }));
+
+    if has_aggregations {
+        let (groups, rows_scanned, shards_scanned) = aggregate_tables(tables, plan, projections)?;
+        let rows = groups
+            .into_values()
+            .map(|(group_values, accumulators)| {
+                let aggregates = accumulators.iter().map(|acc| acc.result()).collect();
+                assemble_row(projections, &group_values, aggregates)
+            })
+            .collect();
+        return Ok((columns, rows, rows_scanned, shards_scanned));
+    }
+
+    // Each table returns enough rows for the whole limit, and the
+    // post-aggregation steps pick from all of them
+    let (mut rows, mut rows_scanned, mut shards_scanned) = (Vec::new(), 0, 0);
+    for table in tables {
+        let plan = bind_table_column(plan, table);
+        let projections = bind_projections(projections, table);
+        let run = table_shards(table, &plan, &projections)?;
+        let execute_start = std::time::Instant::now();
+        let (_, table_rows, scanned, _) = execute_scan(&run.shards, &run.plan, &run.projections)?;
+        record_time(&plan, "execute", execute_start);
+        rows.extend(table_rows);
+        rows_scanned += scanned;
+        shards_scanned += run.shards.len();
+    }
+    Ok((columns, rows, rows_scanned, shards_scanned))
+}
+
+/// Aggregate each table into one set of groups. Also returns the numbers
+/// of rows and shards scanned.
+fn aggregate_tables(
+    tables: &[Arc<Table>],
+    plan: &QueryPlan,
+    projections: &[ProjectionPlan],
+) -> Result<(GroupMap, usize, usize), ExecuteError> {
+    let (mut groups, mut rows_scanned, mut shards_scanned) = (GroupMap::default(), 0, 0);
+    for table in tables {
+        let plan = bind_table_column(plan, table);
+        let projections = bind_projections(projections, table);
+        let run = table_shards(table, &plan, &projections)?;
+        let execute_start = std::time::Instant::now();
+        let (table_groups, scanned) = aggregate_groups(&run.shards, &run.plan, &run.projections)?;
+        merge_groups(&mut groups, table_groups);
+        check_groups(&plan, groups.len())?;
+        record_time(&plan, "execute", execute_start);
+        rows_scanned += scanned;
+        shards_scanned += run.shards.len();
+    }
+    Ok((groups, rows_scanned, shards_scanned))
+}
+
+/// What a plan runs over in one table, and the plan and projections to
+/// run. With a lookup join, each shard is joined with the lookup table
+/// after filtering on its own columns, and columns are named as the
+/// joined shards hold them.
+struct TableShards {
+    shards: Vec<Arc<Shard>>,
+    plan: QueryPlan,
+    projections: Vec<ProjectionPlan>,
+}
+
+fn table_shards(
+    table: &Table,
+    plan: &QueryPlan,
+    projections: &[ProjectionPlan],
+) -> Result<TableShards, ExecuteError> {
+    let start = std::time::Instant::now();
+    let Some(join) = &plan.join else {
+        let shards = get_relevant_shards(table, plan);
+        record_time(plan, "prune", start);
+        return Ok(TableShards {
+            shards,
+            plan: plan.clone(),
+            projections: projections.to_vec(),
+        });
     };
 
-    Ok(QueryResult {
-        columns,
-        rows,
-        rows_scanned,
-        shards_scanned,
-        execution_time_ms,
-        availability: Some(availability),
+    let joined = JoinedPlan::new(join, table, plan, projections)?;
+    let shards = get_relevant_shards(table, &joined.plan);
+    record_time(plan, "prune", start);
+
+    let start = std::time::Instant::now();
+    let shards = shards
+        .par_iter()
+        .map(|shard| Arc::new(joined.join_shard(shard)))
+        .collect();
+    record_time(plan, "join", start);
+    Ok(TableShards {
+        shards,
+        plan: joined.plan,
+        projections: joined.projections,
     })
 }
 
+/// Prune a table's shards for a plan without running it, as EXPLAIN does.
+/// The pruning is recorded in the plan's profile.
+pub fn prune_on_table(table: &Table, plan: &QueryPlan) -> Result<(), ExecuteError> {
+    let plan = &bind_table_column(plan, table);
+    let projections = expand_wildcards(&plan.projections, &wildcard(plan, table.column_names()));
+    match &plan.join {
+        Some(join) => {
+            let joined = JoinedPlan::new(join, table, plan, &projections)?;
+            get_relevant_shards(table, &joined.plan);
+        }
+        None => {
+            get_relevant_shards(table, plan);
+        }
+    }
+    Ok(())
+}
+
+/// Prune the shards of every table a plan reads, as `prune_on_table` does
+pub fn prune_query(engine: &StorageEngine, plan: &QueryPlan) -> Result<(), ExecuteError> {
+    for table in resolve_tables(engine, &plan.table)? {
+        prune_on_table(&table, plan)?;
+    }
+    Ok(())
+}
+
+/// Add the time since `start` to a stage of the plan's profile, if it has one
+fn record_time(plan: &QueryPlan, stage: &'static str, start: std::time::Instant) {
+    if let Some(profile) = &plan.profile {
+        profile.add_time(stage, start);
+    }
+}
+
+/// Count rows read from a shard and those matching the filters in the
+/// plan's profile, if it has one
+fn record_rows(plan: &QueryPlan, read: usize, matched: usize) {
+    if let Some(profile) = &plan.profile {
+        profile.add_rows(read, matched);
+    }
+}
+
+/// Rows a shard is worked through in between checks of the query's control
+const ROW_BATCH: usize = 4096;
+
+/// Whether the plan's query has been cancelled, run out of time or gone
+/// over a limit
+fn is_stopped(plan: &QueryPlan) -> bool {
+    plan.control.as_ref().is_some_and(|control| control.is_stopped())
+}
+
+/// Fail if the plan's query has to stop
+fn check_control(plan: &QueryPlan) -> Result<(), ExecuteError> {
+    match &plan.control {
+        Some(control) => control.check(),
+        None => Ok(()),
+    }
+}
+
+/// Fail if an aggregation has built more groups than the query's limit
+fn check_groups(plan: &QueryPlan, groups: usize) -> Result<(), ExecuteError> {
+    match &plan.control {
+        Some(control) if !control.allows_groups(groups) => control.check(),
+        _ => Ok(()),
+    }
+}
+
+/// Fail if the query would return more rows than its limit
+fn check_output_rows(plan: &QueryPlan, rows: usize) -> Result<(), ExecuteError> {
+    match &plan.control {
+        Some(control) => control.check_output_rows(rows),
+        None => Ok(()),
+    }
+}
+
+/// Count a shard the plan starts reading, and the bytes of the columns it
+/// reads against the query's limit. Returns false if the query has to stop
+/// instead.
+fn start_shard(plan: &QueryPlan, columns: &HashMap<String, Column>) -> bool {
+    let Some(control) = &plan.control else {
+        return true;
+    };
+    control.add_shard();
+    if control.limits().max_scanned_bytes.is_some() {
+        let wildcard = plan
+            .projections
+            .iter()
+            .any(|p| matches!(p, ProjectionPlan::Column { name, .. } if name == "*"));
+        let bytes = columns
+            .iter()
+            .filter(|(name, _)| wildcard || plan.required_columns.contains(name))
+            .map(|(_, column)| column.memory_usage())
+            .sum();
+        control.add_scanned_bytes(bytes);
+    }
+    !control.is_stopped()
+}
+
+/// Output column names, result rows, the number of rows scanned and where
+/// a limited scan's next page starts
+type ExecutedRows = (Vec<String>, Vec<Vec<Value>>, usize, Option<ScanCursor>);
+
+/// Aggregate or scan the given shards, before any post-aggregation steps
+fn execute_shards(
+    shards: &[Arc<Shard>],
+    plan: &QueryPlan,
+    projections: &[ProjectionPlan],
+) -> Result<ExecutedRows, ExecuteError> {
+    match execution_path(plan, projections) {
+        ExecutionPath::SimdAggregation => execute_simd_aggregation(shards, plan, projections),
+        ExecutionPath::Aggregation => execute_aggregation(shards, plan, projections),
+        ExecutionPath::Scan => execute_scan(shards, plan, projections),
+    }
+}
+
+/// How a table's shards are processed for a plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionPath {
+    /// Aggregates without GROUP BY over plain columns, from column stats
+    SimdAggregation,
+    Aggregation,
+    Scan,
+}
+
+impl ExecutionPath {
+    /// Name of the function taking this path
+    pub fn name(self) -> &'static str {
+        match self {
+            ExecutionPath::SimdAggregation => "execute_simd_aggregation",
+            ExecutionPath::Aggregation => "execute_aggregation",
+            ExecutionPath::Scan => "execute_scan",
+        }
+    }
+}
+
+pub fn execution_path(plan: &QueryPlan, projections: &[ProjectionPlan]) -> ExecutionPath {
+    // Check if we need aggregation
+    let has_aggregations = projections
+        .iter()
+        .any(|p| matches!(p, ProjectionPlan::Aggregate { .. }));
+
+    if !has_aggregations {
+        ExecutionPath::Scan
+    } else if plan.group_by.is_none() && can_use_simd_aggregation(projections) {
+        // The fast SIMD path: no GROUP BY, simple filters
+        ExecutionPath::SimdAggregation
+    } else {
+        ExecutionPath::Aggregation
+    }
+}
+
+/// Shards in the plan's time range, less those that bloom filters and
+/// zone maps prove hold no matching rows
 fn get_relevant_shards(table: &Table, plan: &QueryPlan) -> Vec<Arc<Shard>> {
     let shards = if let Some(time_range) = &plan.time_range {
         let start = time_range.start.unwrap_or(i64::MIN);
@@ -139,36 +498,91 @@ fn get_relevant_shards(table: &Table, plan: &QueryPlan) -> Vec<Arc<Shard>> {
     } else {
         table.get_shards()
     };
+    let after_time = shards.len();
+
+    match &plan.filters {
+        Some(filters) => {
+            // Zone maps are checked alongside bloom filters, so a shard one
+            // side of an OR rules out by bloom filter and the other by zone
+            // map is pruned too
+            let bloom: Vec<Arc<Shard>> = shards
+                .into_iter()
+                .filter(|shard| shard_might_match_filters(shard, filters, &bloom_might_match))
+                .collect();
+            let after_bloom = bloom.len();
+            let zone: Vec<Arc<Shard>> = bloom
+                .into_iter()
+                .filter(|shard| {
+                    shard_might_match_filters(shard, filters, &|shard, filter| {
+                        bloom_might_match(shard, filter) && zone_might_match(shard, filter)
+                    })
+                })
+                .collect();
+            record_pruning(plan, table, after_time, after_bloom, &zone);
+            zone
+        }
+        None => {
+            record_pruning(plan, table, after_time, after_time, &shards);
+            shards
+        }
+    }
+}
 
-    // Use bloom filters to prune shards that definitely don't match equality filters
-    if plan.filters.is_empty() {
-        return shards;
+fn record_pruning(
+    plan: &QueryPlan,
+    table: &Table,
+    after_time: usize,
+    after_bloom: usize,
+    shards: &[Arc<Shard>],
+) {
+    if let Some(profile) = &plan.profile {
+        profile.add_pruning(table.shard_count(), after_time, after_bloom, shards.len());
     }
+}
 
-    shards
-        .into_iter()
-        .filter(|shard| shard_might_match_filters(shard, &plan.filters))
-        .collect()
+/// Check if a shard might contain rows matching the filters, given a check
+/// of single predicates. Returns false only if we can definitively prove
+/// the shard has no matching rows.
+fn shard_might_match_filters(
+    shard: &Shard,
+    filters: &FilterExprPlan,
+    might_match: &dyn Fn(&Shard, &FilterPlan) -> bool,
+) -> bool {
+    match filters {
+        FilterExprPlan::Predicate(filter) => might_match(shard, filter),
+        FilterExprPlan::And(children) => children
+            .iter()
+            .all(|c| shard_might_match_filters(shard, c, might_match)),
+        FilterExprPlan::Or(children) => children
+            .iter()
+            .any(|c| shard_might_match_filters(shard, c, might_match)),
+        // Neither can prove a value is present, so NOT never prunes
+        FilterExprPlan::Not(_) | FilterExprPlan::InSubquery { .. } => true,
+    }
 }
 
-/// Check if a shard might contain rows matching the filters using bloom filters.
-/// Returns false only if we can definitively prove the shard has no matching rows.
-fn shard_might_match_filters(shard: &Arc<Shard>, filters: &[FilterPlan]) -> bool {
-    for filter in filters {
-        // Only use bloom filters for equality checks
-        if matches!(filter.operator, FilterOperator::Eq) {
-            if !shard.might_contain_value(&filter.column, &filter.value) {
-                // Bloom filter says this value is definitely not in this shard
-                return false;
-            }
+/// Only use bloom filters for equality and IN checks
+fn bloom_might_match(shard: &Shard, filter: &FilterPlan) -> bool {
+    match (filter.operand.as_column(), filter.operator) {
+        (Some(column), FilterOperator::Eq) => shard.might_contain_value(column, &filter.value),
+        (Some(column), FilterOperator::In) => shard.might_contain_any(column, &filter.values),
+        _ => true,
+    }
+}
+
+fn zone_might_match(shard: &Shard, filter: &FilterPlan) -> bool {
+    match filter.operand.as_column() {
+        Some(column) => {
+            shard.might_match_range(column, filter.operator, &filter.value, &filter.values)
         }
+        None => true,
     }
-    true
 }
 
 /// Check if we can use the fast SIMD aggregation path
 fn can_use_simd_aggregation(projections: &[ProjectionPlan]) -> bool {
-    // Only use SIMD for simple aggregates (COUNT, SUM, AVG, MIN, MAX)
+    // Only use SIMD for simple unconditional aggregates (COUNT, SUM, AVG,
+    // MIN, MAX, STDDEV, VARIANCE) of plain columns
     projections.iter().all(|p| {
         matches!(
             p,
@@ -177,7 +591,11 @@ fn can_use_simd_aggregation(projections: &[ProjectionPlan]) -> bool {
                     | AggregateFunction::Sum
                     | AggregateFunction::Avg
                     | AggregateFunction::Min
-                    | AggregateFunction::Max,
+                    | AggregateFunction::Max
+                    | AggregateFunction::StdDev
+                    | AggregateFunction::Variance,
+                argument: None | Some(ScalarExpr::Column(_)),
+                filter: None,
                 ..
             }
         )
@@ -189,7 +607,7 @@ fn execute_simd_aggregation(
     shards: &[Arc<Shard>],
     plan: &QueryPlan,
     projections: &[ProjectionPlan],
-) -> Result<(Vec<String>, Vec<Vec<Value>>, usize), ExecuteError> {
+) -> Result<ExecutedRows, ExecuteError> {
     let columns: Vec<String> = projections
         .iter()
         .map(|p| match p {
@@ -203,10 +621,15 @@ fn execute_simd_aggregation(
         .par_iter()
         .map(|shard| {
             shard.with_columns(|shard_columns| {
+                if !start_shard(plan, shard_columns) {
+                    return (vec![AggregateStats::default(); projections.len()], 0);
+                }
                 let row_count = shard.row_count();
 
                 // Use predicate pushdown to build a row mask
-                let mask = build_combined_mask(shard_columns, &plan.filters, row_count);
+                let mask = build_combined_mask(shard_columns, plan.filters.as_ref(), row_count);
+
+                record_rows(plan, row_count, mask.count());
 
                 // Early exit if no rows match
                 if mask.none() {
@@ -224,8 +647,8 @@ fn execute_simd_aggregation(
                 let stats: Vec<AggregateStats> = projections
                     .iter()
                     .map(|proj| {
-                        if let ProjectionPlan::Aggregate { column, .. } = proj {
-                            if let Some(col_name) = column {
+                        if let ProjectionPlan::Aggregate { argument, .. } = proj {
+                            if let Some(col_name) = argument.as_ref().and_then(ScalarExpr::as_column) {
                                 if let Some(col) = shard_columns.get(col_name) {
                                     if let Some(ref indices) = matching_indices {
                                         col.aggregate_stats_filtered(indices)
@@ -241,8 +664,7 @@ fn execute_simd_aggregation(
                                 AggregateStats {
                                     sum: count as f64,
                                     count,
-                                    min: None,
-                                    max: None,
+                                    ..Default::default()
                                 }
                             }
                         } else {
@@ -256,6 +678,7 @@ fn execute_simd_aggregation(
             })
         })
         .collect();
+    check_control(plan)?;
 
     // Merge stats from all shards
     let mut merged_stats: Vec<AggregateStats> = projections.iter().map(|_| AggregateStats::default()).collect();
@@ -280,6 +703,12 @@ fn execute_simd_aggregation(
                     AggregateFunction::Avg => stats.avg().map(Value::Float64).unwrap_or(Value::Null),
                     AggregateFunction::Min => stats.min.map(Value::Float64).unwrap_or(Value::Null),
                     AggregateFunction::Max => stats.max.map(Value::Float64).unwrap_or(Value::Null),
+                    AggregateFunction::StdDev => {
+                        stats.variance().map(|v| Value::Float64(v.sqrt())).unwrap_or(Value::Null)
+                    }
+                    AggregateFunction::Variance => {
+                        stats.variance().map(Value::Float64).unwrap_or(Value::Null)
+                    }
                     _ => Value::Null,
                 }
             } else {
@@ -288,17 +717,17 @@ fn execute_simd_aggregation(
         })
         .collect();
 
-    Ok((columns, vec![row], rows_scanned))
+    Ok((columns, vec![row], rows_scanned, None))
 }
 
-fn expand_wildcards(projections: &[ProjectionPlan], table: &Table) -> Vec<ProjectionPlan> {
+fn expand_wildcards(projections: &[ProjectionPlan], columns: &[String]) -> Vec<ProjectionPlan> {
     let mut result = Vec::new();
 
     for proj in projections {
         if let ProjectionPlan::Column { name, .. } = proj {
             if name == "*" {
                 // Expand to all columns in the table
-                for col_name in table.get_schema().keys() {
+                for col_name in columns {
                     result.push(ProjectionPlan::Column {
                         name: col_name.clone(),
                         output_name: col_name.clone(),
@@ -318,99 +747,295 @@ fn execute_scan(
     shards: &[Arc<Shard>],
     plan: &QueryPlan,
     projections: &[ProjectionPlan],
-) -> Result<(Vec<String>, Vec<Vec<Value>>, usize), ExecuteError> {
+) -> Result<ExecutedRows, ExecuteError> {
     // Column names for result
-    let columns: Vec<String> = projections
+    let columns: Vec<String> = projections.iter().map(|p| p.output_name().to_string()).collect();
+
+    // A cursor resumes after the previous page's last row, skipping the
+    // shards before it
+    let mut shards: Vec<&Arc<Shard>> = shards
         .iter()
-        .map(|p| match p {
-            ProjectionPlan::Column { output_name, .. } => output_name.clone(),
-            ProjectionPlan::TimeBucket { output_name, .. } => output_name.clone(),
-            ProjectionPlan::Aggregate { output_name, .. } => output_name.clone(),
-        })
+        .filter(|shard| plan.cursor.is_none_or(|c| shard.start_time >= c.shard_start))
         .collect();
 
-    // Parallel shard processing with predicate pushdown
-    let partial_results: Vec<_> = shards
-        .par_iter()
-        .map(|shard| {
-            shard.with_columns(|shard_columns| {
-                let row_count = shard.row_count();
+    // Rows needed for LIMIT and OFFSET. With ORDER BY, each shard keeps
+    // only its best rows.
+    let wanted = plan.limit.map(|limit| limit + plan.offset.unwrap_or(0));
+    let order = RowOrder::new(&columns, &plan.order_by);
+    let top = wanted.filter(|_| !plan.order_by.is_empty());
+
+    // Shards hold disjoint time ranges, so when rows are ordered by
+    // timestamp, the first shards in that order hold the first rows.
+    // Shards are then scanned in batches of doubling size until enough rows
+    // are found: one more than needed without ORDER BY, to tell whether
+    // there is a next page.
+    let enough = match (wanted, plan.order_by.is_empty(), timestamp_order(plan, projections)) {
+        (Some(wanted), true, _) => Some(wanted + 1),
+        (Some(wanted), false, Some(descending)) => {
+            if descending {
+                shards.reverse();
+            }
+            Some(wanted)
+        }
+        _ => None,
+    };
+    let mut batch_size = match enough {
+        Some(_) => 1,
+        None => shards.len(),
+    };
 
-                // Use predicate pushdown to build a row mask
-                let mask = build_combined_mask(shard_columns, &plan.filters, row_count);
+    let mut rows = Vec::new();
+    let mut positions = Vec::new();
+    let mut rows_scanned = 0;
+    let mut remaining = shards.as_slice();
+    while !remaining.is_empty() {
+        let (batch, rest) = remaining.split_at(batch_size.min(remaining.len()));
+        remaining = rest;
+        batch_size *= 2;
+
+        // Parallel shard processing with predicate pushdown
+        let partial_results: Vec<_> = batch
+            .par_iter()
+            .map(|shard| scan_shard(shard, plan, projections, top.map(|n| (&order, n))))
+            .collect();
+
+        // Merge results from all shards
+        for (shard, (local_rows, local_positions, scanned)) in batch.iter().zip(partial_results) {
+            rows.extend(local_rows);
+            positions.extend(local_positions.into_iter().map(|row| (shard.start_time, row)));
+            rows_scanned += scanned;
+        }
+        check_control(plan)?;
+        if wanted.is_none() {
+            check_output_rows(plan, rows.len())?;
+        }
+        if enough.is_some_and(|enough| rows.len() >= enough) {
+            break;
+        }
+    }
 
-                // Early exit if no rows match
-                if mask.none() {
-                    return (Vec::new(), row_count);
-                }
+    let next_cursor = match wanted {
+        Some(wanted) if wanted > 0 && rows.len() > wanted && plan.order_by.is_empty() => {
+            rows.truncate(wanted);
+            let (shard_start, row) = positions[wanted - 1];
+            Some(ScanCursor {
+                shard_start,
+                row: row + 1,
+            })
+        }
+        _ => None,
+    };
 
-                // Only process matching rows
-                let local_rows: Vec<Vec<Value>> = if mask.all() {
-                    // All rows match - no filtering needed
-                    (0..row_count)
-                        .map(|row_idx| {
-                            projections
-                                .iter()
-                                .map(|p| project_value_unlocked(shard_columns, row_idx, p))
-                                .collect()
-                        })
-                        .collect()
-                } else {
-                    // Use the mask to iterate only matching rows
-                    mask.indices()
-                        .into_iter()
-                        .map(|row_idx| {
-                            projections
-                                .iter()
-                                .map(|p| project_value_unlocked(shard_columns, row_idx, p))
-                                .collect()
-                        })
-                        .collect()
-                };
+    Ok((columns, rows, rows_scanned, next_cursor))
+}
 
-                (local_rows, row_count)
-            })
+/// Whether the scan is ordered by timestamp first, and if so descending
+fn timestamp_order(plan: &QueryPlan, projections: &[ProjectionPlan]) -> Option<bool> {
+    let first = plan.order_by.first()?;
+    projections
+        .iter()
+        .any(|p| {
+            matches!(p, ProjectionPlan::Column { name, output_name }
+                if name == "timestamp" && *output_name == first.column)
         })
-        .collect();
+        .then_some(first.descending)
+}
 
-    // Merge results from all shards
-    let mut rows = Vec::new();
-    let mut rows_scanned = 0;
-    for (local_rows, local_scanned) in partial_results {
-        rows.extend(local_rows);
-        rows_scanned += local_scanned;
-    }
+/// Projected rows of one shard that match the filters, their positions in
+/// the shard, and the number of rows scanned. With `top`, only the best
+/// rows in that order are kept, and positions are not returned.
+fn scan_shard(
+    shard: &Shard,
+    plan: &QueryPlan,
+    projections: &[ProjectionPlan],
+    top: Option<(&RowOrder, usize)>,
+) -> (Vec<Vec<Value>>, Vec<usize>, usize) {
+    let first_row = match plan.cursor {
+        Some(cursor) if cursor.shard_start == shard.start_time => cursor.row,
+        _ => 0,
+    };
+
+    shard.with_columns(|shard_columns| {
+        if !start_shard(plan, shard_columns) {
+            return (Vec::new(), Vec::new(), 0);
+        }
+        let row_count = shard.row_count();
 
-    Ok((columns, rows, rows_scanned))
+        // Use predicate pushdown to build a row mask
+        let mask = build_combined_mask(shard_columns, plan.filters.as_ref(), row_count);
+
+        // Early exit if no rows match
+        if mask.none() || first_row >= row_count {
+            record_rows(plan, row_count.saturating_sub(first_row), 0);
+            return (Vec::new(), Vec::new(), row_count);
+        }
+
+        // Only process matching rows
+        let matching_rows: Vec<usize> = if mask.all() {
+            (first_row..row_count).collect()
+        } else {
+            mask.indices().into_iter().filter(|&i| i >= first_row).collect()
+        };
+        record_rows(plan, row_count - first_row, matching_rows.len());
+
+        let mut expressions = bind_projection_exprs(projections, shard_columns);
+        let local_rows = matching_rows
+            .iter()
+            .enumerate()
+            .take_while(|(i, _)| i % ROW_BATCH != 0 || !is_stopped(plan))
+            .map(|(_, &row_idx)| {
+                projections
+                    .iter()
+                    .zip(expressions.iter_mut())
+                    .map(|(p, expr)| match expr {
+                        Some(expr) => expr.evaluate(row_idx),
+                        None => project_value_unlocked(shard_columns, row_idx, p),
+                    })
+                    .collect::<Vec<Value>>()
+            });
+
+        match top {
+            Some((order, limit)) => {
+                let mut top = TopN::new(order, limit);
+                local_rows.for_each(|row| top.push(row));
+                (top.into_sorted(), Vec::new(), row_count)
+            }
+            None => {
+                let local_rows = local_rows.collect();
+                (local_rows, matching_rows, row_count)
+            }
+        }
+    })
 }
+
 fn execute_aggregation(
     shards: &[Arc<Shard>],
     plan: &QueryPlan,
     projections: &[ProjectionPlan],
-) -> Result<(Vec<String>, Vec<Vec<Value>>, usize), ExecuteError> {
-    let columns: Vec<String> = projections
-        .iter()
-        .map(|p| match p {
-            ProjectionPlan::Column { output_name, .. } => output_name.clone(),
-            ProjectionPlan::TimeBucket { output_name, .. } => output_name.clone(),
-            ProjectionPlan::Aggregate { output_name, .. } => output_name.clone(),
+) -> Result<ExecutedRows, ExecuteError> {
+    let columns: Vec<String> = projections.iter().map(|p| p.output_name().to_string()).collect();
+
+    let (groups, rows_scanned) = aggregate_groups(shards, plan, projections)?;
+
+    // Build result rows
+    let rows: Vec<Vec<Value>> = groups
+        .into_values()
+        .map(|(group_values, accumulators)| {
+            let aggregates = accumulators.iter().map(|acc| acc.result()).collect();
+            assemble_row(projections, &group_values, aggregates)
         })
         .collect();
 
+    Ok((columns, rows, rows_scanned, None))
+}
+
+/// Group values and one accumulator per aggregate projection
+pub type GroupStates = (Vec<Value>, Vec<Box<dyn Accumulator>>);
+
+/// Grouped accumulator states before they are finalized into rows.
+/// Cluster nodes return these so the coordinator can merge them exactly.
+pub struct PartialAggregation {
+    /// Output column names, including HAVING-only aggregates
+    pub columns: Vec<String>,
+    pub groups: Vec<GroupStates>,
+    pub rows_scanned: usize,
+    pub shards_scanned: usize,
+}
+
+/// Run the aggregation part of a plan without finalizing it. HAVING,
+/// ORDER BY and LIMIT are left to whoever merges the partial states.
+pub fn execute_partial_aggregation(
+    engine: &StorageEngine,
+    plan: &QueryPlan,
+) -> Result<PartialAggregation, ExecuteError> {
+    let tables = resolve_tables(engine, &plan.table)?;
+    let columns = if is_glob(&plan.table) {
+        wildcard_columns(&tables)
+    } else {
+        tables[0].column_names()
+    };
+    let projections = expand_wildcards(&plan.projections, &wildcard(plan, columns));
+    let columns = projections.iter().map(|p| p.output_name().to_string()).collect();
+
+    let (groups, rows_scanned, shards_scanned) = aggregate_tables(&tables, plan, &projections)?;
+
+    Ok(PartialAggregation {
+        columns,
+        groups: groups.into_values().collect(),
+        rows_scanned,
+        shards_scanned,
+    })
+}
+
+/// Build an output row from a group's values and its aggregate results,
+/// following the projection order
+pub fn assemble_row(
+    projections: &[ProjectionPlan],
+    group_values: &[Value],
+    aggregates: Vec<Value>,
+) -> Vec<Value> {
+    let mut row = Vec::with_capacity(projections.len());
+    let mut aggregates = aggregates.into_iter();
+    let mut group_idx = 0;
+
+    for proj in projections {
+        match proj {
+            ProjectionPlan::Column { .. }
+            | ProjectionPlan::TimeBucket { .. }
+            | ProjectionPlan::Expression { .. } => {
+                // Find the value in group_values
+                row.push(group_values.get(group_idx).cloned().unwrap_or(Value::Null));
+                group_idx += 1;
+            }
+            ProjectionPlan::Aggregate { .. } => {
+                row.push(aggregates.next().unwrap_or(Value::Null));
+            }
+            // Filled in below, once every column it may refer to is known
+            ProjectionPlan::AggregateExpression { .. } => row.push(Value::Null),
+            // Computed across rows after aggregation
+            ProjectionPlan::Window { .. } => row.push(Value::Null),
+        }
+    }
+
+    for (idx, proj) in projections.iter().enumerate() {
+        if let ProjectionPlan::AggregateExpression { expr, .. } = proj {
+            row[idx] = expr.evaluate(&|name| {
+                projections
+                    .iter()
+                    .position(|p| p.output_name() == name)
+                    .map(|i| row[i].clone())
+                    .unwrap_or(Value::Null)
+            });
+        }
+    }
+    row
+}
+
+/// Group keys mapped to their values and accumulators
+type GroupMap = FxHashMap<Vec<Value>, GroupStates>;
+
+fn aggregate_groups(
+    shards: &[Arc<Shard>],
+    plan: &QueryPlan,
+    projections: &[ProjectionPlan],
+) -> Result<(GroupMap, usize), ExecuteError> {
     // Parallel per-shard aggregation with predicate pushdown
     let partial_results: Vec<_> = shards
         .par_iter()
         .map(|shard| {
             shard.with_columns(|shard_columns| {
                 let row_count = shard.row_count();
-                let mut local_groups: FxHashMap<Vec<Value>, (Vec<Value>, Vec<Box<dyn Accumulator>>)> =
-                    FxHashMap::default();
+                let mut local_groups: GroupMap = FxHashMap::default();
+                if !start_shard(plan, shard_columns) {
+                    return (local_groups, 0);
+                }
 
                 // Use predicate pushdown to build a row mask
-                let mask = build_combined_mask(shard_columns, &plan.filters, row_count);
+                let mask = build_combined_mask(shard_columns, plan.filters.as_ref(), row_count);
 
                 // Early exit if no rows match
                 if mask.none() {
+                    record_rows(plan, row_count, 0);
                     return (local_groups, row_count);
                 }
 
@@ -420,11 +1045,89 @@ fn execute_aggregation(
                 } else {
                     mask.indices()
                 };
+                record_rows(plan, row_count, matching_rows.len());
+
+                // Distinct counts over dictionary-encoded strings hash each
+                // dictionary id once instead of decoding every row
+                let mut id_hashes: Vec<Option<StringIdHashes>> = projections
+                    .iter()
+                    .filter_map(|p| match p {
+                        ProjectionPlan::Aggregate {
+                            function: AggregateFunction::CountDistinct,
+                            argument: Some(ScalarExpr::Column(col)),
+                            ..
+                        } => Some(match shard_columns.get(col) {
+                            Some(Column::String { ids, dictionary }) => {
+                                Some((ids.as_slice(), dictionary.as_ref(), FxHashMap::default()))
+                            }
+                            _ => None,
+                        }),
+                        ProjectionPlan::Aggregate { .. } => Some(None),
+                        _ => None,
+                    })
+                    .collect();
+
+                // Conditional aggregates (COUNT_IF, SUM_IF, ...) only
+                // see rows set in their own mask
+                let condition_masks: Vec<_> = projections
+                    .iter()
+                    .filter_map(|p| match p {
+                        ProjectionPlan::Aggregate { filter, .. } => Some(
+                            filter
+                                .as_ref()
+                                .map(|f| build_combined_mask(shard_columns, Some(f), row_count)),
+                        ),
+                        _ => None,
+                    })
+                    .collect();
+
+                let mut group_exprs: Vec<Option<BoundExpr>> = plan
+                    .group_by
+                    .iter()
+                    .flat_map(|group_by| &group_by.columns)
+                    .map(|col| match col {
+                        GroupByColumnPlan::Expression(expr) => {
+                            Some(BoundExpr::new(expr, shard_columns))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let mut arguments: Vec<Option<BoundExpr>> = projections
+                    .iter()
+                    .filter_map(|p| match p {
+                        ProjectionPlan::Aggregate { argument, .. } => Some(
+                            argument
+                                .as_ref()
+                                .map(|argument| BoundExpr::new(argument, shard_columns)),
+                        ),
+                        _ => None,
+                    })
+                    .collect();
+                let mut keys: Vec<Option<BoundExpr>> = projections
+                    .iter()
+                    .filter_map(|p| match p {
+                        ProjectionPlan::Aggregate { key, .. } => {
+                            Some(key.as_ref().map(|key| BoundExpr::new(key, shard_columns)))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+
+                for (i, row_idx) in matching_rows.into_iter().enumerate() {
+                    if i % ROW_BATCH == 0
+                        && (check_groups(plan, local_groups.len()).is_err() || is_stopped(plan))
+                    {
+                        break;
+                    }
 
-                for row_idx in matching_rows {
                     // Compute group key
                     let group_key = if let Some(ref group_by) = plan.group_by {
-                        compute_group_key_unlocked(shard_columns, row_idx, group_by)
+                        compute_group_key_unlocked(
+                            shard_columns,
+                            row_idx,
+                            group_by,
+                            &mut group_exprs,
+                        )
                     } else {
                         vec![] // Single global group
                     };
@@ -435,8 +1138,8 @@ fn execute_aggregation(
                             let accs: Vec<Box<dyn Accumulator>> = projections
                                 .iter()
                                 .filter_map(|p| {
-                                    if let ProjectionPlan::Aggregate { function, column, .. } = p {
-                                        Some(create_accumulator(*function, column))
+                                    if let ProjectionPlan::Aggregate { function, argument, .. } = p {
+                                        Some(create_accumulator(*function, argument))
                                     } else {
                                         None
                                     }
@@ -448,13 +1151,35 @@ fn execute_aggregation(
                     // Accumulate values
                     let mut acc_idx = 0;
                     for proj in projections {
-                        if let ProjectionPlan::Aggregate { column, .. } = proj {
-                            let value = if let Some(col) = column {
-                                get_value_unlocked(shard_columns, row_idx, col)
+                        if let ProjectionPlan::Aggregate { .. } = proj {
+                            if let Some(condition) = &condition_masks[acc_idx] {
+                                if !condition.get(row_idx) {
+                                    acc_idx += 1;
+                                    continue;
+                                }
+                            }
+
+                            if let Some((ids, dictionary, cache)) = &mut id_hashes[acc_idx] {
+                                if let Some(id) = ids[row_idx] {
+                                    let hash = *cache.entry(id).or_insert_with(|| {
+                                        dictionary.with_string(id, hash_str).unwrap_or_default()
+                                    });
+                                    accumulators[acc_idx].accumulate_hash(hash);
+                                }
+                                acc_idx += 1;
+                                continue;
+                            }
+
+                            let value = if let Some(argument) = &mut arguments[acc_idx] {
+                                argument.evaluate(row_idx)
                             } else {
                                 Value::Int64(1) // COUNT(*)
                             };
-                            accumulators[acc_idx].accumulate(&value);
+                            match &mut keys[acc_idx] {
+                                Some(key) => accumulators[acc_idx]
+                                    .accumulate_keyed(&value, &key.evaluate(row_idx)),
+                                None => accumulators[acc_idx].accumulate(&value),
+                            }
                             acc_idx += 1;
                         }
                     }
@@ -466,70 +1191,41 @@ fn execute_aggregation(
         .collect();
 
     // Merge partial results from all shards
-    let mut groups: FxHashMap<Vec<Value>, (Vec<Value>, Vec<Box<dyn Accumulator>>)> =
-        FxHashMap::default();
+    let mut groups: GroupMap = FxHashMap::default();
     let mut rows_scanned = 0;
 
     for (local_groups, local_scanned) in partial_results {
         rows_scanned += local_scanned;
+        merge_groups(&mut groups, local_groups);
+        check_groups(plan, groups.len())?;
+    }
+    check_control(plan)?;
 
-        for (key, (group_values, local_accs)) in local_groups {
-            match groups.entry(key) {
-                std::collections::hash_map::Entry::Occupied(mut entry) => {
-                    // Merge accumulators
-                    let (_, existing_accs) = entry.get_mut();
-                    for (existing_acc, local_acc) in existing_accs.iter_mut().zip(local_accs.iter())
-                    {
-                        existing_acc.merge(local_acc.as_ref());
-                    }
-                }
-                std::collections::hash_map::Entry::Vacant(entry) => {
-                    entry.insert((group_values, local_accs));
+    Ok((groups, rows_scanned))
+}
+
+/// Merge groups into `groups`, combining the accumulators of equal keys
+fn merge_groups(groups: &mut GroupMap, local_groups: GroupMap) {
+    for (key, (group_values, local_accs)) in local_groups {
+        match groups.entry(key) {
+            std::collections::hash_map::Entry::Occupied(mut entry) => {
+                // Merge accumulators
+                let (_, existing_accs) = entry.get_mut();
+                for (existing_acc, local_acc) in existing_accs.iter_mut().zip(local_accs.iter()) {
+                    existing_acc.merge(local_acc.as_ref());
                 }
             }
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert((group_values, local_accs));
+            }
         }
     }
-
-    // Build result rows
-    let rows: Vec<Vec<Value>> = groups
-        .into_iter()
-        .map(|(_, (group_values, accumulators))| {
-            let mut row = Vec::new();
-            let mut acc_idx = 0;
-            let mut group_idx = 0;
-
-            for proj in projections {
-                match proj {
-                    ProjectionPlan::Column { .. } => {
-                        // Find the value in group_values
-                        if group_idx < group_values.len() {
-                            row.push(group_values[group_idx].clone());
-                            group_idx += 1;
-                        } else {
-                            row.push(Value::Null);
-                        }
-                    }
-                    ProjectionPlan::TimeBucket { .. } => {
-                        if group_idx < group_values.len() {
-                            row.push(group_values[group_idx].clone());
-                            group_idx += 1;
-                        } else {
-                            row.push(Value::Null);
-                        }
-                    }
-                    ProjectionPlan::Aggregate { .. } => {
-                        row.push(accumulators[acc_idx].result());
-                        acc_idx += 1;
-                    }
-                }
-            }
-            row
-        })
-        .collect();
-
-    Ok((columns, rows, rows_scanned))
 }
 
+/// A shard's dictionary ids for a string column, its dictionary, and the
+/// hashes computed so far per id
+type StringIdHashes<'a> = (&'a [Option<u32>], &'a StringDictionary, FxHashMap<u32, u64>);
+
 // Batch column access versions (avoids per-value lock acquisition)
 
 fn get_value_unlocked(columns: &HashMap<String, Column>, row_idx: usize, column: &str) -> Value {
@@ -539,6 +1235,20 @@ fn get_value_unlocked(columns: &HashMap<String, Column>, row_idx: usize, column:
         .unwrap_or(Value::Null)
 }
 
+/// Bind each expression projection to a shard; other projections are `None`
+fn bind_projection_exprs<'a>(
+    projections: &'a [ProjectionPlan],
+    columns: &'a HashMap<String, Column>,
+) -> Vec<Option<BoundExpr<'a>>> {
+    projections
+        .iter()
+        .map(|p| match p {
+            ProjectionPlan::Expression { expr, .. } => Some(BoundExpr::new(expr, columns)),
+            _ => None,
+        })
+        .collect()
+}
+
 fn project_value_unlocked(
     columns: &HashMap<String, Column>,
     row_idx: usize,
@@ -546,64 +1256,110 @@ fn project_value_unlocked(
 ) -> Value {
     match proj {
         ProjectionPlan::Column { name, .. } => get_value_unlocked(columns, row_idx, name),
-        ProjectionPlan::TimeBucket {
-            interval_ms,
-            column,
-            ..
-        } => {
+        ProjectionPlan::TimeBucket { bucket, column, .. } => {
             let ts = get_value_unlocked(columns, row_idx, column)
                 .as_i64()
                 .unwrap_or(0);
-            let bucket = (ts / interval_ms) * interval_ms;
-            Value::Timestamp(bucket)
+            Value::Timestamp(bucket.floor(ts))
+        }
+        ProjectionPlan::Expression { expr, .. } => {
+            expr.evaluate(&|name| get_value_unlocked(columns, row_idx, name))
         }
-        ProjectionPlan::Aggregate { .. } => {
+        ProjectionPlan::Aggregate { .. }
+        | ProjectionPlan::AggregateExpression { .. }
+        | ProjectionPlan::Window { .. } => {
             // Aggregates should not appear in non-aggregation queries
             Value::Null
         }
     }
 }
 
+/// `expressions` holds each GROUP BY expression bound to the shard
 fn compute_group_key_unlocked(
     columns: &HashMap<String, Column>,
     row_idx: usize,
     group_by: &GroupByPlan,
+    expressions: &mut [Option<BoundExpr>],
 ) -> Vec<Value> {
     group_by
         .columns
         .iter()
-        .map(|col| match col {
+        .zip(expressions.iter_mut())
+        .map(|(col, expr)| match col {
             GroupByColumnPlan::Column(name) => get_value_unlocked(columns, row_idx, name),
-            GroupByColumnPlan::TimeBucket { interval_ms, column } => {
+            GroupByColumnPlan::TimeBucket { bucket, column } => {
                 let ts = get_value_unlocked(columns, row_idx, column)
                     .as_i64()
                     .unwrap_or(0);
-                let bucket = (ts / interval_ms) * interval_ms;
-                Value::Timestamp(bucket)
+                Value::Timestamp(bucket.floor(ts))
             }
+            GroupByColumnPlan::Expression(_) => match expr {
+                Some(expr) => expr.evaluate(row_idx),
+                None => Value::Null,
+            },
         })
         .collect()
 }
 
-fn apply_order_by(rows: &mut Vec<Vec<Value>>, columns: &[String], order_by: &[OrderByPlan]) {
-    // Build column index map using FxHashMap for faster lookups
-    let col_indices: FxHashMap<&str, usize> = columns
-        .iter()
-        .enumerate()
-        .map(|(i, c)| (c.as_str(), i))
-        .collect();
+/// Apply HAVING, FILL, window functions, COMPARE TO, ORDER BY and LIMIT to aggregated rows,
+/// then drop the columns that were only computed for HAVING. `previous` holds
+/// the rows of COMPARE TO's previous period. The distributed coordinator
+/// calls this once partial results from every node are merged.
+pub fn apply_post_aggregation(
+    plan: &QueryPlan,
+    columns: &mut Vec<String>,
+    rows: &mut Vec<Vec<Value>>,
+    previous: Option<Vec<Vec<Value>>>,
+) {
+    // Apply HAVING
+    if let Some(having) = &plan.having {
+        rows.retain(|row| row_matches(having, columns, row));
+    }
 
-    rows.sort_by(|a, b| {
-        for ob in order_by {
-            if let Some(&idx) = col_indices.get(ob.column.as_str()) {
-                let cmp = a[idx].cmp(&b[idx]);
-                if cmp != std::cmp::Ordering::Equal {
-                    return if ob.descending { cmp.reverse() } else { cmp };
-                }
+    // Apply FILL, adding the buckets no rows fell into
+    if let Some(fill) = &plan.fill {
+        fill_rows(fill, columns.len(), rows);
+    }
+
+    // Apply window functions across the filled series
+    apply_windows(&plan.projections, columns, rows);
+
+    // Apply COMPARE TO, joining the previous period's rows
+    if let (Some(compare), Some(mut previous)) = (&plan.compare, previous) {
+        if let Some(fill) = plan.previous_period().and_then(|p| p.fill) {
+            fill_rows(&fill, columns.len(), &mut previous);
+        }
+        join_previous(compare, columns, rows, previous, plan.hidden_columns.len());
+    }
+
+    // Apply ORDER BY, keeping only the rows LIMIT needs
+    if !plan.order_by.is_empty() {
+        let order = RowOrder::new(columns, &plan.order_by);
+        match plan.limit {
+            Some(limit) => {
+                let wanted = limit + plan.offset.unwrap_or(0);
+                *rows = top_n(std::mem::take(rows), &order, wanted);
             }
+            None => rows.sort_by(|a, b| order.compare(a, b)),
+        }
+    }
+
+    // Apply OFFSET, then LIMIT
+    if let Some(offset) = plan.offset {
+        rows.drain(..offset.min(rows.len()));
+    }
+    if let Some(limit) = plan.limit {
+        rows.truncate(limit);
+    }
+
+    // Hidden columns are always planned last
+    if !plan.hidden_columns.is_empty() {
+        let visible = columns.len().saturating_sub(plan.hidden_columns.len());
+        columns.truncate(visible);
+        for row in rows.iter_mut() {
+            row.truncate(visible);
         }
-        std::cmp::Ordering::Equal
-    });
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -616,6 +1372,31 @@ pub enum ExecuteError {
 
     #[error("Execution error: {0}")]
     General(String),
+
+    #[error("Subquery {0}")]
+    InvalidSubquery(String),
+
+    #[error("Join: {0}")]
+    InvalidJoin(String),
+
+    #[error("Query cancelled")]
+    Cancelled,
+
+    #[error("Query timed out after {0} ms")]
+    Timeout(u64),
+
+    #[error("Query limit exceeded: {0}")]
+    LimitExceeded(String),
+}
+
+impl ExecuteError {
+    /// Whether the query was cancelled, ran out of time or went over a limit
+    pub fn is_stopped(&self) -> bool {
+        matches!(
+            self,
+            ExecuteError::Cancelled | ExecuteError::Timeout(_) | ExecuteError::LimitExceeded(_)
+        )
+    }
 }
 
 #[cfg(test)]
@@ -657,6 +1438,44 @@ mod tests {
         assert!(result.columns.contains(&"value".to_string()));
     }
 
+    #[test]
+    fn test_limit_offset_and_cursor_pages() {
+        // Three rows per hourly shard, over four shards
+        let engine = StorageEngine::new();
+        for i in 0..12 {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(i * 20 * 60 * 1000));
+            row.insert("value".to_string(), Value::Int64(i));
+            engine.insert("events", row).unwrap();
+        }
+        let run = |sql: &str, cursor: Option<ScanCursor>| {
+            let mut plan = plan_query(parse_query(sql).unwrap()).unwrap();
+            plan.cursor = cursor;
+            execute_query(&engine, &plan).unwrap()
+        };
+        let values = |result: &QueryResult| -> Vec<i64> {
+            result.rows.iter().map(|row| row[0].as_i64().unwrap()).collect()
+        };
+
+        let result = run("SELECT value FROM events LIMIT 3 OFFSET 4", None);
+        assert_eq!(values(&result), vec![4, 5, 6]);
+        let result = run("SELECT value FROM events ORDER BY value DESC LIMIT 2 OFFSET 1", None);
+        assert_eq!(values(&result), vec![10, 9]);
+
+        let sql = "SELECT value FROM events WHERE value != 4 LIMIT 5";
+        let first = run(sql, None);
+        assert_eq!(values(&first), vec![0, 1, 2, 3, 5]);
+        let cursor: ScanCursor = first.next_cursor.as_deref().unwrap().parse().unwrap();
+        let second = run(sql, Some(cursor));
+        assert_eq!(values(&second), vec![6, 7, 8, 9, 10]);
+        // The first shard is not read again
+        assert!(second.rows_scanned <= 9);
+        let cursor: ScanCursor = second.next_cursor.as_deref().unwrap().parse().unwrap();
+        let last = run(sql, Some(cursor));
+        assert_eq!(values(&last), vec![11]);
+        assert!(last.next_cursor.is_none());
+    }
+
     #[test]
     fn test_select_with_filter() {
         let engine = setup_test_engine();
@@ -752,4 +1571,711 @@ mod tests {
         // Timestamps 10000..20000 covers indices 10..20 = 10 rows
         assert_eq!(result.rows[0][0], Value::Int64(10));
     }
+
+    #[test]
+    fn test_or_filter() {
+        let engine = setup_test_engine();
+
+        let query = parse_query(
+            "SELECT COUNT(*) FROM events WHERE value < 10 OR (event = 'view' AND value >= 90)",
+        )
+        .unwrap();
+        let plan = plan_query(query).unwrap();
+        let result = execute_query(&engine, &plan).unwrap();
+
+        // 0..10 plus odd values in 90..100
+        assert_eq!(result.rows[0][0], Value::Int64(15));
+    }
+
+    #[test]
+    fn test_not_filter() {
+        let engine = setup_test_engine();
+
+        let query =
+            parse_query("SELECT COUNT(*) FROM events WHERE NOT (event = 'click' OR value >= 50)")
+                .unwrap();
+        let plan = plan_query(query).unwrap();
+        let result = execute_query(&engine, &plan).unwrap();
+
+        // Odd values below 50
+        assert_eq!(result.rows[0][0], Value::Int64(25));
+
+        // Rows without a value match neither a comparison nor its negation
+        for i in 0..10 {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(i * 1000));
+            row.insert("event".to_string(), Value::String("view".to_string()));
+            engine.insert("events", row).unwrap();
+        }
+        for (sql, expected) in [
+            ("SELECT COUNT(*) FROM events WHERE NOT (event = 'click' OR value >= 50)", 25),
+            ("SELECT COUNT(*) FROM events WHERE NOT (value >= 50)", 50),
+            // False for the new rows, since they are views
+            ("SELECT COUNT(*) FROM events WHERE NOT (value >= 50 AND event = 'click')", 85),
+            ("SELECT COUNT(*) FROM events WHERE NOT (value IS NULL)", 100),
+        ] {
+            let plan = plan_query(parse_query(sql).unwrap()).unwrap();
+            let result = execute_query(&engine, &plan).unwrap();
+            assert_eq!(result.rows[0][0], Value::Int64(expected), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_in_and_between_filters() {
+        let engine = setup_test_engine();
+
+        let query =
+            parse_query("SELECT COUNT(*) FROM events WHERE value IN (1, 2, 3, 500)").unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(result.rows[0][0], Value::Int64(3));
+
+        let query =
+            parse_query("SELECT COUNT(*) FROM events WHERE value BETWEEN 10 AND 19").unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(result.rows[0][0], Value::Int64(10));
+
+        let query =
+            parse_query("SELECT COUNT(*) FROM events WHERE event NOT IN ('click')").unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(result.rows[0][0], Value::Int64(50));
+    }
+
+    #[test]
+    fn test_order_by_limit_reads_newest_shards_first() {
+        // Three rows per hourly shard, over four shards
+        let engine = StorageEngine::new();
+        for i in 0..12 {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(i * 20 * 60 * 1000));
+            row.insert("value".to_string(), Value::Int64(i % 5));
+            engine.insert("events", row).unwrap();
+        }
+        let run = |sql: &str| {
+            let query = parse_query(sql).unwrap();
+            execute_query(&engine, &plan_query(query).unwrap()).unwrap()
+        };
+        let timestamps = |result: &QueryResult| -> Vec<i64> {
+            result.rows.iter().map(|row| row[0].as_i64().unwrap() / (20 * 60 * 1000)).collect()
+        };
+
+        let result = run("SELECT timestamp FROM events ORDER BY timestamp DESC LIMIT 2");
+        assert_eq!(timestamps(&result), vec![11, 10]);
+        assert_eq!(result.rows_scanned, 3);
+        let result = run("SELECT timestamp FROM events ORDER BY timestamp LIMIT 4 OFFSET 1");
+        assert_eq!(timestamps(&result), vec![1, 2, 3, 4]);
+        assert_eq!(result.rows_scanned, 9);
+
+        // Other orders read every shard; ties keep shard order
+        let result = run("SELECT timestamp, value FROM events ORDER BY value DESC LIMIT 3");
+        assert_eq!(timestamps(&result), vec![4, 9, 3]);
+        assert_eq!(result.rows_scanned, 12);
+    }
+
+    #[test]
+    fn test_in_filter_prunes_shards() {
+        let engine = StorageEngine::new();
+        let table = engine.get_or_create_table("logs");
+        for (i, service) in ["api", "auth", "billing"].iter().enumerate() {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(i as i64 * 3_600_000));
+            row.insert("service".to_string(), Value::String(service.to_string()));
+            table.insert_row(row).unwrap();
+        }
+
+        let query =
+            parse_query("SELECT COUNT(*) FROM logs WHERE service IN ('api', 'billing')").unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        assert_eq!(result.shards_scanned, 2);
+        assert_eq!(result.rows[0][0], Value::Int64(2));
+    }
+
+    #[test]
+    fn test_is_null_on_backfilled_columns() {
+        let engine = StorageEngine::new();
+        let table = engine.get_or_create_table("events");
+
+        // First two rows predate the user_id column, the last one omits it
+        for (ts, user) in [(1000, None), (2000, None), (3000, Some(7)), (4000, None)] {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(ts));
+            if let Some(user) = user {
+                row.insert("user_id".to_string(), Value::Int64(user));
+            }
+            table.insert_row(row).unwrap();
+        }
+        // A separate shard that never saw the column at all
+        let mut row = HashMap::new();
+        row.insert("timestamp".to_string(), Value::Timestamp(3_600_000));
+        table.insert_row(row).unwrap();
+
+        let query = parse_query("SELECT COUNT(*) FROM events WHERE user_id IS NULL").unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(result.rows[0][0], Value::Int64(4));
+
+        let query = parse_query("SELECT COUNT(*) FROM events WHERE user_id IS NOT NULL").unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(result.rows[0][0], Value::Int64(1));
+    }
+
+    #[test]
+    fn test_having() {
+        let engine = setup_test_engine();
+
+        // Buckets of 10 rows by time; only the later buckets have a large average
+        let query = parse_query(
+            "SELECT TIME_BUCKET('10 seconds', timestamp) AS bucket, COUNT(*) AS cnt, AVG(value) \
+             FROM events GROUP BY TIME_BUCKET('10 seconds', timestamp) \
+             HAVING AVG(value) > 60 AND cnt >= 10 ORDER BY bucket",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        assert_eq!(result.row_count(), 4);
+        assert_eq!(result.rows[0][0], Value::Timestamp(60_000));
+        assert!(result.rows.iter().all(|r| r[1] == Value::Int64(10)));
+    }
+
+    #[test]
+    fn test_having_aggregate_not_selected() {
+        let engine = setup_test_engine();
+
+        let query =
+            parse_query("SELECT event FROM events GROUP BY event HAVING MAX(value) = 99").unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        // The HAVING-only aggregate is computed but not returned
+        assert_eq!(result.columns, vec!["event".to_string()]);
+        assert_eq!(result.rows, vec![vec![Value::String("view".into())]]);
+    }
+
+    #[test]
+    fn test_having_applies_before_limit() {
+        let engine = setup_test_engine();
+
+        let query = parse_query(
+            "SELECT event, MIN(value) FROM events GROUP BY event \
+             HAVING MIN(value) > 0 ORDER BY event LIMIT 1",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        assert_eq!(result.row_count(), 1);
+        assert_eq!(result.rows[0][0], Value::String("view".into()));
+    }
+
+    #[test]
+    fn test_having_without_group_by() {
+        let engine = setup_test_engine();
+
+        let query = parse_query("SELECT COUNT(*) FROM events HAVING COUNT(*) > 1000").unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(result.row_count(), 0);
+    }
+
+    #[test]
+    fn test_count_distinct() {
+        let engine = StorageEngine::new();
+        let table = engine.get_or_create_table("views");
+
+        // Two shards, with users 5..10 appearing in both
+        for (hour, users) in [(0, 0..10), (1, 5..15)] {
+            for user in users {
+                for page in ["/home", "/docs"] {
+                    let mut row = HashMap::new();
+                    row.insert(
+                        "timestamp".to_string(),
+                        Value::Timestamp(hour * 3_600_000 + user),
+                    );
+                    row.insert("page".to_string(), Value::String(page.to_string()));
+                    row.insert("user_id".to_string(), Value::String(format!("u{}", user)));
+                    row.insert("user_num".to_string(), Value::Int64(user));
+                    table.insert_row(row).unwrap();
+                }
+            }
+        }
+
+        let query = parse_query(
+            "SELECT page, COUNT(DISTINCT user_id), APPROX_COUNT_DISTINCT(user_num) \
+             FROM views GROUP BY page ORDER BY page",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        assert_eq!(result.shards_scanned, 2);
+        assert_eq!(result.row_count(), 2);
+        for row in &result.rows {
+            assert_eq!(row[1], Value::Int64(15));
+            assert_eq!(row[2], Value::Int64(15));
+        }
+
+        let query = parse_query("SELECT COUNT(DISTINCT user_id) FROM views WHERE page = '/home'")
+            .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(result.rows[0][0], Value::Int64(15));
+    }
+
+    #[test]
+    fn test_percentile_quantile_argument() {
+        let engine = setup_test_engine();
+
+        let query = parse_query(
+            "SELECT event, PERCENTILE(value, 0.9), PERCENTILE(value, 99.5) FROM events \
+             GROUP BY event ORDER BY event",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        assert_eq!(result.columns[1], "p90_value");
+        assert_eq!(result.columns[2], "p99.5_value");
+
+        // click holds the even values 0..98
+        let Value::Float64(p90) = result.rows[0][1] else {
+            panic!("Expected float result");
+        };
+        assert!((p90 - 88.0).abs() / 88.0 <= 0.01);
+        assert_eq!(result.rows[0][2], Value::Float64(98.0));
+    }
+
+    #[test]
+    fn test_statistical_aggregates() {
+        let engine = setup_test_engine();
+
+        let query = parse_query(
+            "SELECT event, STDDEV(value), LAST(value), FIRST(latency), ARG_MAX(value, latency), \
+             MEDIAN(value) FROM events GROUP BY event ORDER BY event",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(
+            result.columns[1..],
+            ["stddev_value", "last_value", "first_latency", "arg_max_value_latency", "median_value"]
+        );
+
+        // click holds the even values 0..98, at timestamps in the same order
+        let click = &result.rows[0];
+        let Value::Float64(stddev) = click[1] else {
+            panic!("Expected float result");
+        };
+        assert!((stddev - 850f64.sqrt()).abs() < 1e-9);
+        assert_eq!(click[2..5], [Value::Int64(98), Value::Float64(0.0), Value::Int64(98)]);
+        let Value::Float64(median) = click[5] else {
+            panic!("Expected float result");
+        };
+        // Estimated like P50, which picks the upper of the two middle values
+        assert!((median - 50.0).abs() / 50.0 <= 0.01);
+
+        // Without GROUP BY, STDDEV and VARIANCE take the SIMD path
+        let query = parse_query("SELECT VARIANCE(value), STDDEV(latency) FROM events").unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        let [Value::Float64(variance), Value::Float64(stddev)] = result.rows[0][..] else {
+            panic!("Expected float results");
+        };
+        assert!((variance - 10100.0 / 12.0).abs() < 1e-6);
+        assert!((stddev - (1.5 * 1.5 * 10100.0 / 12.0f64).sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_or_filter_keeps_bloom_pruned_branches() {
+        let engine = StorageEngine::new();
+        let table = engine.get_or_create_table("logs");
+        for (ts, service) in [(1000, "api"), (3_600_000 + 1000, "auth")] {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(ts));
+            row.insert("service".to_string(), Value::String(service.to_string()));
+            table.insert_row(row).unwrap();
+        }
+
+        let query =
+            parse_query("SELECT COUNT(*) FROM logs WHERE service = 'api' OR service = 'auth'")
+                .unwrap();
+        let plan = plan_query(query).unwrap();
+        let result = execute_query(&engine, &plan).unwrap();
+
+        assert_eq!(result.shards_scanned, 2);
+        assert_eq!(result.rows[0][0], Value::Int64(2));
+    }
+
+    #[test]
+    fn test_arithmetic_projection_and_filter() {
+        let engine = setup_test_engine();
+
+        let query = parse_query(
+            "SELECT value, value * 2 + 1 AS doubled FROM events \
+             WHERE value - 90 >= 5 ORDER BY value",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        assert_eq!(result.columns, vec!["value", "doubled"]);
+        assert_eq!(result.row_count(), 5);
+        assert_eq!(result.rows[0], vec![Value::Int64(95), Value::Int64(191)]);
+        assert_eq!(result.rows[4], vec![Value::Int64(99), Value::Int64(199)]);
+    }
+
+    #[test]
+    fn test_expressions_over_and_inside_aggregates() {
+        let engine = setup_test_engine();
+
+        let query = parse_query(
+            "SELECT event, SUM(value) / 10 AS tens, AVG(latency - value) FROM events \
+             GROUP BY event ORDER BY event",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        // The hidden SUM(value) is not returned
+        assert_eq!(result.columns.len(), 3);
+        // latency - value is half of value; clicks are the even values
+        assert_eq!(
+            result.rows[0],
+            vec![
+                Value::String("click".into()),
+                Value::Float64(245.0),
+                Value::Float64(24.5)
+            ]
+        );
+        assert_eq!(result.rows[1][1], Value::Float64(250.0));
+    }
+
+    #[test]
+    fn test_group_by_expression_alias() {
+        let engine = setup_test_engine();
+
+        let query = parse_query(
+            "SELECT value - value % 25 AS quarter, COUNT(*) FROM events \
+             GROUP BY quarter ORDER BY quarter",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        assert_eq!(result.row_count(), 4);
+        for (i, row) in result.rows.iter().enumerate() {
+            assert_eq!(row, &vec![Value::Int64(i as i64 * 25), Value::Int64(25)]);
+        }
+    }
+
+    #[test]
+    fn test_string_functions() {
+        let engine = StorageEngine::new();
+        let requests = [
+            ("/api/users", "GET", "ok user=17"),
+            ("/api/orders", "post", "ok user=17"),
+            ("/checkout/cart", "GET", "ok user=42"),
+            ("/checkout/pay", "get", "failed"),
+        ];
+        for (i, (page, method, message)) in requests.iter().enumerate() {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(i as i64));
+            row.insert("page".to_string(), Value::String(page.to_string()));
+            row.insert("method".to_string(), Value::String(method.to_string()));
+            row.insert("message".to_string(), Value::String(message.to_string()));
+            engine.insert("logs", row).unwrap();
+        }
+
+        let query = parse_query(
+            "SELECT SPLIT_PART(page, '/', 2) AS section, COUNT(*) FROM logs \
+             WHERE UPPER(method) = 'GET' GROUP BY section ORDER BY section",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::String("api".into()), Value::Int64(1)],
+                vec![Value::String("checkout".into()), Value::Int64(2)],
+            ]
+        );
+
+        let query = parse_query(
+            "SELECT REGEXP_EXTRACT(message, 'user=(\\d+)') AS user_id, COUNT(*) FROM logs \
+             WHERE REGEXP_EXTRACT(message, 'user=(\\d+)') IS NOT NULL \
+             GROUP BY REGEXP_EXTRACT(message, 'user=(\\d+)') ORDER BY user_id",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::String("17".into()), Value::Int64(2)],
+                vec![Value::String("42".into()), Value::Int64(1)],
+            ]
+        );
+
+        let query = parse_query(
+            "SELECT CONCAT(LOWER(method), ' ', SUBSTR(page, 2, 3)) AS label FROM logs \
+             WHERE page LIKE '/api%' ORDER BY label",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::String("get api".into())],
+                vec![Value::String("post api".into())],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_case_projection() {
+        let engine = setup_test_engine();
+
+        let query = parse_query(
+            "SELECT value, CASE WHEN value < 10 THEN 'low' WHEN value < 90 THEN 'mid' END AS size, \
+             CASE event WHEN 'click' THEN 1 ELSE 0 END AS is_click FROM events \
+             WHERE value IN (4, 51, 95) ORDER BY value",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        assert_eq!(result.columns, vec!["value", "size", "is_click"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Int64(4), Value::String("low".into()), Value::Int64(1)],
+                vec![Value::Int64(51), Value::String("mid".into()), Value::Int64(0)],
+                vec![Value::Int64(95), Value::Null, Value::Int64(0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_rate_from_sum_case() {
+        let engine = setup_test_engine();
+
+        let query = parse_query(
+            "SELECT SUM(CASE WHEN value >= 80 THEN 1 ELSE 0 END) / COUNT(*) AS error_rate \
+             FROM events",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        assert_eq!(result.columns, vec!["error_rate"]);
+        assert_eq!(result.rows, vec![vec![Value::Float64(0.2)]]);
+    }
+
+    #[test]
+    fn test_error_rate_from_count_if() {
+        let engine = setup_test_engine();
+
+        // Both counts are integers, and the ratio still isn't truncated to 0
+        let query = parse_query(
+            "SELECT event, COUNT_IF(value >= 80) / COUNT(*) AS error_rate FROM events \
+             GROUP BY event ORDER BY event",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::String("click".into()), Value::Float64(0.2)],
+                vec![Value::String("view".into()), Value::Float64(0.2)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conditional_aggregates() {
+        let engine = setup_test_engine();
+
+        let query = parse_query(
+            "SELECT event, COUNT_IF(value >= 50) AS late, SUM_IF(value, value < 10), \
+             COUNT(*) FROM events GROUP BY event ORDER BY event",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        // The conditions only narrow their own aggregate, not the group
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![
+                    Value::String("click".into()),
+                    Value::Int64(25),
+                    Value::Float64(20.0),
+                    Value::Int64(50)
+                ],
+                vec![
+                    Value::String("view".into()),
+                    Value::Int64(25),
+                    Value::Float64(25.0),
+                    Value::Int64(50)
+                ],
+            ]
+        );
+
+        // Without GROUP BY conditional aggregates skip the SIMD path
+        let query = parse_query("SELECT COUNT_IF(event = 'click'), COUNT(*) FROM events").unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int64(50), Value::Int64(100)]]);
+    }
+
+    #[test]
+    fn test_time_bucket_in_time_zone() {
+        let engine = StorageEngine::new();
+        // Hourly events around the start of DST in Berlin, 2024-03-31
+        let start = 1711836000000; // 2024-03-30T22:00:00Z
+        for hour in 0..26 {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(start + hour * 3600 * 1000));
+            engine.insert("events", row).unwrap();
+        }
+
+        let query = parse_query(
+            "SELECT TIME_BUCKET('1 day', timestamp, 'Europe/Berlin') AS day, COUNT(*) \
+             FROM events GROUP BY day ORDER BY day",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        // March 30th ends at 23:00 UTC, and March 31st is only 23 hours long
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Timestamp(start - 23 * 3600 * 1000), Value::Int64(1)],
+                vec![Value::Timestamp(start + 3600 * 1000), Value::Int64(23)],
+                vec![Value::Timestamp(start + 24 * 3600 * 1000), Value::Int64(2)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fill_missing_buckets_per_series() {
+        let engine = StorageEngine::new();
+        // web-1 reports at minutes 0, 1 and 4; web-2 only at minute 2
+        let points = [("web-1", 0, 10), ("web-1", 1, 20), ("web-1", 4, 50), ("web-2", 2, 7)];
+        for (host, minute, value) in points {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(minute * 60_000));
+            row.insert("host".to_string(), Value::String(host.into()));
+            row.insert("value".to_string(), Value::Int64(value));
+            engine.insert("metrics", row).unwrap();
+        }
+
+        let run = |fill: &str| {
+            let query = parse_query(&format!(
+                "SELECT host, TIME_BUCKET('1 minute', timestamp) AS minute, MAX(value) FROM metrics \
+                 WHERE timestamp >= 0 AND timestamp < 300000 GROUP BY host, minute {} \
+                 ORDER BY host, minute",
+                fill
+            ))
+            .unwrap();
+            let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+            result.rows.iter().map(|row| row[2].clone()).collect::<Vec<_>>()
+        };
+
+        let (null, int, float) = (Value::Null, Value::Int64, Value::Float64);
+        assert_eq!(
+            run("FILL(0)"),
+            vec![
+                float(10.0), float(20.0), int(0), int(0), float(50.0),
+                int(0), int(0), float(7.0), int(0), int(0),
+            ]
+        );
+        assert_eq!(run("FILL(NULL)")[2..5], [null.clone(), null.clone(), float(50.0)]);
+        assert_eq!(
+            run("FILL(PREVIOUS)")[5..],
+            [null.clone(), null.clone(), float(7.0), float(7.0), float(7.0)]
+        );
+        // Interpolated between rows; a series' ends have nothing to interpolate from
+        let linear = run("FILL(LINEAR)");
+        assert_eq!(linear[2..4], [float(30.0), float(40.0)]);
+        assert_eq!(linear[5..], [null.clone(), null.clone(), float(7.0), null.clone(), null]);
+    }
+
+    #[test]
+    fn test_window_functions_per_series() {
+        let engine = StorageEngine::new();
+        // A counter per host, reported once a minute; web-1 restarts at minute 3
+        let points = [
+            ("web-1", 0, 100), ("web-1", 1, 160), ("web-1", 2, 280), ("web-1", 3, 30),
+            ("web-2", 0, 10), ("web-2", 2, 70),
+        ];
+        for (host, minute, requests) in points {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(minute * 60_000));
+            row.insert("host".to_string(), Value::String(host.into()));
+            row.insert("requests".to_string(), Value::Int64(requests));
+            engine.insert("metrics", row).unwrap();
+        }
+
+        let query = parse_query(
+            "SELECT host, TIME_BUCKET('1 minute', timestamp) AS minute, MAX(requests) AS total, \
+             RATE(total), DELTA(MAX(requests)) AS delta, LAG(total, 2), \
+             RUNNING_SUM(total) OVER (ORDER BY minute) AS running \
+             FROM metrics GROUP BY host, minute ORDER BY host, minute",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        let column = |i: usize| result.rows.iter().map(|row| row[i].clone()).collect::<Vec<_>>();
+
+        let (null, int, float) = (Value::Null, Value::Int64, Value::Float64);
+        assert_eq!(result.columns.len(), 7);
+        // Requests per second; the restart counts from zero
+        assert_eq!(
+            column(3),
+            vec![null.clone(), float(1.0), float(2.0), float(0.5), null.clone(), float(0.5)]
+        );
+        assert_eq!(column(4)[..2], [null.clone(), int(60)]);
+        assert_eq!(
+            column(5),
+            vec![null.clone(), null.clone(), int(100), int(160), null.clone(), null]
+        );
+        // One partition across both hosts; rows in the same minute share a sum
+        assert_eq!(column(6), vec![int(110), int(270), int(620), int(650), int(110), int(620)]);
+    }
+
+    #[test]
+    fn test_compare_to_previous_period() {
+        let engine = StorageEngine::new();
+        let (hour, day) = (3_600_000, 86_400_000);
+        let requests = [
+            // Yesterday
+            (0, "/a"), (0, "/a"), (hour, "/b"),
+            // Today
+            (day, "/a"), (day, "/a"), (day, "/a"), (day + hour, "/a"), (day + hour, "/a"),
+            (day + hour, "/c"),
+        ];
+        for (ts, endpoint) in requests {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(ts));
+            row.insert("endpoint".to_string(), Value::String(endpoint.into()));
+            engine.insert("logs", row).unwrap();
+        }
+
+        let query = parse_query(
+            "SELECT endpoint, COUNT(*) AS hits FROM logs \
+             WHERE timestamp >= 86400000 AND timestamp < 172800000 GROUP BY endpoint \
+             COMPARE TO '1 day' ORDER BY hits_delta DESC",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+
+        assert_eq!(result.columns, vec!["endpoint", "hits", "hits_prev", "hits_delta"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::String("/a".into()), Value::Int64(5), Value::Int64(2), Value::Int64(3)],
+                vec![Value::String("/c".into()), Value::Int64(1), Value::Null, Value::Null],
+            ]
+        );
+
+        // Buckets are matched to the same hour of the previous day
+        let query = parse_query(
+            "SELECT TIME_BUCKET('1 hour', timestamp) AS hour, COUNT(*) AS hits FROM logs \
+             WHERE timestamp >= 86400000 AND timestamp < 172800000 GROUP BY hour \
+             COMPARE TO 1 day ago ORDER BY hour",
+        )
+        .unwrap();
+        let result = execute_query(&engine, &plan_query(query).unwrap()).unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Timestamp(day), Value::Int64(3), Value::Int64(2), Value::Int64(1)],
+                vec![Value::Timestamp(day + hour), Value::Int64(3), Value::Int64(1), Value::Int64(2)],
+            ]
+        );
+    }
 }
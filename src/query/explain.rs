@@ -0,0 +1,313 @@
+//! EXPLAIN and EXPLAIN ANALYZE
+//!
+//! `EXPLAIN SELECT ...` returns how the query would run instead of its rows:
+//! the time range, the execution path, the columns read and how many shards
+//! are left after each pruning step. `EXPLAIN ANALYZE` runs the query as
+//! well, and adds the rows read and filtered out, the time spent in each
+//! stage and, in cluster mode, what each node did.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::executor::{
+    execute_on_table, execute_query, execution_path, prune_on_table, prune_query, ExecuteError,
+    ExecutionPath, QueryResult,
+};
+use super::glob::is_glob;
+use super::planner::{QueryPlan, TimeRange};
+use crate::data::{Table, Value};
+use crate::storage::StorageEngine;
+
+/// What an EXPLAIN statement reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Explain {
+    /// The plan and its shard pruning, without running the query
+    Plan,
+    /// The plan, then the query's run
+    Analyze,
+}
+
+/// What a plan did while it ran. The executor fills it in when the plan
+/// carries one.
+#[derive(Debug, Default)]
+pub struct Profile {
+    shards_total: AtomicUsize,
+    shards_after_time: AtomicUsize,
+    shards_after_bloom: AtomicUsize,
+    shards_after_zone: AtomicUsize,
+    rows_read: AtomicUsize,
+    rows_matched: AtomicUsize,
+    /// Time spent in each stage, in the order stages first ran
+    stages: Mutex<Vec<(&'static str, f64)>>,
+}
+
+impl Profile {
+    /// Count a table's shards, and those left after pruning by time range,
+    /// bloom filters and zone maps
+    pub fn add_pruning(&self, total: usize, after_time: usize, after_bloom: usize, after: usize) {
+        self.shards_total.fetch_add(total, Ordering::Relaxed);
+        self.shards_after_time.fetch_add(after_time, Ordering::Relaxed);
+        self.shards_after_bloom.fetch_add(after_bloom, Ordering::Relaxed);
+        self.shards_after_zone.fetch_add(after, Ordering::Relaxed);
+    }
+
+    /// Count rows read from a shard and those that passed the filters
+    pub fn add_rows(&self, read: usize, matched: usize) {
+        self.rows_read.fetch_add(read, Ordering::Relaxed);
+        self.rows_matched.fetch_add(matched, Ordering::Relaxed);
+    }
+
+    /// Add the time since `start` to a stage
+    pub fn add_time(&self, stage: &'static str, start: Instant) {
+        let ms = start.elapsed().as_secs_f64() * 1000.0;
+        let mut stages = self.stages.lock();
+        match stages.iter_mut().find(|(name, _)| *name == stage) {
+            Some((_, total)) => *total += ms,
+            None => stages.push((stage, ms)),
+        }
+    }
+
+    pub fn summary(&self) -> ProfileSummary {
+        ProfileSummary {
+            shards_total: self.shards_total.load(Ordering::Relaxed),
+            shards_after_time: self.shards_after_time.load(Ordering::Relaxed),
+            shards_after_bloom: self.shards_after_bloom.load(Ordering::Relaxed),
+            shards_after_zone: self.shards_after_zone.load(Ordering::Relaxed),
+            rows_read: self.rows_read.load(Ordering::Relaxed),
+            rows_matched: self.rows_matched.load(Ordering::Relaxed),
+            stages: self
+                .stages
+                .lock()
+                .iter()
+                .map(|(name, ms)| (name.to_string(), *ms))
+                .collect(),
+        }
+    }
+}
+
+/// A profile's totals, as nodes send them to the coordinator
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileSummary {
+    pub shards_total: usize,
+    pub shards_after_time: usize,
+    pub shards_after_bloom: usize,
+    pub shards_after_zone: usize,
+    pub rows_read: usize,
+    pub rows_matched: usize,
+    /// Milliseconds spent in each stage
+    pub stages: Vec<(String, f64)>,
+}
+
+impl ProfileSummary {
+    /// Add another node's shard and row counts. Stage times stay per node.
+    pub fn add_counts(&mut self, other: &ProfileSummary) {
+        self.shards_total += other.shards_total;
+        self.shards_after_time += other.shards_after_time;
+        self.shards_after_bloom += other.shards_after_bloom;
+        self.shards_after_zone += other.shards_after_zone;
+        self.rows_read += other.rows_read;
+        self.rows_matched += other.rows_matched;
+    }
+
+    pub fn total_ms(&self) -> f64 {
+        self.stages.iter().map(|(_, ms)| ms).sum()
+    }
+}
+
+/// Explain a planned query on this node. It reads `table` if given, such
+/// as the result of a WITH query, and the storage engine otherwise.
+/// `start` is when parsing began.
+pub fn explain_local(
+    engine: &StorageEngine,
+    table: Option<&Table>,
+    mut plan: QueryPlan,
+    explain: Explain,
+    start: Instant,
+) -> Result<QueryResult, ExecuteError> {
+    let profile = Arc::new(Profile::default());
+    profile.add_time("plan", start);
+    plan.profile = Some(profile.clone());
+
+    // Tables read together are always aggregated the general way
+    let path = match execution_path(&plan, &plan.projections) {
+        ExecutionPath::SimdAggregation if table.is_none() && is_glob(&plan.table) => {
+            ExecutionPath::Aggregation
+        }
+        path => path,
+    };
+    let result = match (explain, table) {
+        (Explain::Plan, Some(table)) => prune_on_table(table, &plan).map(|_| None)?,
+        (Explain::Plan, None) => prune_query(engine, &plan).map(|_| None)?,
+        (Explain::Analyze, Some(table)) => Some(execute_on_table(table, &plan)?),
+        (Explain::Analyze, None) => Some(execute_query(engine, &plan)?),
+    };
+    Ok(explain_result(&plan, path, &profile.summary(), result.as_ref(), &[]))
+}
+
+/// EXPLAIN's result: one row per property of the plan and, with the result
+/// of running it, of the run. `nodes` are the profiles of each node of a
+/// cluster, by node id.
+pub fn explain_result(
+    plan: &QueryPlan,
+    path: ExecutionPath,
+    summary: &ProfileSummary,
+    result: Option<&QueryResult>,
+    nodes: &[(String, ProfileSummary)],
+) -> QueryResult {
+    let mut rows = vec![
+        row("table", Value::String(plan.table.clone())),
+        row("time_range", Value::String(format_time_range(plan.time_range.as_ref()))),
+        row("path", Value::String(path.name().to_string())),
+        row("required_columns", Value::String(plan.required_columns.join(", "))),
+    ];
+    if let Some(join) = &plan.join {
+        let kind = if join.left { "LEFT JOIN" } else { "JOIN" };
+        let description = format!(
+            "{} {} ON {} = {}",
+            kind, join.table, join.driving_column, join.lookup_column
+        );
+        rows.push(row("join", Value::String(description)));
+    }
+    rows.extend([
+        count("shards.total", summary.shards_total),
+        count("shards.after_time", summary.shards_after_time),
+        count("shards.after_bloom", summary.shards_after_bloom),
+        count("shards.after_zone", summary.shards_after_zone),
+    ]);
+
+    let Some(result) = result else {
+        return explain_rows(rows, 0, 0);
+    };
+    rows.extend([
+        count("rows.read", summary.rows_read),
+        count("rows.matched", summary.rows_matched),
+        count("rows.filtered", summary.rows_read.saturating_sub(summary.rows_matched)),
+        count("rows.returned", result.rows.len()),
+    ]);
+    for (stage, ms) in &summary.stages {
+        rows.push(row(&format!("time.{}_ms", stage), Value::Float64(*ms)));
+    }
+    rows.push(row("time.total_ms", Value::Float64(summary.total_ms())));
+    for (node, profile) in nodes {
+        rows.extend([
+            row(&format!("node.{}.time_ms", node), Value::Float64(profile.total_ms())),
+            count(&format!("node.{}.shards", node), profile.shards_after_zone),
+            count(&format!("node.{}.rows_read", node), profile.rows_read),
+            count(&format!("node.{}.rows_matched", node), profile.rows_matched),
+        ]);
+    }
+    explain_rows(rows, result.rows_scanned, result.shards_scanned)
+}
+
+fn explain_rows(rows: Vec<Vec<Value>>, rows_scanned: usize, shards_scanned: usize) -> QueryResult {
+    QueryResult {
+        columns: vec!["property".to_string(), "value".to_string()],
+        rows,
+        rows_scanned,
+        shards_scanned,
+        ..QueryResult::empty()
+    }
+}
+
+fn row(property: &str, value: Value) -> Vec<Value> {
+    vec![Value::String(property.to_string()), value]
+}
+
+fn count(property: &str, n: usize) -> Vec<Value> {
+    row(property, Value::Int64(n as i64))
+}
+
+/// `start..end` in epoch milliseconds, leaving out a side without a bound
+fn format_time_range(range: Option<&TimeRange>) -> String {
+    let bound = |bound: Option<i64>| bound.map(|b| b.to_string()).unwrap_or_default();
+    match range {
+        Some(range) => format!("{}..{}", bound(range.start), bound(range.end)),
+        None => "all".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::run_query_at;
+    use std::collections::HashMap;
+
+    fn setup_engine() -> StorageEngine {
+        // Ten one-hour shards, each with latencies i * 10 .. i * 10 + 9
+        let engine = StorageEngine::new();
+        for i in 0..100 {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(i / 10 * 3_600_000));
+            row.insert("latency".to_string(), Value::Int64(i));
+            row.insert("service".to_string(), Value::String(format!("s{}", i / 10)));
+            engine.insert("requests", row).unwrap();
+        }
+        engine
+    }
+
+    fn property<'a>(result: &'a QueryResult, name: &str) -> &'a Value {
+        let row = result.rows.iter().find(|row| row[0] == Value::String(name.into()));
+        &row.unwrap_or_else(|| panic!("no {}", name))[1]
+    }
+
+    #[test]
+    fn test_explain() {
+        let engine = setup_engine();
+        let result = run_query_at(
+            &engine,
+            "EXPLAIN SELECT COUNT(*) FROM requests \
+             WHERE timestamp >= 3600000 AND service = 's5' OR latency > 85",
+            0,
+        )
+        .unwrap();
+        assert_eq!(result.columns, vec!["property", "value"]);
+        assert_eq!(
+            property(&result, "path"),
+            &Value::String("execute_simd_aggregation".into())
+        );
+        assert_eq!(property(&result, "shards.total"), &Value::Int64(10));
+        assert_eq!(property(&result, "shards.after_time"), &Value::Int64(10));
+        // s5 can only be in shard 5, and latencies over 85 only in 8 and 9
+        assert_eq!(property(&result, "shards.after_bloom"), &Value::Int64(10));
+        assert_eq!(property(&result, "shards.after_zone"), &Value::Int64(3));
+        // Nothing ran
+        assert_eq!(result.rows_scanned, 0);
+        assert!(!result.rows.iter().any(|row| row[0] == Value::String("rows.read".into())));
+
+        let result = run_query_at(
+            &engine,
+            "EXPLAIN SELECT service, AVG(latency) FROM requests \
+             WHERE timestamp >= 7200000 AND service = 's5' GROUP BY service",
+            0,
+        )
+        .unwrap();
+        assert_eq!(property(&result, "path"), &Value::String("execute_aggregation".into()));
+        assert_eq!(property(&result, "time_range"), &Value::String("7200000..".into()));
+        assert_eq!(property(&result, "shards.after_time"), &Value::Int64(8));
+        assert_eq!(property(&result, "shards.after_bloom"), &Value::Int64(1));
+    }
+
+    #[test]
+    fn test_explain_analyze() {
+        let engine = setup_engine();
+        let result = run_query_at(
+            &engine,
+            "EXPLAIN ANALYZE SELECT service, latency FROM requests WHERE latency BETWEEN 15 AND 24",
+            0,
+        )
+        .unwrap();
+        assert_eq!(property(&result, "path"), &Value::String("execute_scan".into()));
+        assert_eq!(property(&result, "shards.after_zone"), &Value::Int64(2));
+        assert_eq!(property(&result, "rows.read"), &Value::Int64(20));
+        assert_eq!(property(&result, "rows.matched"), &Value::Int64(10));
+        assert_eq!(property(&result, "rows.filtered"), &Value::Int64(10));
+        assert_eq!(property(&result, "rows.returned"), &Value::Int64(10));
+        for stage in ["time.plan_ms", "time.prune_ms", "time.execute_ms", "time.total_ms"] {
+            assert!(matches!(property(&result, stage), Value::Float64(_)));
+        }
+        assert_eq!(result.rows_scanned, 20);
+    }
+}
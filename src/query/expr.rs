@@ -0,0 +1,481 @@
+//! Scalar expressions
+//!
+//! Arithmetic, CASE and function calls over columns and literals, evaluated
+//! once per row. The same expressions are evaluated over result rows after
+//! aggregation, where column references name output columns such as
+//! `sum_bytes`.
+
+use std::collections::HashMap;
+
+use fxhash::FxHashMap;
+
+use super::functions::ScalarFunction;
+use super::parser::FilterExpr;
+use super::predicate::matches;
+use crate::data::column::Column;
+use crate::data::Value;
+use crate::storage::StringDictionary;
+
+/// Scalar expression tree
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScalarExpr {
+    /// Column reference, resolved by name at evaluation time
+    Column(String),
+    /// Constant value
+    Literal(Value),
+    /// Unary minus
+    Negate(Box<ScalarExpr>),
+    /// Arithmetic on two operands
+    Binary {
+        op: ArithmeticOp,
+        left: Box<ScalarExpr>,
+        right: Box<ScalarExpr>,
+    },
+    /// CASE WHEN: the result of the first branch whose condition matches,
+    /// else `otherwise` (NULL if absent)
+    Case {
+        branches: Vec<(FilterExpr, ScalarExpr)>,
+        otherwise: Option<Box<ScalarExpr>>,
+    },
+    /// Scalar function call, e.g. SPLIT_PART(page, '/', 2)
+    Function {
+        function: ScalarFunction,
+        args: Vec<ScalarExpr>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl ScalarExpr {
+    /// Column name if this expression is a bare column reference
+    pub fn as_column(&self) -> Option<&str> {
+        match self {
+            ScalarExpr::Column(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Names of all columns the expression reads
+    pub fn columns(&self) -> Vec<&str> {
+        let mut columns = Vec::new();
+        self.collect_columns(&mut columns);
+        columns
+    }
+
+    pub(crate) fn collect_columns<'a>(&'a self, columns: &mut Vec<&'a str>) {
+        match self {
+            ScalarExpr::Column(name) => {
+                if !columns.contains(&name.as_str()) {
+                    columns.push(name);
+                }
+            }
+            ScalarExpr::Literal(_) => {}
+            ScalarExpr::Negate(inner) => inner.collect_columns(columns),
+            ScalarExpr::Binary { left, right, .. } => {
+                left.collect_columns(columns);
+                right.collect_columns(columns);
+            }
+            ScalarExpr::Case {
+                branches,
+                otherwise,
+            } => {
+                for (condition, result) in branches {
+                    condition.collect_columns(columns);
+                    result.collect_columns(columns);
+                }
+                if let Some(otherwise) = otherwise {
+                    otherwise.collect_columns(columns);
+                }
+            }
+            ScalarExpr::Function { args, .. } => {
+                for arg in args {
+                    arg.collect_columns(columns);
+                }
+            }
+        }
+    }
+
+    /// Evaluate the expression, looking up column values through `column`.
+    /// Any NULL operand makes the result NULL.
+    pub fn evaluate(&self, column: &dyn Fn(&str) -> Value) -> Value {
+        match self {
+            ScalarExpr::Column(name) => column(name),
+            ScalarExpr::Literal(value) => value.clone(),
+            ScalarExpr::Negate(inner) => negate(&inner.evaluate(column)),
+            ScalarExpr::Binary { op, left, right } => {
+                arithmetic(*op, &left.evaluate(column), &right.evaluate(column))
+            }
+            ScalarExpr::Case {
+                branches,
+                otherwise,
+            } => branches
+                .iter()
+                .find(|(condition, _)| matches(condition, column))
+                .map(|(_, result)| result)
+                .or(otherwise.as_deref())
+                .map(|result| result.evaluate(column))
+                .unwrap_or(Value::Null),
+            ScalarExpr::Function { function, args } => {
+                let args: Vec<Value> = args.iter().map(|arg| arg.evaluate(column)).collect();
+                function.call(&args)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ScalarExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScalarExpr::Column(name) => write!(f, "{}", name),
+            ScalarExpr::Literal(Value::String(s)) => write!(f, "'{}'", s),
+            ScalarExpr::Literal(value) => write!(f, "{}", value),
+            ScalarExpr::Negate(inner) => write!(f, "-{}", Operand(inner)),
+            ScalarExpr::Binary { op, left, right } => {
+                write!(f, "{} {} {}", Operand(left), op, Operand(right))
+            }
+            ScalarExpr::Case {
+                branches,
+                otherwise,
+            } => {
+                write!(f, "CASE")?;
+                for (condition, result) in branches {
+                    write!(f, " WHEN {} THEN {}", condition, result)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, " ELSE {}", otherwise)?;
+                }
+                write!(f, " END")
+            }
+            ScalarExpr::Function { function, args } => {
+                write!(f, "{}(", function.name())?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// An expression bound to one shard's columns.
+///
+/// Expressions that only read one dictionary-encoded string column, such as
+/// `SPLIT_PART(page, '/', 2)`, are evaluated once per dictionary id and the
+/// result reused for every other row with the same id.
+pub struct BoundExpr<'a> {
+    expr: &'a ScalarExpr,
+    columns: &'a HashMap<String, Column>,
+    memo: Option<(DictionaryInput<'a>, FxHashMap<Option<u32>, Value>)>,
+}
+
+impl<'a> BoundExpr<'a> {
+    pub fn new(expr: &'a ScalarExpr, columns: &'a HashMap<String, Column>) -> Self {
+        Self {
+            expr,
+            columns,
+            memo: dictionary_input(expr, columns).map(|input| (input, FxHashMap::default())),
+        }
+    }
+
+    pub fn evaluate(&mut self, row_idx: usize) -> Value {
+        match &mut self.memo {
+            Some((input, memo)) => {
+                let id = input.ids[row_idx];
+                memo.entry(id)
+                    .or_insert_with(|| input.evaluate(self.expr, id))
+                    .clone()
+            }
+            None => {
+                let columns = self.columns;
+                self.expr.evaluate(&|name| {
+                    columns.get(name).map(|c| c.get(row_idx)).unwrap_or(Value::Null)
+                })
+            }
+        }
+    }
+}
+
+/// The single dictionary-encoded column an expression reads
+pub struct DictionaryInput<'a> {
+    name: &'a str,
+    pub ids: &'a [Option<u32>],
+    dictionary: &'a StringDictionary,
+}
+
+impl DictionaryInput<'_> {
+    /// Evaluate `expr` for every row holding dictionary id `id`
+    pub fn evaluate(&self, expr: &ScalarExpr, id: Option<u32>) -> Value {
+        let value = id
+            .and_then(|id| self.dictionary.get_string(id))
+            .map(Value::String)
+            .unwrap_or(Value::Null);
+        expr.evaluate(&|name| {
+            if name == self.name {
+                value.clone()
+            } else {
+                Value::Null
+            }
+        })
+    }
+}
+
+/// Find the dictionary-encoded string column `expr` reads, if it reads
+/// exactly one and is more than a bare reference to it
+pub fn dictionary_input<'a>(
+    expr: &'a ScalarExpr,
+    columns: &'a HashMap<String, Column>,
+) -> Option<DictionaryInput<'a>> {
+    if expr.as_column().is_some() {
+        return None;
+    }
+    match expr.columns().as_slice() {
+        [name] => match columns.get(*name) {
+            Some(Column::String { ids, dictionary }) => Some(DictionaryInput {
+                name,
+                ids,
+                dictionary,
+            }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Displays nested arithmetic in parentheses
+struct Operand<'a>(&'a ScalarExpr);
+
+impl std::fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            ScalarExpr::Binary { .. } => write!(f, "({})", self.0),
+            expr => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl std::fmt::Display for ArithmeticOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            ArithmeticOp::Add => "+",
+            ArithmeticOp::Sub => "-",
+            ArithmeticOp::Mul => "*",
+            ArithmeticOp::Div => "/",
+            ArithmeticOp::Mod => "%",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+/// Negate a numeric value. Non-numeric values and overflow give NULL.
+pub fn negate(value: &Value) -> Value {
+    match value {
+        Value::Int64(i) => i.checked_neg().map(Value::Int64).unwrap_or(Value::Null),
+        Value::Float64(f) => Value::Float64(-f),
+        _ => Value::Null,
+    }
+}
+
+/// Apply an arithmetic operator.
+///
+/// Integers stay integers (division truncates) and any float operand makes
+/// the result a float. Subtracting timestamps gives milliseconds, and adding
+/// or subtracting milliseconds to a timestamp gives a timestamp. Division by
+/// zero, overflow and non-numeric operands give NULL.
+pub fn arithmetic(op: ArithmeticOp, left: &Value, right: &Value) -> Value {
+    match (left, right) {
+        (Value::Int64(a), Value::Int64(b)) => {
+            integer_arithmetic(op, *a, *b).unwrap_or(Value::Null)
+        }
+        (Value::Timestamp(a), Value::Timestamp(b)) => match op {
+            ArithmeticOp::Sub => a.checked_sub(*b).map(Value::Int64).unwrap_or(Value::Null),
+            _ => Value::Null,
+        },
+        (Value::Timestamp(ts), Value::Int64(ms)) | (Value::Int64(ms), Value::Timestamp(ts))
+            if op == ArithmeticOp::Add =>
+        {
+            ts.checked_add(*ms).map(Value::Timestamp).unwrap_or(Value::Null)
+        }
+        (Value::Timestamp(ts), Value::Int64(ms)) if op == ArithmeticOp::Sub => {
+            ts.checked_sub(*ms).map(Value::Timestamp).unwrap_or(Value::Null)
+        }
+        (Value::Float64(_), _) | (_, Value::Float64(_)) => {
+            match (left.as_f64(), right.as_f64()) {
+                (Some(a), Some(b)) => float_arithmetic(op, a, b)
+                    .map(Value::Float64)
+                    .unwrap_or(Value::Null),
+                _ => Value::Null,
+            }
+        }
+        _ => Value::Null,
+    }
+}
+
+fn integer_arithmetic(op: ArithmeticOp, a: i64, b: i64) -> Option<Value> {
+    match op {
+        ArithmeticOp::Add => a.checked_add(b).map(Value::Int64),
+        ArithmeticOp::Sub => a.checked_sub(b).map(Value::Int64),
+        ArithmeticOp::Mul => a.checked_mul(b).map(Value::Int64),
+        // Exact, so COUNT_IF(...) / COUNT(*) is a ratio rather than 0
+        ArithmeticOp::Div => float_arithmetic(op, a as f64, b as f64).map(Value::Float64),
+        ArithmeticOp::Mod => a.checked_rem(b).map(Value::Int64),
+    }
+}
+
+fn float_arithmetic(op: ArithmeticOp, a: f64, b: f64) -> Option<f64> {
+    match op {
+        ArithmeticOp::Add => Some(a + b),
+        ArithmeticOp::Sub => Some(a - b),
+        ArithmeticOp::Mul => Some(a * b),
+        ArithmeticOp::Div if b != 0.0 => Some(a / b),
+        ArithmeticOp::Mod if b != 0.0 => Some(a % b),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(op: ArithmeticOp, left: ScalarExpr, right: ScalarExpr) -> ScalarExpr {
+        ScalarExpr::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    fn column(name: &str) -> ScalarExpr {
+        ScalarExpr::Column(name.to_string())
+    }
+
+    #[test]
+    fn test_integer_and_float_arithmetic() {
+        assert_eq!(
+            arithmetic(ArithmeticOp::Div, &Value::Int64(7), &Value::Int64(2)),
+            Value::Float64(3.5)
+        );
+        assert_eq!(
+            arithmetic(ArithmeticOp::Div, &Value::Float64(7.0), &Value::Int64(2)),
+            Value::Float64(3.5)
+        );
+        assert_eq!(
+            arithmetic(ArithmeticOp::Mod, &Value::Int64(7), &Value::Int64(4)),
+            Value::Int64(3)
+        );
+        assert_eq!(
+            arithmetic(ArithmeticOp::Mul, &Value::Int64(i64::MAX), &Value::Int64(2)),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_division_by_zero_is_null() {
+        assert_eq!(
+            arithmetic(ArithmeticOp::Div, &Value::Int64(1), &Value::Int64(0)),
+            Value::Null
+        );
+        assert_eq!(
+            arithmetic(ArithmeticOp::Div, &Value::Float64(1.0), &Value::Float64(0.0)),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_timestamp_arithmetic() {
+        assert_eq!(
+            arithmetic(ArithmeticOp::Sub, &Value::Timestamp(5000), &Value::Timestamp(2000)),
+            Value::Int64(3000)
+        );
+        assert_eq!(
+            arithmetic(ArithmeticOp::Add, &Value::Timestamp(5000), &Value::Int64(1000)),
+            Value::Timestamp(6000)
+        );
+        assert_eq!(
+            arithmetic(ArithmeticOp::Sub, &Value::Timestamp(5000), &Value::Int64(1000)),
+            Value::Timestamp(4000)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_with_nulls_and_strings() {
+        // latency_ms * 1.0 / size
+        let expr = binary(
+            ArithmeticOp::Div,
+            binary(ArithmeticOp::Mul, column("latency_ms"), ScalarExpr::Literal(Value::Float64(1.0))),
+            column("size"),
+        );
+
+        let row = |name: &str| match name {
+            "latency_ms" => Value::Int64(30),
+            "size" => Value::Int64(4),
+            _ => Value::Null,
+        };
+        assert_eq!(expr.evaluate(&row), Value::Float64(7.5));
+
+        let missing_size = |name: &str| match name {
+            "latency_ms" => Value::Int64(30),
+            _ => Value::Null,
+        };
+        assert_eq!(expr.evaluate(&missing_size), Value::Null);
+
+        let text = |_: &str| Value::String("abc".into());
+        assert_eq!(expr.evaluate(&text), Value::Null);
+    }
+
+    #[test]
+    fn test_columns_and_display() {
+        let expr = binary(
+            ArithmeticOp::Sub,
+            column("end_time"),
+            binary(ArithmeticOp::Add, column("start_time"), column("end_time")),
+        );
+        assert_eq!(expr.columns(), vec!["end_time", "start_time"]);
+        assert_eq!(expr.to_string(), "end_time - (start_time + end_time)");
+        assert_eq!(column("latency").as_column(), Some("latency"));
+        assert_eq!(expr.as_column(), None);
+    }
+
+    #[test]
+    fn test_bound_expr_evaluates_once_per_dictionary_id() {
+        let mut page = Column::new(crate::data::DataType::String);
+        for value in ["/api/users", "/checkout/cart", "/api/orders", "/api/users"] {
+            page.push(&Value::String(value.into()));
+        }
+        page.push(&Value::Null);
+        let columns = HashMap::from([("page".to_string(), page)]);
+
+        let expr = ScalarExpr::Function {
+            function: ScalarFunction::SplitPart,
+            args: vec![
+                column("page"),
+                ScalarExpr::Literal(Value::String("/".into())),
+                ScalarExpr::Literal(Value::Int64(2)),
+            ],
+        };
+        let mut bound = BoundExpr::new(&expr, &columns);
+        let values: Vec<Value> = (0..5).map(|i| bound.evaluate(i)).collect();
+
+        assert_eq!(
+            values,
+            vec![
+                Value::String("api".into()),
+                Value::String("checkout".into()),
+                Value::String("api".into()),
+                Value::String("api".into()),
+                Value::Null,
+            ]
+        );
+        // Three distinct pages plus NULL
+        assert_eq!(bound.memo.map(|(_, memo)| memo.len()), Some(4));
+    }
+}
@@ -0,0 +1,242 @@
+//! Gap filling for time-bucketed aggregations
+//!
+//! `GROUP BY host, TIME_BUCKET('1 minute', timestamp) FILL(0)` emits a row
+//! for every bucket in the query's time range, for each series (each value
+//! of the other group columns), so charts show outages instead of drawing
+//! straight lines across them.
+
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::{Token, TokenWithLocation};
+use std::collections::{BTreeMap, HashMap};
+
+use super::parser::ParseError;
+use super::planner::FillPlan;
+use crate::data::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fill {
+    /// FILL(0), FILL(NULL): the same value in every aggregate column
+    Value(Value),
+    /// FILL(PREVIOUS): repeat the series' last row
+    Previous,
+    /// FILL(LINEAR): interpolate numeric columns between the surrounding rows
+    Linear,
+}
+
+/// Most buckets emitted per series
+pub const MAX_FILL_BUCKETS: usize = 10_000;
+
+/// Remove a `FILL(...)` clause following GROUP BY from the query's tokens.
+/// The SQL parser has no syntax for it, so it is taken out beforehand.
+pub(crate) fn take_fill_clause(
+    tokens: &mut Vec<TokenWithLocation>,
+) -> Result<Option<Fill>, ParseError> {
+    let mut depth = 0;
+    let mut in_group_by = false;
+    let mut start = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match &token.token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::Word(word) if depth == 0 && word.keyword == Keyword::GROUP => in_group_by = true,
+            Token::Word(word)
+                if depth == 0
+                    && in_group_by
+                    && word.quote_style.is_none()
+                    && word.value.eq_ignore_ascii_case("FILL") =>
+            {
+                start = Some(i);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let Some(start) = start else {
+        return Ok(None);
+    };
+
+    // FILL ( argument )
+    let end = (start..tokens.len())
+        .find(|&i| tokens[i].token == Token::RParen)
+        .unwrap_or(tokens.len() - 1);
+    let arguments: Vec<&Token> = tokens[start + 1..end]
+        .iter()
+        .map(|t| &t.token)
+        .filter(|t| !matches!(t, Token::Whitespace(_)))
+        .collect();
+
+    let fill = match arguments.as_slice() {
+        [Token::LParen, argument @ ..] if tokens[end].token == Token::RParen => {
+            fill_argument(argument)
+        }
+        _ => None,
+    };
+    let Some(fill) = fill else {
+        let clause: String = tokens[start..=end].iter().map(|t| t.token.to_string()).collect();
+        return Err(ParseError::InvalidFill(clause));
+    };
+
+    tokens.drain(start..=end);
+    Ok(Some(fill))
+}
+
+fn fill_argument(argument: &[&Token]) -> Option<Fill> {
+    let number = |n: &str| {
+        n.parse::<i64>()
+            .map(Value::Int64)
+            .or_else(|_| n.parse::<f64>().map(Value::Float64))
+            .ok()
+    };
+
+    match argument {
+        [Token::Number(n, _)] => number(n).map(Fill::Value),
+        [Token::Minus, Token::Number(n, _)] => number(&format!("-{}", n)).map(Fill::Value),
+        [Token::Word(word)] if word.keyword == Keyword::NULL => Some(Fill::Value(Value::Null)),
+        [Token::Word(word)] if word.value.eq_ignore_ascii_case("PREVIOUS") => Some(Fill::Previous),
+        [Token::Word(word)] if word.value.eq_ignore_ascii_case("LINEAR") => Some(Fill::Linear),
+        _ => None,
+    }
+}
+
+/// Rows of one series by bucket start
+type Series = BTreeMap<i64, Vec<Value>>;
+
+/// Add rows for missing buckets to aggregated rows of `width` columns
+pub fn fill_rows(plan: &FillPlan, width: usize, rows: &mut Vec<Vec<Value>>) {
+    // Series in the order they first appear
+    let mut series: Vec<(Vec<Value>, Series)> = Vec::new();
+    let mut series_index: HashMap<Vec<Value>, usize> = HashMap::new();
+    let mut unbucketed = Vec::new();
+
+    for row in rows.drain(..) {
+        let Some(bucket) = row[plan.bucket_column].as_i64() else {
+            unbucketed.push(row);
+            continue;
+        };
+        let key: Vec<Value> = plan.key_columns.iter().map(|&i| row[i].clone()).collect();
+        let index = *series_index.entry(key.clone()).or_insert_with(|| {
+            series.push((key, BTreeMap::new()));
+            series.len() - 1
+        });
+        series[index].1.insert(bucket, row);
+    }
+
+    // Every series covers the same buckets: the query's time range, or the
+    // buckets seen in the data where it is unbounded
+    let first = plan
+        .start
+        .map(|start| plan.bucket.floor(start))
+        .or_else(|| series.iter().filter_map(|(_, rows)| rows.keys().next()).min().copied());
+    let last = plan
+        .end
+        .map(|end| plan.bucket.floor(end - 1))
+        .or_else(|| series.iter().filter_map(|(_, rows)| rows.keys().last()).max().copied());
+    let (Some(first), Some(last)) = (first, last) else {
+        *rows = unbucketed;
+        return;
+    };
+
+    // A global series over a range with no data is all gaps
+    if series.is_empty() && plan.key_columns.is_empty() {
+        series.push((Vec::new(), BTreeMap::new()));
+    }
+
+    for (key, mut present) in series {
+        let mut previous: Option<(i64, Vec<Value>)> = None;
+        let mut bucket = first;
+        for _ in 0..MAX_FILL_BUCKETS {
+            if bucket > last {
+                break;
+            }
+            match present.remove(&bucket) {
+                Some(row) => {
+                    rows.push(row.clone());
+                    previous = Some((bucket, row));
+                }
+                None => {
+                    let mut row = vec![Value::Null; width];
+                    for (&i, value) in plan.key_columns.iter().zip(&key) {
+                        row[i] = value.clone();
+                    }
+                    row[plan.bucket_column] = Value::Timestamp(bucket);
+                    let next = present.range(bucket..).next();
+                    for &i in &plan.value_columns {
+                        row[i] = fill_value(&plan.fill, bucket, i, previous.as_ref(), next);
+                    }
+                    rows.push(row);
+                }
+            }
+            bucket = plan.bucket.next(bucket);
+        }
+        // Rows outside the filled range are kept as they are
+        rows.extend(present.into_values());
+    }
+
+    rows.extend(unbucketed);
+}
+
+fn fill_value(
+    fill: &Fill,
+    bucket: i64,
+    column: usize,
+    previous: Option<&(i64, Vec<Value>)>,
+    next: Option<(&i64, &Vec<Value>)>,
+) -> Value {
+    match fill {
+        Fill::Value(value) => value.clone(),
+        Fill::Previous => previous.map_or(Value::Null, |(_, row)| row[column].clone()),
+        Fill::Linear => {
+            let (Some((t0, before)), Some((t1, after))) = (previous, next) else {
+                return Value::Null;
+            };
+            match (before[column].as_f64(), after[column].as_f64()) {
+                (Some(v0), Some(v1)) => {
+                    let fraction = (bucket - t0) as f64 / (t1 - t0) as f64;
+                    Value::Float64(v0 + (v1 - v0) * fraction)
+                }
+                _ => Value::Null,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlparser::dialect::GenericDialect;
+    use sqlparser::tokenizer::Tokenizer;
+
+    fn take(sql: &str) -> (Result<Option<Fill>, ParseError>, String) {
+        let mut tokens = Tokenizer::new(&GenericDialect {}, sql)
+            .tokenize_with_location()
+            .unwrap();
+        let fill = take_fill_clause(&mut tokens);
+        let rest = tokens.iter().map(|t| t.token.to_string()).collect();
+        (fill, rest)
+    }
+
+    #[test]
+    fn test_take_fill_clause() {
+        let (fill, rest) = take("SELECT COUNT(*) FROM t GROUP BY b FILL(0) ORDER BY b");
+        assert_eq!(fill.unwrap(), Some(Fill::Value(Value::Int64(0))));
+        assert_eq!(rest, "SELECT COUNT(*) FROM t GROUP BY b  ORDER BY b");
+
+        let (fill, _) = take("SELECT x FROM t GROUP BY b fill( previous )");
+        assert_eq!(fill.unwrap(), Some(Fill::Previous));
+        let (fill, _) = take("SELECT x FROM t GROUP BY b FILL(-1.5)");
+        assert_eq!(fill.unwrap(), Some(Fill::Value(Value::Float64(-1.5))));
+        let (fill, _) = take("SELECT x FROM t GROUP BY b FILL(NULL)");
+        assert_eq!(fill.unwrap(), Some(Fill::Value(Value::Null)));
+
+        // A column or function named fill is left alone
+        let (fill, rest) = take("SELECT fill(x) FROM t WHERE fill > 1");
+        assert_eq!(fill.unwrap(), None);
+        assert_eq!(rest, "SELECT fill(x) FROM t WHERE fill > 1");
+
+        assert!(matches!(
+            take("SELECT x FROM t GROUP BY b FILL(sideways)").0,
+            Err(ParseError::InvalidFill(_))
+        ));
+    }
+}
@@ -0,0 +1,266 @@
+//! Scalar function registry
+//!
+//! String functions usable anywhere a scalar expression is: projections,
+//! WHERE, GROUP BY and aggregate arguments. Calls are resolved when the
+//! query is parsed, so unknown names, wrong argument counts and invalid
+//! patterns fail before any data is read.
+
+use regex::Regex;
+use std::borrow::Cow;
+
+use super::expr::ScalarExpr;
+use super::parser::ParseError;
+use crate::data::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScalarFunction {
+    Lower,
+    Upper,
+    /// SUBSTR(s, start[, length]), 1-based; a negative start counts from the end
+    Substr,
+    /// CONCAT(a, b, ...), skipping NULLs
+    Concat,
+    /// SPLIT_PART(s, delimiter, n), 1-based; a negative n counts from the end
+    SplitPart,
+    /// REGEXP_EXTRACT(s, pattern[, group]). The pattern must be a literal
+    /// and is compiled once here.
+    RegexpExtract(Pattern),
+}
+
+/// Compiled regular expression, compared by its source
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+/// Names of all registered functions
+const NAMES: &[&str] = &["LOWER", "UPPER", "SUBSTR", "CONCAT", "SPLIT_PART", "REGEXP_EXTRACT"];
+
+impl ScalarFunction {
+    /// Check whether `name` is a scalar function, before its arguments are parsed
+    pub fn is_defined(name: &str) -> bool {
+        NAMES.iter().any(|n| n.eq_ignore_ascii_case(name))
+    }
+
+    /// Look up a function by name and check its arguments
+    pub fn resolve(name: &str, args: &[ScalarExpr]) -> Result<ScalarFunction, ParseError> {
+        let (function, arity) = match name.to_uppercase().as_str() {
+            "LOWER" => (ScalarFunction::Lower, 1..=1),
+            "UPPER" => (ScalarFunction::Upper, 1..=1),
+            "SUBSTR" => (ScalarFunction::Substr, 2..=3),
+            "CONCAT" => (ScalarFunction::Concat, 1..=usize::MAX),
+            "SPLIT_PART" => (ScalarFunction::SplitPart, 3..=3),
+            "REGEXP_EXTRACT" => {
+                let pattern = match args.get(1) {
+                    Some(ScalarExpr::Literal(Value::String(pattern))) => Regex::new(pattern)
+                        .map_err(|e| ParseError::InvalidFunctionArguments(e.to_string()))?,
+                    _ => {
+                        return Err(ParseError::InvalidFunctionArguments(
+                            "REGEXP_EXTRACT pattern must be a string literal".into(),
+                        ))
+                    }
+                };
+                (ScalarFunction::RegexpExtract(Pattern(pattern)), 2..=3)
+            }
+            _ => return Err(ParseError::UnsupportedFunction(name.to_string())),
+        };
+
+        if !arity.contains(&args.len()) {
+            return Err(ParseError::InvalidFunctionArguments(format!(
+                "{} takes {} arguments, got {}",
+                function.name(),
+                match (arity.start(), arity.end()) {
+                    (min, &usize::MAX) => format!("at least {}", min),
+                    (min, max) if min == max => min.to_string(),
+                    (min, max) => format!("{} to {}", min, max),
+                },
+                args.len()
+            )));
+        }
+
+        Ok(function)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScalarFunction::Lower => "LOWER",
+            ScalarFunction::Upper => "UPPER",
+            ScalarFunction::Substr => "SUBSTR",
+            ScalarFunction::Concat => "CONCAT",
+            ScalarFunction::SplitPart => "SPLIT_PART",
+            ScalarFunction::RegexpExtract(_) => "REGEXP_EXTRACT",
+        }
+    }
+
+    /// Apply the function to evaluated arguments. A NULL string argument
+    /// gives NULL, except in CONCAT which skips it.
+    pub fn call(&self, args: &[Value]) -> Value {
+        let result = match self {
+            ScalarFunction::Lower => text(&args[0]).map(|s| s.to_lowercase()),
+            ScalarFunction::Upper => text(&args[0]).map(|s| s.to_uppercase()),
+            ScalarFunction::Substr => {
+                let length = match args.get(2) {
+                    Some(length) => length.as_i64(),
+                    None => Some(i64::MAX),
+                };
+                match (text(&args[0]), args[1].as_i64(), length) {
+                    (Some(s), Some(start), Some(length)) => substr(&s, start, length),
+                    _ => None,
+                }
+            }
+            ScalarFunction::Concat => Some(args.iter().filter_map(text).collect()),
+            ScalarFunction::SplitPart => match (text(&args[0]), text(&args[1]), args[2].as_i64()) {
+                (Some(s), Some(delimiter), Some(n)) => split_part(&s, &delimiter, n),
+                _ => None,
+            },
+            ScalarFunction::RegexpExtract(Pattern(regex)) => {
+                // Without an explicit group, return the first capture group if
+                // the pattern has one and the whole match otherwise
+                let group = match args.get(2) {
+                    Some(group) => group.as_i64().and_then(|g| usize::try_from(g).ok()),
+                    None => Some(usize::from(regex.captures_len() > 1)),
+                };
+                match (text(&args[0]), group) {
+                    (Some(s), Some(group)) => regex
+                        .captures(&s)
+                        .and_then(|captures| captures.get(group))
+                        .map(|m| m.as_str().to_string()),
+                    _ => None,
+                }
+            }
+        };
+
+        result.map(Value::String).unwrap_or(Value::Null)
+    }
+}
+
+/// String form of a value; numbers and timestamps are formatted
+fn text(value: &Value) -> Option<Cow<'_, str>> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(Cow::Borrowed(s)),
+        other => Some(Cow::Owned(other.to_string())),
+    }
+}
+
+fn substr(s: &str, start: i64, length: i64) -> Option<String> {
+    if length < 0 {
+        return None;
+    }
+    let chars = s.chars().count() as i64;
+    let skip = match start {
+        0 | 1 => 0,
+        start if start > 0 => start - 1,
+        start => (chars + start).max(0),
+    };
+    Some(
+        s.chars()
+            .skip(skip as usize)
+            .take(length.min(chars) as usize)
+            .collect(),
+    )
+}
+
+fn split_part(s: &str, delimiter: &str, n: i64) -> Option<String> {
+    if n == 0 {
+        return None;
+    }
+    let parts: Vec<&str> = if delimiter.is_empty() {
+        vec![s]
+    } else {
+        s.split(delimiter).collect()
+    };
+    let index = if n > 0 {
+        n - 1
+    } else {
+        parts.len() as i64 + n
+    };
+    // Out-of-range parts are empty rather than NULL
+    Some(
+        usize::try_from(index)
+            .ok()
+            .and_then(|i| parts.get(i))
+            .unwrap_or(&"")
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Value {
+        let exprs: Vec<ScalarExpr> = args.iter().cloned().map(ScalarExpr::Literal).collect();
+        ScalarFunction::resolve(name, &exprs).unwrap().call(args)
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_case_and_concat() {
+        assert_eq!(call("lower", &[string("GET /API")]), string("get /api"));
+        assert_eq!(call("UPPER", &[string("get")]), string("GET"));
+        assert_eq!(call("UPPER", &[Value::Null]), Value::Null);
+        assert_eq!(
+            call("CONCAT", &[string("user-"), Value::Int64(42), Value::Null]),
+            string("user-42")
+        );
+    }
+
+    #[test]
+    fn test_substr() {
+        let word = string("snorkel");
+        let int = Value::Int64;
+        assert_eq!(call("SUBSTR", &[word.clone(), int(2), int(3)]), string("nor"));
+        assert_eq!(call("SUBSTR", &[word.clone(), int(5)]), string("kel"));
+        assert_eq!(call("SUBSTR", &[word.clone(), int(-3)]), string("kel"));
+        assert_eq!(call("SUBSTR", &[word.clone(), int(10)]), string(""));
+        assert_eq!(call("SUBSTR", &[word, int(1), int(-1)]), Value::Null);
+    }
+
+    #[test]
+    fn test_split_part() {
+        let page = string("/checkout/cart/42");
+        let part = |n| call("SPLIT_PART", &[page.clone(), string("/"), Value::Int64(n)]);
+        assert_eq!(part(2), string("checkout"));
+        assert_eq!(part(-1), string("42"));
+        assert_eq!(part(9), string(""));
+        assert_eq!(part(0), Value::Null);
+    }
+
+    #[test]
+    fn test_regexp_extract() {
+        let message = string("login ok user=1234 took=5ms");
+        let extract = |args: &[Value]| {
+            let mut all = vec![message.clone()];
+            all.extend_from_slice(args);
+            call("REGEXP_EXTRACT", &all)
+        };
+        assert_eq!(extract(&[string(r"user=(\d+)")]), string("1234"));
+        assert_eq!(extract(&[string(r"took=\d+")]), string("took=5"));
+        assert_eq!(extract(&[string(r"user=(\d+)"), Value::Int64(0)]), string("user=1234"));
+        assert_eq!(extract(&[string(r"admin=(\d+)")]), Value::Null);
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let column = ScalarExpr::Column("message".into());
+        assert!(ScalarFunction::resolve("LOWER", &[]).is_err());
+        // The pattern must be a literal, and a valid one
+        assert!(ScalarFunction::resolve("REGEXP_EXTRACT", &[column.clone(), column.clone()])
+            .is_err());
+        assert!(ScalarFunction::resolve(
+            "REGEXP_EXTRACT",
+            &[column.clone(), ScalarExpr::Literal(Value::String("(".into()))]
+        )
+        .is_err());
+        assert!(ScalarFunction::resolve("REVERSE", &[column]).is_err());
+        assert!(ScalarFunction::is_defined("split_part"));
+    }
+}
@@ -0,0 +1,200 @@
+//! Table globs and the `_table` column
+//!
+//! `FROM logs_*` reads every table whose name matches, as one table whose
+//! schema merges theirs. Every query can read `_table`, the name of the
+//! table a row came from, so `GROUP BY _table` splits results by source
+//! and `WHERE _table = 'logs_api'` skips the other tables entirely.
+
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::{Token, TokenWithLocation};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::expr::ScalarExpr;
+use super::planner::{substitute_projections, ProjectionPlan, QueryPlan};
+use crate::data::{DataType, Table, Value};
+use crate::storage::StorageEngine;
+
+/// Name of the column holding each row's table name
+pub const TABLE_COLUMN: &str = "_table";
+
+/// Whether a table name is a glob, matching many tables
+pub fn is_glob(name: &str) -> bool {
+    name.contains('*')
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let [first, middle @ .., last] = parts.as_slice() else {
+        return pattern == name;
+    };
+    if name.len() < first.len() + last.len() || !name.starts_with(first) || !name.ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Tables whose names match a glob, by name
+pub fn matching_tables(engine: &StorageEngine, pattern: &str) -> Vec<Arc<Table>> {
+    let mut names: Vec<String> = engine
+        .list_tables()
+        .into_iter()
+        .filter(|name| glob_matches(pattern, name))
+        .collect();
+    names.sort();
+    names.iter().filter_map(|name| engine.get_table(name)).collect()
+}
+
+/// Schema of tables read together: each column's types merged across them
+pub fn merged_schema(tables: &[Arc<Table>]) -> HashMap<String, DataType> {
+    let mut schema = HashMap::new();
+    for table in tables {
+        for (name, data_type) in table.get_schema() {
+            schema
+                .entry(name)
+                .and_modify(|t: &mut DataType| *t = t.merge(&data_type))
+                .or_insert(data_type);
+        }
+    }
+    schema
+}
+
+/// Quote an unquoted glob after FROM, so `FROM logs_*` reaches the SQL
+/// parser as `FROM "logs_*"`
+pub(crate) fn quote_table_globs(tokens: &mut Vec<TokenWithLocation>) {
+    let mut i = 0;
+    while i < tokens.len() {
+        let after_from = matches!(&tokens[i].token, Token::Word(w) if w.keyword == Keyword::FROM);
+        i += 1;
+        if !after_from {
+            continue;
+        }
+        while i < tokens.len() && matches!(tokens[i].token, Token::Whitespace(_)) {
+            i += 1;
+        }
+
+        // The name runs to the first token that is not a word or `*`
+        let end = (i..tokens.len())
+            .find(|&j| match &tokens[j].token {
+                Token::Word(word) => word.quote_style.is_some(),
+                token => *token != Token::Mul,
+            })
+            .unwrap_or(tokens.len());
+        if !tokens[i..end].iter().any(|t| t.token == Token::Mul) {
+            continue;
+        }
+        let name: String = tokens[i..end].iter().map(|t| t.token.to_string()).collect();
+        tokens[i].token = Token::make_word(&name, Some('"'));
+        tokens.drain(i + 1..end);
+    }
+}
+
+/// Column names `*` expands to over tables read together
+pub fn wildcard_columns(tables: &[Arc<Table>]) -> Vec<String> {
+    let mut columns = vec![TABLE_COLUMN.to_string()];
+    columns.extend(merged_schema(tables).into_keys().filter(|c| c != TABLE_COLUMN));
+    columns
+}
+
+/// The plan as run against one table, with `_table` read as its name
+/// unless the table has a column of that name. Conditions on `_table`
+/// alone become constant, so a table they rule out has no shards to scan.
+pub fn bind_table_column(plan: &QueryPlan, table: &Table) -> QueryPlan {
+    if has_table_column(table) {
+        return plan.clone();
+    }
+    plan.substitute_columns(&table_name(table))
+}
+
+/// Projections with `_table` read as the table's name, as in
+/// `bind_table_column`
+pub fn bind_projections(projections: &[ProjectionPlan], table: &Table) -> Vec<ProjectionPlan> {
+    if has_table_column(table) {
+        return projections.to_vec();
+    }
+    substitute_projections(projections, &table_name(table))
+}
+
+fn has_table_column(table: &Table) -> bool {
+    table.get_schema().contains_key(TABLE_COLUMN)
+}
+
+/// Reads `_table` as the table's name
+fn table_name(table: &Table) -> impl Fn(&str) -> Option<ScalarExpr> {
+    let name = Value::String(table.name().to_string());
+    move |column| (column == TABLE_COLUMN).then(|| ScalarExpr::Literal(name.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::run_query_at;
+
+    fn setup_engine() -> StorageEngine {
+        let engine = StorageEngine::new();
+        for (table, rows) in [("logs_api", 3), ("logs_auth", 2), ("metrics", 4)] {
+            for i in 0..rows {
+                let mut row = HashMap::new();
+                row.insert("timestamp".to_string(), Value::Timestamp(i));
+                row.insert("latency".to_string(), Value::Int64(i * 10));
+                engine.insert(table, row).unwrap();
+            }
+        }
+        engine
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("logs_*", "logs_api"));
+        assert!(glob_matches("*_api", "logs_api"));
+        assert!(glob_matches("l*s_*i", "logs_api"));
+        assert!(glob_matches("*", "metrics"));
+        assert!(!glob_matches("logs_*", "metrics"));
+        assert!(!glob_matches("logs_*_api", "logs_api"));
+        assert!(!glob_matches("logs", "logs_api"));
+    }
+
+    #[test]
+    fn test_table_glob() {
+        let engine = setup_engine();
+        let result = run_query_at(
+            &engine,
+            "SELECT _table, COUNT(*) AS n FROM logs_* GROUP BY _table ORDER BY _table",
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::String("logs_api".into()), Value::Int64(3)],
+                vec![Value::String("logs_auth".into()), Value::Int64(2)],
+            ]
+        );
+
+        // Tables ruled out by `_table` are not scanned
+        let result = run_query_at(
+            &engine,
+            "SELECT SUM(latency) FROM logs_* WHERE _table = 'logs_auth'",
+            0,
+        )
+        .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int64(10)]]);
+        assert_eq!(result.rows_scanned, 2);
+
+        let result = run_query_at(&engine, "SELECT * FROM logs_* WHERE latency = 20", 0).unwrap();
+        assert_eq!(result.columns[0], TABLE_COLUMN);
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0][0], Value::String("logs_api".into()));
+
+        assert!(run_query_at(&engine, "SELECT * FROM traces_*", 0).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+//! Bucketed value distributions for HISTOGRAM and HISTOGRAM_LOG
+//!
+//! HISTOGRAM(col, width) counts values in buckets of a fixed width, aligned
+//! to multiples of it. HISTOGRAM_LOG(col) uses log-linear buckets: nine per
+//! power of ten (1-2, 2-3, ... 9-10, 10-20, ...), so latencies from
+//! microseconds to minutes keep about 10% resolution. Both merge by adding
+//! bucket counts.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::data::value::Histogram;
+
+/// Most buckets a result holds. Wider spans merge neighbouring
+/// fixed-width buckets.
+pub const MAX_HISTOGRAM_BUCKETS: usize = 1000;
+
+/// Log-linear buckets per power of ten
+const LOG_STEPS: i64 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Buckets {
+    /// Buckets of this width
+    Linear(f64),
+    /// Nine buckets per power of ten
+    LogLinear,
+}
+
+/// Mergeable bucket counts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistogramSketch {
+    buckets: Buckets,
+    /// Bucket index -> count
+    counts: BTreeMap<i64, u64>,
+    /// HISTOGRAM_LOG only: zeros, counted in a bucket starting at 0
+    zero_count: u64,
+}
+
+impl HistogramSketch {
+    pub fn new(buckets: Buckets) -> Self {
+        Self {
+            buckets,
+            counts: BTreeMap::new(),
+            zero_count: 0,
+        }
+    }
+
+    /// Count a value. NaN, and negative values in log-linear buckets, are
+    /// skipped.
+    pub fn insert(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        let index = match self.buckets {
+            Buckets::Linear(width) => (value / width).floor() as i64,
+            Buckets::LogLinear if value > 0.0 => log_index(value),
+            Buckets::LogLinear => {
+                if value == 0.0 {
+                    self.zero_count += 1;
+                }
+                return;
+            }
+        };
+        *self.counts.entry(index).or_insert(0) += 1;
+    }
+
+    /// Merge another sketch with the same buckets
+    pub fn merge(&mut self, other: &HistogramSketch) {
+        for (&index, &count) in &other.counts {
+            *self.counts.entry(index).or_insert(0) += count;
+        }
+        self.zero_count += other.zero_count;
+    }
+
+    /// Boundaries and counts of every bucket from the lowest to the highest
+    /// value seen, including empty ones in between. `None` if nothing was
+    /// counted.
+    pub fn histogram(&self) -> Option<Histogram> {
+        let (Some(&first), Some(&last)) = (self.counts.keys().next(), self.counts.keys().last())
+        else {
+            return (self.zero_count > 0).then(|| Histogram {
+                boundaries: vec![0.0, 0.0],
+                counts: vec![self.zero_count],
+            });
+        };
+
+        let mut histogram = match self.buckets {
+            Buckets::Linear(width) => {
+                // Merge neighbours so the span fits in MAX_HISTOGRAM_BUCKETS.
+                // Huge values saturate their index, so the span can be all
+                // of i64 and bound the last bucket at i64::MAX + 1.
+                let span = (last as i128 - first as i128 + 1) as u128;
+                let factor = span.div_ceil(MAX_HISTOGRAM_BUCKETS as u128) as i64;
+                let (first, last) = (first.div_euclid(factor), last.div_euclid(factor));
+                let mut counts = vec![0; (last - first + 1) as usize];
+                for (&index, &count) in &self.counts {
+                    counts[(index.div_euclid(factor) - first) as usize] += count;
+                }
+                let width = width * factor as f64;
+                Histogram {
+                    boundaries: (first as i128..=last as i128 + 1)
+                        .map(|i| i as f64 * width)
+                        .collect(),
+                    counts,
+                }
+            }
+            Buckets::LogLinear => Histogram {
+                boundaries: (first..=last + 1).map(log_lower_bound).collect(),
+                counts: (first..=last)
+                    .map(|i| self.counts.get(&i).copied().unwrap_or(0))
+                    .collect(),
+            },
+        };
+
+        if self.zero_count > 0 {
+            histogram.boundaries.insert(0, 0.0);
+            histogram.counts.insert(0, self.zero_count);
+        }
+        Some(histogram)
+    }
+}
+
+/// Log-linear bucket of a positive value. Consecutive buckets have
+/// consecutive indexes: 1-2 is 0, 2-3 is 1, ... 9-10 is 8, 10-20 is 9.
+fn log_index(value: f64) -> i64 {
+    let mut exponent = value.log10().floor() as i64;
+    // log10 rounds just below powers of ten at some magnitudes
+    if 10f64.powi(exponent as i32 + 1) <= value {
+        exponent += 1;
+    } else if 10f64.powi(exponent as i32) > value {
+        exponent -= 1;
+    }
+    let leading = ((value / 10f64.powi(exponent as i32)).floor() as i64).clamp(1, 9);
+    exponent * LOG_STEPS + leading - 1
+}
+
+fn log_lower_bound(index: i64) -> f64 {
+    let (exponent, step) = (index.div_euclid(LOG_STEPS), index.rem_euclid(LOG_STEPS));
+    (step + 1) as f64 * 10f64.powi(exponent as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(buckets: Buckets, values: &[f64]) -> Histogram {
+        let mut sketch = HistogramSketch::new(buckets);
+        for &value in values {
+            sketch.insert(value);
+        }
+        sketch.histogram().unwrap()
+    }
+
+    #[test]
+    fn test_linear_buckets() {
+        let h = histogram(Buckets::Linear(10.0), &[1.0, 5.0, 12.0, 35.0, -3.0]);
+        assert_eq!(h.boundaries, vec![-10.0, 0.0, 10.0, 20.0, 30.0, 40.0]);
+        assert_eq!(h.counts, vec![1, 2, 1, 0, 1]);
+
+        // A span wider than the bucket limit merges neighbouring buckets
+        let h = histogram(Buckets::Linear(1.0), &[0.0, 1.0, 2500.0]);
+        assert_eq!(h.counts.len(), 834);
+        assert_eq!((h.boundaries[1], h.counts[0]), (3.0, 2));
+
+        // Values beyond the range of bucket indexes
+        let h = histogram(Buckets::Linear(1.0), &[f64::MAX, -f64::MAX, 0.0]);
+        assert_eq!(h.counts.len(), MAX_HISTOGRAM_BUCKETS);
+        assert_eq!(h.counts.iter().sum::<u64>(), 3);
+        let h = histogram(Buckets::Linear(1.0), &[f64::MAX]);
+        assert_eq!(h.counts, vec![1]);
+    }
+
+    #[test]
+    fn test_log_linear_buckets() {
+        let h = histogram(Buckets::LogLinear, &[0.0, 1.5, 9.0, 10.0, 45.0, 1000.0, -1.0]);
+        assert_eq!(h.boundaries[..5], [0.0, 1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(h.counts[..3], [1, 1, 0]);
+        // 9, 10 and 45 fall in 9-10, 10-20 and 40-50
+        assert_eq!(h.counts[9..13], [1, 1, 0, 0]);
+        assert_eq!(h.counts[13], 1);
+        assert_eq!(*h.boundaries.last().unwrap(), 2000.0);
+        assert_eq!(h.counts.iter().sum::<u64>(), 6);
+        assert_eq!(log_index(1000.0), 27);
+        assert_eq!(log_index(0.001), -27);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = HistogramSketch::new(Buckets::Linear(5.0));
+        let mut b = HistogramSketch::new(Buckets::Linear(5.0));
+        a.insert(1.0);
+        b.insert(2.0);
+        b.insert(11.0);
+        a.merge(&b);
+
+        let h = a.histogram().unwrap();
+        assert_eq!(h.boundaries, vec![0.0, 5.0, 10.0, 15.0]);
+        assert_eq!(h.counts, vec![2, 0, 1]);
+        assert!(HistogramSketch::new(Buckets::LogLinear).histogram().is_none());
+    }
+}
@@ -0,0 +1,268 @@
+//! HyperLogLog sketch for approximate distinct counting
+//!
+//! Sketches are mergeable, so per-shard and per-node partial results can be
+//! combined without re-reading the data. Values are hashed with a fixed
+//! algorithm over a fixed encoding, not std's hashers, which may change
+//! between Rust releases, so sketches built on different nodes are
+//! compatible.
+
+use crate::data::Value;
+use serde::{Deserialize, Serialize};
+
+/// Number of index bits. 2^14 registers gives ~0.8% standard error.
+const PRECISION: u32 = 14;
+const NUM_REGISTERS: usize = 1 << PRECISION;
+
+/// Distinct hashes kept exactly before switching to registers. At 8 bytes
+/// per hash this stays below the 16KB register array.
+const SPARSE_LIMIT: usize = 1024;
+
+/// Approximate distinct counter using 16K one-byte registers.
+///
+/// Small sets are tracked exactly as a sorted list of hashes, which keeps
+/// low-cardinality groups cheap and their counts exact.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HyperLogLog {
+    /// Sorted distinct hashes while the sketch is sparse
+    hashes: Vec<u64>,
+    /// Max leading-zero rank seen per register. Empty while sparse.
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a value. Nulls are ignored, as in `COUNT(DISTINCT col)`.
+    pub fn insert(&mut self, value: &Value) {
+        if !value.is_null() {
+            self.insert_hash(hash_value(value));
+        }
+    }
+
+    /// Add a value that was already hashed with [`hash_value`] or [`hash_str`]
+    pub fn insert_hash(&mut self, hash: u64) {
+        if !self.is_dense() {
+            if let Err(pos) = self.hashes.binary_search(&hash) {
+                self.hashes.insert(pos, hash);
+                if self.hashes.len() > SPARSE_LIMIT {
+                    self.densify();
+                }
+            }
+            return;
+        }
+
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // Rank is the position of the first 1 bit after the index bits
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// Merge another sketch into this one
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        if !other.is_dense() {
+            for &hash in &other.hashes {
+                self.insert_hash(hash);
+            }
+            return;
+        }
+
+        if !self.is_dense() {
+            let hashes = std::mem::take(&mut self.hashes);
+            self.registers = other.registers.clone();
+            for hash in hashes {
+                self.insert_hash(hash);
+            }
+            return;
+        }
+
+        for (mine, theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *mine = (*mine).max(*theirs);
+        }
+    }
+
+    /// Estimated number of distinct values (exact while sparse)
+    pub fn estimate(&self) -> u64 {
+        if !self.is_dense() {
+            return self.hashes.len() as u64;
+        }
+
+        let m = NUM_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+
+        let mut sum = 0.0;
+        let mut zeros = 0;
+        for &r in &self.registers {
+            sum += 1.0 / (1u64 << r) as f64;
+            if r == 0 {
+                zeros += 1;
+            }
+        }
+
+        let raw = alpha * m * m / sum;
+
+        // Linear counting is more accurate at small cardinalities
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+
+    fn is_dense(&self) -> bool {
+        !self.registers.is_empty()
+    }
+
+    /// Move the exact hash list into registers
+    fn densify(&mut self) {
+        self.registers = vec![0; NUM_REGISTERS];
+        for hash in std::mem::take(&mut self.hashes) {
+            self.insert_hash(hash);
+        }
+    }
+}
+
+/// Hash a value for insertion into a sketch
+pub fn hash_value(value: &Value) -> u64 {
+    let hasher = match value {
+        Value::Null => StableHasher::new(0),
+        Value::Bool(b) => StableHasher::new(1).write(&[*b as u8]),
+        Value::Int64(i) => StableHasher::new(2).write(&i.to_le_bytes()),
+        Value::Float64(f) => StableHasher::new(3).write(&f.to_bits().to_le_bytes()),
+        Value::String(s) => return hash_str(s),
+        Value::Timestamp(t) => StableHasher::new(5).write(&t.to_le_bytes()),
+        Value::Histogram(h) => {
+            let mut hasher = StableHasher::new(6);
+            for boundary in &h.boundaries {
+                hasher = hasher.write(&boundary.to_bits().to_le_bytes());
+            }
+            for count in &h.counts {
+                hasher = hasher.write(&count.to_le_bytes());
+            }
+            hasher
+        }
+        Value::TopK(entries) => {
+            let mut hasher = StableHasher::new(7);
+            for entry in entries {
+                hasher = hasher.write(&hash_value(&entry.value).to_le_bytes());
+                hasher = hasher.write(&entry.count.to_le_bytes());
+                hasher = hasher.write(&entry.error.to_le_bytes());
+            }
+            hasher
+        }
+    };
+    hasher.finish()
+}
+
+/// Hash a string the same way [`hash_value`] hashes `Value::String`, so
+/// dictionary-encoded columns can be hashed without building a `Value`
+pub fn hash_str(s: &str) -> u64 {
+    StableHasher::new(4).write(s.as_bytes()).finish()
+}
+
+/// 64-bit FNV-1a over a type tag and the value's bytes, finished with the
+/// SplitMix64 mix so every bit of the hash depends on every input bit
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new(tag: u8) -> Self {
+        Self(0xcbf2_9ce4_8422_2325).write(&[tag])
+    }
+
+    fn write(mut self, bytes: &[u8]) -> Self {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+        self
+    }
+
+    fn finish(self) -> u64 {
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(estimate: u64, actual: u64) {
+        let error = (estimate as f64 - actual as f64).abs() / actual as f64;
+        assert!(error < 0.03, "estimate {} vs actual {}", estimate, actual);
+    }
+
+    #[test]
+    fn test_hashes_are_fixed() {
+        // Nodes merge each other's sketches, so these must never change
+        assert_eq!(hash_str("snorkel"), 0x7b1b_8c85_c5a2_8f22);
+        assert_eq!(hash_value(&Value::String("snorkel".into())), hash_str("snorkel"));
+        assert_eq!(hash_value(&Value::Int64(42)), 0x61a9_bca9_ee17_99d4);
+        assert_ne!(hash_value(&Value::Int64(42)), hash_value(&Value::Timestamp(42)));
+    }
+
+    #[test]
+    fn test_empty_sketch() {
+        assert_eq!(HyperLogLog::new().estimate(), 0);
+    }
+
+    #[test]
+    fn test_sparse_merge_crosses_into_dense() {
+        let mut a = HyperLogLog::new();
+        let mut b = HyperLogLog::new();
+        for i in 0..800 {
+            a.insert(&Value::Int64(i));
+            b.insert(&Value::Int64(i + 800));
+        }
+        assert_eq!(a.estimate(), 800);
+
+        a.merge(&b);
+        assert!(a.is_dense());
+        assert_close(a.estimate(), 1600);
+    }
+
+    #[test]
+    fn test_small_cardinality_is_exact() {
+        let mut hll = HyperLogLog::new();
+        for i in 0..100 {
+            hll.insert(&Value::Int64(i % 10));
+        }
+        hll.insert(&Value::Null);
+        assert_eq!(hll.estimate(), 10);
+    }
+
+    #[test]
+    fn test_large_cardinality() {
+        let mut hll = HyperLogLog::new();
+        for i in 0..200_000 {
+            hll.insert(&Value::String(format!("user-{}", i)));
+        }
+        assert_close(hll.estimate(), 200_000);
+    }
+
+    #[test]
+    fn test_merge_overlapping_sketches() {
+        let mut a = HyperLogLog::new();
+        let mut b = HyperLogLog::new();
+        for i in 0..30_000 {
+            a.insert(&Value::Int64(i));
+        }
+        for i in 20_000..50_000 {
+            b.insert(&Value::Int64(i));
+        }
+
+        a.merge(&b);
+        assert_close(a.estimate(), 50_000);
+    }
+
+    #[test]
+    fn test_hash_str_matches_value() {
+        assert_eq!(hash_str("abc"), hash_value(&Value::String("abc".into())));
+    }
+}
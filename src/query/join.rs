@@ -0,0 +1,369 @@
+//! Lookup joins
+//!
+//! `FROM events e JOIN services s ON e.service = s.name` joins every shard
+//! of the FROM table with a small lookup table, fetched whole before the
+//! query runs and sent along to every node in cluster mode. Each shard is
+//! first filtered on its own columns; the rows left pick up the columns of
+//! the lookup rows whose key equals theirs, through a hash index built once
+//! per query. The rest of the query runs over the joined shards as usual.
+//!
+//! Lookup columns are read as `s.team`, or as `team` if the FROM table has
+//! no such column. Keys only match values of the same type, and NULL keys
+//! match nothing.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::executor::{ExecuteError, QueryResult};
+use super::expr::ScalarExpr;
+use super::parser::{FilterExpr, LookupJoin, ParsedQuery};
+use super::planner::{substitute_projections, ProjectionPlan, QueryPlan};
+use super::predicate::build_combined_mask;
+use crate::data::{Column, Shard, Table, Value};
+
+/// Most rows a lookup table may have
+pub const MAX_LOOKUP_ROWS: usize = 100_000;
+
+/// Columns and rows of a lookup table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LookupRows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// SQL fetching a join's lookup table, asking for one row more than allowed
+/// so an oversized table is caught
+pub fn lookup_query(join: &LookupJoin) -> String {
+    format!("SELECT * FROM \"{}\" LIMIT {}", join.table, MAX_LOOKUP_ROWS + 1)
+}
+
+/// A fetched lookup table, checked against its join
+pub fn lookup_rows(join: &LookupJoin, result: QueryResult) -> Result<LookupRows, ExecuteError> {
+    if result.rows.len() > MAX_LOOKUP_ROWS {
+        return Err(ExecuteError::InvalidJoin(format!(
+            "lookup table {} has more than {} rows",
+            join.table, MAX_LOOKUP_ROWS
+        )));
+    }
+    if !result.columns.contains(&join.lookup_column) {
+        return Err(ExecuteError::InvalidJoin(format!(
+            "lookup table {} has no column {}",
+            join.table, join.lookup_column
+        )));
+    }
+    Ok(LookupRows {
+        columns: result.columns,
+        rows: result.rows,
+    })
+}
+
+/// Attach the lookup table's rows to the query's join
+pub fn bind_lookup(parsed: &mut ParsedQuery, rows: Option<LookupRows>) -> Result<(), ExecuteError> {
+    match (&mut parsed.join, rows) {
+        (Some(join), Some(rows)) => join.rows = Some(rows),
+        (None, None) => {}
+        _ => {
+            return Err(ExecuteError::InvalidJoin(
+                "lookup rows do not match the query's join".into(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Names of a lookup table's columns in joined shards
+pub fn lookup_column_names(join: &LookupJoin) -> Vec<String> {
+    let columns = join.rows.iter().flat_map(|rows| &rows.columns);
+    columns.map(|column| format!("{}.{}", join.qualifier, column)).collect()
+}
+
+/// A plan and its lookup table, prepared to join the shards of one FROM
+/// table
+pub struct JoinedPlan {
+    /// The plan with columns named as the joined shards hold them
+    pub plan: QueryPlan,
+    pub projections: Vec<ProjectionPlan>,
+    /// Conditions on the FROM table's own columns, applied before joining
+    pushdown: Option<FilterExpr>,
+    /// FROM table columns the joined shards hold
+    driving_columns: Vec<String>,
+    driving_key: String,
+    /// Lookup columns the joined shards hold, by their names there
+    lookup_columns: Vec<(String, Column)>,
+    /// Lookup rows by key
+    index: HashMap<Value, Vec<usize>>,
+    left: bool,
+}
+
+impl JoinedPlan {
+    pub fn new(
+        join: &LookupJoin,
+        table: &Table,
+        plan: &QueryPlan,
+        projections: &[ProjectionPlan],
+    ) -> Result<Self, ExecuteError> {
+        let rows = join.rows.as_ref().ok_or_else(|| {
+            ExecuteError::InvalidJoin(format!("lookup table {} was not fetched", join.table))
+        })?;
+        let lookup = Table::from_rows(&join.table, &rows.columns, &rows.rows);
+        let lookup_data = lookup.get_shards().first().cloned();
+
+        // Columns of the FROM table keep their names, qualified or not
+        let schema = table.get_schema();
+        let driving_prefix = format!("{}.", join.driving_qualifier);
+        let lookup_prefix = format!("{}.", join.qualifier);
+        let resolve = |column: &str| {
+            if schema.contains_key(column) {
+                return None;
+            }
+            if let Some(unqualified) = column.strip_prefix(&driving_prefix) {
+                return Some(ScalarExpr::Column(unqualified.to_string()));
+            }
+            rows.columns
+                .iter()
+                .any(|c| c == column)
+                .then(|| ScalarExpr::Column(format!("{}{}", lookup_prefix, column)))
+        };
+        let plan = plan.substitute_columns(&resolve);
+        let projections = substitute_projections(projections, &resolve);
+
+        // The key keeps a row count even when nothing else is read
+        let mut needed = vec![join.driving_column.as_str()];
+        needed.extend(plan.required_columns.iter().map(String::as_str));
+        for projection in &projections {
+            if let ProjectionPlan::Column { name, .. } = projection {
+                needed.push(name);
+            }
+        }
+
+        let mut driving_columns: Vec<String> = Vec::new();
+        let mut lookup_columns: Vec<(String, Column)> = Vec::new();
+        for name in needed {
+            let mut held = driving_columns.iter().chain(lookup_columns.iter().map(|(n, _)| n));
+            if held.any(|n| n == name) {
+                continue;
+            }
+            let lookup_column = name
+                .strip_prefix(&lookup_prefix)
+                .filter(|_| !schema.contains_key(name))
+                .and_then(|column| lookup_data.as_ref()?.get_column(column));
+            match lookup_column {
+                Some(column) => lookup_columns.push((name.to_string(), column)),
+                None => driving_columns.push(name.to_string()),
+            }
+        }
+
+        // Conditions reading only the FROM table's columns can run first
+        let reads_lookup = |expr: &FilterExpr| {
+            expr.columns().iter().any(|c| lookup_columns.iter().any(|(name, _)| name == c))
+        };
+        let pushdown = match &plan.filters {
+            Some(FilterExpr::And(children)) => {
+                let own: Vec<FilterExpr> =
+                    children.iter().filter(|c| !reads_lookup(c)).cloned().collect();
+                (!own.is_empty()).then_some(FilterExpr::And(own))
+            }
+            Some(filters) if !reads_lookup(filters) => Some(filters.clone()),
+            _ => None,
+        };
+
+        let key = rows.columns.iter().position(|c| *c == join.lookup_column);
+        let mut index: HashMap<Value, Vec<usize>> = HashMap::new();
+        for (i, row) in rows.rows.iter().enumerate() {
+            match key.map(|key| &row[key]) {
+                Some(value) if !value.is_null() => index.entry(value.clone()).or_default().push(i),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            plan,
+            projections,
+            pushdown,
+            driving_columns,
+            driving_key: join.driving_column.clone(),
+            lookup_columns,
+            index,
+            left: join.left,
+        })
+    }
+
+    /// A shard's rows that pass the FROM table's own conditions, each joined
+    /// with the lookup rows matching it. Only the columns the plan reads are
+    /// kept.
+    pub fn join_shard(&self, shard: &Shard) -> Shard {
+        shard.with_columns(|columns| {
+            let mask = build_combined_mask(columns, self.pushdown.as_ref(), shard.row_count());
+            let key = columns.get(&self.driving_key);
+
+            let mut driving: Vec<(&Column, Column)> = self
+                .driving_columns
+                .iter()
+                .filter_map(|name| columns.get(name))
+                .map(|column| (column, Column::new(column.data_type())))
+                .collect();
+            let mut lookup: Vec<(&Column, Column)> = self
+                .lookup_columns
+                .iter()
+                .map(|(_, column)| (column, Column::new(column.data_type())))
+                .collect();
+
+            for row in mask.iter() {
+                let matches = key
+                    .and_then(|key| self.index.get(&key.get(row)))
+                    .map_or(&[][..], Vec::as_slice);
+                // A LEFT JOIN keeps rows without a match once, with NULLs
+                let lookup_rows: Vec<Option<usize>> = match matches {
+                    [] if self.left => vec![None],
+                    _ => matches.iter().copied().map(Some).collect(),
+                };
+                for lookup_row in lookup_rows {
+                    for (source, joined) in &mut driving {
+                        joined.push(&source.get(row));
+                    }
+                    for (source, joined) in &mut lookup {
+                        joined.push(&lookup_row.map_or(Value::Null, |i| source.get(i)));
+                    }
+                }
+            }
+
+            let names = self
+                .driving_columns
+                .iter()
+                .filter(|name| columns.contains_key(*name))
+                .chain(self.lookup_columns.iter().map(|(name, _)| name));
+            let joined = names.cloned().zip(driving.into_iter().chain(lookup).map(|(_, c)| c));
+            Shard::from_columns(shard.start_time, shard.end_time, joined.collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{run_query_at, QueryError};
+    use crate::storage::StorageEngine;
+
+    fn setup_engine() -> StorageEngine {
+        // Request i hits service i % 4 and takes i ms
+        let engine = StorageEngine::new();
+        let services = ["api", "auth", "billing", "search"];
+        for i in 0..20 {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(i));
+            row.insert("service".to_string(), Value::String(services[i as usize % 4].into()));
+            row.insert("latency".to_string(), Value::Int64(i));
+            engine.insert("requests", row).unwrap();
+        }
+
+        // search has no owner
+        let columns = vec!["name".to_string(), "team".to_string()];
+        let owners = [("api", "core"), ("auth", "core"), ("billing", "payments")];
+        let rows: Vec<Vec<Value>> = owners
+            .iter()
+            .map(|(name, team)| vec![string(name), string(team)])
+            .collect();
+        engine.replace_table(Table::from_rows("services", &columns, &rows));
+        engine
+    }
+
+    fn run(engine: &StorageEngine, sql: &str) -> QueryResult {
+        run_query_at(engine, sql, 0).unwrap()
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.into())
+    }
+
+    #[test]
+    fn test_lookup_join() {
+        let engine = setup_engine();
+        let result = run(
+            &engine,
+            "SELECT s.team, COUNT(*) FROM requests r JOIN services s ON r.service = s.name \
+             GROUP BY s.team ORDER BY s.team",
+        );
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![string("core"), Value::Int64(10)],
+                vec![string("payments"), Value::Int64(5)],
+            ]
+        );
+
+        // Conditions on either side; unqualified lookup columns
+        let result = run(
+            &engine,
+            "SELECT service, team, latency FROM requests JOIN services ON service = services.name \
+             WHERE latency >= 10 AND team = 'core' ORDER BY latency LIMIT 2",
+        );
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![string("api"), string("core"), Value::Int64(12)],
+                vec![string("auth"), string("core"), Value::Int64(13)],
+            ]
+        );
+
+        let result = run(
+            &engine,
+            "SELECT * FROM requests r JOIN services s ON r.service = s.name WHERE latency = 2",
+        );
+        let team = result.columns.iter().position(|c| c == "s.team").unwrap();
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0][team], string("payments"));
+    }
+
+    #[test]
+    fn test_left_join() {
+        let engine = setup_engine();
+        let result = run(
+            &engine,
+            "SELECT s.team, COUNT(*) AS n FROM requests r LEFT JOIN services s \
+             ON r.service = s.name GROUP BY s.team ORDER BY n DESC, s.team",
+        );
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![string("core"), Value::Int64(10)],
+                vec![Value::Null, Value::Int64(5)],
+                vec![string("payments"), Value::Int64(5)],
+            ]
+        );
+
+        let result = run(
+            &engine,
+            "SELECT COUNT(*) FROM requests r LEFT JOIN services s ON r.service = s.name \
+             WHERE s.team IS NULL",
+        );
+        assert_eq!(result.rows, vec![vec![Value::Int64(5)]]);
+    }
+
+    #[test]
+    fn test_lookup_from_with_query() {
+        let engine = setup_engine();
+        let result = run(
+            &engine,
+            "WITH slow AS (SELECT service, MAX(latency) AS worst FROM requests GROUP BY service) \
+             SELECT r.service, AVG(slow.worst) FROM requests r JOIN slow USING (service) \
+             WHERE r.latency < 2 GROUP BY r.service ORDER BY r.service",
+        );
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![string("api"), Value::Float64(16.0)],
+                vec![string("auth"), Value::Float64(17.0)],
+            ]
+        );
+
+        let error = run_query_at(
+            &engine,
+            "SELECT COUNT(*) FROM requests JOIN services ON requests.service = services.owner",
+            0,
+        );
+        assert!(matches!(
+            error,
+            Err(QueryError::Execute(ExecuteError::InvalidJoin(_)))
+        ));
+    }
+}
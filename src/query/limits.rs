@@ -0,0 +1,266 @@
+//! Query deadlines, cancellation and resource limits
+//!
+//! A query run through the API carries a `QueryControl`: its deadline, caps
+//! on the groups it builds, the rows it returns and the bytes of column data
+//! it reads, and a flag `DELETE /queries/:id` sets. The executor checks it
+//! between shards and row batches, so a query stops soon after it is
+//! cancelled or trips a limit, and fails saying which.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use super::executor::ExecuteError;
+
+/// Limits on one query. Unset limits do not apply.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryLimits {
+    /// Milliseconds the query may run for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Groups an aggregation may build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_groups: Option<usize>,
+    /// Rows the query may return
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_rows: Option<usize>,
+    /// Bytes of column data the query may read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_scanned_bytes: Option<usize>,
+}
+
+impl QueryLimits {
+    /// Each limit at the tighter of the two, so a request can lower the
+    /// server's limits but not raise them
+    pub fn tightened(&self, other: &QueryLimits) -> QueryLimits {
+        fn min<T: Ord + Copy>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+        QueryLimits {
+            timeout_ms: min(self.timeout_ms, other.timeout_ms),
+            max_groups: min(self.max_groups, other.max_groups),
+            max_output_rows: min(self.max_output_rows, other.max_output_rows),
+            max_scanned_bytes: min(self.max_scanned_bytes, other.max_scanned_bytes),
+        }
+    }
+}
+
+/// Whether a running query should go on, shared by everything working on it
+#[derive(Debug)]
+pub struct QueryControl {
+    limits: QueryLimits,
+    deadline: Option<Instant>,
+    cancelled: AtomicBool,
+    scanned_bytes: AtomicUsize,
+    shards_scanned: AtomicUsize,
+    /// The first limit the query went over
+    exceeded: Mutex<Option<String>>,
+}
+
+impl QueryControl {
+    /// Control for a query starting now
+    pub fn new(limits: QueryLimits) -> Self {
+        let deadline = limits
+            .timeout_ms
+            .map(|ms| Instant::now() + Duration::from_millis(ms));
+        Self {
+            limits,
+            deadline,
+            cancelled: AtomicBool::new(false),
+            scanned_bytes: AtomicUsize::new(0),
+            shards_scanned: AtomicUsize::new(0),
+            exceeded: Mutex::new(None),
+        }
+    }
+
+    pub fn limits(&self) -> &QueryLimits {
+        &self.limits
+    }
+
+    /// Stop the query at its next check
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the query has been cancelled, run out of time or gone over
+    /// a limit
+    pub fn is_stopped(&self) -> bool {
+        self.check().is_err()
+    }
+
+    /// Why the query has to stop, if it does
+    pub fn check(&self) -> Result<(), ExecuteError> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(ExecuteError::Cancelled);
+        }
+        if let Some(limit) = self.exceeded.lock().clone() {
+            return Err(ExecuteError::LimitExceeded(limit));
+        }
+        match (self.deadline, self.limits.timeout_ms) {
+            (Some(deadline), Some(ms)) if Instant::now() >= deadline => {
+                Err(ExecuteError::Timeout(ms))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a shard the query has started reading
+    pub fn add_shard(&self) {
+        self.shards_scanned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Shards the query has started reading so far on this node
+    pub fn shards_scanned(&self) -> usize {
+        self.shards_scanned.load(Ordering::Relaxed)
+    }
+
+    /// Count bytes of column data read, stopping the query once they go
+    /// over its limit
+    pub fn add_scanned_bytes(&self, bytes: usize) {
+        let total = self.scanned_bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if let Some(max) = self.limits.max_scanned_bytes.filter(|&max| total > max) {
+            self.exceed(format!("scanned more than {} bytes", max));
+        }
+    }
+
+    /// Whether `groups` groups are within the limit. Going over it stops
+    /// the query.
+    pub fn allows_groups(&self, groups: usize) -> bool {
+        match self.limits.max_groups {
+            Some(max) if groups > max => {
+                self.exceed(format!("more than {} groups", max));
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Fail if the query would return more rows than its limit
+    pub fn check_output_rows(&self, rows: usize) -> Result<(), ExecuteError> {
+        match self.limits.max_output_rows {
+            Some(max) if rows > max => {
+                self.exceed(format!("more than {} output rows", max));
+                self.check()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Limits for a peer running part of the query: the same caps, and
+    /// the time left until the deadline
+    pub fn remaining_limits(&self) -> QueryLimits {
+        let timeout_ms = self.deadline.map(|deadline| {
+            deadline.saturating_duration_since(Instant::now()).as_millis() as u64
+        });
+        QueryLimits {
+            timeout_ms,
+            ..self.limits.clone()
+        }
+    }
+
+    fn exceed(&self, limit: String) {
+        self.exceeded.lock().get_or_insert(limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Value;
+    use crate::query::{run_query_page, QueryError};
+    use crate::storage::StorageEngine;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_tightened() {
+        let server = QueryLimits {
+            timeout_ms: Some(60_000),
+            max_groups: Some(1000),
+            ..Default::default()
+        };
+        let request = QueryLimits {
+            timeout_ms: Some(5_000),
+            max_groups: Some(5000),
+            max_output_rows: Some(10),
+            ..Default::default()
+        };
+        let limits = server.tightened(&request);
+        assert_eq!(limits.timeout_ms, Some(5_000));
+        assert_eq!(limits.max_groups, Some(1000));
+        assert_eq!(limits.max_output_rows, Some(10));
+        assert_eq!(limits.max_scanned_bytes, None);
+    }
+
+    #[test]
+    fn test_query_control() {
+        let control = QueryControl::new(QueryLimits {
+            max_scanned_bytes: Some(100),
+            ..Default::default()
+        });
+        control.add_scanned_bytes(60);
+        assert!(control.check().is_ok());
+        control.add_scanned_bytes(60);
+        assert!(matches!(control.check(), Err(ExecuteError::LimitExceeded(_))));
+
+        let control = QueryControl::new(QueryLimits::default());
+        assert!(control.allows_groups(usize::MAX));
+        control.cancel();
+        assert!(matches!(control.check(), Err(ExecuteError::Cancelled)));
+
+        let control = QueryControl::new(QueryLimits {
+            timeout_ms: Some(0),
+            ..Default::default()
+        });
+        assert!(matches!(control.check(), Err(ExecuteError::Timeout(0))));
+        assert_eq!(control.remaining_limits().timeout_ms, Some(0));
+    }
+
+    #[test]
+    fn test_limits_stop_queries() {
+        let engine = StorageEngine::new();
+        for i in 0..1000 {
+            let mut row = HashMap::new();
+            row.insert("timestamp".to_string(), Value::Timestamp(i));
+            row.insert("user_id".to_string(), Value::Int64(i));
+            engine.insert("events", row).unwrap();
+        }
+        let run = |sql: &str, control: QueryControl| {
+            run_query_page(&engine, sql, 0, None, Some(&Arc::new(control)))
+        };
+        let limited = |result: Result<_, QueryError>| {
+            matches!(result, Err(QueryError::Execute(ExecuteError::LimitExceeded(_))))
+        };
+        let groups = "SELECT user_id, COUNT(*) FROM events GROUP BY user_id";
+        let scan = "SELECT user_id FROM events";
+
+        let limits = |limits: QueryLimits| QueryControl::new(limits);
+        let max_groups = |n| limits(QueryLimits { max_groups: Some(n), ..Default::default() });
+        assert!(limited(run(groups, max_groups(100))));
+        assert_eq!(run(groups, max_groups(1000)).unwrap().rows.len(), 1000);
+
+        let max_rows = |n| limits(QueryLimits { max_output_rows: Some(n), ..Default::default() });
+        assert!(limited(run(scan, max_rows(10))));
+        assert!(limited(run(groups, max_rows(10))));
+        assert_eq!(run(&format!("{} LIMIT 10", scan), max_rows(10)).unwrap().rows.len(), 10);
+
+        let max_bytes = limits(QueryLimits { max_scanned_bytes: Some(1), ..Default::default() });
+        assert!(limited(run(scan, max_bytes)));
+
+        let cancelled = QueryControl::new(QueryLimits::default());
+        cancelled.cancel();
+        assert!(matches!(
+            run(groups, cancelled),
+            Err(QueryError::Execute(ExecuteError::Cancelled))
+        ));
+        let expired = limits(QueryLimits { timeout_ms: Some(0), ..Default::default() });
+        assert!(matches!(
+            run(scan, expired),
+            Err(QueryError::Execute(ExecuteError::Timeout(0)))
+        ));
+    }
+}
@@ -1,27 +1,68 @@
 pub mod aggregates;
 pub mod cache;
+pub mod compare;
+pub mod cursor;
+pub mod ddsketch;
 pub mod executor;
+pub mod explain;
+pub mod fill;
+pub mod expr;
+pub mod functions;
+pub mod glob;
+pub mod histogram;
+pub mod hll;
+pub mod join;
+pub mod limits;
 pub mod parser;
 pub mod planner;
 pub mod predicate;
+pub mod registry;
+pub mod relative_time;
 pub mod simd_agg;
+pub mod subquery;
+pub mod time_bucket;
+pub mod topk;
+pub mod topn;
+pub mod window;
 
 pub use cache::{QueryCache, CacheStats};
+pub use cursor::ScanCursor;
 pub use predicate::RowMask;
 pub use simd_agg::AggregateStats;
 pub use executor::{execute_query, ExecuteError, QueryResult, AvailabilityMetrics};
-pub use parser::{parse_query, ParseError, ParsedQuery};
+pub use limits::{QueryControl, QueryLimits};
+pub use parser::{parse_query, parse_query_at, ParseError, ParsedQuery};
 pub use planner::{plan_query, PlanError, QueryPlan};
+pub use registry::QueryRegistry;
+pub use relative_time::query_time;
 
 /// Convenience function to parse, plan, and execute a query
 pub fn run_query(
     engine: &crate::storage::StorageEngine,
     sql: &str,
 ) -> Result<QueryResult, QueryError> {
-    let parsed = parse_query(sql)?;
-    let plan = plan_query(parsed)?;
-    let result = execute_query(engine, &plan)?;
-    Ok(result)
+    run_query_at(engine, sql, query_time())
+}
+
+/// Parse, plan and execute a query with NOW() and ago() resolved against `now`
+pub fn run_query_at(
+    engine: &crate::storage::StorageEngine,
+    sql: &str,
+    now: i64,
+) -> Result<QueryResult, QueryError> {
+    run_query_page(engine, sql, now, None, None)
+}
+
+/// Like `run_query_at`, with a scan resuming where `cursor` says the
+/// previous page stopped, stopping early as `control` says
+pub fn run_query_page(
+    engine: &crate::storage::StorageEngine,
+    sql: &str,
+    now: i64,
+    cursor: Option<ScanCursor>,
+    control: Option<&std::sync::Arc<QueryControl>>,
+) -> Result<QueryResult, QueryError> {
+    subquery::run_nested(engine, sql, now, &subquery::Relations::new(), cursor, control)
 }
 
 #[derive(Debug, thiserror::Error)]
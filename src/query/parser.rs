@@ -1,27 +1,87 @@
 use sqlparser::ast::{
-    BinaryOperator, Expr, FunctionArg, FunctionArgExpr, GroupByExpr, ObjectName, OrderByExpr,
-    SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, Value as SqlValue,
+    BinaryOperator, Expr, Function, FunctionArg, FunctionArgExpr, GroupByExpr, Join,
+    JoinConstraint, JoinOperator, ObjectName, OrderByExpr, SelectItem, SetExpr, SetOperator,
+    SetQuantifier, Statement, TableFactor, TableWithJoins, UnaryOperator, Value as SqlValue,
+    WindowType,
 };
 use sqlparser::dialect::GenericDialect;
-use sqlparser::parser::Parser;
-
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::Tokenizer;
+
+use super::compare::take_compare_clause;
+use super::explain::Explain;
+use super::expr::{negate, ArithmeticOp, ScalarExpr};
+use super::fill::{take_fill_clause, Fill};
+use super::functions::ScalarFunction;
+use super::glob::quote_table_globs;
+use super::join::LookupRows;
+use super::relative_time::{query_time, resolve_query};
+use super::time_bucket::TimeBucket;
+use super::topk::MAX_TOP_K;
+use super::window::WindowFunction;
 use crate::data::Value;
 
 /// Parsed query representation
 #[derive(Debug, Clone)]
 pub struct ParsedQuery {
-    /// Table name
+    /// Table name, or the name of a WITH query or FROM subquery. A name
+    /// with `*` reads every table it matches.
     pub table: String,
+    /// WITH queries as (name, SQL), in the order they are defined. Each
+    /// one may read the ones before it.
+    pub with: Vec<(String, String)>,
+    /// SQL of a FROM subquery, which `table` names
+    pub from_subquery: Option<String>,
+    /// SQL of each SELECT of a UNION ALL, whose rows together make up `table`
+    pub union_all: Vec<String>,
+    /// JOIN with a small lookup table
+    pub join: Option<LookupJoin>,
     /// Selected columns and aggregations
     pub projections: Vec<Projection>,
     /// WHERE conditions
-    pub filters: Vec<Filter>,
+    pub filters: Option<FilterExpr>,
     /// GROUP BY columns
     pub group_by: Vec<GroupByColumn>,
+    /// HAVING conditions, referencing output column names
+    pub having: Option<FilterExpr>,
+    /// Aggregations referenced only inside expressions or HAVING (computed
+    /// but not returned)
+    pub hidden_aggregations: Vec<Projection>,
     /// ORDER BY clauses
     pub order_by: Vec<OrderBy>,
     /// LIMIT
     pub limit: Option<usize>,
+    /// OFFSET: rows skipped before the limit applies
+    pub offset: Option<usize>,
+    /// Reference time NOW() and ago() were resolved against, if the query
+    /// used them
+    pub now: Option<i64>,
+    /// FILL clause after a time-bucketed GROUP BY
+    pub fill: Option<Fill>,
+    /// COMPARE TO offset in milliseconds: how far back the previous period is
+    pub compare_to: Option<i64>,
+    /// EXPLAIN or EXPLAIN ANALYZE before the query
+    pub explain: Option<Explain>,
+}
+
+/// An equi-join with a small lookup table, such as a mapping of services to
+/// teams loaded from CSV. The FROM table drives the join: each of its shards
+/// is joined with the whole lookup table.
+#[derive(Debug, Clone)]
+pub struct LookupJoin {
+    /// Lookup table, or the name of a WITH query
+    pub table: String,
+    /// What the lookup table's columns are qualified with: its alias or name
+    pub qualifier: String,
+    /// What the FROM table's columns are qualified with
+    pub driving_qualifier: String,
+    /// FROM table column matched against `lookup_column`
+    pub driving_column: String,
+    pub lookup_column: String,
+    /// LEFT JOIN: rows without a match are kept, with NULL lookup columns
+    pub left: bool,
+    /// The lookup table's rows, fetched before planning
+    pub rows: Option<LookupRows>,
 }
 
 #[derive(Debug, Clone)]
@@ -30,35 +90,267 @@ pub enum Projection {
     Column(String),
     /// All columns: SELECT *
     Wildcard,
-    /// Aggregation: SELECT COUNT(*), SUM(col), etc.
+    /// Aggregation: SELECT COUNT(*), SUM(col), AVG(end_time - start_time), etc.
     Aggregation {
         function: AggregateFunction,
-        column: Option<String>, // None for COUNT(*)
+        argument: Option<ScalarExpr>, // None for COUNT(*)
+        /// What FIRST, LAST, ARG_MAX and ARG_MIN order rows by
+        key: Option<ScalarExpr>,
+        /// Only rows matching this are aggregated: COUNT_IF(cond) or
+        /// SUM_IF(col, cond)
+        filter: Option<FilterExpr>,
         alias: Option<String>,
     },
-    /// TIME_BUCKET function
+    /// Arithmetic over columns, evaluated per row: SELECT bytes / 1024
+    Expression { expr: ScalarExpr, name: String },
+    /// Arithmetic over aggregate results: SELECT SUM(bytes) / 1024. Each
+    /// aggregate call is replaced by a reference to the output column that
+    /// computes it.
+    AggregateExpression { expr: ScalarExpr, name: String },
+    /// TIME_BUCKET('1 day', column[, 'Europe/Berlin'])
     TimeBucket {
-        interval_ms: i64,
+        bucket: TimeBucket,
         column: String,
         alias: Option<String>,
     },
+    /// Window function over aggregated rows: RATE(SUM(bytes)) or
+    /// MOVING_AVG(p99, 5) OVER (PARTITION BY host ORDER BY minute).
+    /// Without OVER, the planner picks the partition and order.
+    Window {
+        function: WindowFunction,
+        /// Refers to output columns, like HAVING
+        argument: ScalarExpr,
+        partition_by: Option<Vec<String>>,
+        order_by: Option<String>,
+        name: String,
+    },
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AggregateFunction {
     Count,
     Sum,
     Avg,
     Min,
     Max,
-    Percentile(u8), // P50, P90, P99, etc.
+    Percentile(f64), // 0.0 - 100.0: P50, P99, PERCENTILE(col, 99.9), etc.
+    /// COUNT(DISTINCT col) and APPROX_COUNT_DISTINCT(col), estimated with HyperLogLog
+    CountDistinct,
+    /// Sample standard deviation and variance
+    StdDev,
+    Variance,
+    /// Estimated like percentiles
+    Median,
+    /// Value at the earliest or latest timestamp
+    First,
+    Last,
+    /// ARG_MAX(col, key): value on the row with the largest key
+    ArgMax,
+    ArgMin,
+    /// APPROX_TOP_K(col, k): most frequent values, from a Space-Saving sketch
+    ApproxTopK(usize),
+    /// HISTOGRAM(col, width): counts in buckets of a fixed width
+    Histogram(f64),
+    /// HISTOGRAM_LOG(col): counts in log-linear buckets
+    HistogramLog,
 }
 
-#[derive(Debug, Clone)]
+impl AggregateFunction {
+    /// Output column name used when the aggregation has no alias
+    pub fn default_output_name(
+        &self,
+        argument: Option<&ScalarExpr>,
+        key: Option<&ScalarExpr>,
+        filter: Option<&FilterExpr>,
+    ) -> String {
+        let name = match self {
+            AggregateFunction::Percentile(p) => format!("p{}", p),
+            AggregateFunction::ArgMax => "arg_max".to_string(),
+            AggregateFunction::ArgMin => "arg_min".to_string(),
+            AggregateFunction::ApproxTopK(_) => "approx_top_k".to_string(),
+            AggregateFunction::Histogram(_) => "histogram".to_string(),
+            AggregateFunction::HistogramLog => "histogram_log".to_string(),
+            _ => format!("{:?}", self).to_lowercase(),
+        };
+        // FIRST and LAST are always keyed by timestamp
+        let argument = match (argument, key) {
+            (Some(argument), Some(key)) if !self.is_keyed_by_time() => {
+                Some(format!("{}_{}", argument, key))
+            }
+            (argument, _) => argument.map(|a| a.to_string()),
+        };
+
+        match filter {
+            Some(filter) => match argument {
+                Some(argument) => format!("{}_if({}, {})", name, argument, filter),
+                None => format!("{}_if({})", name, filter),
+            },
+            None => format!("{}_{}", name, argument.as_deref().unwrap_or("*")),
+        }
+    }
+
+    fn is_keyed_by_time(&self) -> bool {
+        matches!(self, AggregateFunction::First | AggregateFunction::Last)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Filter {
-    pub column: String,
+    /// Left-hand side: usually a column, possibly arithmetic over columns
+    pub operand: ScalarExpr,
     pub operator: FilterOperator,
     pub value: Value,
+    /// Operands for multi-value operators: the IN list, or BETWEEN's [low, high]
+    pub values: Vec<Value>,
+}
+
+/// Boolean expression tree for WHERE, HAVING and CASE conditions
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterExpr {
+    /// Single comparison: operand op value
+    Predicate(Filter),
+    /// All children must match
+    And(Vec<FilterExpr>),
+    /// At least one child must match
+    Or(Vec<FilterExpr>),
+    /// Child must be false. A predicate on NULL other than IS [NOT] NULL is
+    /// neither true nor false, so `NOT (x = 1)` skips rows where `x` is NULL.
+    Not(Box<FilterExpr>),
+    /// `operand [NOT] IN (SELECT ...)`. The subquery is run first and this
+    /// is replaced by an IN list of its results before planning.
+    InSubquery {
+        operand: ScalarExpr,
+        sql: String,
+        negated: bool,
+    },
+}
+
+impl FilterExpr {
+    /// Combine two expressions with AND, flattening nested ANDs
+    pub fn and(self, other: FilterExpr) -> FilterExpr {
+        let mut children = match self {
+            FilterExpr::And(children) => children,
+            expr => vec![expr],
+        };
+        match other {
+            FilterExpr::And(more) => children.extend(more),
+            expr => children.push(expr),
+        }
+        FilterExpr::And(children)
+    }
+
+    /// Combine two expressions with OR, flattening nested ORs
+    pub fn or(self, other: FilterExpr) -> FilterExpr {
+        let mut children = match self {
+            FilterExpr::Or(children) => children,
+            expr => vec![expr],
+        };
+        match other {
+            FilterExpr::Or(more) => children.extend(more),
+            expr => children.push(expr),
+        }
+        FilterExpr::Or(children)
+    }
+
+    /// Names of all columns the condition reads
+    pub fn columns(&self) -> Vec<&str> {
+        let mut columns = Vec::new();
+        self.collect_columns(&mut columns);
+        columns
+    }
+
+    pub(crate) fn collect_columns<'a>(&'a self, columns: &mut Vec<&'a str>) {
+        match self {
+            FilterExpr::Predicate(filter) => filter.operand.collect_columns(columns),
+            FilterExpr::And(children) | FilterExpr::Or(children) => {
+                for child in children {
+                    child.collect_columns(columns);
+                }
+            }
+            FilterExpr::Not(inner) => inner.collect_columns(columns),
+            FilterExpr::InSubquery { operand, .. } => operand.collect_columns(columns),
+        }
+    }
+
+    /// Whether the condition contains an IN subquery
+    pub fn has_subquery(&self) -> bool {
+        match self {
+            FilterExpr::InSubquery { .. } => true,
+            FilterExpr::And(children) | FilterExpr::Or(children) => {
+                children.iter().any(FilterExpr::has_subquery)
+            }
+            FilterExpr::Not(inner) => inner.has_subquery(),
+            FilterExpr::Predicate(_) => false,
+        }
+    }
+
+    /// Call `f` on each IN subquery, in the order they are written
+    pub fn for_each_subquery_mut(&mut self, f: &mut dyn FnMut(&mut FilterExpr)) {
+        match self {
+            FilterExpr::InSubquery { .. } => f(self),
+            FilterExpr::And(children) | FilterExpr::Or(children) => {
+                for child in children {
+                    child.for_each_subquery_mut(f);
+                }
+            }
+            FilterExpr::Not(inner) => inner.for_each_subquery_mut(f),
+            FilterExpr::Predicate(_) => {}
+        }
+    }
+}
+
+impl std::fmt::Display for FilterExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let child = |c: &FilterExpr| match c {
+            FilterExpr::Predicate(_) => c.to_string(),
+            _ => format!("({})", c),
+        };
+        match self {
+            FilterExpr::Predicate(filter) => write!(f, "{}", filter),
+            FilterExpr::And(children) => {
+                write!(f, "{}", children.iter().map(child).collect::<Vec<_>>().join(" AND "))
+            }
+            FilterExpr::Or(children) => {
+                write!(f, "{}", children.iter().map(child).collect::<Vec<_>>().join(" OR "))
+            }
+            FilterExpr::Not(inner) => write!(f, "NOT {}", child(inner)),
+            FilterExpr::InSubquery {
+                operand,
+                sql,
+                negated,
+            } => {
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "{} {}IN ({})", operand, not, sql)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let literal = |v: &Value| ScalarExpr::Literal(v.clone()).to_string();
+        let operator = match self.operator {
+            FilterOperator::Eq => "=",
+            FilterOperator::NotEq => "!=",
+            FilterOperator::Lt => "<",
+            FilterOperator::LtEq => "<=",
+            FilterOperator::Gt => ">",
+            FilterOperator::GtEq => ">=",
+            FilterOperator::Like => "LIKE",
+            FilterOperator::In | FilterOperator::NotIn => {
+                let list = self.values.iter().map(literal).collect::<Vec<_>>().join(", ");
+                let not = if self.operator == FilterOperator::NotIn { "NOT " } else { "" };
+                return write!(f, "{} {}IN ({})", self.operand, not, list);
+            }
+            FilterOperator::Between => {
+                let bounds = self.values.iter().map(literal).collect::<Vec<_>>();
+                return write!(f, "{} BETWEEN {}", self.operand, bounds.join(" AND "));
+            }
+            FilterOperator::IsNull => return write!(f, "{} IS NULL", self.operand),
+            FilterOperator::IsNotNull => return write!(f, "{} IS NOT NULL", self.operand),
+        };
+        write!(f, "{} {} {}", self.operand, operator, literal(&self.value))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -70,12 +362,18 @@ pub enum FilterOperator {
     Gt,
     GtEq,
     Like,
+    In,
+    NotIn,
+    Between,
+    IsNull,
+    IsNotNull,
 }
 
 #[derive(Debug, Clone)]
 pub enum GroupByColumn {
     Column(String),
-    TimeBucket { interval_ms: i64, column: String },
+    TimeBucket { bucket: TimeBucket, column: String },
+    Expression(ScalarExpr),
 }
 
 #[derive(Debug, Clone)]
@@ -84,10 +382,24 @@ pub struct OrderBy {
     pub descending: bool,
 }
 
-/// Parse a SQL query string
+/// Parse a SQL query string, resolving NOW() and ago() against the current time
 pub fn parse_query(sql: &str) -> Result<ParsedQuery, ParseError> {
+    parse_query_at(sql, query_time())
+}
+
+/// Parse a SQL query string, resolving NOW() and ago() against `now`
+/// (epoch milliseconds)
+pub fn parse_query_at(sql: &str, now: i64) -> Result<ParsedQuery, ParseError> {
     let dialect = GenericDialect {};
-    let statements = Parser::parse_sql(&dialect, sql)?;
+    let mut tokens = Tokenizer::new(&dialect, sql)
+        .tokenize_with_location()
+        .map_err(ParserError::from)?;
+    quote_table_globs(&mut tokens);
+    let fill = take_fill_clause(&mut tokens)?;
+    let compare_to = take_compare_clause(&mut tokens)?;
+    let mut statements = Parser::new(&dialect)
+        .with_tokens_with_locations(tokens)
+        .parse_statements()?;
 
     if statements.is_empty() {
         return Err(ParseError::EmptyQuery);
@@ -97,178 +409,810 @@ pub fn parse_query(sql: &str) -> Result<ParsedQuery, ParseError> {
         return Err(ParseError::MultipleStatements);
     }
 
-    match &statements[0] {
-        Statement::Query(query) => parse_select(query),
-        _ => Err(ParseError::UnsupportedStatement),
-    }
+    let (query, explain) = match &mut statements[0] {
+        Statement::Query(query) => (query, None),
+        Statement::Explain {
+            analyze, statement, ..
+        } => match &mut **statement {
+            Statement::Query(query) => {
+                let explain = if *analyze {
+                    Explain::Analyze
+                } else {
+                    Explain::Plan
+                };
+                (query, Some(explain))
+            }
+            _ => return Err(ParseError::UnsupportedStatement),
+        },
+        _ => return Err(ParseError::UnsupportedStatement),
+    };
+
+    let relative = resolve_query(query, now)?;
+    let mut parsed = parse_select(query)?;
+    parsed.now = relative.then_some(now);
+    parsed.fill = fill;
+    parsed.compare_to = compare_to;
+    parsed.explain = explain;
+    Ok(parsed)
 }
 
 fn parse_select(query: &sqlparser::ast::Query) -> Result<ParsedQuery, ParseError> {
     let select = match &*query.body {
         SetExpr::Select(select) => select,
+        SetExpr::SetOperation { .. } => return parse_union(query),
         _ => return Err(ParseError::UnsupportedQuery("Only SELECT queries supported".into())),
     };
 
-    let table = parse_table_name(&select.from)?;
-    let projections = parse_projections(&select.projection)?;
+    let with = parse_with(query)?;
+    let (table, from_subquery, join) = parse_from(&select.from)?;
+    let (projections, mut hidden_aggregations) = parse_projections(&select.projection)?;
     let filters = parse_where(&select.selection)?;
     let group_by = parse_group_by(&select.group_by)?;
+    let having = parse_having(&select.having, &projections, &mut hidden_aggregations)?;
     let order_by = parse_order_by(&query.order_by)?;
     let limit = parse_limit(&query.limit)?;
+    let offset = parse_limit(&query.offset.as_ref().map(|offset| offset.value.clone()))?;
 
     Ok(ParsedQuery {
         table,
+        with,
+        from_subquery,
+        union_all: Vec::new(),
+        join,
         projections,
         filters,
         group_by,
+        having,
+        hidden_aggregations,
         order_by,
         limit,
+        offset,
+        now: None,
+        fill: None,
+        compare_to: None,
+        explain: None,
+    })
+}
+
+/// A UNION ALL reads the rows of all its SELECTs like a table named
+/// "union", then applies its ORDER BY, LIMIT and OFFSET to them
+fn parse_union(query: &sqlparser::ast::Query) -> Result<ParsedQuery, ParseError> {
+    let mut union_all = Vec::new();
+    union_branches(&query.body, &mut union_all)?;
+
+    Ok(ParsedQuery {
+        table: "union".to_string(),
+        with: parse_with(query)?,
+        from_subquery: None,
+        union_all,
+        join: None,
+        projections: vec![Projection::Wildcard],
+        filters: None,
+        group_by: Vec::new(),
+        having: None,
+        hidden_aggregations: Vec::new(),
+        order_by: parse_order_by(&query.order_by)?,
+        limit: parse_limit(&query.limit)?,
+        offset: parse_limit(&query.offset.as_ref().map(|offset| offset.value.clone()))?,
+        now: None,
+        fill: None,
+        compare_to: None,
+        explain: None,
     })
 }
 
-fn parse_table_name(from: &[TableWithJoins]) -> Result<String, ParseError> {
+/// SQL of each SELECT in a chain of UNION ALLs
+fn union_branches(body: &SetExpr, branches: &mut Vec<String>) -> Result<(), ParseError> {
+    match body {
+        SetExpr::SetOperation {
+            op: SetOperator::Union,
+            set_quantifier: SetQuantifier::All,
+            left,
+            right,
+        } => {
+            union_branches(left, branches)?;
+            union_branches(right, branches)
+        }
+        SetExpr::SetOperation { op, .. } => Err(ParseError::UnsupportedQuery(format!(
+            "{} (only UNION ALL is supported)",
+            op
+        ))),
+        SetExpr::Select(_) => {
+            branches.push(body.to_string());
+            Ok(())
+        }
+        SetExpr::Query(query) => {
+            branches.push(query.to_string());
+            Ok(())
+        }
+        _ => Err(ParseError::UnsupportedQuery("Only SELECT queries supported".into())),
+    }
+}
+
+/// WITH queries as (name, SQL)
+fn parse_with(query: &sqlparser::ast::Query) -> Result<Vec<(String, String)>, ParseError> {
+    let Some(with) = &query.with else {
+        return Ok(Vec::new());
+    };
+    if with.recursive {
+        return Err(ParseError::UnsupportedQuery("WITH RECURSIVE".into()));
+    }
+
+    let mut queries: Vec<(String, String)> = Vec::new();
+    for cte in &with.cte_tables {
+        if !cte.alias.columns.is_empty() {
+            return Err(ParseError::UnsupportedQuery(format!(
+                "column list in WITH {}",
+                cte.alias
+            )));
+        }
+        let name = cte.alias.name.value.clone();
+        if queries.iter().any(|(defined, _)| *defined == name) {
+            return Err(ParseError::UnsupportedQuery(format!("WITH {} defined twice", name)));
+        }
+        queries.push((name, cte.query.to_string()));
+    }
+    Ok(queries)
+}
+
+/// The table the query reads, the SQL of a FROM subquery, and a lookup join
+fn parse_from(
+    from: &[TableWithJoins],
+) -> Result<(String, Option<String>, Option<LookupJoin>), ParseError> {
     if from.is_empty() {
         return Err(ParseError::MissingTable);
     }
 
     if from.len() > 1 {
-        return Err(ParseError::JoinsNotSupported);
+        return Err(ParseError::UnsupportedJoin("tables must be joined with JOIN".into()));
     }
 
     let table = &from[0];
-    if !table.joins.is_empty() {
-        return Err(ParseError::JoinsNotSupported);
-    }
+    let (name, subquery, alias) = match &table.relation {
+        TableFactor::Table { name, alias, .. } => (object_name_to_string(name), None, alias),
+        TableFactor::Derived {
+            lateral: false,
+            subquery,
+            alias,
+        } => {
+            let name = alias.as_ref().map_or("subquery".to_string(), |a| a.name.value.clone());
+            (name, Some(subquery.to_string()), alias)
+        }
+        _ => return Err(ParseError::UnsupportedTableExpression),
+    };
 
-    match &table.relation {
-        TableFactor::Table { name, .. } => Ok(object_name_to_string(name)),
-        _ => Err(ParseError::UnsupportedTableExpression),
+    let driving_qualifier = alias.as_ref().map_or(name.clone(), |a| a.name.value.clone());
+    let join = match table.joins.as_slice() {
+        [] => None,
+        [join] => Some(parse_join(join, &driving_qualifier)?),
+        _ => return Err(ParseError::UnsupportedJoin("only one JOIN per query".into())),
+    };
+    Ok((name, subquery, join))
+}
+
+/// A JOIN or LEFT JOIN with a lookup table, on one pair of equal columns
+fn parse_join(join: &Join, driving_qualifier: &str) -> Result<LookupJoin, ParseError> {
+    let (constraint, left) = match &join.join_operator {
+        JoinOperator::Inner(constraint) => (constraint, false),
+        JoinOperator::LeftOuter(constraint) => (constraint, true),
+        _ => {
+            return Err(ParseError::UnsupportedJoin(
+                "only JOIN and LEFT JOIN, with the FROM table driving the join".into(),
+            ))
+        }
+    };
+    let TableFactor::Table { name, alias, .. } = &join.relation else {
+        return Err(ParseError::UnsupportedJoin(
+            "the joined table must be a table or WITH query".into(),
+        ));
+    };
+
+    let table = object_name_to_string(name);
+    let qualifier = alias.as_ref().map_or(table.clone(), |a| a.name.value.clone());
+    if qualifier == driving_qualifier {
+        return Err(ParseError::UnsupportedJoin(format!(
+            "both tables are named {}; give one an alias",
+            qualifier
+        )));
     }
+
+    let (driving_column, lookup_column) = match constraint {
+        JoinConstraint::On(expr) => parse_join_condition(expr, driving_qualifier, &qualifier)?,
+        JoinConstraint::Using(columns) if columns.len() == 1 => {
+            (columns[0].value.clone(), columns[0].value.clone())
+        }
+        _ => {
+            return Err(ParseError::UnsupportedJoin(
+                "joins need ON a = b or USING (column)".into(),
+            ))
+        }
+    };
+
+    Ok(LookupJoin {
+        table,
+        qualifier,
+        driving_qualifier: driving_qualifier.to_string(),
+        driving_column,
+        lookup_column,
+        left,
+        rows: None,
+    })
+}
+
+/// The FROM table's and the lookup table's columns in `ON a = b`. Columns
+/// are told apart by their qualifiers, one of which is needed.
+fn parse_join_condition(
+    expr: &Expr,
+    driving_qualifier: &str,
+    qualifier: &str,
+) -> Result<(String, String), ParseError> {
+    let invalid = || {
+        ParseError::UnsupportedJoin(format!(
+            "ON must compare a column of each table, qualified as {}.column or {}.column",
+            driving_qualifier, qualifier
+        ))
+    };
+    let Expr::BinaryOp {
+        left,
+        op: BinaryOperator::Eq,
+        right,
+    } = expr
+    else {
+        return Err(invalid());
+    };
+    let (a, b) = (extract_column_name(left)?, extract_column_name(right)?);
+
+    let lookup_prefix = format!("{}.", qualifier);
+    let driving_prefix = format!("{}.", driving_qualifier);
+    let (driving, lookup) = match (a.starts_with(&lookup_prefix), b.starts_with(&lookup_prefix)) {
+        (false, true) => (a, b),
+        (true, false) => (b, a),
+        _ => match (a.starts_with(&driving_prefix), b.starts_with(&driving_prefix)) {
+            (true, false) => (a, b),
+            (false, true) => (b, a),
+            _ => return Err(invalid()),
+        },
+    };
+
+    let strip = |column: &str, prefix: &str| {
+        column.strip_prefix(prefix).unwrap_or(column).to_string()
+    };
+    Ok((strip(&driving, &driving_prefix), strip(&lookup, &lookup_prefix)))
 }
 
 fn object_name_to_string(name: &ObjectName) -> String {
     name.0.iter().map(|i| i.value.clone()).collect::<Vec<_>>().join(".")
 }
 
-fn parse_projections(items: &[SelectItem]) -> Result<Vec<Projection>, ParseError> {
+/// Parse the select list. Also returns the aggregations that expressions
+/// refer to but that are not selected themselves.
+fn parse_projections(items: &[SelectItem]) -> Result<(Vec<Projection>, Vec<Projection>), ParseError> {
     let mut projections = Vec::new();
+    let mut hidden = Vec::new();
 
     for item in items {
-        match item {
+        let projection = match item {
             SelectItem::UnnamedExpr(expr) => {
-                projections.push(parse_projection_expr(expr, None)?);
-            }
-            SelectItem::ExprWithAlias { expr, alias } => {
-                projections.push(parse_projection_expr(expr, Some(alias.value.clone()))?);
-            }
-            SelectItem::Wildcard(_) => {
-                projections.push(Projection::Wildcard);
+                parse_projection_expr(expr, None, &projections, &mut hidden)?
             }
+            SelectItem::ExprWithAlias { expr, alias } => parse_projection_expr(
+                expr,
+                Some(alias.value.clone()),
+                &projections,
+                &mut hidden,
+            )?,
+            SelectItem::Wildcard(_) => Projection::Wildcard,
             _ => return Err(ParseError::UnsupportedProjection),
-        }
+        };
+        projections.push(projection);
     }
 
-    Ok(projections)
+    Ok((projections, hidden))
 }
 
-fn parse_projection_expr(expr: &Expr, alias: Option<String>) -> Result<Projection, ParseError> {
+fn parse_projection_expr(
+    expr: &Expr,
+    alias: Option<String>,
+    selected: &[Projection],
+    hidden: &mut Vec<Projection>,
+) -> Result<Projection, ParseError> {
     match expr {
-        Expr::Identifier(ident) => Ok(Projection::Column(ident.value.clone())),
-
-        Expr::Function(func) => {
-            let func_name = func.name.to_string().to_uppercase();
-
-            match func_name.as_str() {
-                "COUNT" | "SUM" | "AVG" | "MIN" | "MAX" => {
-                    let agg_func = match func_name.as_str() {
-                        "COUNT" => AggregateFunction::Count,
-                        "SUM" => AggregateFunction::Sum,
-                        "AVG" => AggregateFunction::Avg,
-                        "MIN" => AggregateFunction::Min,
-                        "MAX" => AggregateFunction::Max,
-                        _ => unreachable!(),
-                    };
+        Expr::Identifier(_) | Expr::CompoundIdentifier(_) => {
+            Ok(Projection::Column(extract_column_name(expr)?))
+        }
+
+        Expr::Function(func) if func.name.to_string().eq_ignore_ascii_case("TIME_BUCKET") => {
+            let (bucket, column) = parse_time_bucket_args(&func.args)?;
+            Ok(Projection::TimeBucket {
+                bucket,
+                column,
+                alias,
+            })
+        }
+
+        Expr::Function(func) if WindowFunction::is_defined(&func.name.to_string()) => {
+            parse_window_call(func, alias, selected, hidden)
+        }
+
+        _ => {
+            if let Some(call) = parse_aggregate_call(expr)? {
+                return Ok(Projection::Aggregation {
+                    function: call.function,
+                    argument: call.argument,
+                    key: call.key,
+                    filter: call.filter,
+                    alias,
+                });
+            }
+
+            let mut aggregated = false;
+            let scalar = parse_scalar_expr(expr, &mut |call| {
+                aggregated = true;
+                aggregate_reference(call, selected, hidden)
+            })?;
+
+            // Unaliased expressions are named after their SQL text
+            let name = alias.unwrap_or_else(|| expr.to_string());
+            if aggregated {
+                Ok(Projection::AggregateExpression { expr: scalar, name })
+            } else {
+                Ok(Projection::Expression { expr: scalar, name })
+            }
+        }
+    }
+}
 
-                    let column = parse_function_column_arg(&func.args)?;
+/// Parse a window function call. Its first argument is an expression over
+/// output columns and aggregates; any others are integer literals.
+fn parse_window_call(
+    func: &Function,
+    alias: Option<String>,
+    selected: &[Projection],
+    hidden: &mut Vec<Projection>,
+) -> Result<Projection, ParseError> {
+    let args = func
+        .args
+        .iter()
+        .map(|arg| match arg {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => Ok(expr),
+            _ => Err(ParseError::InvalidFunctionArguments(func.to_string())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let Some((argument, options)) = args.split_first() else {
+        return Err(ParseError::InvalidFunctionArguments(func.to_string()));
+    };
 
-                    Ok(Projection::Aggregation {
-                        function: agg_func,
-                        column,
-                        alias,
-                    })
+    let argument = parse_scalar_expr(argument, &mut |call| {
+        aggregate_reference(call, selected, hidden)
+    })?;
+    let options = options
+        .iter()
+        .map(|option| extract_value(option))
+        .collect::<Result<Vec<_>, _>>()?;
+    let function = WindowFunction::resolve(&func.name.to_string(), &options)?;
+
+    let (partition_by, order_by) = match &func.over {
+        None => (None, None),
+        Some(WindowType::WindowSpec(spec)) => {
+            if spec.window_frame.is_some() {
+                return Err(ParseError::InvalidWindow(format!(
+                    "window frames are not supported: {}",
+                    func
+                )));
+            }
+            let partition_by = spec
+                .partition_by
+                .iter()
+                .map(extract_column_name)
+                .collect::<Result<Vec<_>, _>>()?;
+            // Rows are always ordered ascending by a single column
+            let order_by = match spec.order_by.as_slice() {
+                [] => None,
+                [order] if order.asc != Some(false) => Some(extract_column_name(&order.expr)?),
+                _ => {
+                    return Err(ParseError::InvalidWindow(format!(
+                        "ORDER BY must be a single ascending column: {}",
+                        func
+                    )))
                 }
-                "PERCENTILE" | "P50" | "P90" | "P95" | "P99" => {
-                    let percentile = match func_name.as_str() {
-                        "P50" => 50,
-                        "P90" => 90,
-                        "P95" => 95,
-                        "P99" => 99,
-                        "PERCENTILE" => 50,
-                        _ => 50,
-                    };
+            };
+            (Some(partition_by), order_by)
+        }
+        Some(WindowType::NamedWindow(name)) => {
+            return Err(ParseError::InvalidWindow(format!(
+                "named windows are not supported: {}",
+                name
+            )))
+        }
+    };
 
-                    let column = parse_function_column_arg(&func.args)?;
+    Ok(Projection::Window {
+        function,
+        argument,
+        partition_by,
+        order_by,
+        name: alias.unwrap_or_else(|| func.to_string()),
+    })
+}
 
-                    Ok(Projection::Aggregation {
-                        function: AggregateFunction::Percentile(percentile),
-                        column,
-                        alias,
-                    })
+/// Aggregate function call, before it is given an output column
+struct AggregateCall {
+    function: AggregateFunction,
+    argument: Option<ScalarExpr>,
+    key: Option<ScalarExpr>,
+    filter: Option<FilterExpr>,
+}
+
+impl AggregateCall {
+    /// Check if a projection computes this aggregate, returning its alias
+    fn alias_in(&self, projection: &Projection) -> Option<Option<String>> {
+        match projection {
+            Projection::Aggregation {
+                function,
+                argument,
+                key,
+                filter,
+                alias,
+            } if *function == self.function
+                && *argument == self.argument
+                && *key == self.key
+                && *filter == self.filter =>
+            {
+                Some(alias.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parse an aggregate function call. Returns `None` for anything that is
+/// not a call to an aggregate function.
+fn parse_aggregate_call(expr: &Expr) -> Result<Option<AggregateCall>, ParseError> {
+    let Expr::Function(func) = expr else {
+        return Ok(None);
+    };
+    let func_name = func.name.to_string().to_uppercase();
+
+    // Conditional variants take their condition as the last argument
+    let (base_name, args, condition) = match func_name.strip_suffix("_IF") {
+        Some(base @ ("COUNT" | "SUM" | "AVG" | "MIN" | "MAX")) => {
+            let Some((condition, args)) = func.args.split_last() else {
+                return Err(ParseError::UnsupportedExpression(func.to_string()));
+            };
+            let FunctionArg::Unnamed(FunctionArgExpr::Expr(condition)) = condition else {
+                return Err(ParseError::UnsupportedExpression(func.to_string()));
+            };
+            (base, args, Some(parse_row_condition(condition)?))
+        }
+        _ => (func_name.as_str(), func.args.as_slice(), None),
+    };
+
+    // FIRST and LAST order by timestamp; ARG_MAX and ARG_MIN by their second argument
+    let mut key = None;
+    let (function, argument) = match base_name {
+        "COUNT" | "APPROX_COUNT_DISTINCT" if func.distinct || base_name != "COUNT" => {
+            let argument = parse_function_argument(args)?
+                .ok_or_else(|| ParseError::UnsupportedExpression(format!("{}", func)))?;
+            (AggregateFunction::CountDistinct, Some(argument))
+        }
+        "COUNT" => (AggregateFunction::Count, parse_function_argument(args)?),
+        "SUM" => (AggregateFunction::Sum, parse_function_argument(args)?),
+        "AVG" => (AggregateFunction::Avg, parse_function_argument(args)?),
+        "MIN" => (AggregateFunction::Min, parse_function_argument(args)?),
+        "MAX" => (AggregateFunction::Max, parse_function_argument(args)?),
+        "P50" | "P90" | "P95" | "P99" | "PERCENTILE" => {
+            let percentile = match base_name {
+                "P50" => 50.0,
+                "P90" => 90.0,
+                "P95" => 95.0,
+                "P99" => 99.0,
+                _ => parse_percentile_arg(args)?,
+            };
+            (
+                AggregateFunction::Percentile(percentile),
+                parse_function_argument(args)?,
+            )
+        }
+        "STDDEV" | "STDDEV_SAMP" | "VARIANCE" | "VAR_SAMP" | "MEDIAN" | "FIRST" | "LAST" => {
+            let function = match base_name {
+                "STDDEV" | "STDDEV_SAMP" => AggregateFunction::StdDev,
+                "VARIANCE" | "VAR_SAMP" => AggregateFunction::Variance,
+                "MEDIAN" => AggregateFunction::Median,
+                "FIRST" => AggregateFunction::First,
+                _ => AggregateFunction::Last,
+            };
+            if args.len() != 1 {
+                return Err(ParseError::InvalidFunctionArguments(func.to_string()));
+            }
+            if function.is_keyed_by_time() {
+                key = Some(ScalarExpr::Column("timestamp".to_string()));
+            }
+            (function, Some(required_argument(func, args)?))
+        }
+        "APPROX_TOP_K" => {
+            let k = match args.get(1) {
+                Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(k))) if args.len() == 2 => {
+                    extract_value(k)?.as_i64()
                 }
-                "TIME_BUCKET" => {
-                    let (interval_ms, column) = parse_time_bucket_args(&func.args)?;
-                    Ok(Projection::TimeBucket {
-                        interval_ms,
-                        column,
-                        alias,
-                    })
+                _ => None,
+            };
+            match k {
+                Some(k) if k >= 1 && k as usize <= MAX_TOP_K => (
+                    AggregateFunction::ApproxTopK(k as usize),
+                    Some(required_argument(func, args)?),
+                ),
+                _ => {
+                    return Err(ParseError::InvalidFunctionArguments(format!(
+                        "{}: k must be between 1 and {}",
+                        func, MAX_TOP_K
+                    )))
                 }
-                _ => Err(ParseError::UnsupportedFunction(func_name)),
             }
         }
-
-        Expr::CompoundIdentifier(idents) => {
-            let col_name = idents.iter().map(|i| i.value.clone()).collect::<Vec<_>>().join(".");
-            Ok(Projection::Column(col_name))
+        "HISTOGRAM" => {
+            let width = match args.get(1) {
+                Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(width))) if args.len() == 2 => {
+                    extract_value(width)?.as_f64()
+                }
+                _ => None,
+            };
+            match width {
+                Some(width) if width > 0.0 && width.is_finite() => (
+                    AggregateFunction::Histogram(width),
+                    Some(required_argument(func, args)?),
+                ),
+                _ => {
+                    return Err(ParseError::InvalidFunctionArguments(format!(
+                        "{}: bucket width must be a positive number",
+                        func
+                    )))
+                }
+            }
+        }
+        "HISTOGRAM_LOG" => {
+            if args.len() != 1 {
+                return Err(ParseError::InvalidFunctionArguments(func.to_string()));
+            }
+            (AggregateFunction::HistogramLog, Some(required_argument(func, args)?))
         }
+        "ARG_MAX" | "ARG_MIN" => {
+            let [_, key_arg] = args else {
+                return Err(ParseError::InvalidFunctionArguments(func.to_string()));
+            };
+            key = Some(required_argument(func, std::slice::from_ref(key_arg))?);
+            let function = if base_name == "ARG_MAX" {
+                AggregateFunction::ArgMax
+            } else {
+                AggregateFunction::ArgMin
+            };
+            (function, Some(required_argument(func, args)?))
+        }
+        _ => return Ok(None),
+    };
 
-        _ => Err(ParseError::UnsupportedExpression(format!("{:?}", expr))),
+    Ok(Some(AggregateCall {
+        function,
+        argument,
+        key,
+        filter: condition,
+    }))
+}
+
+/// First argument of an aggregate that cannot take `*`
+fn required_argument(func: &Function, args: &[FunctionArg]) -> Result<ScalarExpr, ParseError> {
+    parse_function_argument(args)?
+        .ok_or_else(|| ParseError::InvalidFunctionArguments(func.to_string()))
+}
+
+/// Resolve an aggregate call inside an expression or HAVING to the output
+/// column that computes it. Aggregations that are not selected are added to
+/// `hidden` so they are computed without being returned.
+fn aggregate_reference(
+    call: &Expr,
+    selected: &[Projection],
+    hidden: &mut Vec<Projection>,
+) -> Result<ScalarExpr, ParseError> {
+    let Some(call) = parse_aggregate_call(call)? else {
+        return Err(ParseError::UnsupportedFunction(call.to_string()));
+    };
+
+    let selected_alias = selected.iter().find_map(|p| call.alias_in(p));
+    let name = call
+        .function
+        .default_output_name(call.argument.as_ref(), call.key.as_ref(), call.filter.as_ref());
+
+    match selected_alias {
+        Some(alias) => Ok(ScalarExpr::Column(alias.unwrap_or(name))),
+        None => {
+            if !hidden.iter().any(|p| call.alias_in(p).is_some()) {
+                hidden.push(Projection::Aggregation {
+                    function: call.function,
+                    argument: call.argument,
+                    key: call.key,
+                    filter: call.filter,
+                    alias: None,
+                });
+            }
+            Ok(ScalarExpr::Column(name))
+        }
     }
 }
 
-fn parse_function_column_arg(args: &[FunctionArg]) -> Result<Option<String>, ParseError> {
+/// First argument of an aggregate call: `None` for `*`, otherwise a column
+/// or an expression over columns
+fn parse_function_argument(args: &[FunctionArg]) -> Result<Option<ScalarExpr>, ParseError> {
     if args.is_empty() {
         return Ok(None);
     }
 
     match &args[0] {
         FunctionArg::Unnamed(FunctionArgExpr::Wildcard) => Ok(None),
-        FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(ident))) => {
-            Ok(Some(ident.value.clone()))
+        FunctionArg::Unnamed(FunctionArgExpr::Expr(expr))
+        | FunctionArg::Named { arg: FunctionArgExpr::Expr(expr), .. } => {
+            parse_scalar_expr(expr, &mut no_functions).map(Some)
         }
-        FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::CompoundIdentifier(idents))) => {
-            Ok(Some(idents.iter().map(|i| i.value.clone()).collect::<Vec<_>>().join(".")))
+        _ => Err(ParseError::UnsupportedExpression("Complex function argument".into())),
+    }
+}
+
+/// Parse an arithmetic expression. `function` handles function calls, since
+/// what they may refer to depends on the clause being parsed.
+fn parse_scalar_expr(
+    expr: &Expr,
+    function: &mut dyn FnMut(&Expr) -> Result<ScalarExpr, ParseError>,
+) -> Result<ScalarExpr, ParseError> {
+    match expr {
+        Expr::Identifier(_) | Expr::CompoundIdentifier(_) => {
+            Ok(ScalarExpr::Column(extract_column_name(expr)?))
         }
-        FunctionArg::Named { arg: FunctionArgExpr::Expr(Expr::Identifier(ident)), .. } => {
-            Ok(Some(ident.value.clone()))
+        Expr::Value(value) => Ok(ScalarExpr::Literal(sql_value_to_value(value)?)),
+        Expr::Nested(inner) => parse_scalar_expr(inner, function),
+        Expr::UnaryOp {
+            op: UnaryOperator::Plus,
+            expr,
+        } => parse_scalar_expr(expr, function),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match parse_scalar_expr(expr, function)? {
+            ScalarExpr::Literal(value) => Ok(ScalarExpr::Literal(negate(&value))),
+            inner => Ok(ScalarExpr::Negate(Box::new(inner))),
+        },
+        Expr::BinaryOp { left, op, right } => {
+            let op = match op {
+                BinaryOperator::Plus => ArithmeticOp::Add,
+                BinaryOperator::Minus => ArithmeticOp::Sub,
+                BinaryOperator::Multiply => ArithmeticOp::Mul,
+                BinaryOperator::Divide => ArithmeticOp::Div,
+                BinaryOperator::Modulo => ArithmeticOp::Mod,
+                _ => return Err(ParseError::UnsupportedOperator(format!("{:?}", op))),
+            };
+            Ok(ScalarExpr::Binary {
+                op,
+                left: Box::new(parse_scalar_expr(left, function)?),
+                right: Box::new(parse_scalar_expr(right, function)?),
+            })
         }
-        _ => Err(ParseError::UnsupportedExpression("Complex function argument".into())),
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            let mut branches = Vec::with_capacity(conditions.len());
+            for (condition, result) in conditions.iter().zip(results) {
+                let condition = match operand {
+                    // CASE x WHEN 1 THEN ... tests x = 1
+                    Some(operand) => FilterExpr::Predicate(Filter {
+                        operand: parse_scalar_expr(operand, function)?,
+                        operator: FilterOperator::Eq,
+                        value: extract_value(condition)?,
+                        values: Vec::new(),
+                    }),
+                    None => parse_filter_expr(condition, &mut |operand| {
+                        parse_scalar_expr(operand, function)
+                    })?,
+                };
+                if condition.has_subquery() {
+                    return Err(ParseError::UnsupportedExpression(
+                        "IN subquery inside CASE".into(),
+                    ));
+                }
+                branches.push((condition, parse_scalar_expr(result, function)?));
+            }
+            let otherwise = match else_result {
+                Some(result) => Some(Box::new(parse_scalar_expr(result, function)?)),
+                None => None,
+            };
+            Ok(ScalarExpr::Case {
+                branches,
+                otherwise,
+            })
+        }
+        Expr::Substring {
+            expr,
+            substring_from,
+            substring_for,
+            ..
+        } => {
+            let mut args = vec![
+                parse_scalar_expr(expr, function)?,
+                match substring_from {
+                    Some(from) => parse_scalar_expr(from, function)?,
+                    None => ScalarExpr::Literal(Value::Int64(1)),
+                },
+            ];
+            if let Some(length) = substring_for {
+                args.push(parse_scalar_expr(length, function)?);
+            }
+            Ok(ScalarExpr::Function {
+                function: ScalarFunction::Substr,
+                args,
+            })
+        }
+        Expr::Function(func) if ScalarFunction::is_defined(&func.name.to_string()) => {
+            let mut args = Vec::with_capacity(func.args.len());
+            for arg in &func.args {
+                match arg {
+                    FunctionArg::Unnamed(FunctionArgExpr::Expr(arg)) => {
+                        args.push(parse_scalar_expr(arg, function)?)
+                    }
+                    _ => return Err(ParseError::InvalidFunctionArguments(func.to_string())),
+                }
+            }
+            Ok(ScalarExpr::Function {
+                function: ScalarFunction::resolve(&func.name.to_string(), &args)?,
+                args,
+            })
+        }
+        Expr::Function(_) => function(expr),
+        _ => Err(ParseError::UnsupportedExpression(format!("{:?}", expr))),
+    }
+}
+
+/// Function handler for clauses evaluated per row, where aggregates are not allowed
+fn no_functions(call: &Expr) -> Result<ScalarExpr, ParseError> {
+    Err(ParseError::UnsupportedFunction(call.to_string()))
+}
+
+/// Second argument of PERCENTILE(col, p). Values below 1 are quantiles
+/// (0.95), the rest percentages (99.9), so 1 is p1 and p100 is 100.
+/// Defaults to the median.
+fn parse_percentile_arg(args: &[FunctionArg]) -> Result<f64, ParseError> {
+    let Some(arg) = args.get(1) else {
+        return Ok(50.0);
+    };
+
+    let value = match arg {
+        FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => extract_value(expr)?,
+        _ => return Err(ParseError::InvalidPercentile(arg.to_string())),
+    };
+
+    match value.as_f64() {
+        Some(p) if (0.0..1.0).contains(&p) => Ok(p * 100.0),
+        Some(p) if (1.0..=100.0).contains(&p) => Ok(p),
+        _ => Err(ParseError::InvalidPercentile(arg.to_string())),
     }
 }
 
-fn parse_time_bucket_args(args: &[FunctionArg]) -> Result<(i64, String), ParseError> {
-    if args.len() < 2 {
+fn parse_time_bucket_args(args: &[FunctionArg]) -> Result<(TimeBucket, String), ParseError> {
+    if args.len() < 2 || args.len() > 3 {
         return Err(ParseError::InvalidTimeBucket);
     }
 
-    // First arg: interval string like '5 minutes'
-    let interval_ms = match &args[0] {
+    let string_arg = |arg: &FunctionArg| match arg {
         FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(SqlValue::SingleQuotedString(s)))) => {
-            parse_interval(s)?
+            Ok(s.clone())
         }
-        _ => return Err(ParseError::InvalidTimeBucket),
+        _ => Err(ParseError::InvalidTimeBucket),
     };
 
+    // First arg: interval string like '5 minutes' or '1 month'; optional
+    // third arg: time zone like 'Europe/Berlin'
+    let interval = string_arg(&args[0])?;
+    let time_zone = args.get(2).map(string_arg).transpose()?;
+    let bucket = TimeBucket::parse(&interval, time_zone.as_deref())?;
+
     // Second arg: column name
     let column = match &args[1] {
         FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(ident))) => ident.value.clone(),
@@ -278,11 +1222,21 @@ fn parse_time_bucket_args(args: &[FunctionArg]) -> Result<(i64, String), ParseEr
         _ => return Err(ParseError::InvalidTimeBucket),
     };
 
-    Ok((interval_ms, column))
+    Ok((bucket, column))
 }
 
-fn parse_interval(s: &str) -> Result<i64, ParseError> {
-    let parts: Vec<&str> = s.trim().split_whitespace().collect();
+/// Parse an interval such as `5 minutes` or the compact `15m`
+pub(crate) fn parse_interval(s: &str) -> Result<i64, ParseError> {
+    let s = s.trim();
+    let parts: Vec<&str> = match s.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [compact] => {
+            let split = compact
+                .find(|c: char| !c.is_ascii_digit())
+                .ok_or_else(|| ParseError::InvalidInterval(s.to_string()))?;
+            vec![&compact[..split], &compact[split..]]
+        }
+        parts => parts.to_vec(),
+    };
     if parts.len() != 2 {
         return Err(ParseError::InvalidInterval(s.to_string()));
     }
@@ -298,28 +1252,60 @@ fn parse_interval(s: &str) -> Result<i64, ParseError> {
         "m" | "minute" | "minutes" => 60 * 1000,
         "h" | "hour" | "hours" => 3600 * 1000,
         "d" | "day" | "days" => 86400 * 1000,
+        "w" | "week" | "weeks" => 7 * 86400 * 1000,
         _ => return Err(ParseError::InvalidInterval(s.to_string())),
     };
 
     Ok(value * multiplier)
 }
 
-fn parse_where(selection: &Option<Expr>) -> Result<Vec<Filter>, ParseError> {
+fn parse_where(selection: &Option<Expr>) -> Result<Option<FilterExpr>, ParseError> {
     let Some(expr) = selection else {
-        return Ok(vec![]);
+        return Ok(None);
+    };
+
+    parse_row_condition(expr).map(Some)
+}
+
+/// Parse a condition evaluated against input rows, where aggregates are not allowed
+fn parse_row_condition(expr: &Expr) -> Result<FilterExpr, ParseError> {
+    parse_filter_expr(expr, &mut |operand| parse_scalar_expr(operand, &mut no_functions))
+}
+
+fn parse_having(
+    having: &Option<Expr>,
+    projections: &[Projection],
+    hidden: &mut Vec<Projection>,
+) -> Result<Option<FilterExpr>, ParseError> {
+    let Some(expr) = having else {
+        return Ok(None);
+    };
+
+    // Operands are output columns: aliases and group columns by name, and
+    // aggregate calls by the name of the matching projection
+    let mut operand = |operand: &Expr| {
+        parse_scalar_expr(operand, &mut |call| {
+            aggregate_reference(call, projections, hidden)
+        })
     };
 
-    parse_filter_expr(expr)
+    parse_filter_expr(expr, &mut operand).map(Some)
 }
 
-fn parse_filter_expr(expr: &Expr) -> Result<Vec<Filter>, ParseError> {
+/// Parse a boolean condition. `operand` parses the left-hand side of each
+/// predicate into the expression it tests.
+fn parse_filter_expr(
+    expr: &Expr,
+    operand: &mut dyn FnMut(&Expr) -> Result<ScalarExpr, ParseError>,
+) -> Result<FilterExpr, ParseError> {
     match expr {
         Expr::BinaryOp { left, op, right } => {
             match op {
                 BinaryOperator::And => {
-                    let mut filters = parse_filter_expr(left)?;
-                    filters.extend(parse_filter_expr(right)?);
-                    Ok(filters)
+                    Ok(parse_filter_expr(left, operand)?.and(parse_filter_expr(right, operand)?))
+                }
+                BinaryOperator::Or => {
+                    Ok(parse_filter_expr(left, operand)?.or(parse_filter_expr(right, operand)?))
                 }
                 BinaryOperator::Eq
                 | BinaryOperator::NotEq
@@ -327,7 +1313,7 @@ fn parse_filter_expr(expr: &Expr) -> Result<Vec<Filter>, ParseError> {
                 | BinaryOperator::LtEq
                 | BinaryOperator::Gt
                 | BinaryOperator::GtEq => {
-                    let column = extract_column_name(left)?;
+                    let target = operand(left)?;
                     let value = extract_value(right)?;
                     let operator = match op {
                         BinaryOperator::Eq => FilterOperator::Eq,
@@ -339,25 +1325,99 @@ fn parse_filter_expr(expr: &Expr) -> Result<Vec<Filter>, ParseError> {
                         _ => unreachable!(),
                     };
 
-                    Ok(vec![Filter {
-                        column,
+                    Ok(FilterExpr::Predicate(Filter {
+                        operand: target,
                         operator,
                         value,
-                    }])
+                        values: Vec::new(),
+                    }))
                 }
                 _ => Err(ParseError::UnsupportedOperator(format!("{:?}", op))),
             }
         }
-        Expr::Like { expr, pattern, .. } => {
-            let column = extract_column_name(expr)?;
+        Expr::Like {
+            negated,
+            expr,
+            pattern,
+            ..
+        } => {
+            let target = operand(expr)?;
             let value = extract_value(pattern)?;
-            Ok(vec![Filter {
-                column,
+            let filter = FilterExpr::Predicate(Filter {
+                operand: target,
                 operator: FilterOperator::Like,
                 value,
-            }])
+                values: Vec::new(),
+            });
+            if *negated {
+                Ok(FilterExpr::Not(Box::new(filter)))
+            } else {
+                Ok(filter)
+            }
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let target = operand(expr)?;
+            let values = list.iter().map(extract_value).collect::<Result<Vec<_>, _>>()?;
+            Ok(FilterExpr::Predicate(Filter {
+                operand: target,
+                operator: if *negated {
+                    FilterOperator::NotIn
+                } else {
+                    FilterOperator::In
+                },
+                value: Value::Null,
+                values,
+            }))
+        }
+        Expr::InSubquery {
+            expr,
+            subquery,
+            negated,
+        } => Ok(FilterExpr::InSubquery {
+            operand: operand(expr)?,
+            sql: subquery.to_string(),
+            negated: *negated,
+        }),
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => {
+            let target = operand(expr)?;
+            let filter = FilterExpr::Predicate(Filter {
+                operand: target,
+                operator: FilterOperator::Between,
+                value: Value::Null,
+                values: vec![extract_value(low)?, extract_value(high)?],
+            });
+            if *negated {
+                Ok(FilterExpr::Not(Box::new(filter)))
+            } else {
+                Ok(filter)
+            }
         }
-        Expr::Nested(inner) => parse_filter_expr(inner),
+        Expr::IsNull(expr) => Ok(FilterExpr::Predicate(Filter {
+            operand: operand(expr)?,
+            operator: FilterOperator::IsNull,
+            value: Value::Null,
+            values: Vec::new(),
+        })),
+        Expr::IsNotNull(expr) => Ok(FilterExpr::Predicate(Filter {
+            operand: operand(expr)?,
+            operator: FilterOperator::IsNotNull,
+            value: Value::Null,
+            values: Vec::new(),
+        })),
+        Expr::UnaryOp {
+            op: sqlparser::ast::UnaryOperator::Not,
+            expr,
+        } => Ok(FilterExpr::Not(Box::new(parse_filter_expr(expr, operand)?))),
+        Expr::Nested(inner) => parse_filter_expr(inner, operand),
         _ => Err(ParseError::UnsupportedExpression(format!("{:?}", expr))),
     }
 }
@@ -390,31 +1450,10 @@ fn extract_value(expr: &Expr) -> Result<Value, ParseError> {
             }
             Err(ParseError::ExpectedValue)
         }
-        // Handle NOW() - INTERVAL expressions
-        Expr::BinaryOp { left, op: BinaryOperator::Minus, right } => {
-            if is_now_function(left) {
-                if let Expr::Interval(interval) = right.as_ref() {
-                    let interval_str = interval.value.to_string();
-                    // Remove quotes if present
-                    let interval_str = interval_str.trim_matches('\'');
-                    let interval_ms = parse_interval(interval_str)?;
-                    let now = chrono::Utc::now().timestamp_millis();
-                    return Ok(Value::Timestamp(now - interval_ms));
-                }
-            }
-            Err(ParseError::ExpectedValue)
-        }
         _ => Err(ParseError::ExpectedValue),
     }
 }
 
-fn is_now_function(expr: &Expr) -> bool {
-    if let Expr::Function(func) = expr {
-        return func.name.to_string().to_uppercase() == "NOW";
-    }
-    false
-}
-
 fn sql_value_to_value(v: &SqlValue) -> Result<Value, ParseError> {
     match v {
         SqlValue::Number(n, _) => {
@@ -452,16 +1491,16 @@ fn parse_group_by(group_by: &GroupByExpr) -> Result<Vec<GroupByColumn>, ParseErr
                 let col_name = idents.iter().map(|i| i.value.clone()).collect::<Vec<_>>().join(".");
                 result.push(GroupByColumn::Column(col_name));
             }
-            Expr::Function(func) => {
-                let func_name = func.name.to_string().to_uppercase();
-                if func_name == "TIME_BUCKET" {
-                    let (interval_ms, column) = parse_time_bucket_args(&func.args)?;
-                    result.push(GroupByColumn::TimeBucket { interval_ms, column });
-                } else {
-                    return Err(ParseError::UnsupportedGroupByExpression);
-                }
+            Expr::Function(func) if func.name.to_string().eq_ignore_ascii_case("TIME_BUCKET") => {
+                let (bucket, column) = parse_time_bucket_args(&func.args)?;
+                result.push(GroupByColumn::TimeBucket { bucket, column });
+            }
+            _ => {
+                let expr = parse_scalar_expr(expr, &mut |_| {
+                    Err(ParseError::UnsupportedGroupByExpression)
+                })?;
+                result.push(GroupByColumn::Expression(expr));
             }
-            _ => return Err(ParseError::UnsupportedGroupByExpression),
         }
     }
 
@@ -522,8 +1561,8 @@ pub enum ParseError {
     #[error("Missing FROM table")]
     MissingTable,
 
-    #[error("JOINs are not supported")]
-    JoinsNotSupported,
+    #[error("Unsupported join: {0}")]
+    UnsupportedJoin(String),
 
     #[error("Unsupported table expression")]
     UnsupportedTableExpression,
@@ -537,6 +1576,9 @@ pub enum ParseError {
     #[error("Unsupported expression: {0}")]
     UnsupportedExpression(String),
 
+    #[error("Invalid function arguments: {0}")]
+    InvalidFunctionArguments(String),
+
     #[error("Unsupported operator: {0}")]
     UnsupportedOperator(String),
 
@@ -555,22 +1597,41 @@ pub enum ParseError {
     #[error("Invalid interval: {0}")]
     InvalidInterval(String),
 
+    #[error("Invalid percentile: {0} (expected 0-1 or 0-100)")]
+    InvalidPercentile(String),
+
     #[error("Invalid TIME_BUCKET arguments")]
     InvalidTimeBucket,
 
+    #[error("Unknown time zone: {0}")]
+    InvalidTimeZone(String),
+
+    #[error("Invalid FILL clause: {0}")]
+    InvalidFill(String),
+
+    #[error("Invalid COMPARE TO clause: {0}")]
+    InvalidCompare(String),
+
+    #[error("Invalid window function: {0}")]
+    InvalidWindow(String),
+
     #[error("Unsupported GROUP BY expression")]
     UnsupportedGroupByExpression,
 
     #[error("Unsupported ORDER BY expression")]
     UnsupportedOrderByExpression,
 
-    #[error("Invalid LIMIT value")]
+    #[error("Invalid LIMIT or OFFSET value")]
     InvalidLimit,
+
+    #[error("Invalid pagination cursor: {0}")]
+    InvalidCursor(String),
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::query::time_bucket::BucketInterval;
 
     #[test]
     fn test_simple_select() {
@@ -592,9 +1653,9 @@ mod tests {
         let query = parse_query("SELECT COUNT(*), SUM(value), AVG(latency) FROM events").unwrap();
         assert_eq!(query.projections.len(), 3);
 
-        if let Projection::Aggregation { function, column, .. } = &query.projections[0] {
+        if let Projection::Aggregation { function, argument, .. } = &query.projections[0] {
             assert_eq!(*function, AggregateFunction::Count);
-            assert!(column.is_none());
+            assert!(argument.is_none());
         } else {
             panic!("Expected aggregation");
         }
@@ -604,11 +1665,290 @@ mod tests {
     fn test_where_clause() {
         let query =
             parse_query("SELECT * FROM events WHERE event = 'click' AND value > 100").unwrap();
-        assert_eq!(query.filters.len(), 2);
-        assert_eq!(query.filters[0].column, "event");
-        assert_eq!(query.filters[0].operator, FilterOperator::Eq);
-        assert_eq!(query.filters[1].column, "value");
-        assert_eq!(query.filters[1].operator, FilterOperator::Gt);
+        let Some(FilterExpr::And(filters)) = &query.filters else {
+            panic!("Expected AND filter");
+        };
+        assert_eq!(filters.len(), 2);
+        let FilterExpr::Predicate(first) = &filters[0] else {
+            panic!("Expected predicate");
+        };
+        assert_eq!(first.operand.as_column(), Some("event"));
+        assert_eq!(first.operator, FilterOperator::Eq);
+        let FilterExpr::Predicate(second) = &filters[1] else {
+            panic!("Expected predicate");
+        };
+        assert_eq!(second.operand.as_column(), Some("value"));
+        assert_eq!(second.operator, FilterOperator::Gt);
+    }
+
+    #[test]
+    fn test_where_or_clause() {
+        let query =
+            parse_query("SELECT * FROM events WHERE status = 500 OR status = 503 OR status = 504")
+                .unwrap();
+        let Some(FilterExpr::Or(filters)) = &query.filters else {
+            panic!("Expected OR filter");
+        };
+        assert_eq!(filters.len(), 3);
+    }
+
+    #[test]
+    fn test_where_not_and_parentheses() {
+        let query = parse_query(
+            "SELECT * FROM events WHERE event = 'click' AND (status = 500 OR NOT value > 10)",
+        )
+        .unwrap();
+        let Some(FilterExpr::And(filters)) = &query.filters else {
+            panic!("Expected AND filter");
+        };
+        assert!(matches!(filters[0], FilterExpr::Predicate(_)));
+        let FilterExpr::Or(alternatives) = &filters[1] else {
+            panic!("Expected nested OR");
+        };
+        assert!(matches!(alternatives[0], FilterExpr::Predicate(_)));
+        assert!(matches!(alternatives[1], FilterExpr::Not(_)));
+    }
+
+    #[test]
+    fn test_where_not_like() {
+        let query = parse_query("SELECT * FROM events WHERE page NOT LIKE '/api/%'").unwrap();
+        let Some(FilterExpr::Not(inner)) = &query.filters else {
+            panic!("Expected NOT filter");
+        };
+        assert!(
+            matches!(inner.as_ref(), FilterExpr::Predicate(f) if f.operator == FilterOperator::Like)
+        );
+    }
+
+    fn single_predicate(query: &ParsedQuery) -> &Filter {
+        match &query.filters {
+            Some(FilterExpr::Predicate(filter)) => filter,
+            other => panic!("Expected single predicate, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_where_in_list() {
+        let query = parse_query("SELECT * FROM events WHERE country IN ('US', 'CA')").unwrap();
+        let filter = single_predicate(&query);
+        assert_eq!(filter.operator, FilterOperator::In);
+        assert_eq!(
+            filter.values,
+            vec![Value::String("US".into()), Value::String("CA".into())]
+        );
+
+        let query = parse_query("SELECT * FROM events WHERE status NOT IN (500, 503)").unwrap();
+        let filter = single_predicate(&query);
+        assert_eq!(filter.operator, FilterOperator::NotIn);
+        assert_eq!(filter.values, vec![Value::Int64(500), Value::Int64(503)]);
+    }
+
+    #[test]
+    fn test_where_between() {
+        let query =
+            parse_query("SELECT * FROM events WHERE latency BETWEEN 100 AND 200").unwrap();
+        let filter = single_predicate(&query);
+        assert_eq!(filter.operator, FilterOperator::Between);
+        assert_eq!(filter.values, vec![Value::Int64(100), Value::Int64(200)]);
+
+        let query =
+            parse_query("SELECT * FROM events WHERE latency NOT BETWEEN 100 AND 200").unwrap();
+        assert!(matches!(query.filters, Some(FilterExpr::Not(_))));
+    }
+
+    #[test]
+    fn test_where_is_null() {
+        let query = parse_query("SELECT * FROM events WHERE user_id IS NULL").unwrap();
+        assert_eq!(single_predicate(&query).operator, FilterOperator::IsNull);
+
+        let query = parse_query("SELECT * FROM events WHERE user_id IS NOT NULL").unwrap();
+        assert_eq!(single_predicate(&query).operator, FilterOperator::IsNotNull);
+    }
+
+    #[test]
+    fn test_having_resolves_output_names() {
+        let query = parse_query(
+            "SELECT endpoint, COUNT(*) AS cnt, P99(latency) FROM logs GROUP BY endpoint \
+             HAVING COUNT(*) > 100 AND P99(latency) > 500 AND MAX(latency) < 2000",
+        )
+        .unwrap();
+
+        let Some(FilterExpr::And(children)) = &query.having else {
+            panic!("Expected AND, got {:?}", query.having);
+        };
+        let columns: Vec<&str> = children
+            .iter()
+            .map(|c| match c {
+                FilterExpr::Predicate(f) => f.operand.as_column().unwrap(),
+                other => panic!("Expected predicate, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(columns, vec!["cnt", "p99_latency", "max_latency"]);
+
+        // Only MAX(latency) is missing from the select list
+        assert_eq!(query.hidden_aggregations.len(), 1);
+        assert!(matches!(
+            &query.hidden_aggregations[0],
+            Projection::Aggregation {
+                function: AggregateFunction::Max,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_count_distinct() {
+        let query = parse_query(
+            "SELECT page, COUNT(DISTINCT user_id), APPROX_COUNT_DISTINCT(session) AS sessions \
+             FROM views GROUP BY page",
+        )
+        .unwrap();
+
+        match &query.projections[1] {
+            Projection::Aggregation {
+                function, argument, ..
+            } => {
+                assert_eq!(*function, AggregateFunction::CountDistinct);
+                assert_eq!(argument.as_ref().and_then(ScalarExpr::as_column), Some("user_id"));
+            }
+            other => panic!("Expected aggregation, got {:?}", other),
+        }
+        assert!(matches!(
+            &query.projections[2],
+            Projection::Aggregation {
+                function: AggregateFunction::CountDistinct,
+                ..
+            }
+        ));
+
+        assert!(parse_query("SELECT COUNT(DISTINCT *) FROM views").is_err());
+    }
+
+    #[test]
+    fn test_statistical_aggregates() {
+        let query = parse_query(
+            "SELECT STDDEV(latency), VAR_SAMP(latency), MEDIAN(latency), LAST(version), \
+             ARG_MAX(host, latency) FROM logs",
+        )
+        .unwrap();
+
+        let functions: Vec<_> = query
+            .projections
+            .iter()
+            .map(|p| match p {
+                Projection::Aggregation { function, key, .. } => (*function, key.clone()),
+                other => panic!("Expected aggregation, got {:?}", other),
+            })
+            .collect();
+        let column = |name: &str| Some(ScalarExpr::Column(name.into()));
+        assert_eq!(
+            functions,
+            vec![
+                (AggregateFunction::StdDev, None),
+                (AggregateFunction::Variance, None),
+                (AggregateFunction::Median, None),
+                (AggregateFunction::Last, column("timestamp")),
+                (AggregateFunction::ArgMax, column("latency")),
+            ]
+        );
+
+        for sql in ["SELECT ARG_MAX(host) FROM logs", "SELECT FIRST(*) FROM logs"] {
+            assert!(
+                matches!(parse_query(sql), Err(ParseError::InvalidFunctionArguments(_))),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_approx_top_k() {
+        let query = parse_query("SELECT APPROX_TOP_K(url, 20) FROM requests").unwrap();
+        assert!(matches!(
+            &query.projections[0],
+            Projection::Aggregation {
+                function: AggregateFunction::ApproxTopK(20),
+                ..
+            }
+        ));
+
+        for sql in [
+            "SELECT APPROX_TOP_K(url) FROM requests",
+            "SELECT APPROX_TOP_K(url, 0) FROM requests",
+            "SELECT APPROX_TOP_K(url, 5000) FROM requests",
+        ] {
+            assert!(
+                matches!(parse_query(sql), Err(ParseError::InvalidFunctionArguments(_))),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let sql = "SELECT HISTOGRAM(latency, 50), HISTOGRAM_LOG(latency) FROM requests";
+        let query = parse_query(sql).unwrap();
+        assert!(matches!(
+            &query.projections[0],
+            Projection::Aggregation {
+                function: AggregateFunction::Histogram(w),
+                ..
+            } if *w == 50.0
+        ));
+        assert!(matches!(
+            &query.projections[1],
+            Projection::Aggregation {
+                function: AggregateFunction::HistogramLog,
+                ..
+            }
+        ));
+        let latency = ScalarExpr::Column("latency".to_string());
+        assert_eq!(
+            AggregateFunction::HistogramLog.default_output_name(Some(&latency), None, None),
+            "histogram_log_latency"
+        );
+
+        for sql in [
+            "SELECT HISTOGRAM(latency) FROM requests",
+            "SELECT HISTOGRAM(latency, 0) FROM requests",
+            "SELECT HISTOGRAM_LOG(latency, 10) FROM requests",
+        ] {
+            assert!(
+                matches!(parse_query(sql), Err(ParseError::InvalidFunctionArguments(_))),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_percentile_arguments() {
+        let query = parse_query(
+            "SELECT PERCENTILE(latency, 99.9), PERCENTILE(latency, 0.95), \
+             PERCENTILE(latency), P99(latency), PERCENTILE(latency, 1), \
+             PERCENTILE(latency, 100) FROM logs",
+        )
+        .unwrap();
+        let percentiles: Vec<f64> = query
+            .projections
+            .iter()
+            .map(|p| match p {
+                Projection::Aggregation {
+                    function: AggregateFunction::Percentile(p),
+                    ..
+                } => *p,
+                other => panic!("Expected percentile, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(percentiles, vec![99.9, 95.0, 50.0, 99.0, 1.0, 100.0]);
+
+        assert_eq!(
+            AggregateFunction::Percentile(99.9)
+                .default_output_name(Some(&ScalarExpr::Column("latency".into())), None, None),
+            "p99.9_latency"
+        );
+        assert!(parse_query("SELECT PERCENTILE(latency, 150) FROM logs").is_err());
     }
 
     #[test]
@@ -618,6 +1958,115 @@ mod tests {
         assert!(matches!(&query.group_by[0], GroupByColumn::Column(c) if c == "event"));
     }
 
+    #[test]
+    fn test_arithmetic_projections() {
+        let query = parse_query(
+            "SELECT bytes / 1024 AS kb, SUM(bytes) / 1024, AVG(end_time - start_time) FROM logs",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            &query.projections[0],
+            Projection::Expression { name, .. } if name == "kb"
+        ));
+
+        // The SUM is computed as a hidden aggregation the expression refers to
+        let Projection::AggregateExpression { expr, name } = &query.projections[1] else {
+            panic!("Expected aggregate expression, got {:?}", query.projections[1]);
+        };
+        assert_eq!(name, "SUM(bytes) / 1024");
+        assert_eq!(expr.columns(), vec!["sum_bytes"]);
+        assert_eq!(query.hidden_aggregations.len(), 1);
+
+        let Projection::Aggregation { argument: Some(argument), .. } = &query.projections[2] else {
+            panic!("Expected aggregation, got {:?}", query.projections[2]);
+        };
+        assert_eq!(argument.columns(), vec!["end_time", "start_time"]);
+    }
+
+    #[test]
+    fn test_expressions_in_where_and_group_by() {
+        let query = parse_query(
+            "SELECT status / 100, COUNT(*) FROM logs WHERE latency_ms * 1.0 / size > 2.5 \
+             GROUP BY status / 100",
+        )
+        .unwrap();
+
+        let filter = single_predicate(&query);
+        assert_eq!(filter.operand.columns(), vec!["latency_ms", "size"]);
+        assert!(matches!(query.group_by[0], GroupByColumn::Expression(_)));
+
+        assert!(parse_query("SELECT * FROM logs WHERE COUNT(*) > 1").is_err());
+    }
+
+    #[test]
+    fn test_case_and_conditional_aggregates() {
+        let query = parse_query(
+            "SELECT CASE WHEN status >= 500 THEN 'error' ELSE 'ok' END AS outcome, \
+             COUNT_IF(status >= 500), SUM_IF(bytes, region = 'us') FROM logs \
+             GROUP BY outcome",
+        )
+        .unwrap();
+
+        let Projection::Expression { expr, name } = &query.projections[0] else {
+            panic!("Expected expression, got {:?}", query.projections[0]);
+        };
+        assert_eq!(name, "outcome");
+        assert!(matches!(expr, ScalarExpr::Case { branches, otherwise: Some(_) } if branches.len() == 1));
+        assert_eq!(expr.columns(), vec!["status"]);
+
+        let Projection::Aggregation { function, argument: None, filter: Some(filter), .. } =
+            &query.projections[1]
+        else {
+            panic!("Expected COUNT_IF, got {:?}", query.projections[1]);
+        };
+        assert_eq!(*function, AggregateFunction::Count);
+        assert_eq!(filter.columns(), vec!["status"]);
+
+        let Projection::Aggregation { function, argument: Some(_), filter: Some(filter), .. } =
+            &query.projections[2]
+        else {
+            panic!("Expected filtered SUM, got {:?}", query.projections[2]);
+        };
+        assert_eq!(*function, AggregateFunction::Sum);
+        assert_eq!(filter.columns(), vec!["region"]);
+
+        assert!(parse_query("SELECT COUNT_IF(COUNT(*) > 1) FROM logs").is_err());
+    }
+
+    #[test]
+    fn test_string_functions() {
+        let query = parse_query(
+            "SELECT SPLIT_PART(page, '/', 2) AS section, COUNT(*) FROM logs \
+             WHERE LOWER(method) = 'get' AND REGEXP_EXTRACT(message, 'user=(\\d+)') IS NOT NULL \
+             GROUP BY section",
+        )
+        .unwrap();
+
+        let Projection::Expression { expr, .. } = &query.projections[0] else {
+            panic!("Expected expression, got {:?}", query.projections[0]);
+        };
+        assert_eq!(expr.to_string(), "SPLIT_PART(page, '/', 2)");
+
+        let columns: Vec<&str> = query.filters.as_ref().unwrap().columns();
+        assert_eq!(columns, vec!["method", "message"]);
+
+        let query = parse_query("SELECT SUBSTRING(page FROM 2 FOR 3) FROM logs").unwrap();
+        let Projection::Expression { expr, .. } = &query.projections[0] else {
+            panic!("Expected expression, got {:?}", query.projections[0]);
+        };
+        assert_eq!(expr.to_string(), "SUBSTR(page, 2, 3)");
+
+        assert!(matches!(
+            parse_query("SELECT UPPER(a, b) FROM logs"),
+            Err(ParseError::InvalidFunctionArguments(_))
+        ));
+        assert!(matches!(
+            parse_query("SELECT REGEXP_EXTRACT(message, pattern) FROM logs"),
+            Err(ParseError::InvalidFunctionArguments(_))
+        ));
+    }
+
     #[test]
     fn test_order_by_and_limit() {
         let query =
@@ -628,31 +2077,256 @@ mod tests {
         assert_eq!(query.limit, Some(100));
     }
 
+    #[test]
+    fn test_limit_offset() {
+        let query = parse_query("SELECT * FROM events LIMIT 50 OFFSET 100").unwrap();
+        assert_eq!((query.limit, query.offset), (Some(50), Some(100)));
+        assert_eq!(parse_query("SELECT * FROM events LIMIT 50").unwrap().offset, None);
+        assert!(matches!(
+            parse_query("SELECT * FROM events LIMIT 50 OFFSET -1"),
+            Err(ParseError::InvalidLimit)
+        ));
+    }
+
     #[test]
     fn test_time_bucket() {
         let query = parse_query(
             "SELECT TIME_BUCKET('5 minutes', timestamp), COUNT(*) FROM events GROUP BY TIME_BUCKET('5 minutes', timestamp)"
         ).unwrap();
 
-        if let Projection::TimeBucket { interval_ms, column, .. } = &query.projections[0] {
-            assert_eq!(*interval_ms, 5 * 60 * 1000);
+        if let Projection::TimeBucket { bucket, column, .. } = &query.projections[0] {
+            assert_eq!(bucket.interval, BucketInterval::Fixed(5 * 60 * 1000));
+            assert_eq!(bucket.time_zone, None);
             assert_eq!(column, "timestamp");
         } else {
             panic!("Expected time bucket");
         }
     }
 
+    #[test]
+    fn test_time_bucket_calendar_and_time_zone() {
+        let query = parse_query(
+            "SELECT TIME_BUCKET('1 month', timestamp, 'Europe/Berlin') AS month, COUNT(*) \
+             FROM events GROUP BY month",
+        )
+        .unwrap();
+
+        if let Projection::TimeBucket { bucket, .. } = &query.projections[0] {
+            assert_eq!(bucket.interval, BucketInterval::Months(1));
+            assert_eq!(bucket.time_zone, Some(chrono_tz::Europe::Berlin));
+        } else {
+            panic!("Expected time bucket");
+        }
+
+        assert!(matches!(
+            parse_query("SELECT TIME_BUCKET('1 day', timestamp, 'Nowhere/Special') FROM events"),
+            Err(ParseError::InvalidTimeZone(_))
+        ));
+    }
+
+    #[test]
+    fn test_window_functions() {
+        let query = parse_query(
+            "SELECT host, TIME_BUCKET('1 minute', timestamp) AS minute, \
+             MOVING_AVG(SUM(bytes) / 1024, 5) OVER (PARTITION BY host ORDER BY minute) AS kb, \
+             RATE(requests) FROM metrics GROUP BY host, minute",
+        )
+        .unwrap();
+
+        if let Projection::Window {
+            function,
+            argument,
+            partition_by,
+            order_by,
+            name,
+        } = &query.projections[2]
+        {
+            assert_eq!(*function, WindowFunction::MovingAvg(5));
+            assert_eq!(argument.to_string(), "sum_bytes / 1024");
+            assert_eq!(partition_by.as_deref(), Some(&["host".to_string()][..]));
+            assert_eq!(order_by.as_deref(), Some("minute"));
+            assert_eq!(name, "kb");
+        } else {
+            panic!("Expected window function");
+        }
+        // The aggregate is computed without being returned
+        assert_eq!(query.hidden_aggregations.len(), 1);
+
+        if let Projection::Window {
+            function,
+            partition_by,
+            order_by,
+            ..
+        } = &query.projections[3]
+        {
+            assert_eq!(*function, WindowFunction::Rate);
+            assert_eq!((partition_by, order_by), (&None, &None));
+        } else {
+            panic!("Expected window function");
+        }
+
+        for sql in [
+            "SELECT LAG(x) OVER (ORDER BY t DESC) FROM m GROUP BY t",
+            "SELECT LAG(x) OVER (ORDER BY t ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) FROM m",
+        ] {
+            assert!(matches!(parse_query(sql), Err(ParseError::InvalidWindow(_))), "{}", sql);
+        }
+        assert!(matches!(
+            parse_query("SELECT MOVING_AVG(x) FROM m"),
+            Err(ParseError::InvalidFunctionArguments(_))
+        ));
+    }
+
     #[test]
     fn test_interval_parsing() {
         assert_eq!(parse_interval("5 minutes").unwrap(), 5 * 60 * 1000);
         assert_eq!(parse_interval("1 hour").unwrap(), 3600 * 1000);
         assert_eq!(parse_interval("1 day").unwrap(), 86400 * 1000);
         assert_eq!(parse_interval("100 ms").unwrap(), 100);
+        assert_eq!(parse_interval("15m").unwrap(), 15 * 60 * 1000);
+        assert_eq!(parse_interval("250ms").unwrap(), 250);
+        assert_eq!(parse_interval("2 weeks").unwrap(), 14 * 86400 * 1000);
+        assert!(parse_interval("m").is_err());
+        assert!(parse_interval("15").is_err());
+    }
+
+    #[test]
+    fn test_relative_time_literals() {
+        let now = 10_000_000;
+        let value_at = |sql: &str| {
+            let query = parse_query_at(sql, now).unwrap();
+            assert_eq!(query.now, Some(now));
+            single_predicate(&query).value.clone()
+        };
+
+        assert_eq!(
+            value_at("SELECT * FROM logs WHERE timestamp > NOW() - INTERVAL '1 hour'"),
+            Value::Int64(now - 3_600_000)
+        );
+        assert_eq!(
+            value_at("SELECT * FROM logs WHERE timestamp > NOW() - INTERVAL '2' MINUTE"),
+            Value::Int64(now - 120_000)
+        );
+        assert_eq!(
+            value_at("SELECT * FROM logs WHERE timestamp >= ago('15m')"),
+            Value::Int64(now - 900_000)
+        );
+        assert_eq!(
+            value_at("SELECT * FROM logs WHERE timestamp < NOW()"),
+            Value::Int64(now)
+        );
+
+        let query = parse_query_at(
+            "SELECT * FROM logs WHERE timestamp BETWEEN ago('2h') AND ago('1h')",
+            now,
+        )
+        .unwrap();
+        assert_eq!(
+            single_predicate(&query).values,
+            vec![Value::Int64(now - 7_200_000), Value::Int64(now - 3_600_000)]
+        );
+
+        // Queries without relative times do not depend on when they run
+        let query = parse_query_at("SELECT * FROM logs WHERE timestamp > 1000", now).unwrap();
+        assert_eq!(query.now, None);
+
+        assert!(parse_query_at("SELECT * FROM logs WHERE timestamp > ago('soon')", now).is_err());
     }
 
     #[test]
-    fn test_joins_rejected() {
-        let result = parse_query("SELECT * FROM a JOIN b ON a.id = b.id");
-        assert!(matches!(result, Err(ParseError::JoinsNotSupported)));
+    fn test_subqueries() {
+        let query = parse_query_at(
+            "WITH recent AS (SELECT user_id FROM logs WHERE timestamp > ago('1h')) \
+             SELECT COUNT(*) FROM (SELECT user_id FROM recent) AS r \
+             WHERE user_id NOT IN (SELECT user_id FROM admins)",
+            10_000_000,
+        )
+        .unwrap();
+        // Relative times inside nested queries are resolved too
+        assert_eq!(query.now, Some(10_000_000));
+        assert_eq!(query.with.len(), 1);
+        assert_eq!(query.with[0].0, "recent");
+        assert!(query.with[0].1.contains("timestamp > 6400000"));
+        assert_eq!(query.table, "r");
+        assert_eq!(query.from_subquery.as_deref(), Some("SELECT user_id FROM recent"));
+        match &query.filters {
+            Some(FilterExpr::InSubquery { sql, negated, .. }) => {
+                assert_eq!(sql, "SELECT user_id FROM admins");
+                assert!(*negated);
+            }
+            other => panic!("Expected IN subquery, got {:?}", other),
+        }
+
+        assert!(parse_query("WITH RECURSIVE t AS (SELECT 1) SELECT * FROM t").is_err());
+        assert!(parse_query("WITH t AS (SELECT a FROM x), t AS (SELECT b FROM y) SELECT * FROM t")
+            .is_err());
+        assert!(parse_query(
+            "SELECT CASE WHEN a IN (SELECT a FROM x) THEN 1 ELSE 0 END FROM events"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_union_all_and_globs() {
+        let query = parse_query(
+            "SELECT a FROM x UNION ALL SELECT b FROM y UNION ALL SELECT c FROM z \
+             ORDER BY a LIMIT 5",
+        )
+        .unwrap();
+        assert_eq!(
+            query.union_all,
+            vec!["SELECT a FROM x", "SELECT b FROM y", "SELECT c FROM z"]
+        );
+        assert_eq!(query.order_by.len(), 1);
+        assert_eq!(query.limit, Some(5));
+        assert!(matches!(
+            parse_query("SELECT a FROM x UNION SELECT b FROM y"),
+            Err(ParseError::UnsupportedQuery(_))
+        ));
+
+        let query = parse_query("SELECT COUNT(*) FROM logs_* WHERE _table = 'logs_api'").unwrap();
+        assert_eq!(query.table, "logs_*");
+        assert!(query.union_all.is_empty());
+    }
+
+    #[test]
+    fn test_lookup_joins() {
+        let query = parse_query(
+            "SELECT s.team, COUNT(*) FROM events e LEFT JOIN services s ON s.name = e.service \
+             GROUP BY s.team",
+        )
+        .unwrap();
+        assert_eq!(query.table, "events");
+        let join = query.join.unwrap();
+        assert_eq!(join.table, "services");
+        assert_eq!(join.qualifier, "s");
+        assert_eq!(join.driving_qualifier, "e");
+        assert_eq!(join.driving_column, "service");
+        assert_eq!(join.lookup_column, "name");
+        assert!(join.left);
+
+        let join = parse_query("SELECT * FROM events JOIN services USING (service)")
+            .unwrap()
+            .join
+            .unwrap();
+        assert_eq!(join.qualifier, "services");
+        assert_eq!(join.driving_qualifier, "events");
+        assert!(!join.left);
+
+        for sql in [
+            "SELECT * FROM a, b",
+            "SELECT * FROM a RIGHT JOIN b ON a.id = b.id",
+            "SELECT * FROM a JOIN b ON a.id = b.id JOIN c ON a.id = c.id",
+            "SELECT * FROM a JOIN b ON id = key",
+            "SELECT * FROM a JOIN b ON a.id > b.id",
+            "SELECT * FROM a JOIN a ON a.id = a.id",
+            "SELECT * FROM a JOIN (SELECT id FROM b) AS b ON a.id = b.id",
+        ] {
+            assert!(
+                matches!(parse_query(sql), Err(ParseError::UnsupportedJoin(_))),
+                "{}",
+                sql
+            );
+        }
     }
 }
@@ -1,27 +1,278 @@
+use std::sync::Arc;
+
+use super::cursor::ScanCursor;
+use super::explain::Profile;
+use super::expr::ScalarExpr;
+use super::fill::{Fill, MAX_FILL_BUCKETS};
+use super::limits::QueryControl;
 use super::parser::{
-    AggregateFunction, FilterOperator, GroupByColumn, ParsedQuery, Projection,
+    AggregateFunction, Filter, FilterExpr, FilterOperator, GroupByColumn, LookupJoin, ParsedQuery,
+    Projection,
 };
+use super::predicate::matches;
+use super::time_bucket::TimeBucket;
+use super::window::WindowFunction;
 use crate::data::Value;
 
 /// Query execution plan
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct QueryPlan {
     /// Table name to query
     pub table: String,
     /// Time range filter (optimization)
     pub time_range: Option<TimeRange>,
     /// Column filters
-    pub filters: Vec<FilterPlan>,
+    pub filters: Option<FilterExprPlan>,
     /// Columns to read from storage
     pub required_columns: Vec<String>,
     /// Projection plan
     pub projections: Vec<ProjectionPlan>,
     /// Group by plan
     pub group_by: Option<GroupByPlan>,
+    /// Post-aggregation filter over output columns
+    pub having: Option<FilterExprPlan>,
+    /// Output columns computed only for expressions or HAVING, dropped from the result
+    pub hidden_columns: Vec<String>,
     /// Order by plan
     pub order_by: Vec<OrderByPlan>,
     /// Result limit
     pub limit: Option<usize>,
+    /// Rows skipped before the limit
+    pub offset: Option<usize>,
+    /// Where a paged scan resumes. Comes from the request, not the SQL.
+    pub cursor: Option<ScanCursor>,
+    /// Gap filling for time-bucketed aggregations
+    pub fill: Option<FillPlan>,
+    /// Comparison with an earlier period
+    pub compare: Option<ComparePlan>,
+    /// Lookup join, with the lookup table's rows
+    pub join: Option<LookupJoin>,
+    /// Where the executor records what the plan did, for EXPLAIN
+    pub profile: Option<Arc<Profile>>,
+    /// Deadline, cancellation and limits the executor checks as it goes
+    pub control: Option<Arc<QueryControl>>,
+}
+
+impl QueryPlan {
+    /// Check if any projection is an aggregation
+    pub fn has_aggregations(&self) -> bool {
+        self.projections
+            .iter()
+            .any(|p| matches!(p, ProjectionPlan::Aggregate { .. }))
+    }
+
+    /// Plan for COMPARE TO's previous period: the same aggregation over the
+    /// time range moved back by the offset. HAVING, ORDER BY and LIMIT only
+    /// apply to the current period.
+    pub fn previous_period(&self) -> Option<QueryPlan> {
+        let compare = self.compare.as_ref()?;
+        let offset = compare.offset_ms;
+        let range = TimeRange {
+            start: compare.range.start.map(|start| start - offset),
+            end: compare.range.end.map(|end| end - offset),
+        };
+
+        // Time predicates are moved back too, and the range is spelled out
+        // in case the current period's end was implied by NOW()
+        let mut filters: Vec<FilterExprPlan> = self
+            .filters
+            .iter()
+            .map(|filters| shift_time_filters(filters, offset))
+            .collect();
+        let bounds = [(FilterOperator::GtEq, range.start), (FilterOperator::Lt, range.end)];
+        for (operator, bound) in bounds {
+            if let Some(bound) = bound {
+                filters.push(FilterExprPlan::Predicate(FilterPlan {
+                    operand: ScalarExpr::Column("timestamp".to_string()),
+                    operator,
+                    value: Value::Int64(bound),
+                    values: Vec::new(),
+                }));
+            }
+        }
+
+        Some(QueryPlan {
+            time_range: Some(range),
+            filters: Some(FilterExprPlan::And(filters)),
+            having: None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+            fill: self.fill.as_ref().map(|fill| FillPlan {
+                start: fill.start.map(|start| start - offset),
+                end: fill.end.map(|end| end - offset),
+                ..fill.clone()
+            }),
+            compare: None,
+            ..self.clone()
+        })
+    }
+
+    /// The plan with stored columns read differently: `substitute` gives
+    /// the expression a column is read as, or `None` to leave it be.
+    /// Conditions left without any column are folded to constants.
+    pub fn substitute_columns(&self, substitute: &dyn Fn(&str) -> Option<ScalarExpr>) -> QueryPlan {
+        let mut required_columns: Vec<String> = Vec::new();
+        for column in &self.required_columns {
+            let column = match substitute(column) {
+                None => column.clone(),
+                Some(ScalarExpr::Column(renamed)) => renamed,
+                Some(_) => continue,
+            };
+            if !required_columns.contains(&column) {
+                required_columns.push(column);
+            }
+        }
+
+        QueryPlan {
+            filters: self.filters.as_ref().map(|f| substitute_filter(f, substitute)),
+            required_columns,
+            projections: substitute_projections(&self.projections, substitute),
+            group_by: self.group_by.clone().map(|mut group_by| {
+                for column in &mut group_by.columns {
+                    match column {
+                        GroupByColumnPlan::Column(name) => match substitute(name) {
+                            Some(ScalarExpr::Column(renamed)) => *name = renamed,
+                            Some(expr) => *column = GroupByColumnPlan::Expression(expr),
+                            None => {}
+                        },
+                        GroupByColumnPlan::TimeBucket { column, .. } => {
+                            if let Some(ScalarExpr::Column(renamed)) = substitute(column) {
+                                *column = renamed;
+                            }
+                        }
+                        GroupByColumnPlan::Expression(expr) => {
+                            *expr = substitute_expr(expr, substitute)
+                        }
+                    }
+                }
+                group_by
+            }),
+            ..self.clone()
+        }
+    }
+}
+
+/// Projections with stored columns read differently, as in
+/// `QueryPlan::substitute_columns`
+pub fn substitute_projections(
+    projections: &[ProjectionPlan],
+    substitute: &dyn Fn(&str) -> Option<ScalarExpr>,
+) -> Vec<ProjectionPlan> {
+    projections
+        .iter()
+        .map(|projection| match projection {
+            ProjectionPlan::Column { name, output_name } => match substitute(name) {
+                Some(ScalarExpr::Column(renamed)) => ProjectionPlan::Column {
+                    name: renamed,
+                    output_name: output_name.clone(),
+                },
+                Some(expr) => ProjectionPlan::Expression {
+                    expr,
+                    output_name: output_name.clone(),
+                },
+                None => projection.clone(),
+            },
+            ProjectionPlan::Expression { expr, output_name } => ProjectionPlan::Expression {
+                expr: substitute_expr(expr, substitute),
+                output_name: output_name.clone(),
+            },
+            ProjectionPlan::Aggregate {
+                function,
+                argument,
+                key,
+                filter,
+                output_name,
+            } => ProjectionPlan::Aggregate {
+                function: *function,
+                argument: argument.as_ref().map(|e| substitute_expr(e, substitute)),
+                key: key.as_ref().map(|e| substitute_expr(e, substitute)),
+                filter: filter.as_ref().map(|f| substitute_filter(f, substitute)),
+                output_name: output_name.clone(),
+            },
+            ProjectionPlan::TimeBucket {
+                bucket,
+                column,
+                output_name,
+            } => ProjectionPlan::TimeBucket {
+                bucket: *bucket,
+                column: match substitute(column) {
+                    Some(ScalarExpr::Column(renamed)) => renamed,
+                    _ => column.clone(),
+                },
+                output_name: output_name.clone(),
+            },
+            // These read output columns, not stored ones
+            ProjectionPlan::AggregateExpression { .. } | ProjectionPlan::Window { .. } => {
+                projection.clone()
+            }
+        })
+        .collect()
+}
+
+fn substitute_expr(
+    expr: &ScalarExpr,
+    substitute: &dyn Fn(&str) -> Option<ScalarExpr>,
+) -> ScalarExpr {
+    match expr {
+        ScalarExpr::Column(column) => substitute(column).unwrap_or_else(|| expr.clone()),
+        ScalarExpr::Literal(_) => expr.clone(),
+        ScalarExpr::Negate(inner) => {
+            ScalarExpr::Negate(Box::new(substitute_expr(inner, substitute)))
+        }
+        ScalarExpr::Binary { op, left, right } => ScalarExpr::Binary {
+            op: *op,
+            left: Box::new(substitute_expr(left, substitute)),
+            right: Box::new(substitute_expr(right, substitute)),
+        },
+        ScalarExpr::Case {
+            branches,
+            otherwise,
+        } => ScalarExpr::Case {
+            branches: branches
+                .iter()
+                .map(|(condition, result)| {
+                    (substitute_filter(condition, substitute), substitute_expr(result, substitute))
+                })
+                .collect(),
+            otherwise: otherwise.as_ref().map(|e| Box::new(substitute_expr(e, substitute))),
+        },
+        ScalarExpr::Function { function, args } => ScalarExpr::Function {
+            function: function.clone(),
+            args: args.iter().map(|arg| substitute_expr(arg, substitute)).collect(),
+        },
+    }
+}
+
+fn substitute_filter(
+    expr: &FilterExprPlan,
+    substitute: &dyn Fn(&str) -> Option<ScalarExpr>,
+) -> FilterExprPlan {
+    match expr {
+        FilterExprPlan::Predicate(filter) => {
+            let mut filter = filter.clone();
+            filter.operand = substitute_expr(&filter.operand, substitute);
+            if !filter.operand.columns().is_empty() {
+                return FilterExprPlan::Predicate(filter);
+            }
+            // An empty AND always matches and an empty OR never does
+            if matches(&FilterExprPlan::Predicate(filter), &|_| Value::Null) {
+                FilterExprPlan::And(Vec::new())
+            } else {
+                FilterExprPlan::Or(Vec::new())
+            }
+        }
+        FilterExprPlan::And(children) => {
+            FilterExprPlan::And(children.iter().map(|c| substitute_filter(c, substitute)).collect())
+        }
+        FilterExprPlan::Or(children) => {
+            FilterExprPlan::Or(children.iter().map(|c| substitute_filter(c, substitute)).collect())
+        }
+        FilterExprPlan::Not(inner) => {
+            FilterExprPlan::Not(Box::new(substitute_filter(inner, substitute)))
+        }
+        FilterExprPlan::InSubquery { .. } => expr.clone(),
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -30,13 +281,58 @@ pub struct TimeRange {
     pub end: Option<i64>,
 }
 
-#[derive(Debug, Clone)]
-pub struct FilterPlan {
-    pub column: String,
-    pub operator: FilterOperator,
-    pub value: Value,
+impl TimeRange {
+    /// A range with no bounds on either side
+    pub fn unbounded() -> Self {
+        Self {
+            start: None,
+            end: None,
+        }
+    }
+
+    /// Check if either side of the range is bounded
+    pub fn is_bounded(&self) -> bool {
+        self.start.is_some() || self.end.is_some()
+    }
+
+    /// Narrow this range to the overlap with another (AND)
+    pub fn intersect(&self, other: &TimeRange) -> TimeRange {
+        TimeRange {
+            start: match (self.start, other.start) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                (a, b) => a.or(b),
+            },
+            end: match (self.end, other.end) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+
+    /// Widen this range to cover another (OR). A side is only bounded
+    /// if both ranges are bounded on that side.
+    pub fn union(&self, other: &TimeRange) -> TimeRange {
+        TimeRange {
+            start: match (self.start, other.start) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                _ => None,
+            },
+            end: match (self.end, other.end) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                _ => None,
+            },
+        }
+    }
 }
 
+/// Filters are executed as parsed. CASE conditions inside expressions use
+/// the same type, so both share one evaluator in `query::predicate`.
+pub type FilterPlan = Filter;
+
+/// Boolean filter tree evaluated by combining row masks. NOT takes the rows
+/// where its child is false, leaving out those it is unknown for (NULL).
+pub type FilterExprPlan = FilterExpr;
+
 #[derive(Debug, Clone)]
 pub enum ProjectionPlan {
     /// Pass through a column value
@@ -44,15 +340,47 @@ pub enum ProjectionPlan {
     /// Compute an aggregation
     Aggregate {
         function: AggregateFunction,
-        column: Option<String>,
+        argument: Option<ScalarExpr>,
+        /// What FIRST, LAST, ARG_MAX and ARG_MIN order rows by
+        key: Option<ScalarExpr>,
+        /// Only rows matching this condition are aggregated
+        filter: Option<FilterExprPlan>,
         output_name: String,
     },
+    /// Evaluate an expression over each input row
+    Expression { expr: ScalarExpr, output_name: String },
+    /// Evaluate an expression over other output columns once aggregates
+    /// are final
+    AggregateExpression { expr: ScalarExpr, output_name: String },
     /// Compute time bucket
     TimeBucket {
-        interval_ms: i64,
+        bucket: TimeBucket,
         column: String,
         output_name: String,
     },
+    /// Evaluate a window function across aggregated rows
+    Window {
+        function: WindowFunction,
+        /// Expression over other output columns
+        argument: ScalarExpr,
+        partition_by: Vec<String>,
+        order_by: String,
+        output_name: String,
+    },
+}
+
+impl ProjectionPlan {
+    /// Name of the column this projection produces
+    pub fn output_name(&self) -> &str {
+        match self {
+            ProjectionPlan::Column { output_name, .. }
+            | ProjectionPlan::Aggregate { output_name, .. }
+            | ProjectionPlan::TimeBucket { output_name, .. }
+            | ProjectionPlan::Expression { output_name, .. }
+            | ProjectionPlan::AggregateExpression { output_name, .. }
+            | ProjectionPlan::Window { output_name, .. } => output_name,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -63,7 +391,39 @@ pub struct GroupByPlan {
 #[derive(Debug, Clone)]
 pub enum GroupByColumnPlan {
     Column(String),
-    TimeBucket { interval_ms: i64, column: String },
+    TimeBucket { bucket: TimeBucket, column: String },
+    Expression(ScalarExpr),
+}
+
+/// FILL over output columns
+#[derive(Debug, Clone)]
+pub struct FillPlan {
+    pub fill: Fill,
+    pub bucket: TimeBucket,
+    /// Output column holding the bucket start
+    pub bucket_column: usize,
+    /// Other group columns, which identify a series
+    pub key_columns: Vec<usize>,
+    /// Aggregate columns, filled in missing buckets
+    pub value_columns: Vec<usize>,
+    /// Range of buckets to emit; where unbounded, the data's own range
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+/// COMPARE TO over output columns
+#[derive(Debug, Clone)]
+pub struct ComparePlan {
+    /// How far back the previous period is
+    pub offset_ms: i64,
+    /// The current period, bounded on both sides
+    pub range: TimeRange,
+    /// Group columns, which rows of both periods are joined on
+    pub key_columns: Vec<usize>,
+    /// Key columns holding time buckets, matched relative to each period
+    pub buckets: Vec<(usize, TimeBucket)>,
+    /// Selected aggregate columns, each given `_prev` and `_delta` columns
+    pub value_columns: Vec<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -76,32 +436,38 @@ pub struct OrderByPlan {
 pub fn plan_query(query: ParsedQuery) -> Result<QueryPlan, PlanError> {
     let mut required_columns = Vec::new();
     let mut projections = Vec::new();
-    let mut time_range = TimeRange {
-        start: None,
-        end: None,
-    };
 
     // Always need timestamp for time-based operations
     required_columns.push("timestamp".to_string());
 
-    // Extract time range from filters
-    let mut filters = Vec::new();
-    for filter in &query.filters {
-        if filter.column == "timestamp" {
-            update_time_range(&mut time_range, &filter.operator, &filter.value);
-        }
-        filters.push(FilterPlan {
-            column: filter.column.clone(),
-            operator: filter.operator,
-            value: filter.value.clone(),
-        });
-        if !required_columns.contains(&filter.column) {
-            required_columns.push(filter.column.clone());
-        }
+    // IN subqueries are run and replaced by their results before planning
+    if query.filters.iter().chain(&query.having).any(FilterExpr::has_subquery) {
+        return Err(PlanError::UnresolvedSubquery);
     }
+    // So are lookup tables
+    if let Some(join) = query.join.as_ref().filter(|join| join.rows.is_none()) {
+        return Err(PlanError::UnresolvedLookup(join.table.clone()));
+    }
+
+    // Plan filters and extract the time range they imply
+    let filters = query
+        .filters
+        .as_ref()
+        .map(|expr| plan_filter(expr, &mut required_columns));
+    let time_range = filters
+        .as_ref()
+        .map(filter_time_range)
+        .unwrap_or_else(TimeRange::unbounded);
 
-    // Plan projections
-    for (idx, proj) in query.projections.iter().enumerate() {
+    // Plan projections. Hidden aggregations are planned after the selected
+    // ones so they can be stripped from the end of each row.
+    let hidden_start = query.projections.len();
+    for (idx, proj) in query
+        .projections
+        .iter()
+        .chain(query.hidden_aggregations.iter())
+        .enumerate()
+    {
         match proj {
             Projection::Wildcard => {
                 // Will be expanded during execution when we know the schema
@@ -121,29 +487,44 @@ pub fn plan_query(query: ParsedQuery) -> Result<QueryPlan, PlanError> {
             }
             Projection::Aggregation {
                 function,
-                column,
+                argument,
+                key,
+                filter,
                 alias,
             } => {
-                if let Some(col) = column {
-                    if !required_columns.contains(col) {
-                        required_columns.push(col.clone());
-                    }
+                for expr in argument.iter().chain(key) {
+                    require_columns(&mut required_columns, expr);
                 }
+                let filter = filter
+                    .as_ref()
+                    .map(|filter| plan_filter(filter, &mut required_columns));
                 let output_name = alias.clone().unwrap_or_else(|| {
-                    format!(
-                        "{}_{}",
-                        format!("{:?}", function).to_lowercase(),
-                        column.as_deref().unwrap_or("*")
-                    )
+                    function.default_output_name(argument.as_ref(), key.as_ref(), filter.as_ref())
                 });
                 projections.push(ProjectionPlan::Aggregate {
                     function: *function,
-                    column: column.clone(),
+                    argument: argument.clone(),
+                    key: key.clone(),
+                    filter,
                     output_name,
                 });
             }
+            Projection::Expression { expr, name } => {
+                require_columns(&mut required_columns, expr);
+                projections.push(ProjectionPlan::Expression {
+                    expr: expr.clone(),
+                    output_name: name.clone(),
+                });
+            }
+            Projection::AggregateExpression { expr, name } => {
+                // Refers to output columns, not stored ones
+                projections.push(ProjectionPlan::AggregateExpression {
+                    expr: expr.clone(),
+                    output_name: name.clone(),
+                });
+            }
             Projection::TimeBucket {
-                interval_ms,
+                bucket,
                 column,
                 alias,
             } => {
@@ -154,14 +535,32 @@ pub fn plan_query(query: ParsedQuery) -> Result<QueryPlan, PlanError> {
                     .clone()
                     .unwrap_or_else(|| format!("time_bucket_{}", idx));
                 projections.push(ProjectionPlan::TimeBucket {
-                    interval_ms: *interval_ms,
+                    bucket: *bucket,
                     column: column.clone(),
                     output_name,
                 });
             }
+            Projection::Window {
+                function,
+                argument,
+                partition_by,
+                order_by,
+                name,
+            } => {
+                // Defaults are filled in once every output column is known
+                projections.push(ProjectionPlan::Window {
+                    function: *function,
+                    argument: argument.clone(),
+                    partition_by: partition_by.clone().unwrap_or_default(),
+                    order_by: order_by.clone().unwrap_or_default(),
+                    output_name: name.clone(),
+                });
+            }
         }
     }
 
+    plan_windows(&query.projections, &mut projections)?;
+
     // Plan group by
     let group_by = if query.group_by.is_empty() {
         None
@@ -171,25 +570,51 @@ pub fn plan_query(query: ParsedQuery) -> Result<QueryPlan, PlanError> {
             .iter()
             .map(|gb| match gb {
                 GroupByColumn::Column(name) => {
+                    // GROUP BY may name a computed projection by its alias
+                    if let Some(computed) = group_by_alias(&projections, name) {
+                        if let GroupByColumnPlan::Expression(expr) = &computed {
+                            require_columns(&mut required_columns, expr);
+                        }
+                        return computed;
+                    }
                     if !required_columns.contains(name) {
                         required_columns.push(name.clone());
                     }
                     GroupByColumnPlan::Column(name.clone())
                 }
-                GroupByColumn::TimeBucket { interval_ms, column } => {
+                GroupByColumn::TimeBucket { bucket, column } => {
                     if !required_columns.contains(column) {
                         required_columns.push(column.clone());
                     }
                     GroupByColumnPlan::TimeBucket {
-                        interval_ms: *interval_ms,
+                        bucket: *bucket,
                         column: column.clone(),
                     }
                 }
+                GroupByColumn::Expression(expr) => {
+                    require_columns(&mut required_columns, expr);
+                    GroupByColumnPlan::Expression(expr.clone())
+                }
             })
             .collect();
         Some(GroupByPlan { columns })
     };
 
+    let hidden_columns: Vec<String> = projections[hidden_start..]
+        .iter()
+        .map(|p| p.output_name().to_string())
+        .collect();
+
+    // Plan HAVING against output columns; these are not read from storage
+    let having = match &query.having {
+        Some(expr) => {
+            let having = plan_filter(expr, &mut Vec::new());
+            check_having_columns(&having, &projections)?;
+            Some(having)
+        }
+        None => None,
+    };
+
     // Plan order by
     let order_by = query
         .order_by
@@ -200,8 +625,29 @@ pub fn plan_query(query: ParsedQuery) -> Result<QueryPlan, PlanError> {
         })
         .collect();
 
+    let fill = match &query.fill {
+        Some(fill) => Some(plan_fill(
+            fill,
+            &projections,
+            group_by.as_ref(),
+            &time_range,
+            query.now,
+        )?),
+        None => None,
+    };
+
+    let compare = match query.compare_to {
+        Some(offset_ms) => Some(plan_compare(
+            offset_ms,
+            &projections[..hidden_start],
+            &time_range,
+            query.now,
+        )?),
+        None => None,
+    };
+
     // Determine time range for shard pruning
-    let time_range_opt = if time_range.start.is_some() || time_range.end.is_some() {
+    let time_range_opt = if time_range.is_bounded() {
         Some(time_range)
     } else {
         None
@@ -214,17 +660,350 @@ pub fn plan_query(query: ParsedQuery) -> Result<QueryPlan, PlanError> {
         required_columns,
         projections,
         group_by,
+        having,
+        hidden_columns,
         order_by,
         limit: query.limit,
+        offset: query.offset,
+        cursor: None,
+        fill,
+        compare,
+        join: query.join,
+        profile: None,
+        control: None,
     })
 }
 
-fn update_time_range(range: &mut TimeRange, op: &FilterOperator, value: &Value) {
-    let Some(ts) = value.as_i64() else {
+/// Locate the time bucket FILL steps through, and the columns it fills
+fn plan_fill(
+    fill: &Fill,
+    projections: &[ProjectionPlan],
+    group_by: Option<&GroupByPlan>,
+    time_range: &TimeRange,
+    now: Option<i64>,
+) -> Result<FillPlan, PlanError> {
+    let grouped_bucket = group_by
+        .into_iter()
+        .flat_map(|g| &g.columns)
+        .find_map(|c| match c {
+            GroupByColumnPlan::TimeBucket { bucket, column } => Some((bucket, column)),
+            _ => None,
+        })
+        .ok_or_else(|| PlanError::InvalidFill("requires GROUP BY TIME_BUCKET(...)".into()))?;
+
+    let bucket_column = projections
+        .iter()
+        .position(|p| {
+            matches!(p, ProjectionPlan::TimeBucket { bucket, column, .. }
+                if (bucket, column) == grouped_bucket)
+        })
+        .ok_or_else(|| PlanError::InvalidFill("requires the time bucket to be selected".into()))?;
+
+    let mut key_columns = Vec::new();
+    let mut value_columns = Vec::new();
+    for (i, projection) in projections.iter().enumerate() {
+        match projection {
+            _ if i == bucket_column => {}
+            ProjectionPlan::Aggregate { .. } | ProjectionPlan::AggregateExpression { .. } => {
+                value_columns.push(i)
+            }
+            // Computed after FILL
+            ProjectionPlan::Window { .. } => {}
+            _ => key_columns.push(i),
+        }
+    }
+
+    // Relative ranges such as `timestamp > ago('1h')` extend to the present
+    let start = time_range.start;
+    let end = time_range.end.or(now.map(|now| now + 1));
+    let bucket = *grouped_bucket.0;
+    if let (Some(start), Some(end)) = (start, end) {
+        let buckets = std::iter::successors(Some(bucket.floor(start)), |&b| Some(bucket.next(b)))
+            .take_while(|&b| b < end)
+            .take(MAX_FILL_BUCKETS + 1)
+            .count();
+        if buckets > MAX_FILL_BUCKETS {
+            return Err(PlanError::InvalidFill(format!(
+                "would produce more than {} buckets per series",
+                MAX_FILL_BUCKETS
+            )));
+        }
+    }
+
+    Ok(FillPlan {
+        fill: fill.clone(),
+        bucket,
+        bucket_column,
+        key_columns,
+        value_columns,
+        start,
+        end,
+    })
+}
+
+/// Split the selected columns into join keys and compared aggregates
+fn plan_compare(
+    offset_ms: i64,
+    projections: &[ProjectionPlan],
+    time_range: &TimeRange,
+    now: Option<i64>,
+) -> Result<ComparePlan, PlanError> {
+    if !projections.iter().any(|p| matches!(p, ProjectionPlan::Aggregate { .. })) {
+        return Err(PlanError::InvalidCompare("requires an aggregation".into()));
+    }
+
+    // Relative ranges such as `timestamp > ago('1h')` end at the reference time
+    let range = TimeRange {
+        start: time_range.start,
+        end: time_range.end.or(now.map(|now| now + 1)),
+    };
+    if range.start.is_none() || range.end.is_none() {
+        return Err(PlanError::InvalidCompare(
+            "requires a time range on timestamp in WHERE".into(),
+        ));
+    }
+
+    let mut key_columns = Vec::new();
+    let mut buckets = Vec::new();
+    let mut value_columns = Vec::new();
+    for (i, projection) in projections.iter().enumerate() {
+        match projection {
+            ProjectionPlan::Aggregate { .. } | ProjectionPlan::AggregateExpression { .. } => {
+                value_columns.push(i)
+            }
+            ProjectionPlan::TimeBucket { bucket, .. } => {
+                key_columns.push(i);
+                buckets.push((i, *bucket));
+            }
+            ProjectionPlan::Window { .. } => {}
+            _ => key_columns.push(i),
+        }
+    }
+
+    Ok(ComparePlan {
+        offset_ms,
+        range,
+        key_columns,
+        buckets,
+        value_columns,
+    })
+}
+
+/// Resolve the partition and order of window functions. Without OVER, each
+/// series of the GROUP BY is a partition, ordered by the selected time bucket.
+fn plan_windows(
+    selected: &[Projection],
+    projections: &mut [ProjectionPlan],
+) -> Result<(), PlanError> {
+    let outputs: Vec<String> = projections.iter().map(|p| p.output_name().to_string()).collect();
+    let series: Vec<String> = projections
+        .iter()
+        .filter(|p| matches!(p, ProjectionPlan::Column { .. } | ProjectionPlan::Expression { .. }))
+        .map(|p| p.output_name().to_string())
+        .collect();
+    let bucket = projections
+        .iter()
+        .find(|p| matches!(p, ProjectionPlan::TimeBucket { .. }))
+        .map(|p| p.output_name().to_string());
+    let aggregated = projections.iter().any(|p| matches!(p, ProjectionPlan::Aggregate { .. }));
+
+    for (projection, planned) in selected.iter().zip(projections.iter_mut()) {
+        let (
+            Projection::Window {
+                partition_by: partition,
+                order_by: order,
+                ..
+            },
+            ProjectionPlan::Window {
+                argument,
+                partition_by,
+                order_by,
+                output_name,
+                ..
+            },
+        ) = (projection, planned)
+        else {
+            continue;
+        };
+
+        if !aggregated {
+            return Err(PlanError::InvalidWindow(format!(
+                "{} requires an aggregation",
+                output_name
+            )));
+        }
+        *partition_by = partition.clone().unwrap_or_else(|| series.clone());
+        *order_by = order.clone().or_else(|| bucket.clone()).ok_or_else(|| {
+            PlanError::InvalidWindow(format!(
+                "{} requires ORDER BY in OVER (...) or a selected TIME_BUCKET",
+                output_name
+            ))
+        })?;
+
+        let referenced = partition_by
+            .iter()
+            .map(String::as_str)
+            .chain([order_by.as_str()])
+            .chain(argument.columns());
+        for column in referenced {
+            if !outputs.iter().any(|o| o == column) {
+                return Err(PlanError::InvalidWindow(format!(
+                    "{} references unknown column: {}",
+                    output_name, column
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Move the timestamp bounds of a filter back by `offset` milliseconds
+fn shift_time_filters(expr: &FilterExprPlan, offset: i64) -> FilterExprPlan {
+    let shift = |value: &Value| match value {
+        Value::Int64(ts) => Value::Int64(ts - offset),
+        Value::Timestamp(ts) => Value::Timestamp(ts - offset),
+        other => other.clone(),
+    };
+
+    match expr {
+        FilterExprPlan::Predicate(filter) if filter.operand.as_column() == Some("timestamp") => {
+            FilterExprPlan::Predicate(FilterPlan {
+                value: shift(&filter.value),
+                values: filter.values.iter().map(shift).collect(),
+                ..filter.clone()
+            })
+        }
+        FilterExprPlan::Predicate(_) | FilterExprPlan::InSubquery { .. } => expr.clone(),
+        FilterExprPlan::And(children) => {
+            FilterExprPlan::And(children.iter().map(|c| shift_time_filters(c, offset)).collect())
+        }
+        FilterExprPlan::Or(children) => {
+            FilterExprPlan::Or(children.iter().map(|c| shift_time_filters(c, offset)).collect())
+        }
+        FilterExprPlan::Not(inner) => {
+            FilterExprPlan::Not(Box::new(shift_time_filters(inner, offset)))
+        }
+    }
+}
+
+/// Ensure every HAVING predicate refers to a column the query produces
+fn check_having_columns(
+    expr: &FilterExprPlan,
+    projections: &[ProjectionPlan],
+) -> Result<(), PlanError> {
+    match expr {
+        FilterExprPlan::Predicate(filter) => filter.operand.columns().into_iter().try_for_each(
+            |column| {
+                if projections.iter().any(|p| p.output_name() == column) {
+                    Ok(())
+                } else {
+                    Err(PlanError::UnknownHavingColumn(column.to_string()))
+                }
+            },
+        ),
+        FilterExprPlan::And(children) | FilterExprPlan::Or(children) => children
+            .iter()
+            .try_for_each(|c| check_having_columns(c, projections)),
+        FilterExprPlan::Not(inner) => check_having_columns(inner, projections),
+        FilterExprPlan::InSubquery { .. } => Err(PlanError::UnresolvedSubquery),
+    }
+}
+
+/// Add the columns an expression reads to the columns to load
+fn require_columns(required_columns: &mut Vec<String>, expr: &ScalarExpr) {
+    for column in expr.columns() {
+        if !required_columns.iter().any(|c| c == column) {
+            required_columns.push(column.to_string());
+        }
+    }
+}
+
+/// Resolve a GROUP BY name that refers to a computed projection's alias
+fn group_by_alias(projections: &[ProjectionPlan], name: &str) -> Option<GroupByColumnPlan> {
+    projections.iter().find_map(|p| match p {
+        ProjectionPlan::Expression { expr, output_name } if output_name == name => {
+            Some(GroupByColumnPlan::Expression(expr.clone()))
+        }
+        ProjectionPlan::TimeBucket {
+            bucket,
+            column,
+            output_name,
+        } if output_name == name => Some(GroupByColumnPlan::TimeBucket {
+            bucket: *bucket,
+            column: column.clone(),
+        }),
+        _ => None,
+    })
+}
+
+fn plan_filter(expr: &FilterExpr, required_columns: &mut Vec<String>) -> FilterExprPlan {
+    for column in expr.columns() {
+        if !required_columns.iter().any(|c| c == column) {
+            required_columns.push(column.to_string());
+        }
+    }
+    expr.clone()
+}
+
+/// Compute the tightest time range that contains every row the filter can match.
+/// AND intersects child ranges, OR takes their hull, and NOT is left unbounded.
+fn filter_time_range(expr: &FilterExprPlan) -> TimeRange {
+    match expr {
+        FilterExprPlan::Predicate(filter) => {
+            let mut range = TimeRange::unbounded();
+            if filter.operand.as_column() == Some("timestamp") {
+                update_time_range(&mut range, filter);
+            }
+            range
+        }
+        FilterExprPlan::And(children) => children
+            .iter()
+            .fold(TimeRange::unbounded(), |range, c| {
+                range.intersect(&filter_time_range(c))
+            }),
+        FilterExprPlan::Or(children) => children
+            .iter()
+            .map(filter_time_range)
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or_else(TimeRange::unbounded),
+        FilterExprPlan::Not(_) | FilterExprPlan::InSubquery { .. } => TimeRange::unbounded(),
+    }
+}
+
+fn update_time_range(range: &mut TimeRange, filter: &FilterPlan) {
+    match filter.operator {
+        FilterOperator::In => {
+            // Bound by the smallest and largest listed timestamps
+            let timestamps: Option<Vec<i64>> = filter.values.iter().map(|v| v.as_i64()).collect();
+            if let Some(timestamps) = timestamps {
+                if let (Some(min), Some(max)) = (timestamps.iter().min(), timestamps.iter().max()) {
+                    *range = range.intersect(&TimeRange {
+                        start: Some(*min),
+                        end: Some(max + 1),
+                    });
+                }
+            }
+            return;
+        }
+        FilterOperator::Between => {
+            if let [low, high] = filter.values.as_slice() {
+                if let (Some(low), Some(high)) = (low.as_i64(), high.as_i64()) {
+                    *range = range.intersect(&TimeRange {
+                        start: Some(low),
+                        end: Some(high + 1),
+                    });
+                }
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let Some(ts) = filter.value.as_i64() else {
         return;
     };
 
-    match op {
+    match filter.operator {
         FilterOperator::Gt => {
             range.start = Some(range.start.map(|s| s.max(ts + 1)).unwrap_or(ts + 1));
         }
@@ -256,11 +1035,7 @@ pub fn has_aggregations(plan: &QueryPlan) -> bool {
 pub fn get_output_columns(plan: &QueryPlan) -> Vec<String> {
     plan.projections
         .iter()
-        .map(|p| match p {
-            ProjectionPlan::Column { output_name, .. } => output_name.clone(),
-            ProjectionPlan::Aggregate { output_name, .. } => output_name.clone(),
-            ProjectionPlan::TimeBucket { output_name, .. } => output_name.clone(),
-        })
+        .map(|p| p.output_name().to_string())
         .collect()
 }
 
@@ -268,12 +1043,30 @@ pub fn get_output_columns(plan: &QueryPlan) -> Vec<String> {
 pub enum PlanError {
     #[error("Planning error: {0}")]
     General(String),
+
+    #[error("HAVING references unknown column: {0}")]
+    UnknownHavingColumn(String),
+
+    #[error("FILL {0}")]
+    InvalidFill(String),
+
+    #[error("COMPARE TO {0}")]
+    InvalidCompare(String),
+
+    #[error("Window function {0}")]
+    InvalidWindow(String),
+
+    #[error("IN subquery was not run before planning")]
+    UnresolvedSubquery,
+
+    #[error("Lookup table {0} was not fetched before planning")]
+    UnresolvedLookup(String),
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::query::parser::parse_query;
+    use crate::query::parser::{parse_query, parse_query_at};
 
     #[test]
     fn test_simple_plan() {
@@ -296,6 +1089,73 @@ mod tests {
         assert_eq!(time_range.end, Some(2000));
     }
 
+    #[test]
+    fn test_relative_time_folded_into_time_range() {
+        let query = parse_query_at(
+            "SELECT COUNT(*) FROM events WHERE timestamp >= ago('1h') AND timestamp < NOW()",
+            7_200_000,
+        )
+        .unwrap();
+        let plan = plan_query(query).unwrap();
+
+        let time_range = plan.time_range.unwrap();
+        assert_eq!(time_range.start, Some(3_600_000));
+        assert_eq!(time_range.end, Some(7_200_000));
+    }
+
+    #[test]
+    fn test_plan_time_range_with_or() {
+        let query = parse_query(
+            "SELECT * FROM events WHERE (timestamp >= 1000 AND timestamp < 2000) OR (timestamp >= 5000 AND timestamp < 6000)",
+        )
+        .unwrap();
+        let plan = plan_query(query).unwrap();
+
+        let time_range = plan.time_range.unwrap();
+        assert_eq!(time_range.start, Some(1000));
+        assert_eq!(time_range.end, Some(6000));
+    }
+
+    #[test]
+    fn test_plan_time_range_or_with_unbounded_branch() {
+        let query = parse_query(
+            "SELECT * FROM events WHERE timestamp >= 1000 OR event = 'click'",
+        )
+        .unwrap();
+        let plan = plan_query(query).unwrap();
+
+        // The second branch can match rows at any time
+        assert!(plan.time_range.is_none());
+    }
+
+    #[test]
+    fn test_plan_time_range_ignores_not() {
+        let query = parse_query(
+            "SELECT * FROM events WHERE timestamp >= 1000 AND NOT timestamp >= 5000",
+        )
+        .unwrap();
+        let plan = plan_query(query).unwrap();
+
+        let time_range = plan.time_range.unwrap();
+        assert_eq!(time_range.start, Some(1000));
+        assert_eq!(time_range.end, None);
+    }
+
+    #[test]
+    fn test_plan_time_range_between_and_in() {
+        let query = parse_query("SELECT * FROM events WHERE timestamp BETWEEN 1000 AND 2000")
+            .unwrap();
+        let time_range = plan_query(query).unwrap().time_range.unwrap();
+        assert_eq!(time_range.start, Some(1000));
+        assert_eq!(time_range.end, Some(2001));
+
+        let query = parse_query("SELECT * FROM events WHERE timestamp IN (3000, 1000, 2000)")
+            .unwrap();
+        let time_range = plan_query(query).unwrap().time_range.unwrap();
+        assert_eq!(time_range.start, Some(1000));
+        assert_eq!(time_range.end, Some(3001));
+    }
+
     #[test]
     fn test_plan_aggregation() {
         let query = parse_query("SELECT event, COUNT(*), AVG(latency) FROM events GROUP BY event")
@@ -322,4 +1182,102 @@ mod tests {
         assert!(plan.required_columns.contains(&"value".to_string()));
         assert!(plan.required_columns.contains(&"user_id".to_string()));
     }
+
+    #[test]
+    fn test_fill_plan() {
+        let plan = plan_query(
+            parse_query_at(
+                "SELECT host, TIME_BUCKET('1 minute', timestamp) AS minute, COUNT(*) FROM logs \
+                 WHERE timestamp > NOW() - INTERVAL '1 hour' GROUP BY host, minute FILL(0)",
+                7_200_000,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let fill = plan.fill.unwrap();
+        assert_eq!(fill.bucket_column, 1);
+        assert_eq!(fill.key_columns, vec![0]);
+        assert_eq!(fill.value_columns, vec![2]);
+        // A relative range is filled up to the reference time
+        assert_eq!((fill.start, fill.end), (Some(3_600_001), Some(7_200_001)));
+
+        for sql in [
+            "SELECT host, COUNT(*) FROM logs GROUP BY host FILL(0)",
+            "SELECT COUNT(*) FROM logs GROUP BY TIME_BUCKET('1 minute', timestamp) FILL(0)",
+            "SELECT TIME_BUCKET('1 ms', timestamp) AS t, COUNT(*) FROM logs \
+             WHERE timestamp BETWEEN 0 AND 3600000 GROUP BY t FILL(0)",
+        ] {
+            let err = plan_query(parse_query(sql).unwrap()).unwrap_err();
+            assert!(matches!(err, PlanError::InvalidFill(_)), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_window_plan() {
+        let plan = plan_query(
+            parse_query(
+                "SELECT host, TIME_BUCKET('1 minute', timestamp) AS minute, SUM(bytes), \
+                 DELTA(sum_bytes), RUNNING_SUM(sum_bytes) OVER (ORDER BY minute) \
+                 FROM logs GROUP BY host, minute",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        // Without OVER, each host is a series ordered by the bucket
+        let ProjectionPlan::Window { partition_by, order_by, .. } = &plan.projections[3] else {
+            panic!("Expected window function");
+        };
+        assert_eq!(partition_by, &vec!["host".to_string()]);
+        assert_eq!(order_by, "minute");
+        let ProjectionPlan::Window { partition_by, .. } = &plan.projections[4] else {
+            panic!("Expected window function");
+        };
+        assert!(partition_by.is_empty());
+
+        for sql in [
+            "SELECT host, COUNT(*), LAG(COUNT(*)) FROM logs GROUP BY host",
+            "SELECT TIME_BUCKET('1 minute', timestamp) AS m, COUNT(*), LAG(bytes) \
+             FROM logs GROUP BY m",
+            "SELECT TIME_BUCKET('1 minute', timestamp) AS m, DELTA(bytes) FROM logs GROUP BY m",
+        ] {
+            let err = plan_query(parse_query(sql).unwrap()).unwrap_err();
+            assert!(matches!(err, PlanError::InvalidWindow(_)), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_previous_period_plan() {
+        let plan = plan_query(
+            parse_query_at(
+                "SELECT endpoint, COUNT(*) FROM logs WHERE timestamp > ago('1h') \
+                 GROUP BY endpoint HAVING COUNT(*) > 1 COMPARE TO '1 day' LIMIT 5",
+                100_000_000,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let compare = plan.compare.as_ref().unwrap();
+        assert_eq!(compare.key_columns, vec![0]);
+        assert_eq!(compare.value_columns, vec![1]);
+        // The current period ends at the reference time
+        assert_eq!(compare.range.end, Some(100_000_001));
+
+        let previous = plan.previous_period().unwrap();
+        let range = previous.time_range.unwrap();
+        assert_eq!(range.start, Some(96_400_001 - 86_400_000));
+        assert_eq!(range.end, Some(100_000_001 - 86_400_000));
+        assert!(previous.having.is_none() && previous.limit.is_none());
+        assert_eq!(filter_time_range(previous.filters.as_ref().unwrap()).start, range.start);
+
+        for sql in [
+            "SELECT endpoint FROM logs WHERE timestamp > 5 AND timestamp < 10 COMPARE TO '1 day'",
+            "SELECT COUNT(*) FROM logs COMPARE TO '1 day'",
+        ] {
+            let err = plan_query(parse_query(sql).unwrap()).unwrap_err();
+            assert!(matches!(err, PlanError::InvalidCompare(_)), "{}", sql);
+        }
+    }
 }
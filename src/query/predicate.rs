@@ -3,10 +3,12 @@
 //! Builds row masks for filters before scanning full rows,
 //! reducing the amount of data that needs to be processed.
 
+use super::expr::{dictionary_input, ScalarExpr};
 use super::parser::FilterOperator;
-use super::planner::FilterPlan;
+use super::planner::{FilterExprPlan, FilterPlan};
 use crate::data::column::Column;
 use crate::data::Value;
+use fxhash::FxHashMap;
 use std::collections::HashMap;
 
 /// A bitmask representing which rows pass a filter
@@ -276,6 +278,14 @@ fn evaluate_filter(value: &Value, filter: &FilterPlan) -> bool {
                 false
             }
         }
+        FilterOperator::In => !value.is_null() && filter.values.contains(value),
+        FilterOperator::NotIn => !value.is_null() && !filter.values.contains(value),
+        FilterOperator::Between => match filter.values.as_slice() {
+            [low, high] => !value.is_null() && value >= low && value <= high,
+            _ => false,
+        },
+        FilterOperator::IsNull => value.is_null(),
+        FilterOperator::IsNotNull => !value.is_null(),
     }
 }
 
@@ -286,36 +296,184 @@ fn like_match(s: &str, pattern: &str) -> bool {
         .unwrap_or(false)
 }
 
-/// Build a combined mask for all filters (AND logic)
+/// Build a combined mask for a filter tree (None = all rows pass)
 pub fn build_combined_mask(
     columns: &HashMap<String, Column>,
-    filters: &[FilterPlan],
+    filters: Option<&FilterExprPlan>,
     row_count: usize,
 ) -> RowMask {
-    if filters.is_empty() {
-        return RowMask::all_true(row_count);
+    match filters {
+        Some(expr) => build_expr_mask(columns, expr, row_count),
+        None => RowMask::all_true(row_count),
     }
+}
 
-    let mut result: Option<RowMask> = None;
-
-    for filter in filters {
-        if let Some(column) = columns.get(&filter.column) {
-            let mask = build_filter_mask(column, filter, row_count);
+/// Recursively combine predicate masks with AND/OR/NOT
+fn build_expr_mask(
+    columns: &HashMap<String, Column>,
+    expr: &FilterExprPlan,
+    row_count: usize,
+) -> RowMask {
+    match expr {
+        FilterExprPlan::Predicate(filter) => match &filter.operand {
+            ScalarExpr::Column(name) => match columns.get(name) {
+                Some(column) => build_filter_mask(column, filter, row_count),
+                // Column not present in this shard - every row reads as NULL
+                None if evaluate_filter(&Value::Null, filter) => RowMask::all_true(row_count),
+                None => RowMask::all_false(row_count),
+            },
+            operand => build_computed_filter_mask(columns, operand, filter, row_count),
+        },
+        FilterExprPlan::And(children) => {
+            let mut result = RowMask::all_true(row_count);
+            for child in children {
+                if result.none() {
+                    break;
+                }
+                result.and(&build_expr_mask(columns, child, row_count));
+            }
+            result
+        }
+        FilterExprPlan::Or(children) => {
+            let mut result = RowMask::all_false(row_count);
+            for child in children {
+                if result.all() {
+                    break;
+                }
+                result.or(&build_expr_mask(columns, child, row_count));
+            }
+            result
+        }
+        FilterExprPlan::Not(inner) => build_false_mask(columns, inner, row_count),
+        // Bound to an IN list before planning
+        FilterExprPlan::InSubquery { .. } => RowMask::all_false(row_count),
+    }
+}
 
-            result = Some(match result {
-                Some(mut r) => {
-                    r.and(&mask);
-                    r
+/// Rows where a filter tree is false, which NOT matches. A predicate on a
+/// NULL operand, other than IS [NOT] NULL, is neither true nor false, so
+/// such rows match neither `x IN (1)` nor `NOT (x IN (1))`.
+fn build_false_mask(
+    columns: &HashMap<String, Column>,
+    expr: &FilterExprPlan,
+    row_count: usize,
+) -> RowMask {
+    match expr {
+        FilterExprPlan::Predicate(filter) => {
+            let mut result = build_expr_mask(columns, expr, row_count);
+            result.not();
+            if !is_null_test(filter) {
+                let not_null = FilterExprPlan::Predicate(FilterPlan {
+                    operator: FilterOperator::IsNotNull,
+                    ..filter.clone()
+                });
+                result.and(&build_expr_mask(columns, &not_null, row_count));
+            }
+            result
+        }
+        FilterExprPlan::And(children) => {
+            let mut result = RowMask::all_false(row_count);
+            for child in children {
+                if result.all() {
+                    break;
                 }
-                None => mask,
-            });
-        } else {
-            // Column not found - no rows match
-            return RowMask::all_false(row_count);
+                result.or(&build_false_mask(columns, child, row_count));
+            }
+            result
+        }
+        FilterExprPlan::Or(children) => {
+            let mut result = RowMask::all_true(row_count);
+            for child in children {
+                if result.none() {
+                    break;
+                }
+                result.and(&build_false_mask(columns, child, row_count));
+            }
+            result
+        }
+        FilterExprPlan::Not(inner) => build_expr_mask(columns, inner, row_count),
+        FilterExprPlan::InSubquery { .. } => RowMask::all_false(row_count),
+    }
+}
+
+/// IS NULL and IS NOT NULL, the only predicates that are never unknown
+fn is_null_test(filter: &FilterPlan) -> bool {
+    matches!(filter.operator, FilterOperator::IsNull | FilterOperator::IsNotNull)
+}
+
+/// Build a row mask for a filter whose operand is computed from columns
+fn build_computed_filter_mask(
+    columns: &HashMap<String, Column>,
+    operand: &ScalarExpr,
+    filter: &FilterPlan,
+    row_count: usize,
+) -> RowMask {
+    let mut mask = RowMask::all_false(row_count);
+
+    // Over a single dictionary-encoded column, test each distinct id once
+    if let Some(input) = dictionary_input(operand, columns) {
+        let mut matches: FxHashMap<Option<u32>, bool> = FxHashMap::default();
+        for (i, &id) in input.ids.iter().enumerate().take(row_count) {
+            let matched = *matches
+                .entry(id)
+                .or_insert_with(|| evaluate_filter(&input.evaluate(operand, id), filter));
+            if matched {
+                mask.set(i);
+            }
+        }
+        return mask;
+    }
+
+    for i in 0..row_count {
+        let value = operand.evaluate(&|name| {
+            columns.get(name).map(|c| c.get(i)).unwrap_or(Value::Null)
+        });
+        if evaluate_filter(&value, filter) {
+            mask.set(i);
+        }
+    }
+
+    mask
+}
+
+/// Evaluate a filter tree against a single result row (used for HAVING)
+pub fn row_matches(expr: &FilterExprPlan, columns: &[String], row: &[Value]) -> bool {
+    matches(expr, &|name| {
+        columns
+            .iter()
+            .position(|c| c == name)
+            .and_then(|idx| row.get(idx))
+            .cloned()
+            .unwrap_or(Value::Null)
+    })
+}
+
+/// Evaluate a filter tree against one row, reading values through `column`.
+/// Used for HAVING and CASE conditions, where there is no column to mask.
+pub fn matches(expr: &FilterExprPlan, column: &dyn Fn(&str) -> Value) -> bool {
+    match expr {
+        FilterExprPlan::Predicate(filter) => {
+            evaluate_filter(&filter.operand.evaluate(column), filter)
         }
+        FilterExprPlan::And(children) => children.iter().all(|c| matches(c, column)),
+        FilterExprPlan::Or(children) => children.iter().any(|c| matches(c, column)),
+        FilterExprPlan::Not(inner) => is_false(inner, column),
+        FilterExprPlan::InSubquery { .. } => false,
     }
+}
 
-    result.unwrap_or_else(|| RowMask::all_true(row_count))
+/// Whether a filter tree is false for one row, like `build_false_mask`
+fn is_false(expr: &FilterExprPlan, column: &dyn Fn(&str) -> Value) -> bool {
+    match expr {
+        FilterExprPlan::Predicate(filter) => {
+            let value = filter.operand.evaluate(column);
+            (is_null_test(filter) || !value.is_null()) && !evaluate_filter(&value, filter)
+        }
+        FilterExprPlan::And(children) => children.iter().any(|c| is_false(c, column)),
+        FilterExprPlan::Or(children) => children.iter().all(|c| is_false(c, column)),
+        FilterExprPlan::Not(inner) => matches(inner, column),
+        FilterExprPlan::InSubquery { .. } => false,
+    }
 }
 
 #[cfg(test)]
@@ -401,6 +559,178 @@ mod tests {
         assert_eq!(indices, vec![1, 5, 10]);
     }
 
+    fn make_columns() -> HashMap<String, Column> {
+        let mut status = Column::new(crate::data::DataType::Int64);
+        let mut event = Column::new(crate::data::DataType::String);
+        for i in 0..10 {
+            status.push(&Value::Int64(if i % 3 == 0 { 500 } else { 200 }));
+            event.push(&Value::String(if i % 2 == 0 { "click" } else { "view" }.to_string()));
+        }
+        let mut columns = HashMap::new();
+        columns.insert("status".to_string(), status);
+        columns.insert("event".to_string(), event);
+        columns
+    }
+
+    fn predicate(column: &str, operator: FilterOperator, value: Value) -> FilterExprPlan {
+        FilterExprPlan::Predicate(FilterPlan {
+            operand: ScalarExpr::Column(column.to_string()),
+            operator,
+            value,
+            values: Vec::new(),
+        })
+    }
+
+    fn multi_predicate(column: &str, operator: FilterOperator, values: Vec<Value>) -> FilterExprPlan {
+        FilterExprPlan::Predicate(FilterPlan {
+            operand: ScalarExpr::Column(column.to_string()),
+            operator,
+            value: Value::Null,
+            values,
+        })
+    }
+
+    #[test]
+    fn test_combined_mask_or() {
+        let columns = make_columns();
+        let expr = FilterExprPlan::Or(vec![
+            predicate("status", FilterOperator::Eq, Value::Int64(500)),
+            predicate("event", FilterOperator::Eq, Value::String("view".into())),
+        ]);
+
+        let mask = build_combined_mask(&columns, Some(&expr), 10);
+        // status=500 at 0,3,6,9; view at 1,3,5,7,9
+        assert_eq!(mask.indices(), vec![0, 1, 3, 5, 6, 7, 9]);
+    }
+
+    #[test]
+    fn test_combined_mask_not_and_nesting() {
+        let columns = make_columns();
+        let expr = FilterExprPlan::And(vec![
+            predicate("event", FilterOperator::Eq, Value::String("click".into())),
+            FilterExprPlan::Not(Box::new(predicate(
+                "status",
+                FilterOperator::Eq,
+                Value::Int64(500),
+            ))),
+        ]);
+
+        let mask = build_combined_mask(&columns, Some(&expr), 10);
+        // clicks at 0,2,4,6,8 minus status=500 at 0,6
+        assert_eq!(mask.indices(), vec![2, 4, 8]);
+    }
+
+    #[test]
+    fn test_in_and_between_masks() {
+        let columns = make_columns();
+
+        let in_list = multi_predicate(
+            "event",
+            FilterOperator::In,
+            vec![Value::String("view".into()), Value::String("scroll".into())],
+        );
+        assert_eq!(build_combined_mask(&columns, Some(&in_list), 10).indices(), vec![1, 3, 5, 7, 9]);
+
+        let not_in = multi_predicate("status", FilterOperator::NotIn, vec![Value::Int64(500)]);
+        assert_eq!(build_combined_mask(&columns, Some(&not_in), 10).count(), 6);
+
+        let between = multi_predicate(
+            "status",
+            FilterOperator::Between,
+            vec![Value::Int64(400), Value::Int64(500)],
+        );
+        assert_eq!(build_combined_mask(&columns, Some(&between), 10).indices(), vec![0, 3, 6, 9]);
+    }
+
+    #[test]
+    fn test_is_null_masks() {
+        let mut columns = make_columns();
+        let mut user = Column::new(crate::data::DataType::Int64);
+        for i in 0..10 {
+            user.push(&if i < 4 { Value::Null } else { Value::Int64(i) });
+        }
+        columns.insert("user".to_string(), user);
+
+        let is_null = predicate("user", FilterOperator::IsNull, Value::Null);
+        assert_eq!(build_combined_mask(&columns, Some(&is_null), 10).indices(), vec![0, 1, 2, 3]);
+
+        let not_null = predicate("user", FilterOperator::IsNotNull, Value::Null);
+        assert_eq!(build_combined_mask(&columns, Some(&not_null), 10).count(), 6);
+
+        // A column missing from the shard is NULL for every row
+        let missing = predicate("region", FilterOperator::IsNull, Value::Null);
+        assert!(build_combined_mask(&columns, Some(&missing), 10).all());
+
+        // NOT IN, like IN, never matches NULL
+        let not_in = multi_predicate("user", FilterOperator::NotIn, vec![Value::Int64(5)]);
+        assert_eq!(build_combined_mask(&columns, Some(&not_in), 10).indices(), vec![4, 6, 7, 8, 9]);
+        let not_in = multi_predicate("region", FilterOperator::NotIn, vec![Value::Int64(5)]);
+        assert!(build_combined_mask(&columns, Some(&not_in), 10).none());
+
+        // Neither does NOT over IN or BETWEEN, so it agrees with NOT IN
+        let not = |expr| FilterExprPlan::Not(Box::new(expr));
+        let in_list = multi_predicate("user", FilterOperator::In, vec![Value::Int64(5)]);
+        assert_eq!(
+            build_combined_mask(&columns, Some(&not(in_list.clone())), 10).indices(),
+            vec![4, 6, 7, 8, 9]
+        );
+        let between = multi_predicate(
+            "user",
+            FilterOperator::Between,
+            vec![Value::Int64(5), Value::Int64(7)],
+        );
+        assert_eq!(build_combined_mask(&columns, Some(&not(between)), 10).indices(), vec![4, 8, 9]);
+        let in_list = multi_predicate("region", FilterOperator::In, vec![Value::Int64(5)]);
+        assert!(build_combined_mask(&columns, Some(&not(in_list)), 10).none());
+        let is_null = predicate("region", FilterOperator::IsNull, Value::Null);
+        assert!(build_combined_mask(&columns, Some(&not(is_null)), 10).none());
+    }
+
+    #[test]
+    fn test_row_matches() {
+        let columns = vec!["endpoint".to_string(), "count_*".to_string()];
+        let row = vec![Value::String("/api".into()), Value::Int64(150)];
+
+        let expr = FilterExprPlan::And(vec![
+            predicate("count_*", FilterOperator::Gt, Value::Int64(100)),
+            predicate("endpoint", FilterOperator::NotEq, Value::String("/health".into())),
+        ]);
+        assert!(row_matches(&expr, &columns, &row));
+
+        let expr = FilterExprPlan::Not(Box::new(predicate(
+            "count_*",
+            FilterOperator::GtEq,
+            Value::Int64(150),
+        )));
+        assert!(!row_matches(&expr, &columns, &row));
+
+        // NOT over a NULL operand is unknown, so the row doesn't match
+        let row = vec![Value::Null, Value::Int64(150)];
+        let expr = FilterExprPlan::Not(Box::new(multi_predicate(
+            "endpoint",
+            FilterOperator::In,
+            vec![Value::String("/health".into())],
+        )));
+        assert!(!row_matches(&expr, &columns, &row));
+        let is_null = predicate("endpoint", FilterOperator::IsNull, Value::Null);
+        assert!(!row_matches(&FilterExprPlan::Not(Box::new(is_null)), &columns, &row));
+    }
+
+    #[test]
+    fn test_combined_mask_missing_column() {
+        let columns = make_columns();
+        let missing = predicate("region", FilterOperator::Eq, Value::String("us".into()));
+
+        assert!(build_combined_mask(&columns, Some(&missing), 10).none());
+        assert!(build_combined_mask(&columns, None, 10).all());
+
+        let either = FilterExprPlan::Or(vec![
+            missing,
+            predicate("status", FilterOperator::Eq, Value::Int64(200)),
+        ]);
+        assert_eq!(build_combined_mask(&columns, Some(&either), 10).count(), 6);
+    }
+
     #[test]
     fn test_row_mask_large() {
         let mut mask = RowMask::all_true(10000);
@@ -0,0 +1,361 @@
+//! Queries running on this node, and the log of slow ones
+//!
+//! Each query run through the API is registered by id while it runs, so
+//! `GET /queries` can list it and `DELETE /queries/:id` cancel it. So is a
+//! node's part of a query another node coordinates, tagged with that node
+//! and the query's id there, so cancelling the query there stops it here.
+//! Once it finishes, a query that ran for longer than the server's threshold
+//! is recorded as a row of the `_snorkel_queries` table, which can be queried
+//! like any other. Before the first slow query it reads as empty.
+
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::executor::QueryResult;
+use super::limits::QueryControl;
+use crate::data::{Table, Value};
+use crate::storage::StorageEngine;
+
+/// Table slow queries are recorded in
+pub const QUERY_LOG_TABLE: &str = "_snorkel_queries";
+
+/// Columns of each `QUERY_LOG_TABLE` row
+const QUERY_LOG_COLUMNS: [&str; 9] = [
+    "timestamp",
+    "query_id",
+    "sql",
+    "duration_ms",
+    "status",
+    "error",
+    "rows_scanned",
+    "shards_scanned",
+    "rows_returned",
+];
+
+/// `QUERY_LOG_TABLE` as it reads before any query has been logged to it
+pub fn empty_query_log() -> Table {
+    let columns: Vec<String> = QUERY_LOG_COLUMNS.iter().map(|c| c.to_string()).collect();
+    Table::from_rows(QUERY_LOG_TABLE, &columns, &[])
+}
+
+#[derive(Debug, Default)]
+pub struct QueryRegistry {
+    queries: RwLock<HashMap<String, Arc<QueryEntry>>>,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug)]
+struct QueryEntry {
+    sql: String,
+    /// `<node>/<query id>` of the query this is a part of
+    part_of: Option<String>,
+    /// Milliseconds since the epoch
+    started_at: i64,
+    start: Instant,
+    control: Arc<QueryControl>,
+}
+
+/// A running query, as `GET /queries` lists it
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryInfo {
+    pub query_id: String,
+    pub sql: String,
+    /// When the query started, in milliseconds since the epoch
+    pub started_at: i64,
+    pub elapsed_ms: u64,
+    /// Shards this node has started reading for the query so far
+    pub shards_scanned: usize,
+    /// For a part of another node's query: `<node>/<query id>` there
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub part_of: Option<String>,
+}
+
+impl QueryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track a query until the returned guard is dropped, under `id` or a
+    /// generated one. Returns None if the id is taken by a running query.
+    pub fn register(
+        self: &Arc<Self>,
+        id: Option<String>,
+        sql: &str,
+        control: Arc<QueryControl>,
+    ) -> Option<RunningQuery> {
+        let mut queries = self.queries.write();
+        let id = match id {
+            Some(id) if queries.contains_key(&id) => return None,
+            Some(id) => id,
+            None => self.generate_id(&queries),
+        };
+        Some(self.insert(&mut queries, id, None, sql, control))
+    }
+
+    /// Track this node's part of a query coordinated by another node, under
+    /// a generated id, until the returned guard is dropped. `part_of` is
+    /// `<node>/<query id>` of the query there.
+    pub fn register_part(
+        self: &Arc<Self>,
+        part_of: Option<String>,
+        sql: &str,
+        control: Arc<QueryControl>,
+    ) -> RunningQuery {
+        let mut queries = self.queries.write();
+        let id = self.generate_id(&queries);
+        self.insert(&mut queries, id, part_of, sql, control)
+    }
+
+    /// Cancel a running query. Returns false if there is none by that id.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.queries.read().get(id) {
+            Some(entry) => {
+                entry.control.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancel this node's parts of another node's query, returning how many
+    /// there were
+    pub fn cancel_parts(&self, part_of: &str) -> usize {
+        let mut cancelled = 0;
+        for entry in self.queries.read().values() {
+            if entry.part_of.as_deref() == Some(part_of) {
+                entry.control.cancel();
+                cancelled += 1;
+            }
+        }
+        cancelled
+    }
+
+    /// Running queries, oldest first
+    pub fn list(&self) -> Vec<QueryInfo> {
+        let mut queries: Vec<QueryInfo> = self
+            .queries
+            .read()
+            .iter()
+            .map(|(id, entry)| QueryInfo {
+                query_id: id.clone(),
+                sql: entry.sql.clone(),
+                started_at: entry.started_at,
+                elapsed_ms: entry.start.elapsed().as_millis() as u64,
+                shards_scanned: entry.control.shards_scanned(),
+                part_of: entry.part_of.clone(),
+            })
+            .collect();
+        queries.sort_by(|a, b| (a.started_at, &a.query_id).cmp(&(b.started_at, &b.query_id)));
+        queries
+    }
+
+    pub fn len(&self) -> usize {
+        self.queries.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// A new id, skipping ids clients have chosen
+    fn generate_id(&self, queries: &HashMap<String, Arc<QueryEntry>>) -> String {
+        loop {
+            let id = format!("q-{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+            if !queries.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    fn insert(
+        self: &Arc<Self>,
+        queries: &mut HashMap<String, Arc<QueryEntry>>,
+        id: String,
+        part_of: Option<String>,
+        sql: &str,
+        control: Arc<QueryControl>,
+    ) -> RunningQuery {
+        let entry = Arc::new(QueryEntry {
+            sql: sql.to_string(),
+            part_of,
+            started_at: chrono::Utc::now().timestamp_millis(),
+            start: Instant::now(),
+            control,
+        });
+        queries.insert(id.clone(), entry.clone());
+        RunningQuery {
+            registry: self.clone(),
+            id,
+            entry,
+        }
+    }
+}
+
+/// A registered query. Dropping it, as when the client goes away before the
+/// query finishes, unregisters the query and stops it.
+#[derive(Debug)]
+pub struct RunningQuery {
+    registry: Arc<QueryRegistry>,
+    id: String,
+    entry: Arc<QueryEntry>,
+}
+
+impl RunningQuery {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn control(&self) -> &Arc<QueryControl> {
+        &self.entry.control
+    }
+
+    /// Record the finished query in `QUERY_LOG_TABLE` if it ran for at least
+    /// `threshold_ms`
+    pub fn log_if_slow(
+        &self,
+        engine: &StorageEngine,
+        threshold_ms: Option<u64>,
+        result: &Result<QueryResult, String>,
+    ) {
+        let elapsed = self.entry.start.elapsed();
+        if threshold_ms.is_none_or(|ms| elapsed < Duration::from_millis(ms)) {
+            return;
+        }
+
+        let int = |n: usize| Value::Int64(n as i64);
+        let (status, error, rows_scanned, shards_scanned, rows_returned) = match result {
+            Ok(result) => (
+                "ok",
+                Value::Null,
+                int(result.rows_scanned),
+                int(result.shards_scanned),
+                int(result.rows.len()),
+            ),
+            Err(e) => (
+                "error",
+                Value::String(e.clone()),
+                Value::Null,
+                int(self.entry.control.shards_scanned()),
+                Value::Null,
+            ),
+        };
+        let values = [
+            Value::Timestamp(self.entry.started_at),
+            Value::String(self.id.clone()),
+            Value::String(self.entry.sql.clone()),
+            Value::Int64(elapsed.as_millis() as i64),
+            Value::String(status.to_string()),
+            error,
+            rows_scanned,
+            shards_scanned,
+            rows_returned,
+        ];
+        let row = QUERY_LOG_COLUMNS.iter().map(|c| c.to_string()).zip(values).collect();
+        if let Err(e) = engine.insert(QUERY_LOG_TABLE, row) {
+            tracing::warn!("Failed to log slow query {}: {}", self.id, e);
+        }
+    }
+}
+
+impl Drop for RunningQuery {
+    fn drop(&mut self) {
+        self.registry.queries.write().remove(&self.id);
+        self.entry.control.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::limits::QueryLimits;
+    use crate::query::run_query_at;
+
+    #[test]
+    fn test_register_and_cancel() {
+        let registry = Arc::new(QueryRegistry::new());
+        let control = Arc::new(QueryControl::new(QueryLimits::default()));
+        let sql = "SELECT COUNT(*) FROM events";
+        let running = registry.register(Some("report".into()), sql, control.clone()).unwrap();
+        assert_eq!(running.id(), "report");
+        assert!(registry.register(Some("report".into()), sql, control.clone()).is_none());
+
+        let generated = registry.register(None, sql, control.clone()).unwrap();
+        assert_ne!(generated.id(), "report");
+        let queries = registry.list();
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[0].sql, sql);
+        assert_eq!(queries[0].part_of, None);
+
+        assert!(registry.cancel("report"));
+        assert!(control.is_stopped());
+        assert!(!registry.cancel("missing"));
+
+        drop(running);
+        drop(generated);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_parts() {
+        let registry = Arc::new(QueryRegistry::new());
+        let control = || Arc::new(QueryControl::new(QueryLimits::default()));
+        let (first, second, other) = (control(), control(), control());
+        let sql = "SELECT COUNT(*) FROM events";
+        let _parts = [
+            registry.register_part(Some("node-1/report".into()), sql, first.clone()),
+            registry.register_part(Some("node-1/report".into()), sql, second.clone()),
+            registry.register_part(Some("node-2/report".into()), sql, other.clone()),
+        ];
+
+        let part_of: Vec<Option<String>> = registry.list().into_iter().map(|q| q.part_of).collect();
+        assert_eq!(part_of.iter().flatten().filter(|p| *p == "node-1/report").count(), 2);
+        assert_eq!(registry.cancel_parts("node-1/report"), 2);
+        assert!(first.is_stopped() && second.is_stopped());
+        assert!(!other.is_stopped());
+        assert_eq!(registry.cancel_parts("node-1/missing"), 0);
+    }
+
+    #[test]
+    fn test_slow_query_log() {
+        let engine = StorageEngine::new();
+        let registry = Arc::new(QueryRegistry::new());
+        let control = Arc::new(QueryControl::new(QueryLimits::default()));
+        let running = registry.register(None, "SELECT 1", control.clone()).unwrap();
+
+        running.log_if_slow(&engine, None, &Err("failed".into()));
+        assert!(engine.get_table(QUERY_LOG_TABLE).is_none());
+        // Nothing logged yet reads as no rows rather than a missing table
+        let result = run_query_at(
+            &engine,
+            "SELECT query_id, duration_ms FROM _snorkel_queries WHERE duration_ms > 100",
+            0,
+        )
+        .unwrap();
+        assert_eq!(result.columns, vec!["query_id", "duration_ms"]);
+        assert!(result.rows.is_empty());
+        let result = run_query_at(&engine, "SELECT COUNT(*) FROM _snorkel_queries", 0).unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int64(0)]]);
+
+        running.log_if_slow(&engine, Some(0), &Err("failed".into()));
+        running.log_if_slow(&engine, Some(60_000), &Err("failed".into()));
+
+        let result = run_query_at(
+            &engine,
+            "SELECT query_id, status, error FROM _snorkel_queries",
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Value::String(running.id().to_string()),
+                Value::String("error".into()),
+                Value::String("failed".into()),
+            ]]
+        );
+    }
+}
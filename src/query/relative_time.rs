@@ -0,0 +1,172 @@
+//! Relative time literals
+//!
+//! `NOW()`, `NOW() - INTERVAL '1 hour'` and `ago('15m')` are replaced by
+//! epoch milliseconds before the query is parsed further, so the planner
+//! sees plain constants and can fold them into the query's time range.
+//! A query is resolved against one reference time throughout, and cluster
+//! peers are sent the coordinator's, so every node scans the same window.
+
+use sqlparser::ast::{
+    BinaryOperator, Expr, FunctionArg, FunctionArgExpr, GroupByExpr, Query, SelectItem, SetExpr,
+    TableFactor, Value as SqlValue,
+};
+
+use super::parser::{parse_interval, ParseError};
+
+/// Reference time for a new query, truncated to the second so that
+/// repeated dashboard queries within a second share a cache entry
+pub fn query_time() -> i64 {
+    let now = chrono::Utc::now().timestamp_millis();
+    now - now.rem_euclid(1000)
+}
+
+/// Replace relative time literals in a SELECT, and the queries nested in
+/// it, with `now`-based constants. Returns true if the query referred to
+/// the current time at all.
+pub(crate) fn resolve_query(query: &mut Query, now: i64) -> Result<bool, ParseError> {
+    let mut resolved = false;
+    for cte in query.with.iter_mut().flat_map(|with| &mut with.cte_tables) {
+        resolved |= resolve_query(&mut cte.query, now)?;
+    }
+
+    Ok(resolve_set_expr(&mut query.body, now)? || resolved)
+}
+
+/// Resolve each SELECT of a set expression, such as every branch of a UNION ALL
+fn resolve_set_expr(body: &mut SetExpr, now: i64) -> Result<bool, ParseError> {
+    let select = match body {
+        SetExpr::Select(select) => select,
+        SetExpr::Query(query) => return resolve_query(query, now),
+        SetExpr::SetOperation { left, right, .. } => {
+            let left = resolve_set_expr(left, now)?;
+            return Ok(resolve_set_expr(right, now)? || left);
+        }
+        _ => return Ok(false),
+    };
+
+    let mut resolved = false;
+    for from in &mut select.from {
+        if let TableFactor::Derived { subquery, .. } = &mut from.relation {
+            resolved |= resolve_query(subquery, now)?;
+        }
+    }
+    for item in &mut select.projection {
+        match item {
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                resolved |= resolve_expr(expr, now)?;
+            }
+            _ => {}
+        }
+    }
+    if let Some(selection) = &mut select.selection {
+        resolved |= resolve_expr(selection, now)?;
+    }
+    if let GroupByExpr::Expressions(exprs) = &mut select.group_by {
+        for expr in exprs {
+            resolved |= resolve_expr(expr, now)?;
+        }
+    }
+    if let Some(having) = &mut select.having {
+        resolved |= resolve_expr(having, now)?;
+    }
+
+    Ok(resolved)
+}
+
+fn resolve_expr(expr: &mut Expr, now: i64) -> Result<bool, ParseError> {
+    if let Some(ms) = relative_time(expr, now)? {
+        *expr = Expr::Value(SqlValue::Number(ms.to_string(), false));
+        return Ok(true);
+    }
+
+    let mut resolved = false;
+    match expr {
+        Expr::BinaryOp { left, right, .. } => {
+            resolved |= resolve_expr(left, now)?;
+            resolved |= resolve_expr(right, now)?;
+        }
+        Expr::UnaryOp { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr) => resolved |= resolve_expr(expr, now)?,
+        Expr::InSubquery { expr, subquery, .. } => {
+            resolved |= resolve_expr(expr, now)?;
+            resolved |= resolve_query(subquery, now)?;
+        }
+        Expr::InList { expr, list, .. } => {
+            resolved |= resolve_expr(expr, now)?;
+            for item in list {
+                resolved |= resolve_expr(item, now)?;
+            }
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            resolved |= resolve_expr(expr, now)?;
+            resolved |= resolve_expr(low, now)?;
+            resolved |= resolve_expr(high, now)?;
+        }
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            for expr in operand
+                .iter_mut()
+                .chain(else_result.iter_mut())
+                .map(|e| e.as_mut())
+                .chain(conditions.iter_mut())
+                .chain(results.iter_mut())
+            {
+                resolved |= resolve_expr(expr, now)?;
+            }
+        }
+        Expr::Function(func) => {
+            for arg in &mut func.args {
+                if let FunctionArg::Unnamed(FunctionArgExpr::Expr(arg)) = arg {
+                    resolved |= resolve_expr(arg, now)?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(resolved)
+}
+
+/// Milliseconds for a relative time literal, or `None` if `expr` is not one
+fn relative_time(expr: &Expr, now: i64) -> Result<Option<i64>, ParseError> {
+    match expr {
+        Expr::Function(func) => match func.name.to_string().to_uppercase().as_str() {
+            "NOW" if func.args.is_empty() => Ok(Some(now)),
+            "AGO" => match func.args.as_slice() {
+                [FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                    SqlValue::SingleQuotedString(interval),
+                )))] => Ok(Some(now - parse_interval(interval)?)),
+                _ => Err(ParseError::InvalidInterval(func.to_string())),
+            },
+            _ => Ok(None),
+        },
+        Expr::BinaryOp { left, op, right } => {
+            let sign = match op {
+                BinaryOperator::Plus => 1,
+                BinaryOperator::Minus => -1,
+                _ => return Ok(None),
+            };
+            let (Some(time), Expr::Interval(interval)) = (relative_time(left, now)?, right.as_ref())
+            else {
+                return Ok(None);
+            };
+            // INTERVAL '1 hour' or INTERVAL '1' HOUR
+            let value = interval.value.to_string();
+            let value = value.trim_matches('\'');
+            let interval_ms = match &interval.leading_field {
+                Some(unit) => parse_interval(&format!("{} {}", value, unit))?,
+                None => parse_interval(value)?,
+            };
+            Ok(Some(time + sign * interval_ms))
+        }
+        Expr::Nested(inner) => relative_time(inner, now),
+        _ => Ok(None),
+    }
+}
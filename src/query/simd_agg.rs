@@ -104,6 +104,8 @@ pub fn max_f64_dense(values: &[f64]) -> Option<f64> {
 #[derive(Debug, Clone, Default)]
 pub struct AggregateStats {
     pub sum: f64,
+    /// Sum of squared deviations from the mean, for variance
+    pub m2: f64,
     pub count: usize,
     pub min: Option<f64>,
     pub max: Option<f64>,
@@ -114,6 +116,7 @@ impl AggregateStats {
     #[inline]
     pub fn compute_f64(values: &[Option<f64>]) -> Self {
         let mut sum = 0.0;
+        let mut deviations = Deviations::default();
         let mut count = 0usize;
         let mut min: Option<f64> = None;
         let mut max: Option<f64> = None;
@@ -121,11 +124,12 @@ impl AggregateStats {
         for v in values.iter().filter_map(|v| *v) {
             sum += v;
             count += 1;
+            deviations.add(v, count);
             min = Some(min.map_or(v, |m| m.min(v)));
             max = Some(max.map_or(v, |m| m.max(v)));
         }
 
-        Self { sum, count, min, max }
+        Self { sum, m2: deviations.m2, count, min, max }
     }
 
     /// Compute all basic aggregates for dense f64 slice
@@ -136,17 +140,20 @@ impl AggregateStats {
         }
 
         let mut sum = 0.0;
+        let mut deviations = Deviations::default();
         let mut min = values[0];
         let mut max = values[0];
 
-        for &v in values {
+        for (i, &v) in values.iter().enumerate() {
             sum += v;
+            deviations.add(v, i + 1);
             if v < min { min = v; }
             if v > max { max = v; }
         }
 
         Self {
             sum,
+            m2: deviations.m2,
             count: values.len(),
             min: Some(min),
             max: Some(max),
@@ -157,6 +164,7 @@ impl AggregateStats {
     #[inline]
     pub fn compute_i64(values: &[Option<i64>]) -> Self {
         let mut sum = 0i64;
+        let mut deviations = Deviations::default();
         let mut count = 0usize;
         let mut min: Option<i64> = None;
         let mut max: Option<i64> = None;
@@ -164,12 +172,14 @@ impl AggregateStats {
         for v in values.iter().filter_map(|v| *v) {
             sum += v;
             count += 1;
+            deviations.add(v as f64, count);
             min = Some(min.map_or(v, |m| m.min(v)));
             max = Some(max.map_or(v, |m| m.max(v)));
         }
 
         Self {
             sum: sum as f64,
+            m2: deviations.m2,
             count,
             min: min.map(|v| v as f64),
             max: max.map(|v| v as f64),
@@ -184,17 +194,20 @@ impl AggregateStats {
         }
 
         let mut sum = 0i64;
+        let mut deviations = Deviations::default();
         let mut min = values[0];
         let mut max = values[0];
 
-        for &v in values {
+        for (i, &v) in values.iter().enumerate() {
             sum += v;
+            deviations.add(v as f64, i + 1);
             if v < min { min = v; }
             if v > max { max = v; }
         }
 
         Self {
             sum: sum as f64,
+            m2: deviations.m2,
             count: values.len(),
             min: Some(min as f64),
             max: Some(max as f64),
@@ -204,6 +217,14 @@ impl AggregateStats {
     /// Merge two aggregate stats
     #[inline]
     pub fn merge(&mut self, other: &Self) {
+        // Chan's parallel update, as in VarianceAccumulator::merge_parts
+        if let (Some(a), Some(b)) = (self.avg(), other.avg()) {
+            let (n_a, n_b) = (self.count as f64, other.count as f64);
+            let delta = b - a;
+            self.m2 += other.m2 + delta * delta * n_a * n_b / (n_a + n_b);
+        } else {
+            self.m2 += other.m2;
+        }
         self.sum += other.sum;
         self.count += other.count;
         self.min = match (self.min, other.min) {
@@ -227,6 +248,33 @@ impl AggregateStats {
             None
         }
     }
+
+    /// Get the sample variance
+    #[inline]
+    pub fn variance(&self) -> Option<f64> {
+        if self.count < 2 {
+            return None;
+        }
+        Some(self.m2 / (self.count - 1) as f64)
+    }
+}
+
+/// Running mean and sum of squared deviations, updated with Welford's
+/// algorithm
+#[derive(Default)]
+struct Deviations {
+    mean: f64,
+    m2: f64,
+}
+
+impl Deviations {
+    /// Add `v`, the `count`th value
+    #[inline]
+    fn add(&mut self, v: f64, count: usize) {
+        let delta = v - self.mean;
+        self.mean += delta / count as f64;
+        self.m2 += delta * (v - self.mean);
+    }
 }
 
 /// Vectorized filtering - returns indices of matching elements
@@ -306,6 +354,8 @@ mod tests {
         assert!((stats.min.unwrap() - 1.0).abs() < 0.001);
         assert!((stats.max.unwrap() - 4.0).abs() < 0.001);
         assert!((stats.avg().unwrap() - 2.5).abs() < 0.001);
+        // Sample variance of 1, 2, 3, 4
+        assert!((stats.variance().unwrap() - 5.0 / 3.0).abs() < 0.001);
     }
 
     #[test]
@@ -320,16 +370,19 @@ mod tests {
 
     #[test]
     fn test_merge_stats() {
+        // 1, 2, 3, 4 and 4, 5, 6
         let mut stats1 = AggregateStats {
             sum: 10.0,
+            m2: 5.0,
             count: 4,
             min: Some(1.0),
             max: Some(4.0),
         };
         let stats2 = AggregateStats {
             sum: 15.0,
+            m2: 2.0,
             count: 3,
-            min: Some(0.5),
+            min: Some(4.0),
             max: Some(6.0),
         };
 
@@ -337,8 +390,19 @@ mod tests {
 
         assert_eq!(stats1.count, 7);
         assert!((stats1.sum - 25.0).abs() < 0.001);
-        assert!((stats1.min.unwrap() - 0.5).abs() < 0.001);
+        assert!((stats1.min.unwrap() - 1.0).abs() < 0.001);
         assert!((stats1.max.unwrap() - 6.0).abs() < 0.001);
+        assert!((stats1.variance().unwrap() - 124.0 / 42.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_variance_of_large_values() {
+        // A sum of squares loses these to rounding
+        let base = 1_000_000_000i64;
+        let mut stats = AggregateStats::compute_i64_dense(&[base + 1, base + 2]);
+        stats.merge(&AggregateStats::compute_i64(&[Some(base + 3), None, Some(base + 4)]));
+        assert_eq!(stats.count, 4);
+        assert!((stats.variance().unwrap() - 5.0 / 3.0).abs() < 1e-6);
     }
 
     #[test]
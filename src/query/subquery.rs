@@ -0,0 +1,325 @@
+//! WITH queries, subqueries and UNION ALL
+//!
+//! Nested queries run first, as queries of their own. The result of a WITH
+//! query or FROM subquery becomes an in-memory table that the outer query
+//! reads like any other, as do the rows of all the SELECTs of a UNION ALL
+//! together; the single column an IN subquery returns becomes its IN list.
+//! Subqueries are not correlated: they cannot read the outer query's
+//! columns.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::executor::{execute_on_table, execute_query, ExecuteError, QueryResult};
+use super::explain::explain_local;
+use super::join::{bind_lookup, lookup_query, lookup_rows};
+use super::limits::QueryControl;
+use super::parser::{
+    parse_query_at, Filter, FilterExpr, FilterOperator, ParsedQuery, Projection,
+};
+use super::planner::plan_query;
+use super::{QueryError, ScanCursor};
+use crate::data::{Table, Value};
+use crate::storage::StorageEngine;
+
+/// Results of WITH queries, by name
+pub type Relations = HashMap<String, Arc<Table>>;
+
+/// SQL of each IN subquery, in the order `bind_in_lists` takes their results
+pub fn in_subqueries(parsed: &mut ParsedQuery) -> Vec<String> {
+    let mut queries = Vec::new();
+    for condition in conditions_mut(parsed) {
+        condition.for_each_subquery_mut(&mut |expr| {
+            if let FilterExpr::InSubquery { sql, .. } = expr {
+                queries.push(sql.clone());
+            }
+        });
+    }
+    queries
+}
+
+/// Replace each IN subquery with an IN list of its results
+pub fn bind_in_lists(parsed: &mut ParsedQuery, lists: &[Vec<Value>]) -> Result<(), ExecuteError> {
+    let mut lists = lists.iter();
+    let mut missing = false;
+    for condition in conditions_mut(parsed) {
+        condition.for_each_subquery_mut(&mut |expr| {
+            let FilterExpr::InSubquery { operand, negated, .. } = expr else {
+                return;
+            };
+            let Some(values) = lists.next() else {
+                missing = true;
+                return;
+            };
+            *expr = FilterExpr::Predicate(Filter {
+                operand: operand.clone(),
+                operator: if *negated {
+                    FilterOperator::NotIn
+                } else {
+                    FilterOperator::In
+                },
+                value: Value::Null,
+                values: values.clone(),
+            });
+        });
+    }
+
+    if missing || lists.next().is_some() {
+        return Err(ExecuteError::InvalidSubquery(
+            "results do not match the IN subqueries of the query".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// The values an IN subquery returned
+pub fn in_list(result: QueryResult) -> Result<Vec<Value>, ExecuteError> {
+    if result.columns.len() != 1 {
+        return Err(ExecuteError::InvalidSubquery(format!(
+            "in IN must return one column, not {}",
+            result.columns.len()
+        )));
+    }
+    Ok(result.rows.into_iter().filter_map(|row| row.into_iter().next()).collect())
+}
+
+/// A query result as a table the outer query can read
+pub fn relation(name: &str, result: &QueryResult) -> Arc<Table> {
+    Arc::new(Table::from_rows(name, &result.columns, &result.rows))
+}
+
+/// The rows of every SELECT of a UNION ALL as one table, named after the
+/// first one's columns
+pub fn union_relation(name: &str, results: Vec<QueryResult>) -> Result<Arc<Table>, ExecuteError> {
+    let mut results = results.into_iter();
+    let (columns, mut rows) = match results.next() {
+        Some(first) => (first.columns, first.rows),
+        None => (Vec::new(), Vec::new()),
+    };
+    for result in results {
+        if result.columns.len() != columns.len() {
+            return Err(ExecuteError::InvalidSubquery(format!(
+                "in UNION ALL returns {} columns, not {}",
+                result.columns.len(),
+                columns.len()
+            )));
+        }
+        rows.extend(result.rows);
+    }
+    Ok(Arc::new(Table::from_rows(name, &columns, &rows)))
+}
+
+/// Parse, plan and run a query on this node, running the queries nested in
+/// it and fetching its lookup table first. `relations` are the WITH queries
+/// it can read, and `control` stops the query and those nested in it.
+pub fn run_nested(
+    engine: &StorageEngine,
+    sql: &str,
+    now: i64,
+    relations: &Relations,
+    cursor: Option<ScanCursor>,
+    control: Option<&Arc<QueryControl>>,
+) -> Result<QueryResult, QueryError> {
+    let start = Instant::now();
+    if let Some(control) = control {
+        control.check()?;
+    }
+    let mut parsed = parse_query_at(sql, now)?;
+    let mut relations = relations.clone();
+    let (mut rows_scanned, mut shards_scanned) = (0, 0);
+    let mut run = |sql: &str, relations: &Relations| {
+        let result = run_nested(engine, sql, now, relations, None, control)?;
+        rows_scanned += result.rows_scanned;
+        shards_scanned += result.shards_scanned;
+        Ok::<_, QueryError>(result)
+    };
+
+    for (name, sql) in std::mem::take(&mut parsed.with) {
+        let result = run(&sql, &relations)?;
+        relations.insert(name.clone(), relation(&name, &result));
+    }
+
+    let mut lists = Vec::new();
+    for sql in in_subqueries(&mut parsed) {
+        lists.push(in_list(run(&sql, &relations)?)?);
+    }
+    bind_in_lists(&mut parsed, &lists)?;
+
+    let lookup = match &parsed.join {
+        Some(join) => Some(lookup_rows(join, run(&lookup_query(join), &relations)?)?),
+        None => None,
+    };
+    bind_lookup(&mut parsed, lookup)?;
+
+    let mut branches = Vec::new();
+    for sql in std::mem::take(&mut parsed.union_all) {
+        branches.push(run(&sql, &relations)?);
+    }
+
+    let table = match parsed.from_subquery.take() {
+        Some(sql) => Some(relation(&parsed.table, &run(&sql, &relations)?)),
+        None if !branches.is_empty() => Some(union_relation(&parsed.table, branches)?),
+        None => relations.get(&parsed.table).cloned(),
+    };
+
+    let explain = parsed.explain;
+    let mut plan = plan_query(parsed)?;
+    plan.cursor = cursor;
+    plan.control = control.cloned();
+    let mut result = match (explain, table) {
+        (Some(explain), table) => explain_local(engine, table.as_deref(), plan, explain, start)?,
+        (None, Some(table)) => execute_on_table(&table, &plan)?,
+        (None, None) => execute_query(engine, &plan)?,
+    };
+    result.rows_scanned += rows_scanned;
+    result.shards_scanned += shards_scanned;
+    Ok(result)
+}
+
+/// Conditions that may hold IN subqueries: WHERE, HAVING and aggregate
+/// FILTER clauses
+fn conditions_mut(parsed: &mut ParsedQuery) -> impl Iterator<Item = &mut FilterExpr> {
+    let aggregate_filters = parsed
+        .projections
+        .iter_mut()
+        .chain(&mut parsed.hidden_aggregations)
+        .filter_map(|p| match p {
+            Projection::Aggregation { filter, .. } => filter.as_mut(),
+            _ => None,
+        });
+    parsed.filters.iter_mut().chain(&mut parsed.having).chain(aggregate_filters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::run_query_at;
+
+    fn setup_engine() -> StorageEngine {
+        // user i has i sessions; endpoint /e{n} is hit n + 1 times, n ms each
+        let engine = StorageEngine::new();
+        let mut timestamp = 0;
+        let mut insert = |table: &str, columns: Vec<(&str, Value)>| {
+            timestamp += 1;
+            let mut row: HashMap<String, Value> = columns
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect();
+            row.insert("timestamp".to_string(), Value::Timestamp(timestamp));
+            engine.insert(table, row).unwrap();
+        };
+        for user in 0..10 {
+            for _ in 0..user {
+                insert("sessions", vec![("user_id", Value::Int64(user))]);
+            }
+        }
+        for n in 0..20 {
+            for _ in 0..=n {
+                insert(
+                    "logs",
+                    vec![
+                        ("endpoint", Value::String(format!("/e{}", n))),
+                        ("latency", Value::Int64(n)),
+                    ],
+                );
+            }
+        }
+        engine
+    }
+
+    fn run(engine: &StorageEngine, sql: &str) -> QueryResult {
+        run_query_at(engine, sql, 0).unwrap()
+    }
+
+    #[test]
+    fn test_from_subquery() {
+        let engine = setup_engine();
+        let result = run(
+            &engine,
+            "SELECT COUNT(*) FROM (SELECT user_id FROM sessions \
+             GROUP BY user_id HAVING COUNT(*) > 5) AS active",
+        );
+        assert_eq!(result.rows, vec![vec![Value::Int64(4)]]);
+        // The subquery's scan is counted
+        assert_eq!(result.rows_scanned, 45 + 4);
+    }
+
+    #[test]
+    fn test_in_subquery() {
+        let engine = setup_engine();
+        let result = run(
+            &engine,
+            "WITH busiest AS (SELECT endpoint, COUNT(*) AS hits FROM logs \
+             GROUP BY endpoint ORDER BY hits DESC LIMIT 3) \
+             SELECT endpoint, P99(latency) FROM logs \
+             WHERE endpoint IN (SELECT endpoint FROM busiest) \
+             GROUP BY endpoint ORDER BY endpoint",
+        );
+        let endpoints: Vec<&Value> = result.rows.iter().map(|row| &row[0]).collect();
+        assert_eq!(
+            endpoints,
+            vec![
+                &Value::String("/e17".into()),
+                &Value::String("/e18".into()),
+                &Value::String("/e19".into())
+            ]
+        );
+
+        let result = run(
+            &engine,
+            "SELECT COUNT(*) FROM sessions WHERE user_id NOT IN \
+             (SELECT user_id FROM sessions WHERE user_id > 2)",
+        );
+        assert_eq!(result.rows, vec![vec![Value::Int64(3)]]);
+
+        let error = run_query_at(
+            &engine,
+            "SELECT * FROM logs WHERE endpoint IN (SELECT endpoint, latency FROM logs)",
+            0,
+        );
+        assert!(matches!(
+            error,
+            Err(QueryError::Execute(ExecuteError::InvalidSubquery(_)))
+        ));
+    }
+
+    #[test]
+    fn test_with_queries() {
+        let engine = setup_engine();
+        let result = run(
+            &engine,
+            "WITH counts AS (SELECT endpoint, COUNT(*) AS hits FROM logs GROUP BY endpoint), \
+             busy AS (SELECT endpoint, hits FROM counts WHERE hits > 17) \
+             SELECT SUM(hits) FROM busy",
+        );
+        assert_eq!(result.rows, vec![vec![Value::Int64(18 + 19 + 20)]]);
+    }
+
+    #[test]
+    fn test_union_all() {
+        let engine = setup_engine();
+        let result = run(
+            &engine,
+            "SELECT user_id FROM sessions WHERE user_id = 2 \
+             UNION ALL SELECT latency FROM logs WHERE latency = 1 \
+             ORDER BY user_id DESC LIMIT 3",
+        );
+        // Columns are named after the first SELECT's
+        assert_eq!(result.columns, vec!["user_id".to_string()]);
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Int64(2)], vec![Value::Int64(2)], vec![Value::Int64(1)]]
+        );
+
+        let error = run_query_at(
+            &engine,
+            "SELECT user_id FROM sessions UNION ALL SELECT endpoint, latency FROM logs",
+            0,
+        );
+        assert!(matches!(
+            error,
+            Err(QueryError::Execute(ExecuteError::InvalidSubquery(_)))
+        ));
+    }
+}
@@ -0,0 +1,255 @@
+//! TIME_BUCKET intervals
+//!
+//! Sub-day intervals are fixed lengths of time. Days, weeks, months,
+//! quarters and years follow the calendar of the bucket's time zone, so a
+//! day bucket starts at local midnight even when DST makes the day 23 or
+//! 25 hours long. Without a time zone, buckets are floored in UTC.
+
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, Offset, TimeZone};
+use chrono_tz::Tz;
+
+use super::parser::{parse_interval, ParseError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeBucket {
+    pub interval: BucketInterval,
+    /// IANA zone the buckets are aligned to, e.g. `Europe/Berlin`
+    pub time_zone: Option<Tz>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BucketInterval {
+    /// Milliseconds
+    Fixed(i64),
+    Days(i64),
+    /// Weeks starting on Monday
+    Weeks(i64),
+    /// Months, quarters (3) and years (12), aligned to January
+    Months(i64),
+}
+
+impl TimeBucket {
+    /// Parse an interval such as `'15 minutes'`, `'1 week'` or `'1 quarter'`
+    /// and an optional time zone name
+    pub fn parse(interval: &str, time_zone: Option<&str>) -> Result<TimeBucket, ParseError> {
+        let interval = match calendar_interval(interval) {
+            Some(interval) => interval,
+            None => BucketInterval::Fixed(parse_interval(interval)?),
+        };
+        let length = match interval {
+            BucketInterval::Fixed(n)
+            | BucketInterval::Days(n)
+            | BucketInterval::Weeks(n)
+            | BucketInterval::Months(n) => n,
+        };
+        if length <= 0 {
+            return Err(ParseError::InvalidTimeBucket);
+        }
+
+        let time_zone = time_zone
+            .map(|name| {
+                name.parse::<Tz>()
+                    .map_err(|_| ParseError::InvalidTimeZone(name.to_string()))
+            })
+            .transpose()?;
+
+        Ok(TimeBucket {
+            interval,
+            time_zone,
+        })
+    }
+
+    /// Start of the bucket containing `ts`, in epoch milliseconds
+    pub fn floor(&self, ts: i64) -> i64 {
+        let tz = match (self.interval, self.time_zone) {
+            (BucketInterval::Fixed(ms), None) => return ts.div_euclid(ms) * ms,
+            (BucketInterval::Fixed(ms), Some(tz)) => {
+                // Floor the local wall-clock time, using the offset in effect at `ts`
+                let offset = utc_offset_ms(tz, ts);
+                return (ts + offset).div_euclid(ms) * ms - offset;
+            }
+            (_, tz) => tz.unwrap_or(Tz::UTC),
+        };
+
+        let Some(time) = DateTime::from_timestamp_millis(ts) else {
+            return ts;
+        };
+        let date = time.with_timezone(&tz).date_naive();
+        let start = match self.interval {
+            BucketInterval::Days(n) => floor_days(date, epoch(), n),
+            // 1970-01-05 was a Monday
+            BucketInterval::Weeks(n) => floor_days(date, epoch() + Duration::days(4), 7 * n),
+            BucketInterval::Months(n) => {
+                let months = date.year() as i64 * 12 + date.month0() as i64;
+                let start = months - months.rem_euclid(n);
+                let (year, month0) = (start.div_euclid(12), start.rem_euclid(12));
+                NaiveDate::from_ymd_opt(year as i32, month0 as u32 + 1, 1).unwrap_or(date)
+            }
+            BucketInterval::Fixed(_) => unreachable!(),
+        };
+
+        local_midnight(tz, start)
+    }
+
+    /// Start of the bucket following the one that starts at `start`
+    pub fn next(&self, start: i64) -> i64 {
+        let tz = match self.interval {
+            BucketInterval::Fixed(ms) => {
+                // The local offset may change within the bucket
+                let next = self.floor(start + ms);
+                return if next > start { next } else { start + ms };
+            }
+            _ => self.time_zone.unwrap_or(Tz::UTC),
+        };
+
+        let Some(time) = DateTime::from_timestamp_millis(start) else {
+            return start;
+        };
+        let date = time.with_timezone(&tz).date_naive();
+        let next = match self.interval {
+            BucketInterval::Days(n) => date + Duration::days(n),
+            BucketInterval::Weeks(n) => date + Duration::days(7 * n),
+            BucketInterval::Months(n) => date
+                .checked_add_months(Months::new(n as u32))
+                .unwrap_or(date),
+            BucketInterval::Fixed(_) => unreachable!(),
+        };
+
+        local_midnight(tz, next)
+    }
+}
+
+/// Day-based units, which are counted on the calendar rather than in milliseconds
+fn calendar_interval(interval: &str) -> Option<BucketInterval> {
+    let interval = interval.trim();
+    let (value, unit) = match interval.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [value, unit] => (*value, *unit),
+        [compact] => compact.split_at(compact.find(|c: char| !c.is_ascii_digit())?),
+        _ => return None,
+    };
+    let value: i64 = value.parse().ok()?;
+
+    match unit.to_lowercase().as_str() {
+        "d" | "day" | "days" => Some(BucketInterval::Days(value)),
+        "w" | "week" | "weeks" => Some(BucketInterval::Weeks(value)),
+        "month" | "months" => Some(BucketInterval::Months(value)),
+        "quarter" | "quarters" => Some(BucketInterval::Months(value * 3)),
+        "y" | "year" | "years" => Some(BucketInterval::Months(value * 12)),
+        _ => None,
+    }
+}
+
+fn epoch() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()
+}
+
+/// Latest date on or before `date` that is a whole number of `n`-day
+/// periods from `origin`
+fn floor_days(date: NaiveDate, origin: NaiveDate, n: i64) -> NaiveDate {
+    let days = (date - origin).num_days();
+    origin + Duration::days(days - days.rem_euclid(n))
+}
+
+fn utc_offset_ms(tz: Tz, ts: i64) -> i64 {
+    let Some(time) = DateTime::from_timestamp_millis(ts) else {
+        return 0;
+    };
+    tz.offset_from_utc_datetime(&time.naive_utc()).fix().local_minus_utc() as i64 * 1000
+}
+
+/// First instant of `date` in `tz`. When DST repeats midnight the earlier
+/// one is used; when it skips midnight, the day starts when the gap ends.
+fn local_midnight(tz: Tz, date: NaiveDate) -> i64 {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    (0..=24 * 4)
+        .find_map(|quarter_hours| {
+            tz.from_local_datetime(&(midnight + Duration::minutes(15 * quarter_hours)))
+                .earliest()
+        })
+        .map(|start| start.timestamp_millis())
+        .unwrap_or_else(|| midnight.and_utc().timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(rfc3339: &str) -> i64 {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().timestamp_millis()
+    }
+
+    fn floor(interval: &str, time_zone: Option<&str>, time: &str) -> i64 {
+        TimeBucket::parse(interval, time_zone).unwrap().floor(ms(time))
+    }
+
+    #[test]
+    fn test_fixed_intervals() {
+        assert_eq!(floor("5 minutes", None, "2024-03-01T10:07:30Z"), ms("2024-03-01T10:05:00Z"));
+        // Half-hour offsets shift hour buckets
+        assert_eq!(
+            floor("1 hour", Some("Asia/Kolkata"), "2024-03-01T10:07:30Z"),
+            ms("2024-03-01T09:30:00Z")
+        );
+        assert_eq!(floor("1 hour", None, "1969-12-31T23:30:00Z"), ms("1969-12-31T23:00:00Z"));
+    }
+
+    #[test]
+    fn test_days_in_time_zone() {
+        assert_eq!(floor("1 day", None, "2024-03-01T23:30:00Z"), ms("2024-03-01T00:00:00Z"));
+        assert_eq!(
+            floor("1 day", Some("Europe/Berlin"), "2024-03-01T23:30:00Z"),
+            ms("2024-03-01T23:00:00Z")
+        );
+        // DST starts on 2024-03-31 in Berlin: the day is 23 hours long
+        assert_eq!(
+            floor("1 day", Some("Europe/Berlin"), "2024-03-31T21:59:59Z"),
+            ms("2024-03-30T23:00:00Z")
+        );
+        assert_eq!(
+            floor("1 day", Some("Europe/Berlin"), "2024-03-31T22:00:00Z"),
+            ms("2024-03-31T22:00:00Z")
+        );
+        // ...and ends on 2024-10-27, making that day 25 hours long
+        assert_eq!(
+            floor("1 day", Some("Europe/Berlin"), "2024-10-27T22:59:59Z"),
+            ms("2024-10-26T22:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_calendar_units() {
+        // 2024-03-07 is a Thursday
+        assert_eq!(floor("1 week", None, "2024-03-07T12:00:00Z"), ms("2024-03-04T00:00:00Z"));
+        assert_eq!(floor("1w", None, "2024-03-04T00:00:00Z"), ms("2024-03-04T00:00:00Z"));
+        assert_eq!(floor("1 month", None, "2024-02-29T12:00:00Z"), ms("2024-02-01T00:00:00Z"));
+        assert_eq!(
+            floor("1 month", Some("America/New_York"), "2024-03-01T03:00:00Z"),
+            ms("2024-02-01T05:00:00Z")
+        );
+        assert_eq!(floor("1 quarter", None, "2024-08-15T00:00:00Z"), ms("2024-07-01T00:00:00Z"));
+        assert_eq!(floor("1 year", None, "2024-08-15T00:00:00Z"), ms("2024-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_next_bucket() {
+        let next = |interval: &str, time_zone: Option<&str>, start: &str| {
+            TimeBucket::parse(interval, time_zone).unwrap().next(ms(start))
+        };
+        assert_eq!(next("5 minutes", None, "2024-03-01T10:05:00Z"), ms("2024-03-01T10:10:00Z"));
+        assert_eq!(
+            next("1 day", Some("Europe/Berlin"), "2024-03-30T23:00:00Z"),
+            ms("2024-03-31T22:00:00Z")
+        );
+        assert_eq!(next("1 month", None, "2024-01-01T00:00:00Z"), ms("2024-02-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(TimeBucket::parse("0 minutes", None).is_err());
+        assert!(TimeBucket::parse("1 fortnight", None).is_err());
+        assert!(matches!(
+            TimeBucket::parse("1 day", Some("Mars/Olympus_Mons")),
+            Err(ParseError::InvalidTimeZone(_))
+        ));
+    }
+}
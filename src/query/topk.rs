@@ -0,0 +1,258 @@
+//! Space-Saving heavy hitters sketch
+//!
+//! At most `capacity` values are counted. A value that is not counted
+//! replaces the one with the lowest count and takes over that count as its
+//! possible overestimate, so each count is too high by at most its `error`
+//! and every value seen more than total / capacity times is counted.
+//! Sketches merge as in Agarwal et al., "Mergeable Summaries" (2012).
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::data::{TopKEntry, Value};
+
+/// Counters kept per value returned, so counts near the top stay accurate
+const COUNTERS_PER_RESULT: usize = 10;
+
+/// Fewest counters kept for small k
+const MIN_CAPACITY: usize = 100;
+
+/// Most values APPROX_TOP_K returns
+pub const MAX_TOP_K: usize = 1000;
+
+/// Mergeable heavy hitters sketch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpaceSaving {
+    capacity: usize,
+    /// Values inserted, counted or not
+    total: u64,
+    counters: Vec<TopKEntry>,
+    /// Counter slot by value; rebuilt after deserializing
+    #[serde(skip)]
+    index: HashMap<Value, usize>,
+    /// (count, slot) of every counter, to find the lowest
+    #[serde(skip)]
+    by_count: BTreeSet<(u64, usize)>,
+}
+
+impl SpaceSaving {
+    /// A sketch sized to answer the top `k` values
+    pub fn for_top_k(k: usize) -> Self {
+        Self::with_capacity((k * COUNTERS_PER_RESULT).max(MIN_CAPACITY))
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            total: 0,
+            counters: Vec::new(),
+            index: HashMap::new(),
+            by_count: BTreeSet::new(),
+        }
+    }
+
+    /// Count a value. NULL is ignored.
+    pub fn insert(&mut self, value: &Value) {
+        if value.is_null() {
+            return;
+        }
+        self.ensure_index();
+        self.total += 1;
+
+        if let Some(&slot) = self.index.get(value) {
+            self.bump(slot, 1);
+        } else if self.counters.len() < self.capacity {
+            self.push(TopKEntry {
+                value: value.clone(),
+                count: 1,
+                error: 0,
+            });
+        } else if let Some(&(min, slot)) = self.by_count.first() {
+            // Replace the least counted value, which may have occurred up
+            // to `min` times without being counted
+            let replaced = std::mem::replace(&mut self.counters[slot].value, value.clone());
+            self.index.remove(&replaced);
+            self.index.insert(value.clone(), slot);
+            self.counters[slot].error = min;
+            self.bump(slot, 1);
+        }
+    }
+
+    /// Merge another sketch into this one
+    pub fn merge(&mut self, other: &SpaceSaving) {
+        // A value missing from a full sketch may have occurred up to its
+        // lowest count times
+        let self_floor = self.floor();
+        let other_floor = other.floor();
+
+        let mut merged: HashMap<Value, TopKEntry> = self
+            .counters
+            .drain(..)
+            .map(|c| {
+                let counter = TopKEntry {
+                    count: c.count + other_floor,
+                    error: c.error + other_floor,
+                    value: c.value.clone(),
+                };
+                (c.value, counter)
+            })
+            .collect();
+        for c in &other.counters {
+            match merged.get_mut(&c.value) {
+                Some(counter) => {
+                    // Both sides counted it: replace the assumed floor with the real count
+                    counter.count = counter.count - other_floor + c.count;
+                    counter.error = counter.error - other_floor + c.error;
+                }
+                None => {
+                    merged.insert(
+                        c.value.clone(),
+                        TopKEntry {
+                            value: c.value.clone(),
+                            count: c.count + self_floor,
+                            error: c.error + self_floor,
+                        },
+                    );
+                }
+            }
+        }
+
+        let mut counters: Vec<TopKEntry> = merged.into_values().collect();
+        sort_counters(&mut counters);
+        counters.truncate(self.capacity.max(other.capacity));
+
+        self.capacity = self.capacity.max(other.capacity);
+        self.total += other.total;
+        self.counters = counters;
+        self.index.clear();
+        self.by_count.clear();
+        self.ensure_index();
+    }
+
+    /// The `k` values with the highest counts, highest first
+    pub fn top(&self, k: usize) -> Vec<TopKEntry> {
+        let mut counters = self.counters.clone();
+        sort_counters(&mut counters);
+        counters.truncate(k);
+        counters
+    }
+
+    /// Values inserted, counted or not
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Lowest count once the sketch is full, else zero
+    fn floor(&self) -> u64 {
+        if self.counters.len() < self.capacity {
+            return 0;
+        }
+        self.counters.iter().map(|c| c.count).min().unwrap_or(0)
+    }
+
+    fn push(&mut self, counter: TopKEntry) {
+        let slot = self.counters.len();
+        self.index.insert(counter.value.clone(), slot);
+        self.by_count.insert((counter.count, slot));
+        self.counters.push(counter);
+    }
+
+    fn bump(&mut self, slot: usize, by: u64) {
+        let counter = &mut self.counters[slot];
+        self.by_count.remove(&(counter.count, slot));
+        counter.count += by;
+        self.by_count.insert((counter.count, slot));
+    }
+
+    fn ensure_index(&mut self) {
+        if self.index.len() == self.counters.len() {
+            return;
+        }
+        self.index = self
+            .counters
+            .iter()
+            .enumerate()
+            .map(|(slot, c)| (c.value.clone(), slot))
+            .collect();
+        self.by_count = self
+            .counters
+            .iter()
+            .enumerate()
+            .map(|(slot, c)| (c.count, slot))
+            .collect();
+    }
+}
+
+/// Highest count first; ties by value so results are stable
+fn sort_counters(counters: &mut [TopKEntry]) {
+    counters.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_exact_below_capacity() {
+        let mut sketch = SpaceSaving::with_capacity(10);
+        for (value, times) in [("a", 5), ("b", 3), ("c", 7)] {
+            for _ in 0..times {
+                sketch.insert(&string(value));
+            }
+        }
+        sketch.insert(&Value::Null);
+
+        let top = sketch.top(2);
+        assert_eq!(top.len(), 2);
+        assert_eq!((&top[0].value, top[0].count, top[0].error), (&string("c"), 7, 0));
+        assert_eq!((&top[1].value, top[1].count, top[1].error), (&string("a"), 5, 0));
+        assert_eq!(sketch.total(), 15);
+    }
+
+    #[test]
+    fn test_heavy_hitters_survive_eviction() {
+        let mut sketch = SpaceSaving::with_capacity(20);
+        for i in 0..10_000 {
+            // Every third value is "hot"; the rest are all distinct
+            if i % 3 == 0 {
+                sketch.insert(&string("hot"));
+            } else {
+                sketch.insert(&Value::Int64(i));
+            }
+        }
+
+        let top = sketch.top(1);
+        assert_eq!(top[0].value, string("hot"));
+        // The estimate is never below the true count, and at most `error` above it
+        assert!(top[0].count >= 3334);
+        assert!(top[0].count - top[0].error <= 3334);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut left = SpaceSaving::with_capacity(4);
+        let mut right = SpaceSaving::with_capacity(4);
+        for (i, value) in ["a", "b", "a", "c", "a", "d", "e"].iter().enumerate() {
+            left.insert(&string(value));
+            right.insert(&string(if i % 2 == 0 { "b" } else { value }));
+        }
+
+        left.merge(&right);
+        let top = left.top(2);
+        assert_eq!(left.total(), 14);
+        // b occurs 6 times, but the left sketch evicted it
+        assert_eq!(top[0].value, string("b"));
+        assert!(top[0].count >= 6 && top[0].count - top[0].error <= 6);
+        assert_eq!(top[1].value, string("a"));
+
+        // Round trip through the wire format
+        let json = serde_json::to_string(&left).unwrap();
+        let mut restored: SpaceSaving = serde_json::from_str(&json).unwrap();
+        restored.insert(&string("a"));
+        assert_eq!(restored.top(2)[1].count, top[1].count + 1);
+    }
+}
@@ -0,0 +1,161 @@
+//! Bounded selection for ORDER BY ... LIMIT
+//!
+//! Only the best `limit` rows are kept, in a max-heap whose top is the worst
+//! row kept so far, so ordering a large result costs O(rows · log limit)
+//! time and O(limit) memory instead of a full sort.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use super::planner::OrderByPlan;
+use crate::data::Value;
+
+/// How ORDER BY compares result rows
+#[derive(Debug, Clone)]
+pub struct RowOrder {
+    /// Column index and whether it sorts descending. Columns not in the
+    /// result are skipped.
+    keys: Vec<(usize, bool)>,
+}
+
+impl RowOrder {
+    pub fn new(columns: &[String], order_by: &[OrderByPlan]) -> Self {
+        let keys = order_by
+            .iter()
+            .filter_map(|ob| {
+                let index = columns.iter().position(|c| *c == ob.column)?;
+                Some((index, ob.descending))
+            })
+            .collect();
+        Self { keys }
+    }
+
+    pub fn compare(&self, a: &[Value], b: &[Value]) -> Ordering {
+        for &(index, descending) in &self.keys {
+            let cmp = a[index].cmp(&b[index]);
+            if cmp != Ordering::Equal {
+                return if descending { cmp.reverse() } else { cmp };
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+/// The first `limit` rows of a stream in ORDER BY order
+pub struct TopN<'a> {
+    order: &'a RowOrder,
+    limit: usize,
+    heap: BinaryHeap<Ranked<'a>>,
+    pushed: usize,
+}
+
+impl<'a> TopN<'a> {
+    pub fn new(order: &'a RowOrder, limit: usize) -> Self {
+        Self {
+            order,
+            limit,
+            heap: BinaryHeap::with_capacity(limit.saturating_add(1).min(4096)),
+            pushed: 0,
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<Value>) {
+        if self.limit == 0 {
+            return;
+        }
+        let ranked = Ranked {
+            row,
+            seq: self.pushed,
+            order: self.order,
+        };
+        self.pushed += 1;
+
+        if self.heap.len() < self.limit {
+            self.heap.push(ranked);
+        } else if let Some(mut worst) = self.heap.peek_mut() {
+            if ranked < *worst {
+                *worst = ranked;
+            }
+        }
+    }
+
+    /// Rows kept, best first. Rows that compare equal stay in the order
+    /// they were pushed, as with a stable sort.
+    pub fn into_sorted(self) -> Vec<Vec<Value>> {
+        self.heap.into_sorted_vec().into_iter().map(|r| r.row).collect()
+    }
+}
+
+/// Sort `rows` and keep the first `limit`
+pub fn top_n(rows: Vec<Vec<Value>>, order: &RowOrder, limit: usize) -> Vec<Vec<Value>> {
+    let mut top = TopN::new(order, limit);
+    for row in rows {
+        top.push(row);
+    }
+    top.into_sorted()
+}
+
+/// A row and when it was pushed, ordered by the row and then by arrival
+struct Ranked<'a> {
+    row: Vec<Value>,
+    seq: usize,
+    order: &'a RowOrder,
+}
+
+impl Ord for Ranked<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.order
+            .compare(&self.row, &other.row)
+            .then(self.seq.cmp(&other.seq))
+    }
+}
+
+impl PartialOrd for Ranked<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_n_matches_sort_then_truncate() {
+        let columns = vec!["host".to_string(), "latency".to_string()];
+        let order = RowOrder::new(
+            &columns,
+            &[
+                OrderByPlan {
+                    column: "latency".to_string(),
+                    descending: true,
+                },
+                OrderByPlan {
+                    column: "missing".to_string(),
+                    descending: false,
+                },
+            ],
+        );
+        let rows: Vec<Vec<Value>> = [("a", 5), ("b", 9), ("c", 1), ("d", 9), ("e", 7)]
+            .iter()
+            .map(|&(host, latency)| vec![Value::String(host.into()), Value::Int64(latency)])
+            .collect();
+
+        let mut sorted = rows.clone();
+        sorted.sort_by(|a, b| order.compare(a, b));
+        for limit in 0..=6 {
+            let expected: Vec<_> = sorted.iter().take(limit).cloned().collect();
+            assert_eq!(top_n(rows.clone(), &order, limit), expected);
+        }
+        // Ties keep their input order
+        assert_eq!(top_n(rows, &order, 2)[0][0], Value::String("b".into()));
+    }
+}
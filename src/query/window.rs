@@ -0,0 +1,241 @@
+//! Window functions over aggregated rows
+//!
+//! Evaluated once aggregation is done, across the rows of each partition in
+//! order: `RATE(MAX(requests))` per time bucket, or
+//! `MOVING_AVG(p99, 5) OVER (PARTITION BY host ORDER BY minute)`. Without
+//! OVER, every series of the GROUP BY is a partition, ordered by its time
+//! bucket.
+
+use std::collections::HashMap;
+
+use super::expr::{arithmetic, ArithmeticOp};
+use super::parser::ParseError;
+use super::planner::ProjectionPlan;
+use crate::data::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowFunction {
+    /// LAG(x[, n]): x from n rows earlier
+    Lag(usize),
+    /// LEAD(x[, n]): x from n rows later
+    Lead(usize),
+    /// DELTA(x): change since the previous row
+    Delta,
+    /// RATE(x): increase per second since the previous row. A decrease is
+    /// taken as a counter reset, so the increase is x itself.
+    Rate,
+    /// RUNNING_SUM(x): sum of x over this and all earlier rows, including
+    /// rows with the same order value
+    RunningSum,
+    /// MOVING_AVG(x, n): mean of x over this and the n - 1 earlier rows
+    MovingAvg(usize),
+}
+
+/// Names of all window functions
+const NAMES: &[&str] = &[
+    "LAG", "LEAD", "DELTA", "RATE", "RUNNING_SUM", "CUMULATIVE_SUM", "MOVING_AVG",
+];
+
+impl WindowFunction {
+    pub fn is_defined(name: &str) -> bool {
+        NAMES.iter().any(|n| n.eq_ignore_ascii_case(name))
+    }
+
+    /// Look up a window function by name. `options` are the arguments after
+    /// the first, which must be positive integers.
+    pub fn resolve(name: &str, options: &[Value]) -> Result<WindowFunction, ParseError> {
+        let count = |default: Option<usize>| match (options, default) {
+            ([], Some(default)) => Ok(default),
+            ([n], _) => match n.as_i64() {
+                Some(n) if n > 0 => Ok(n as usize),
+                _ => Err(ParseError::InvalidFunctionArguments(format!(
+                    "{} expects a positive row count, got {}",
+                    name, n
+                ))),
+            },
+            _ => Err(ParseError::InvalidFunctionArguments(format!(
+                "wrong number of arguments to {}",
+                name
+            ))),
+        };
+
+        match name.to_uppercase().as_str() {
+            "LAG" => Ok(WindowFunction::Lag(count(Some(1))?)),
+            "LEAD" => Ok(WindowFunction::Lead(count(Some(1))?)),
+            "MOVING_AVG" => Ok(WindowFunction::MovingAvg(count(None)?)),
+            other => {
+                if !options.is_empty() {
+                    return Err(ParseError::InvalidFunctionArguments(format!(
+                        "{} takes one argument",
+                        name
+                    )));
+                }
+                match other {
+                    "DELTA" => Ok(WindowFunction::Delta),
+                    "RATE" => Ok(WindowFunction::Rate),
+                    "RUNNING_SUM" | "CUMULATIVE_SUM" => Ok(WindowFunction::RunningSum),
+                    _ => Err(ParseError::UnsupportedFunction(name.to_string())),
+                }
+            }
+        }
+    }
+
+    /// Results for one partition, given its argument values and order
+    /// values in order
+    fn evaluate(&self, values: &[Value], order: &[Value]) -> Vec<Value> {
+        let earlier = |i: usize, n: usize| i.checked_sub(n).map(|j| &values[j]);
+        // RUNNING_SUM's total through the end of the current run of peers
+        let (mut summed_to, mut sum) = (0, Value::Null);
+
+        (0..values.len())
+            .map(|i| match self {
+                WindowFunction::Lag(n) => earlier(i, *n).cloned().unwrap_or(Value::Null),
+                WindowFunction::Lead(n) => values.get(i + n).cloned().unwrap_or(Value::Null),
+                WindowFunction::Delta => match earlier(i, 1) {
+                    Some(previous) => arithmetic(ArithmeticOp::Sub, &values[i], previous),
+                    None => Value::Null,
+                },
+                WindowFunction::Rate => {
+                    let (Some(previous), Some(value)) =
+                        (earlier(i, 1).and_then(Value::as_f64), values[i].as_f64())
+                    else {
+                        return Value::Null;
+                    };
+                    let increase = if value >= previous { value - previous } else { value };
+                    match (order[i].as_i64(), order[i - 1].as_i64()) {
+                        (Some(t), Some(t0)) if t > t0 => {
+                            Value::Float64(increase / ((t - t0) as f64 / 1000.0))
+                        }
+                        _ => Value::Null,
+                    }
+                }
+                WindowFunction::RunningSum => {
+                    // Rows tied on the order column are peers and share a sum
+                    if i >= summed_to {
+                        summed_to = i + order[i..].iter().take_while(|o| **o == order[i]).count();
+                        for v in values[i..summed_to].iter().filter(|v| !v.is_null()) {
+                            sum = match &sum {
+                                Value::Null => v.clone(),
+                                sum => arithmetic(ArithmeticOp::Add, sum, v),
+                            };
+                        }
+                    }
+                    sum.clone()
+                }
+                WindowFunction::MovingAvg(n) => {
+                    let window: Vec<f64> = values[(i + 1).saturating_sub(*n)..=i]
+                        .iter()
+                        .filter_map(Value::as_f64)
+                        .collect();
+                    if window.is_empty() {
+                        Value::Null
+                    } else {
+                        Value::Float64(window.iter().sum::<f64>() / window.len() as f64)
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Fill in the window function columns of aggregated rows
+pub fn apply_windows(projections: &[ProjectionPlan], columns: &[String], rows: &mut [Vec<Value>]) {
+    let index = |name: &str| columns.iter().position(|c| c == name);
+
+    for (column, projection) in projections.iter().enumerate() {
+        let ProjectionPlan::Window {
+            function,
+            argument,
+            partition_by,
+            order_by,
+            ..
+        } = projection
+        else {
+            continue;
+        };
+        let partition_columns: Vec<Option<usize>> =
+            partition_by.iter().map(|c| index(c)).collect();
+        let Some(order_column) = index(order_by) else {
+            continue;
+        };
+
+        let mut partitions: HashMap<Vec<Value>, Vec<usize>> = HashMap::new();
+        for (i, row) in rows.iter().enumerate() {
+            let key = partition_columns
+                .iter()
+                .map(|c| c.map_or(Value::Null, |c| row[c].clone()))
+                .collect();
+            partitions.entry(key).or_default().push(i);
+        }
+
+        for mut members in partitions.into_values() {
+            members.sort_by(|&a, &b| rows[a][order_column].cmp(&rows[b][order_column]));
+            let values: Vec<Value> = members
+                .iter()
+                .map(|&i| {
+                    let row = &rows[i];
+                    argument.evaluate(&|name| index(name).map_or(Value::Null, |c| row[c].clone()))
+                })
+                .collect();
+            let order: Vec<Value> =
+                members.iter().map(|&i| rows[i][order_column].clone()).collect();
+
+            for (i, result) in members.into_iter().zip(function.evaluate(&values, &order)) {
+                rows[i][column] = result;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(function: &str, options: &[i64], values: &[i64]) -> Vec<Value> {
+        let options: Vec<Value> = options.iter().copied().map(Value::Int64).collect();
+        let values: Vec<Value> = values.iter().copied().map(Value::Int64).collect();
+        let order: Vec<Value> =
+            (0..values.len() as i64).map(|i| Value::Timestamp(i * 10_000)).collect();
+        WindowFunction::resolve(function, &options)
+            .unwrap()
+            .evaluate(&values, &order)
+    }
+
+    #[test]
+    fn test_offsets_and_deltas() {
+        let (null, int) = (Value::Null, Value::Int64);
+        assert_eq!(evaluate("LAG", &[], &[1, 2, 3]), vec![null.clone(), int(1), int(2)]);
+        assert_eq!(evaluate("lag", &[2], &[1, 2, 3]), vec![null.clone(), null.clone(), int(1)]);
+        assert_eq!(evaluate("LEAD", &[], &[1, 2, 3]), vec![int(2), int(3), null.clone()]);
+        assert_eq!(evaluate("DELTA", &[], &[5, 3, 10]), vec![null, int(-2), int(7)]);
+    }
+
+    #[test]
+    fn test_rate_handles_counter_resets() {
+        // Rows are 10 seconds apart; the counter restarts after 100
+        assert_eq!(
+            evaluate("RATE", &[], &[0, 50, 100, 20]),
+            vec![Value::Null, Value::Float64(5.0), Value::Float64(5.0), Value::Float64(2.0)]
+        );
+    }
+
+    #[test]
+    fn test_running_sum_and_moving_avg() {
+        let int = Value::Int64;
+        assert_eq!(evaluate("RUNNING_SUM", &[], &[1, 2, 3]), vec![int(1), int(3), int(6)]);
+        // Peers share the sum through the last of them; NULLs are skipped
+        let order: Vec<Value> = [1, 2, 2, 3].into_iter().map(Value::Timestamp).collect();
+        let values = [int(1), int(2), Value::Null, int(4)];
+        assert_eq!(
+            WindowFunction::RunningSum.evaluate(&values, &order),
+            vec![int(1), int(3), int(3), int(7)]
+        );
+        assert_eq!(
+            evaluate("MOVING_AVG", &[2], &[2, 4, 8]),
+            vec![Value::Float64(2.0), Value::Float64(3.0), Value::Float64(6.0)]
+        );
+        assert!(WindowFunction::resolve("MOVING_AVG", &[]).is_err());
+        assert!(WindowFunction::resolve("LAG", &[Value::Int64(0)]).is_err());
+        assert!(WindowFunction::resolve("RATE", &[Value::Int64(2)]).is_err());
+    }
+}
@@ -47,6 +47,15 @@ impl StorageEngine {
         Ok(table)
     }
 
+    /// Add a table built elsewhere, such as one loaded from CSV, replacing
+    /// any table of the same name
+    pub fn replace_table(&self, table: Table) -> Arc<Table> {
+        let table = Arc::new(table);
+        self.tables.insert(table.name().to_string(), Arc::clone(&table));
+        self.sync_memory();
+        table
+    }
+
     /// Get or create a table (auto-creates with default config if not exists)
     pub fn get_or_create_table(&self, name: &str) -> Arc<Table> {
         if let Some(table) = self.tables.get(name) {
@@ -351,6 +351,10 @@ const ChartManager = {
         const yMax = Math.max(...allValues);
         const thresholdMarks = this.buildThresholdMarks(yMin, yMax);
 
+        // Explicit nulls come from FILL(NULL) and mark gaps; only bridge
+        // points that are merely absent for a series
+        const hasGaps = rows.some(r => r[metric.idx] === null);
+
         // Create a series for each unique value in the second dimension
         const series = seriesValues.map((sv, i) => {
             const seriesConfig = {
@@ -359,7 +363,7 @@ const ChartManager = {
                 areaStyle: this.chartType === 'area' ? { opacity: 0.4 } : undefined,
                 data: xValues.map(x => dataMap[x]?.[sv] ?? null),
                 smooth: true,
-                connectNulls: true
+                connectNulls: !hasGaps
             };
             // Add threshold marks to first series only
             if (i === 0 && this.threshold) {